@@ -1,16 +1,22 @@
 use crate::building_blocks::MemtableEntry;
-use core::cell::RefCell;
 use std::collections::BTreeMap;
-use std::rc::Rc;
+use std::ops::Bound;
 
 use crate::building_blocks::StorageCRUD;
 
 // NOTE: keys are cloned
 // node: (key, MemtableEntry(key, value))
+#[derive(Clone)]
 pub struct BTree<K, V> {
     inner: BTreeMap<K, V>,
 }
 
+impl<K: Ord, V> Default for BTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K: Ord, V> BTree<K, V> {
     pub fn new() -> Self {
         BTree {
@@ -44,39 +50,26 @@ impl<K: Ord, V> BTree<K, V> {
     }
 }
 
-impl StorageCRUD for BTree<String, Rc<RefCell<MemtableEntry>>> {
+impl StorageCRUD for BTree<Vec<u8>, MemtableEntry> {
     fn create(&mut self, item: MemtableEntry) {
-        let res = self.get(&item.key);
-        if let Some(old_item) = res {
-            _ = old_item.replace(item);
-        } else {
-            let ckey = item.key.clone();
-            self.insert(ckey, Rc::new(RefCell::new(item)));
-        }
+        self.insert(item.key.clone(), item);
     }
 
-    fn read(&mut self, key: String) -> Option<Rc<RefCell<MemtableEntry>>> {
-        let res = self.get(&key);
-        if res.is_some() {
-            Some(Rc::clone(res.unwrap()))
-        } else {
-            None
-        }
+    fn read(&self, key: &[u8]) -> Option<MemtableEntry> {
+        self.inner.get(key).cloned()
     }
 
     fn update(&mut self, item: MemtableEntry) {
-        let old_item = self.get(&item.key);
-        if let Some(old_item) = old_item {
-            _ = old_item.borrow_mut().update(item.value);
+        if let Some(old_item) = self.inner.get_mut(&item.key) {
+            old_item.update(item.timestamp, item.value, item.expires_at);
         } else {
             self.create(item);
         }
     }
 
     fn delete(&mut self, item: MemtableEntry) {
-        let old_item = self.get(&item.key);
-        if let Some(old_item) = old_item {
-            _ = old_item.borrow_mut().delete();
+        if let Some(old_item) = self.inner.get_mut(&item.key) {
+            old_item.delete(item.timestamp);
         } else {
             self.create(item);
         }
@@ -86,9 +79,26 @@ impl StorageCRUD for BTree<String, Rc<RefCell<MemtableEntry>>> {
         self.clear();
     }
 
-    fn entries(&self) -> Vec<Rc<RefCell<MemtableEntry>>> {
+    fn entries(&self) -> Vec<MemtableEntry> {
         self.values()
-            .map(|e| Rc::clone(e))
-            .collect::<Vec<Rc<RefCell<MemtableEntry>>>>()
+            .cloned()
+            .collect()
+    }
+
+    fn first_from(&self, from: Bound<&[u8]>) -> Option<MemtableEntry> {
+        self.inner.range::<[u8], _>((from, Bound::Unbounded))
+            .next()
+            .map(|(_, entry)| entry.clone())
+    }
+
+    fn last_before(&self, before: Option<&[u8]>) -> Option<MemtableEntry> {
+        let end = before.map_or(Bound::Unbounded, Bound::Excluded);
+        self.inner.range::<[u8], _>((Bound::Unbounded, end))
+            .next_back()
+            .map(|(_, entry)| entry.clone())
+    }
+
+    fn boxed_clone(&self) -> Box<dyn StorageCRUD> {
+        Box::new(self.clone())
     }
 }
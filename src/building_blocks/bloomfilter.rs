@@ -26,7 +26,7 @@ impl BloomFilter{
     pub fn new(item_count: u64, fp_prob: f64) -> Self{
         // size = -(items * log(probability)) / (log(2)^2)
         let bit_arr_len = -((item_count as f64 * fp_prob.log(EULER_NUMBER)) /
-                            (2_f64.log(EULER_NUMBER).powi(2) as f64))
+                            2_f64.log(EULER_NUMBER).powi(2))
                             .round() as u64;
         let bit_arr_len = closest_pow(bit_arr_len);
 
@@ -57,6 +57,20 @@ impl BloomFilter{
         }
     }
 
+    /// false positive probability the filter was sized for
+    pub fn fp_prob(&self) -> f64 {
+        self.fp_prob
+    }
+
+    pub fn hash_functions(&self) -> u64 {
+        self.hash_functions
+    }
+
+    /// number of bits in the filter
+    pub fn bit_len(&self) -> u64 {
+        self.bit_arr_len
+    }
+
     pub fn add(&mut self, item: &[u8]) -> Result<()>{
         for seed in self.seeds.iter(){
             let hash_result = murmur3_x64_128(&mut Cursor::new(item), *seed)
@@ -92,6 +106,14 @@ impl BloomFilter{
             .deserialize(&crc_ser[..])
             .context("deserializing crc")?;
 
+        // a corrupted len must not allocate more than the file holds
+        let file_len = file.metadata()
+            .context("getting filter file metadata")?
+            .len();
+        if len > file_len {
+            return Err(anyhow!("corrupted filter len"));
+        }
+
         let mut filter_ser = vec![0; len as usize];
         file.read_exact(&mut filter_ser)
             .context("reading filter")?;
@@ -160,11 +182,11 @@ mod tests{
         let mut bf = BloomFilter::new(100_000, 0.02);
 
         assert_eq!(bf.add(b"temp").unwrap(), ());
-        assert_eq!(bf.check(b"temp").unwrap(), true);
+        assert!(bf.check(b"temp").unwrap());
 
-        assert_eq!(bf.check(b"temp1").unwrap(), false);
+        assert!(!bf.check(b"temp1").unwrap());
         assert_eq!(bf.add(b"temp1").unwrap(), ());
-        assert_eq!(bf.check(b"temp1").unwrap(), true);
+        assert!(bf.check(b"temp1").unwrap());
     }
 
     #[test]
@@ -1,153 +1,305 @@
-use std::collections::{HashMap, VecDeque};
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
 
-/// LRU cache
-pub struct Cache {
-    /// None is not used as a placeholder rather it represnts a tombstone
-    data: VecDeque<Option<Vec<u8>>>,
+/// lookups answered by the cache and the ones that were not, with what it holds now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    /// sum of the sizes the entries were added with
+    pub bytes: usize,
+}
+
+/// LRU cache bounded by the bytes of its entries, the size of an entry is given when it is added
+/// entries are kept in a list ordered by use and the map points into it, so every operation is O(1)
+pub struct Cache<K, V> {
+    /// max number of bytes in the cache
+    capacity: usize,
+
+    size: usize,
+
+    /// mapping keys to their nodes
+    mapping: HashMap<K, usize>,
+
+    /// nodes of the list, slots of removed ones are reused
+    nodes: Vec<Option<Node<K, V>>>,
+
+    free: Vec<usize>,
 
-    /// max number of entries in the cash
-    capacity: u64,
+    /// most recently used node
+    head: Option<usize>,
 
-    size: u64,
+    /// least recently used node, evicted first
+    tail: Option<usize>,
 
-    /// mapping keys to indices in the data
-    mapping: HashMap<Vec<u8>, usize>,
+    hits: u64,
+    misses: u64,
+
+    /// calls of remove and remove_range so far
+    removals: u64,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    size: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
 }
 
-impl Cache {
-    pub fn new(capacity: u64) -> Self {
+impl<K: Hash + Eq + Clone, V> Cache<K, V> {
+    pub fn new(capacity: usize) -> Self {
         Cache {
-            data: VecDeque::with_capacity(capacity as usize),
             capacity,
             size: 0,
-            mapping: HashMap::with_capacity(capacity as usize),
+            mapping: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+            hits: 0,
+            misses: 0,
+            removals: 0,
         }
     }
 
-    pub fn find(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
-        let index = self.mapping.get(key);
-        if index.is_none() { None }
-        else {
-            let index = *index.unwrap();
-
-            let element = self.data.remove(index);
-            assert!(element.is_some());
-            let element = element.unwrap();
+    /// the entry becomes the most recently used one
+    pub fn find<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(&index) = self.mapping.get(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.unlink(index);
+        self.push_front(index);
+        self.nodes[index].as_ref().map(|node| &node.value)
+    }
 
-            let value = element.clone();
-            self.data.push_front(element);
+    /// adds a new entry to the cache or replaces the entry with the same key,
+    /// least recently used entries are evicted until it fits and an entry bigger than the cache is not kept
+    pub fn add(&mut self, key: K, value: V, size: usize) {
+        if let Some(&index) = self.mapping.get(&key) {
+            self.remove_node(index);
+        }
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let Some(tail) = self.tail else {
+                break;
+            };
+            self.remove_node(tail);
+        }
 
-            // update indices of the elements that were moved
-            for entry in self.mapping.iter_mut() {
-                if *entry.1 < index { *entry.1 += 1; }
+        let node = Node { key: key.clone(), value, size, prev: None, next: None };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = Some(node);
+                index
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
             }
+        };
+        self.push_front(index);
+        self.mapping.insert(key, index);
+        self.size += size;
+    }
 
-            *self.mapping.get_mut(key).unwrap() = 0;
-            Some(value)
-        }
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.removals += 1;
+        let index = *self.mapping.get(key)?;
+        Some(self.remove_node(index))
+    }
+
+    /// changes with every removal, also of keys that are not cached, a value read
+    /// elsewhere is added only if nothing was removed since the read started
+    pub fn removals(&self) -> u64 {
+        self.removals
     }
 
-    /// adds a new entry to the cache or updates an existing entry with the same key
-    pub fn add(&mut self, key: &[u8], value: Option<&[u8]>) {
-        let index = self.mapping.get(key);
-        if let Some(index) = index {
-            let index = index.clone();
-            let mut element = self.data.remove(index).unwrap();
-            let new_value = if let Some(value) = value { Some(value.to_owned()) } else { None };
-            element = new_value;
-            self.data.push_front(element);
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits, misses: self.misses, entries: self.mapping.len(), bytes: self.size }
+    }
 
-            for entry in self.mapping.iter_mut() {
-                if *entry.1 < index { *entry.1 += 1; }
-            }
-            *self.mapping.get_mut(key).unwrap() = 0;
-        } else {
-            self.check_space();
-            for entry in self.mapping.iter_mut() { *entry.1 += 1 }
-            self.mapping.insert(key.to_vec(), 0);
-
-            let value = if let Some(value) = value { Some(value.to_owned()) } else { None };
-            self.data.push_front(value);
-            self.size += 1;
+    fn remove_node(&mut self, index: usize) -> V {
+        self.unlink(index);
+        let node = self.nodes[index].take().unwrap();
+        self.mapping.remove(&node.key);
+        self.free.push(index);
+        self.size -= node.size;
+        node.value
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let node = self.nodes[index].as_mut().unwrap();
+        let (prev, next) = (node.prev.take(), node.next.take());
+        match prev {
+            Some(prev) => self.nodes[prev].as_mut().unwrap().next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].as_mut().unwrap().prev = prev,
+            None => self.tail = prev,
         }
     }
 
-    /// removes an entry that was used the least if there is no space
-    fn check_space(&mut self) {
-        assert!(self.size <= self.capacity);
-        if self.size == self.capacity {
-            _ = self.data.pop_back();
-            let mut key = None;
-            for entry in self.mapping.iter() {
-                if entry.1 == &((self.capacity-1) as usize) {
-                    key = Some(entry.0.clone());
-                    break;
-                }
-            }
-            assert!(key.is_some());
-            let key = key.unwrap();
-            _ = self.mapping.remove(&key);
-            self.size -= 1;
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].as_mut().unwrap().next = self.head;
+        match self.head {
+            Some(head) => self.nodes[head].as_mut().unwrap().prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+}
+
+impl<V> Cache<Vec<u8>, V> {
+    /// drops the cached entries of the keys in [start, end)
+    pub fn remove_range(&mut self, start: &[u8], end: &[u8]) {
+        self.removals += 1;
+        let keys = self.mapping.keys()
+            .filter(|key| &key[..] >= start && &key[..] < end)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(&key);
         }
     }
 }
 
+#[cfg(test)]
 mod tests {
-    use super::Cache;
+    use super::{Cache, CacheStats};
+
+    /// the values in the tests take as many bytes as they have
+    fn add(cache: &mut Cache<Vec<u8>, Option<Vec<u8>>>, key: &[u8], value: Option<&[u8]>) {
+        let size = key.len() + value.map_or(0, <[u8]>::len);
+        cache.add(key.to_vec(), value.map(<[u8]>::to_vec), size);
+    }
 
     #[test]
     fn adding_and_overflowing() {
-        let mut cache = Cache::new(3);
+        let mut cache = Cache::new(12);
 
         let key1 = b"key1";
         let key2 = b"key2";
         let key3 = b"key3";
         let key4 = b"key4";
 
-        cache.add(key1, None);
-        assert_eq!(cache.size, 1);
+        add(&mut cache, key1, None);
+        assert_eq!(cache.stats().bytes, 4);
+
+        add(&mut cache, key1, None);
+        assert_eq!(cache.stats().bytes, 4);
 
-        cache.add(key1, None);
-        assert_eq!(cache.size, 1);
+        add(&mut cache, key2, None);
+        assert_eq!(cache.stats().bytes, 8);
 
-        cache.add(key2, None);
-        assert_eq!(cache.size, 2);
+        add(&mut cache, key3, None);
+        assert_eq!(cache.stats().bytes, 12);
+
+        add(&mut cache, key4, None);
+        assert_eq!(cache.stats().bytes, 12);
+
+        assert!(cache.find(&key1[..]).is_none());
+        assert!(cache.find(&key2[..]).is_some());
+        assert!(cache.find(&key3[..]).is_some());
+        assert!(cache.find(&key4[..]).is_some());
+    }
 
-        cache.add(key3, None);
-        assert_eq!(cache.size, 3);
+    #[test]
+    fn evicting_by_size() {
+        let mut cache = Cache::new(16);
+        add(&mut cache, b"a", Some(b"1234"));
+        add(&mut cache, b"b", Some(b"1234"));
+        add(&mut cache, b"c", Some(b"1234"));
+        assert!(cache.find(&b"a"[..]).is_some());
 
-        cache.add(key4, None);
-        assert_eq!(cache.size, 3);
+        // b and c are the least recently used ones, both have to go for the big entry to fit
+        add(&mut cache, b"d", Some(b"12345678"));
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().bytes, 14);
+        assert!(cache.find(&b"a"[..]).is_some());
+        assert!(cache.find(&b"b"[..]).is_none());
+        assert!(cache.find(&b"c"[..]).is_none());
 
-        assert!(cache.find(key1).is_none());
-        assert!(cache.find(key2).is_some());
-        assert!(cache.find(key3).is_some());
-        assert!(cache.find(key4).is_some());
+        // bigger than the whole cache
+        add(&mut cache, b"e", Some(&[0; 16]));
+        assert!(cache.find(&b"e"[..]).is_none());
+        assert_eq!(cache.stats().entries, 2);
     }
 
     #[test]
     fn finding() {
-        let mut cache = Cache::new(2);
-        assert!(cache.find(b"key").is_none());
+        let mut cache = Cache::new(16);
+        assert!(cache.find(&b"key"[..]).is_none());
+
+        add(&mut cache, b"key", None);
+        assert!(cache.find(&b"key"[..]).is_some());
 
-        cache.add(b"key", None);
-        assert!(cache.find(b"key").is_some());
+        add(&mut cache, b"key2", None);
+        assert!(cache.find(&b"key2"[..]).is_some());
 
-        cache.add(b"key2", None);
-        assert!(cache.find(b"key2").is_some());
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1, entries: 2, bytes: 7 });
     }
 
     #[test]
     fn updating() {
-        let mut cache = Cache::new(2);
-        cache.add(b"key", None);
-        let value = cache.find(b"key");
+        let mut cache = Cache::new(16);
+        add(&mut cache, b"key", None);
+        let value = cache.find(&b"key"[..]);
         assert!(value.is_some());
-        assert_eq!(value, Some(None));
+        assert_eq!(value, Some(&None));
 
-        cache.add(b"key", Some(b"value"));
-        let value = cache.find(b"key");
+        add(&mut cache, b"key", Some(b"value"));
+        let value = cache.find(&b"key"[..]);
         assert!(value.is_some());
-        assert_eq!(value, Some(Some(b"value".to_vec())));
+        assert_eq!(value, Some(&Some(b"value".to_vec())));
+        assert_eq!(cache.stats().bytes, 8);
+    }
+
+    #[test]
+    fn removing() {
+        let mut cache = Cache::new(16);
+        add(&mut cache, b"a", Some(b"value"));
+        assert_eq!(cache.remove(&b"a"[..]), Some(Some(b"value".to_vec())));
+        assert_eq!(cache.remove(&b"a"[..]), None);
+        assert!(cache.find(&b"a"[..]).is_none());
+        assert_eq!(cache.stats().bytes, 0);
+
+        // the slot of the removed entry is reused
+        add(&mut cache, b"b", None);
+        add(&mut cache, b"c", None);
+        assert!(cache.find(&b"b"[..]).is_some());
+        assert!(cache.find(&b"c"[..]).is_some());
+    }
+
+    #[test]
+    fn removing_range() {
+        let mut cache = Cache::new(3);
+        add(&mut cache, b"a", None);
+        add(&mut cache, b"b", None);
+        add(&mut cache, b"c", None);
+
+        cache.remove_range(b"b", b"c");
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.find(&b"b"[..]).is_none());
+
+        // the least recently used entry is still evicted first
+        add(&mut cache, b"d", None);
+        add(&mut cache, b"e", None);
+        assert!(cache.find(&b"a"[..]).is_none());
+        assert!(cache.find(&b"c"[..]).is_some());
+        assert!(cache.find(&b"d"[..]).is_some());
     }
 }
@@ -60,20 +60,22 @@ impl CountMinSketch {
     /// and increment the cell at index hash % column_count,
     /// for every row respectively
     pub fn add(&mut self, item: &str) -> Result<()> {
-        // current hash function index
-        let mut hash_index = 0;
+        self.add_count(item, 1)
+    }
+
+    /// same as adding the item count times
+    pub fn add_count(&mut self, item: &str, count: u64) -> Result<()> {
         let mut col_index;
 
-        for seed in self.seeds.iter() {
+        for (hash_index, seed) in self.seeds.iter().enumerate() {
             let hash_result =
                 murmur3_x64_128(&mut Cursor::new(item), *seed).context("error hashing an item")?;
 
             // get column index
             col_index = modulo(hash_result, self.column_count as u128);
 
-            self.matrix[hash_index][col_index] += 1;
-
-            hash_index += 1;
+            let cell = &mut self.matrix[hash_index][col_index];
+            *cell = cell.saturating_add(count);
         }
         Ok(())
     }
@@ -82,13 +84,11 @@ impl CountMinSketch {
     /// and track the values at index hash % column_count,
     /// for every row respectively, then return the minimum of those values
     pub fn count(&self, item: &str) -> Result<u64> {
-        // current hash function index
-        let mut hash_index = 0;
         let mut col_index;
         let mut min = u64::MAX;
         let mut curr;
 
-        for seed in self.seeds.iter() {
+        for (hash_index, seed) in self.seeds.iter().enumerate() {
             let hash_result =
                 murmur3_x64_128(&mut Cursor::new(item), *seed).context("error hashing an item")?;
 
@@ -100,22 +100,20 @@ impl CountMinSketch {
             if curr < min {
                 min = curr;
             }
-
-            hash_index += 1;
         }
         Ok(min)
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .serialize(&self)
-            .context("serializing cms")?)
+            .context("serializing cms")
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .deserialize(data)
-            .context("deserializing cms")?)
+            .context("deserializing cms")
     }
 }
 
@@ -133,7 +131,17 @@ mod tests {
         }
 
         let val = cms.count("foo").unwrap();
-        assert_eq!(val >= 4, true);
+        assert!(val >= 4);
+    }
+
+    #[test]
+    fn add_many() {
+        let mut cms = CountMinSketch::new(0.1, 0.1);
+        cms.add_count("foo", 1_000_000_000).unwrap();
+        cms.add("foo").unwrap();
+        cms.add_count("foo", 0).unwrap();
+
+        assert!(cms.count("foo").unwrap() >= 1_000_000_001);
     }
 
     #[test]
@@ -146,7 +154,7 @@ mod tests {
         }
 
         let val = cms.count("foo").unwrap();
-        assert_eq!(val >= 2, true);
+        assert!(val >= 2);
     }
 
     #[test]
@@ -160,7 +168,7 @@ mod tests {
         }
 
         let val = cms.count("foo").unwrap();
-        assert_eq!(val > 0, false);
+        assert_eq!(val, 0);
     }
 
     #[test]
@@ -175,7 +183,7 @@ mod tests {
         }
 
         let val = cms.count("bigfoot").unwrap();
-        assert_eq!(val > 0, false);
+        assert_eq!(val, 0);
     }
 
     #[test]
@@ -191,7 +199,7 @@ mod tests {
         }
 
         let val = cms.count("egg").unwrap();
-        assert_eq!(val >= 1, true);
+        assert!(val >= 1);
     }
 
     #[test]
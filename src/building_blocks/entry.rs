@@ -5,35 +5,77 @@ use bincode::Options;
 use crate::building_blocks::BINCODE_OPTIONS;
 use super::MemtableEntry;
 
-/// |CRC(u32),Timestamp(u128),Tombstone(u8),Key len(u64),Value len(8B),key,value|
+/// |CRC(u32),Timestamp(u128),Key len(u64),key,Tombstone(u8),Value len(8B),value,Kind(u32),End len(8B),end,Expires(u8),Expires at(u128)|
 /// a single data entry
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Entry {
     /// nanos
     pub timestamp: u128,
 
+    /// first deleted key for range tombstones
     pub key: Vec<u8>,
 
     /// value is optional incase of a tombstone
-    pub value: Option<Vec<u8>>
+    pub value: Option<Vec<u8>>,
+
+    pub kind: EntryKind,
+
+    /// nanos, the entry reads as a tombstone from then on
+    pub expires_at: Option<u128>,
+}
+
+/// what the entry applies to
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum EntryKind {
+    /// value or tombstone of a single key
+    #[default]
+    Point,
+
+    /// deletes all versions older than the entry of the keys in [key, end), never has a value
+    /// sstables keep these in a separate block
+    RangeDelete { end: Vec<u8> },
 }
 
 impl From<&MemtableEntry> for Entry {
     fn from(memtable_entry: &MemtableEntry) -> Self {
-        let key = memtable_entry.key
-            .chars()
-            .map(|c| c as u8)
-            .collect::<Vec<u8>>();
-
         Entry {
             timestamp: memtable_entry.timestamp,
-            key,
-            value: memtable_entry.value.clone()
+            key: memtable_entry.key.clone(),
+            value: memtable_entry.value.clone(),
+            kind: EntryKind::Point,
+            expires_at: memtable_entry.expires_at,
         }
     }
 }
 
 impl Entry {
+    /// range tombstone for the keys in [start, end)
+    pub fn range_delete(timestamp: u128, start: Vec<u8>, end: Vec<u8>) -> Self {
+        Entry { timestamp, key: start, value: None, kind: EntryKind::RangeDelete { end }, expires_at: None }
+    }
+
+    /// true if the entry has expired by the given time
+    pub fn is_expired(&self, now: u128) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_range_delete(&self) -> bool {
+        matches!(self.kind, EntryKind::RangeDelete { .. })
+    }
+
+    /// exclusive end of a range tombstone, None for point entries
+    pub fn range_end(&self) -> Option<&[u8]> {
+        match &self.kind {
+            EntryKind::RangeDelete { end } => Some(end),
+            EntryKind::Point => None,
+        }
+    }
+
+    /// true if the entry is a range tombstone that deletes the key
+    pub fn covers(&self, key: &[u8]) -> bool {
+        self.range_end().is_some_and(|end| &self.key[..] <= key && key < end)
+    }
+
     /// entry is serialized in format:
     /// length of the entry(8b)+crc(4b)+entry
     pub fn serialize(&self) -> Result<Vec<u8>> {
@@ -80,13 +122,32 @@ impl Entry {
     }
 }
 
+/// timestamp of the newest range tombstone not newer than `timestamp` that deletes the key
+/// versions older than it are deleted
+pub fn newest_range_delete(tombstones: &[Entry], key: &[u8], timestamp: u128) -> Option<u128> {
+    tombstones.iter()
+        .filter(|tombstone| tombstone.timestamp <= timestamp && tombstone.covers(key))
+        .map(|tombstone| tombstone.timestamp)
+        .max()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Entry;
+    use crate::building_blocks::MemtableEntry;
+    use super::{Entry, EntryKind, newest_range_delete};
+
+    #[test]
+    fn from_memtable_entry_binary_key() {
+        let key = vec![0xc3, 0x28, 0xff, 0x00];
+        let memtable_entry = MemtableEntry::new(1, key.clone(), Some(vec![1]));
+
+        let entry = Entry::from(&memtable_entry);
+        assert_eq!(entry.key, key);
+    }
 
     #[test]
     fn ser_deser_with_value() {
-        let entry = Entry{timestamp: 123, key: vec![1, 1, 1, 1, 1, 0], value: Some(vec![1, 1, 1])};
+        let entry = Entry{timestamp: 123, key: vec![1, 1, 1, 1, 1, 0], value: Some(vec![1, 1, 1]), kind: EntryKind::Point, expires_at: None};
 
         let entry_ser = entry.serialize();
         assert!(entry_ser.is_ok());
@@ -101,7 +162,7 @@ mod tests {
 
     #[test]
     fn ser_deser_without_value() {
-        let entry = Entry{timestamp: 123, key: vec![1, 1, 1, 1, 1, 0], value: None};
+        let entry = Entry{timestamp: 123, key: vec![1, 1, 1, 1, 1, 0], value: None, kind: EntryKind::Point, expires_at: None};
 
         let entry_ser = entry.serialize();
         assert!(entry_ser.is_ok());
@@ -113,4 +174,28 @@ mod tests {
 
         assert_eq!(entry, entry_deser);
     }
+
+    #[test]
+    fn ser_deser_range_delete() {
+        let entry = Entry::range_delete(123, b"a".to_vec(), b"c".to_vec());
+        let entry_ser = entry.serialize().unwrap();
+        assert_eq!(Entry::deserialize(&entry_ser[8..]).unwrap(), entry);
+    }
+
+    #[test]
+    fn range_delete_covers_half_open_range() {
+        let tombstones = vec![
+            Entry::range_delete(10, b"b".to_vec(), b"d".to_vec()),
+            Entry::range_delete(30, b"c".to_vec(), b"e".to_vec()),
+        ];
+        assert!(!tombstones[0].covers(b"a"));
+        assert!(tombstones[0].covers(b"b"));
+        assert!(tombstones[0].covers(b"cz"));
+        assert!(!tombstones[0].covers(b"d"));
+
+        assert_eq!(newest_range_delete(&tombstones, b"c", u128::MAX), Some(30));
+        assert_eq!(newest_range_delete(&tombstones, b"c", 20), Some(10));
+        assert_eq!(newest_range_delete(&tombstones, b"c", 5), None);
+        assert_eq!(newest_range_delete(&tombstones, b"e", u128::MAX), None);
+    }
 }
@@ -18,15 +18,12 @@ const HLL_MAX_PRECISION: u8 = 16;
 impl HyperLogLog{
     /// will panic if number of bits is not in range [4,16]
     pub fn new(number_of_bits: u8) -> Self{
-        if number_of_bits < HLL_MIN_PRECISION || number_of_bits > HLL_MAX_PRECISION{
+        if !(HLL_MIN_PRECISION..=HLL_MAX_PRECISION).contains(&number_of_bits){
             panic!("Number of bits has to be in range [4, 16]!");
         }
         let number_of_buckets = 1 << number_of_bits;
 
-        let mut buckets: Vec<u8> = Vec::with_capacity(number_of_buckets as usize);
-        for _ in 0..number_of_buckets {
-            buckets.push(0);
-        }
+        let buckets: Vec<u8> = vec![0; number_of_buckets as usize];
 
         HyperLogLog {
             number_of_bits,
@@ -42,7 +39,7 @@ impl HyperLogLog{
 
         let bucket = hash >> (64 - self.number_of_bits);
 
-        let mask: u64 = ((1 << self.number_of_bits) - 1) << 64 - self.number_of_bits;
+        let mask: u64 = ((1 << self.number_of_bits) - 1) << (64 - self.number_of_bits);
 
         let lower = hash | mask;
         let zeros = lower.trailing_zeros() as u8 + 1;
@@ -57,7 +54,7 @@ impl HyperLogLog{
         let mut empty_buckets = 0;
 
         for bucket_value in self.buckets.iter(){
-            sum += 2f64.powf(-1.0 * *bucket_value as f64);
+            sum += 2f64.powf(-(*bucket_value as f64));
             if *bucket_value == 0{
                 empty_buckets += 1;
             }
@@ -80,15 +77,15 @@ impl HyperLogLog{
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>>{
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .serialize(&self)
-            .context("serializing hll")?)
+            .context("serializing hll")
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self>{
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .deserialize(data)
-            .context("deserializing hll")?)
+            .context("deserializing hll")
     }
 }
 
@@ -1,5 +1,12 @@
-use anyhow::Result;
-use crate::building_blocks::{sstable::LSMTreeUnderlying, Entry};
+use std::{fs::{remove_dir_all, rename}, rc::Rc, sync::{Arc, Mutex, MutexGuard}};
+use anyhow::{Context, Result, anyhow};
+use serde::{Serialize, Deserialize};
+use crate::building_blocks::{sstable::{BlockCache, Compression, LSMTreeUnderlying, TableReader}, CacheStats, Entry, EntryKind, EntryIterator, SSTableCursor, SnapshotList, newest_range_delete};
+use crate::utils::helpers::sync_dir;
+use super::{Manifest, TableCache};
+
+/// number of tables kept open when the config does not set it
+pub const TABLE_CACHE_CAPACITY: usize = 64;
 
 #[derive(Debug)]
 pub struct TableNode {
@@ -13,6 +20,37 @@ pub struct Level {
     pub(super) nodes: Vec<TableNode>,
 }
 
+/// how tables are merged into the lower levels
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum Compaction {
+    /// once a level holds size_threshold tables all of them are merged into one table on the next level
+    #[default]
+    Tiered,
+    /// lower levels are limited by size and split into tables with disjoint key ranges
+    Leveled(LeveledCompaction),
+}
+
+/// level 0 is compacted once it holds size_threshold tables, the last level is unbounded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeveledCompaction {
+    /// byte budget of level 1
+    pub base_level_bytes: u64,
+    /// every next level gets this many times the budget of the previous one
+    pub multiplier: u64,
+    /// compaction output is split into tables of about this size
+    pub table_bytes: u64,
+}
+
+impl Default for LeveledCompaction {
+    fn default() -> Self {
+        LeveledCompaction {
+            base_level_bytes: 10 << 20,
+            multiplier: 10,
+            table_bytes: 2 << 20,
+        }
+    }
+}
+
 pub struct LSMTree<S: LSMTreeUnderlying> {
     pub(super) levels: Vec<Level>,
     // level size ?
@@ -23,13 +61,402 @@ pub struct LSMTree<S: LSMTreeUnderlying> {
     pub(super) data_dir: String,
     pub(super) size_threshold: usize,
     pub(super) last_table: usize,
+    /// highest WAL sequence whose records are in the tables
+    pub(super) flushed_sequence: u64,
+    /// live snapshots, merges keep the versions they can still see
+    pub(super) snapshots: SnapshotList,
+    pub(super) compaction: Compaction,
+    /// codec of the tables written by merges
+    pub(super) compression: Compression,
+    /// per level, last key of the table that was compacted last, leveled compaction goes round-robin
+    pub(super) compaction_pointers: Vec<Vec<u8>>,
+    /// log of table additions and removals, the source of truth for the levels
+    pub(super) manifest: Manifest,
+    /// opened tables by their path, shared by lookups, scans and merges
+    pub(super) tables: TableCache<S::Reader>,
+    /// blocks read by point lookups in any table, None if they are not cached
+    pub(super) block_cache: Option<Arc<BlockCache>>,
     pub(super) marker: std::marker::PhantomData<S>,
 }
 
-pub trait LSMTreeInterface {
-    fn get(&self, key: Vec<u8>) -> Option<Entry>;
+pub trait LSMTreeInterface: Send + Sync {
+    /// newest version of the key that is not newer than the timestamp
+    fn get_at(&self, key: &[u8], timestamp: u128) -> Result<Option<Entry>>;
     fn insert(&mut self, table_name: &str) -> Result<()>;
     fn load(&mut self) -> Result<()>;
-    fn prefix_scan(&mut self, prefix: &str) -> Result<Vec<Entry>>;
-    fn range_scan(&mut self, start_key: &str, end_key:&str) -> Result<Vec<Entry>>;
+
+    /// inserts a flushed memtable holding the WAL records up to the sequence,
+    /// the sequence is recorded in the manifest together with the table
+    /// unlike insert it does not compact, the caller compacts without holding the tree exclusively
+    fn insert_flushed(&mut self, table_name: &str, sequence: u64) -> Result<()>;
+
+    /// writes the tables of the next compaction the levels need, None if they need none
+    /// the levels are only read so lookups and scans run next to it
+    fn build_compaction(&self) -> Result<Option<CompactionOutput>>;
+
+    /// replaces the merged tables with the ones the compaction wrote
+    fn apply_compaction(&mut self, output: CompactionOutput) -> Result<()>;
+
+    /// WAL records up to it are in the tables and do not have to be replayed
+    fn flushed_sequence(&self) -> u64;
+
+    /// one sorted source per table for the merging iterator
+    fn sources(&self) -> Result<Vec<Box<dyn EntryIterator>>>;
+
+    /// range tombstones of all tables, the merging iterator needs them next to the sources
+    fn range_tombstones(&self) -> Result<Vec<Entry>>;
+
+    /// registry of live snapshots shared with the database
+    fn set_snapshots(&mut self, snapshots: SnapshotList);
+
+    /// None if blocks are not cached
+    fn block_cache_stats(&self) -> Result<Option<CacheStats>>;
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Entry>> {
+        self.get_at(&key, u128::MAX)
+    }
+
+    /// compacts until the levels need no more compaction
+    fn compact(&mut self) -> Result<()> {
+        while let Some(output) = self.build_compaction().context("building compaction")? {
+            self.apply_compaction(output).context("applying compaction")?;
+        }
+        Ok(())
+    }
+}
+
+/// tables a compaction wrote under temporary names, the levels do not refer to them yet
+pub struct CompactionOutput {
+    /// level and name of each merged table
+    pub(super) inputs: Vec<(usize, String)>,
+    /// level and temporary name of each new table, in the order they join the level
+    pub(super) outputs: Vec<(usize, String)>,
+    /// level and last key of the table leveled compaction picked, the next pick starts after it
+    pub(super) pointer: Option<(usize, Vec<u8>)>,
+}
+
+impl<S: LSMTreeUnderlying> LSMTree<S> {
+    /// tables written with another codec stay readable
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// number of tables kept open, the least recently used one is closed first
+    pub fn with_table_cache(mut self, capacity: usize) -> Self {
+        self.tables = TableCache::new(capacity);
+        self
+    }
+
+    /// bytes of blocks kept for point lookups, 0 turns the cache off
+    /// tables that are already open keep reading without it
+    pub fn with_block_cache(mut self, capacity: usize) -> Self {
+        self.block_cache = (capacity > 0).then(|| Arc::new(BlockCache::new(capacity)));
+        self
+    }
+
+    /// newest version of the key that is not newer than the timestamp, tables are read from the newest
+    /// each table finds the key with binary searches and at most one read of the index and the data
+    pub(super) fn get_from_tables(&self, key: &[u8], timestamp: u128) -> Result<Option<Entry>> {
+        // newest range tombstone deleting the key in the tables read so far
+        let mut range_delete = None;
+        for level in &self.levels {
+            for table in level.nodes.iter().rev() {
+                let reader = self.table(&table.path)?;
+                let mut reader = lock(&reader)?;
+                range_delete = range_delete.max(newest_range_delete(reader.range_tombstones(), key, timestamp));
+
+                let versions = reader.get(key)
+                    .with_context(|| format!("reading {}", table.path))?;
+                // versions are stored from the newest to the oldest
+                let Some(entry) = versions.into_iter().find(|entry| entry.timestamp <= timestamp) else {
+                    continue;
+                };
+
+                if range_delete.is_some_and(|deleted| deleted > entry.timestamp) {
+                    return Ok(None);
+                }
+
+                // a tombstone hides the older tables
+                return Ok(entry.value.is_some().then_some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// reader of the table from the table cache, it is loaded if it is not there
+    pub(super) fn table(&self, table: &str) -> Result<Arc<Mutex<S::Reader>>> {
+        let path = format!("{}/{table}", self.data_dir);
+        self.tables.get(&path, || {
+            let reader = S::Reader::load(&path).with_context(|| format!("loading {path}"))?;
+            Ok(match &self.block_cache {
+                Some(cache) => reader.with_block_cache(Arc::clone(cache)),
+                None => reader,
+            })
+        })
+    }
+
+    /// a new table can get the name of a removed one, so its reader can not be kept
+    pub(super) fn forget_tables(&self, tables: &[String]) -> Result<()> {
+        for table in tables {
+            self.tables.remove(&format!("{}/{table}", self.data_dir))?;
+        }
+        Ok(())
+    }
+
+    /// lazy cursors over all tables, newest versions can be in any of them
+    pub(super) fn table_sources(&self) -> Result<Vec<Box<dyn EntryIterator>>> {
+        let mut sources: Vec<Box<dyn EntryIterator>> = Vec::new();
+        for level in &self.levels {
+            for table in &level.nodes {
+                let cursor = SSTableCursor::new(self.table(&table.path)?)
+                    .with_context(|| format!("creating cursor over {}", table.path))?;
+                sources.push(Box::new(cursor));
+            }
+        }
+        Ok(sources)
+    }
+
+    /// lowest level holding size_threshold tables, tiered compaction merges it into the next one
+    pub(super) fn tiered_level(&self) -> Option<usize> {
+        (0..self.levels.len().saturating_sub(1))
+            .find(|&level_num| self.levels[level_num].nodes.len() >= self.size_threshold)
+    }
+
+    /// renames the new tables and swaps them for the merged ones,
+    /// the manifest records the swap before the merged tables leave the disk
+    pub(super) fn apply(&mut self, output: CompactionOutput) -> Result<()> {
+        let mut added = Vec::new();
+        for (level_num, table) in output.outputs {
+            self.last_table += 1;
+            let name = format!("sstable-{level_num}-{}", self.last_table);
+            rename(format!("{}/{table}", self.data_dir), format!("{}/{name}", self.data_dir))
+                .with_context(|| format!("renaming {table}"))?;
+            added.push((level_num, name));
+        }
+        // the manifest can name the tables only once the new names are durable
+        sync_dir(&self.data_dir)?;
+
+        let removed = output.inputs.iter().map(|(_, path)| path.clone()).collect::<Vec<_>>();
+        self.log_edit(added.clone(), removed.clone())
+            .context("recording compaction")?;
+
+        for (level_num, path) in &output.inputs {
+            self.levels[*level_num].nodes.retain(|node| node.path != *path);
+        }
+        for (level_num, path) in added {
+            self.levels[level_num].nodes.push(TableNode { path });
+        }
+        if let Some((level_num, key)) = output.pointer {
+            if self.compaction_pointers.len() < self.levels.len() {
+                self.compaction_pointers.resize(self.levels.len(), vec![]);
+            }
+            self.compaction_pointers[level_num] = key;
+        }
+
+        for table in removed {
+            remove_dir_all(format!("{}/{table}", self.data_dir))
+                .with_context(|| format!("removing {table}"))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn range_tombstones_of<'a>(&self, tables: impl IntoIterator<Item = &'a str>) -> Result<Vec<Entry>> {
+        let mut tombstones = Vec::new();
+        for table in tables {
+            let reader = self.table(table)?;
+            tombstones.extend_from_slice(lock(&reader)?.range_tombstones());
+        }
+        Ok(tombstones)
+    }
+}
+
+pub(super) fn lock<R>(reader: &Mutex<R>) -> Result<MutexGuard<'_, R>> {
+    reader.lock().map_err(|_| anyhow!("sstable reader lock poisoned"))
+}
+
+/// temporary name of a table a compaction writes, it is named from the table counter once
+/// the compaction is applied, until then a crash leaves only an unreferenced directory behind
+pub(super) fn compaction_table(idx: usize) -> String {
+    format!("compaction-{idx}")
+}
+
+/// number at the end of an "sstable-<level>-<number>" table name, newer tables of a level have bigger numbers
+pub(super) fn table_number(path: &str) -> usize {
+    path.rsplit('-')
+        .next()
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(0)
+}
+
+/// out of entries with the same key sorted by timestamp keeps the newest one
+/// and for every snapshot the newest version it can see, returned newest first
+fn versions_to_keep(entries: &[Rc<Entry>], snapshots: &[u128]) -> Vec<Rc<Entry>> {
+    let mut keep: Vec<Rc<Entry>> = Vec::new();
+    if let Some(newest) = entries.last() {
+        keep.push(Rc::clone(newest));
+    }
+
+    // going from the newest snapshot keeps the versions ordered
+    for snapshot in snapshots.iter().rev() {
+        let visible = entries.iter().rev().find(|entry| entry.timestamp <= *snapshot);
+        if let Some(visible) = visible {
+            if !keep.iter().any(|kept| Rc::ptr_eq(kept, visible)) {
+                keep.push(Rc::clone(visible));
+            }
+        }
+    }
+
+    keep
+}
+
+/// versions of one key a merge writes, newest first
+/// tombstones are kept while an older table outside of the merge can hold the key,
+/// otherwise one is dropped once no older version is left below it
+/// versions deleted by the range tombstones of the merged tables are dropped like the ones below a tombstone
+/// versions expired by `now` become tombstones unless a snapshot still sees their value
+pub(super) fn resolve_versions(
+    entries: &mut [Rc<Entry>],
+    snapshots: &[u128],
+    older: &[(Vec<u8>, Vec<u8>)],
+    range_tombstones: &[Entry],
+    now: u128,
+) -> Vec<Rc<Entry>> {
+    entries.sort_by_key(|entry| entry.timestamp);
+    let Some(key) = entries.first().map(|entry| entry.key.clone()) else {
+        return vec![];
+    };
+
+    for entry in entries.iter_mut() {
+        let seen = snapshots.iter().any(|snapshot| entry.timestamp <= *snapshot && !entry.is_expired(*snapshot));
+        if entry.is_expired(now) && !seen {
+            *entry = Rc::new(Entry { value: None, expires_at: None, ..Entry::clone(entry) });
+        }
+    }
+
+    // stand-ins for the range tombstones, they are written to the range tombstone block and not here
+    // a version with the same timestamp as a range tombstone is not deleted by it, so they sort below it
+    let range_deletes = range_tombstones.iter()
+        .filter(|tombstone| tombstone.covers(&key))
+        .map(|tombstone| Rc::new(Entry { timestamp: tombstone.timestamp, key: key.clone(), value: None, kind: EntryKind::Point, expires_at: None }))
+        .collect::<Vec<_>>();
+    let mut versions = range_deletes.iter().chain(entries.iter()).cloned().collect::<Vec<_>>();
+    versions.sort_by_key(|entry| entry.timestamp);
+    let mut keep = versions_to_keep(&versions, snapshots);
+
+    // a read that gets past a dropped tombstone finds nothing, same as with the tombstone
+    if !older.iter().any(|(first, last)| *first <= key && key <= *last) {
+        while keep.last().is_some_and(|entry| entry.value.is_none()) {
+            keep.pop();
+        }
+    }
+
+    keep.retain(|entry| !range_deletes.iter().any(|range_delete| Rc::ptr_eq(range_delete, entry)));
+    keep
+}
+
+/// range tombstones a merge writes, one is dropped once no older table can hold a key in its range
+/// and no snapshot needs a version below it
+pub(super) fn resolve_range_tombstones(
+    range_tombstones: Vec<Entry>,
+    snapshots: &[u128],
+    older: &[(Vec<u8>, Vec<u8>)],
+) -> Vec<Entry> {
+    range_tombstones.into_iter()
+        .filter(|tombstone| {
+            let end = tombstone.range_end().unwrap_or_default();
+            older.iter().any(|(first, last)| &first[..] < end && tombstone.key <= *last)
+                || snapshots.iter().any(|snapshot| *snapshot < tombstone.timestamp)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::building_blocks::{Entry, EntryKind};
+    use super::{resolve_range_tombstones, resolve_versions, versions_to_keep};
+
+    fn versions() -> Vec<Rc<Entry>> {
+        [(10, Some(1)), (20, None), (30, Some(3))]
+            .into_iter()
+            .map(|(timestamp, value)| Rc::new(Entry {
+                timestamp,
+                key: b"key".to_vec(),
+                value: value.map(|value| vec![value]),
+                kind: EntryKind::Point,
+                expires_at: None,
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn keep_versions_for_snapshots() {
+        let entries = versions();
+        let timestamps = |kept: Vec<Rc<Entry>>| kept.iter().map(|e| e.timestamp).collect::<Vec<_>>();
+
+        assert_eq!(timestamps(versions_to_keep(&entries, &[])), vec![30]);
+        assert_eq!(timestamps(versions_to_keep(&entries, &[15, 25])), vec![30, 20, 10]);
+        assert_eq!(timestamps(versions_to_keep(&entries, &[12, 15, 35])), vec![30, 10]);
+        assert_eq!(timestamps(versions_to_keep(&entries, &[5])), vec![30]);
+    }
+
+    #[test]
+    fn drop_tombstones_only_below_everything() {
+        let timestamps = |kept: Vec<Rc<Entry>>| kept.iter().map(|e| e.timestamp).collect::<Vec<_>>();
+        let older = vec![(b"a".to_vec(), b"z".to_vec())];
+
+        // the newest version is a tombstone, nothing older survives so it goes too
+        let mut entries = versions();
+        entries.push(Rc::new(Entry { timestamp: 40, key: b"key".to_vec(), value: None, kind: EntryKind::Point, expires_at: None }));
+        assert_eq!(timestamps(resolve_versions(&mut entries, &[], &[], &[], 0)), Vec::<u128>::new());
+        assert_eq!(timestamps(resolve_versions(&mut entries, &[], &older, &[], 0)), vec![40]);
+
+        // a tombstone above a version a snapshot needs hides that version from newer reads
+        assert_eq!(timestamps(resolve_versions(&mut entries, &[35], &[], &[], 0)), vec![40, 30]);
+        assert_eq!(timestamps(resolve_versions(&mut entries, &[25], &[], &[], 0)), Vec::<u128>::new());
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[25], &[], &[], 0)), vec![30]);
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[15], &[], &[], 0)), vec![30, 10]);
+    }
+
+    #[test]
+    fn range_tombstones_drop_older_versions() {
+        let timestamps = |kept: Vec<Rc<Entry>>| kept.iter().map(|e| e.timestamp).collect::<Vec<_>>();
+        let older = vec![(b"a".to_vec(), b"z".to_vec())];
+        let tombstones = vec![Entry::range_delete(25, b"k".to_vec(), b"l".to_vec())];
+
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[], &[], &tombstones, 0)), vec![30]);
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[22], &[], &tombstones, 0)), vec![30]);
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[15], &older, &tombstones, 0)), vec![30, 10]);
+
+        // nothing newer than the range tombstone is left
+        let tombstones = vec![Entry::range_delete(35, b"k".to_vec(), b"l".to_vec())];
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[], &older, &tombstones, 0)), Vec::<u128>::new());
+        assert_eq!(timestamps(resolve_versions(&mut versions(), &[32], &[], &tombstones, 0)), vec![30]);
+
+        // the range tombstone is kept while it can still delete something
+        assert_eq!(resolve_range_tombstones(tombstones.clone(), &[], &[]), vec![]);
+        assert_eq!(resolve_range_tombstones(tombstones.clone(), &[32], &[]), tombstones);
+        assert_eq!(resolve_range_tombstones(tombstones.clone(), &[], &older), tombstones);
+        assert_eq!(resolve_range_tombstones(tombstones.clone(), &[], &[(b"l".to_vec(), b"z".to_vec())]), vec![]);
+    }
+
+    #[test]
+    fn expired_versions_become_tombstones() {
+        let timestamps = |kept: Vec<Rc<Entry>>| kept.iter().map(|e| (e.timestamp, e.value.is_some())).collect::<Vec<_>>();
+        let older = vec![(b"a".to_vec(), b"z".to_vec())];
+        let expiring = || {
+            let mut entries = versions();
+            entries[2] = Rc::new(Entry { expires_at: Some(40), ..Entry::clone(&entries[2]) });
+            entries
+        };
+
+        assert_eq!(timestamps(resolve_versions(&mut expiring(), &[], &[], &[], 35)), vec![(30, true)]);
+        assert_eq!(timestamps(resolve_versions(&mut expiring(), &[], &[], &[], 40)), vec![]);
+        assert_eq!(timestamps(resolve_versions(&mut expiring(), &[], &older, &[], 40)), vec![(30, false)]);
+
+        // a snapshot taken before the expiry still reads the value
+        assert_eq!(timestamps(resolve_versions(&mut expiring(), &[35], &[], &[], 50)), vec![(30, true)]);
+        assert_eq!(timestamps(resolve_versions(&mut expiring(), &[45], &[], &[], 50)), vec![]);
+    }
 }
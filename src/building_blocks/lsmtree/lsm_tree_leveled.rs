@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use crate::building_blocks::{sstable::{LSMTreeUnderlying, TableReader}, Entry};
+use crate::utils::helpers::get_timestamp;
+use std::fs::read_dir;
+use std::iter::Peekable;
+use std::rc::Rc;
+use super::{LSMTree, Compaction, CompactionOutput, LeveledCompaction, resolve_versions, resolve_range_tombstones, compaction_table, lock};
+
+type TableEntries = Peekable<Box<dyn Iterator<Item = Result<Entry>>>>;
+
+/// level, indices of its tables to push down and the last key of a table picked round-robin
+type Pick = (usize, Vec<usize>, Option<Vec<u8>>);
+
+impl<S: LSMTreeUnderlying> LSMTree<S> {
+    pub fn with_compaction(mut self, compaction: Compaction) -> Self {
+        self.compaction = compaction;
+        self
+    }
+
+    /// next compaction while level 0 is not under the size threshold or another level is over its budget
+    pub(super) fn build_leveled(&self, options: &LeveledCompaction) -> Result<Option<CompactionOutput>> {
+        let Some((level_num, inputs, pointer)) = self.pick_compaction(options)? else {
+            return Ok(None);
+        };
+        self.compact_into_next(level_num, inputs, pointer, options)
+            .with_context(|| format!("compacting level {level_num}"))
+            .map(Some)
+    }
+
+    /// tables to push into the next level
+    fn pick_compaction(&self, options: &LeveledCompaction) -> Result<Option<Pick>> {
+        if self.levels.len() < 2 {
+            return Ok(None);
+        }
+
+        // level 0 tables overlap, all of them go down together
+        let level_zero = self.levels[0].nodes.len();
+        if level_zero > 0 && level_zero >= self.size_threshold {
+            return Ok(Some((0, (0..level_zero).collect(), None)));
+        }
+
+        let mut budget = options.base_level_bytes;
+        for level_num in 1..self.levels.len() - 1 {
+            if self.level_bytes(level_num)? > budget {
+                let (table, last) = self.next_table(level_num)?;
+                return Ok(Some((level_num, vec![table], Some(last))));
+            }
+            budget = budget.saturating_mul(options.multiplier);
+        }
+        Ok(None)
+    }
+
+    /// first table after the one compacted last and its last key, wraps around at the end of the level
+    fn next_table(&self, level_num: usize) -> Result<(usize, Vec<u8>)> {
+        let pointer = self.compaction_pointers.get(level_num).map_or(&[][..], |pointer| &pointer[..]);
+
+        let ranges = self.key_ranges(level_num)?;
+        let after = ranges.iter()
+            .enumerate()
+            .filter(|(_, (first, _))| &first[..] > pointer)
+            .min_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+        let first = ranges.iter()
+            .enumerate()
+            .min_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+
+        let (idx, (_, last)) = after.or(first).context("picking table of an empty level")?;
+        Ok((idx, last.clone()))
+    }
+
+    /// merges the input tables with the overlapping tables of the next level,
+    /// the output is split into tables of about table_bytes with disjoint key ranges
+    fn compact_into_next(
+        &self,
+        level_num: usize,
+        inputs: Vec<usize>,
+        pointer: Option<Vec<u8>>,
+        options: &LeveledCompaction,
+    ) -> Result<CompactionOutput> {
+        let input_ranges = self.key_ranges(level_num)?;
+        let low = inputs.iter().map(|&i| &input_ranges[i].0).min().context("no input tables")?.clone();
+        let high = inputs.iter().map(|&i| &input_ranges[i].1).max().context("no input tables")?.clone();
+
+        let overlapping: Vec<usize> = self.key_ranges(level_num + 1)?
+            .iter()
+            .enumerate()
+            .filter(|(_, (first, last))| *first <= high && *last >= low)
+            .map(|(i, _)| i)
+            .collect();
+
+        let mut merged: Vec<String> = inputs.iter()
+            .map(|&i| self.levels[level_num].nodes[i].path.clone())
+            .collect();
+        let lower: Vec<String> = overlapping.iter()
+            .map(|&i| self.levels[level_num + 1].nodes[i].path.clone())
+            .collect();
+        merged.extend(lower);
+
+        let mut sources: Vec<TableEntries> = Vec::new();
+        for path in &merged {
+            let reader = self.table(path)?;
+            let entries = lock(&reader)?.entries()
+                .with_context(|| format!("reading {path}"))?;
+            sources.push(entries.peekable());
+        }
+
+        // versions visible to live snapshots survive the merge
+        let snapshots = self.snapshots.timestamps();
+        // the next level holds the key only in the overlapping tables, those are merged too
+        let older = self.older_ranges(level_num + 2).context("reading key ranges")?;
+        let all_range_tombstones = self.range_tombstones_of(merged.iter().map(|path| &path[..]))
+            .context("reading range tombstones")?;
+        let range_tombstones = resolve_range_tombstones(all_range_tombstones.clone(), &snapshots, &older);
+        // entries expired by now are dropped like tombstones
+        let now = get_timestamp()?;
+
+        let mut outputs: Vec<(usize, String)> = Vec::new();
+        // first key of the table being filled, the ones before it went to the previous table
+        let mut lower: Option<Vec<u8>> = None;
+        let mut pending: Vec<Entry> = Vec::new();
+        let mut pending_bytes = 0;
+        let mut versions: Vec<Rc<Entry>> = Vec::new();
+        loop {
+            // an error on any source stops the compaction before entries go out of order
+            for source in sources.iter_mut() {
+                if let Some(Err(_)) = source.peek() {
+                    source.next().transpose()?;
+                }
+            }
+
+            let mut smallest: Option<(usize, &[u8])> = None;
+            for (idx, source) in sources.iter_mut().enumerate() {
+                if let Some(Ok(entry)) = source.peek() {
+                    if smallest.is_none_or(|(_, key)| entry.key[..] < *key) {
+                        smallest = Some((idx, &entry.key));
+                    }
+                }
+            }
+            let entry = match smallest.map(|(idx, _)| idx) {
+                Some(idx) => sources[idx].next().transpose()?,
+                None => None,
+            };
+
+            let same_key = matches!((&entry, versions.first()), (Some(entry), Some(first)) if entry.key == first.key);
+            if !same_key && !versions.is_empty() {
+                for kept in resolve_versions(&mut versions, &snapshots, &older, &all_range_tombstones, now) {
+                    pending_bytes += entry_bytes(&kept);
+                    pending.push((*kept).clone());
+                }
+                versions.clear();
+
+                // tables are cut only between keys so all versions of a key stay together
+                if let Some(next) = entry.as_ref().filter(|_| pending_bytes >= options.table_bytes) {
+                    let upper = Some(next.key.clone());
+                    pending.extend(clip(&range_tombstones, lower.as_deref(), upper.as_deref()));
+                    outputs.push(self.write_table(level_num + 1, outputs.len(), std::mem::take(&mut pending))?);
+                    pending_bytes = 0;
+                    lower = upper;
+                }
+            }
+
+            match entry {
+                Some(entry) => versions.push(Rc::new(entry)),
+                None => break,
+            }
+        }
+        pending.extend(clip(&range_tombstones, lower.as_deref(), None));
+        if !pending.is_empty() {
+            outputs.push(self.write_table(level_num + 1, outputs.len(), pending)?);
+        }
+
+        // the picked tables come first in the merged ones, the overlapping ones of the next level after them
+        let levels = std::iter::repeat_n(level_num, inputs.len()).chain(std::iter::repeat(level_num + 1));
+        Ok(CompactionOutput {
+            inputs: levels.zip(merged).collect(),
+            outputs,
+            pointer: pointer.map(|pointer| (level_num, pointer)),
+        })
+    }
+
+    /// the table gets its name once the compaction is applied
+    fn write_table(&self, level_num: usize, idx: usize, entries: Vec<Entry>) -> Result<(usize, String)> {
+        let name = compaction_table(idx);
+        S::write(&self.data_dir, &name, entries, self.fp_prob, self.summary_nth, self.compression)
+            .with_context(|| format!("writing {name}"))?;
+        Ok((level_num, name))
+    }
+
+    pub(super) fn key_ranges(&self, level_num: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.levels[level_num].nodes
+            .iter()
+            .map(|table| {
+                let reader = self.table(&table.path)
+                    .with_context(|| format!("reading key range of {}", table.path))?;
+                let reader = lock(&reader)?;
+                let (first, last) = reader.range();
+                Ok((first.to_vec(), last.to_vec()))
+            })
+            .collect()
+    }
+
+    /// key ranges of all tables from the level down
+    pub(super) fn older_ranges(&self, from_level: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut ranges = Vec::new();
+        for level_num in from_level..self.levels.len() {
+            ranges.extend(self.key_ranges(level_num)?);
+        }
+        Ok(ranges)
+    }
+
+    /// size of all files of the tables on the level
+    pub(super) fn level_bytes(&self, level_num: usize) -> Result<u64> {
+        let mut bytes = 0;
+        for table in &self.levels[level_num].nodes {
+            let dir = format!("{}/{}", self.data_dir, table.path);
+            for file in read_dir(&dir).with_context(|| format!("reading {dir}"))? {
+                bytes += file.context("reading table file")?
+                    .metadata()
+                    .context("getting table file metadata")?
+                    .len();
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// parts of the range tombstones inside [lower, upper), the key ranges of the output tables stay disjoint
+fn clip(range_tombstones: &[Entry], lower: Option<&[u8]>, upper: Option<&[u8]>) -> Vec<Entry> {
+    range_tombstones.iter()
+        .filter_map(|tombstone| {
+            let end = tombstone.range_end()?;
+            let start = lower.map_or(&tombstone.key[..], |lower| lower.max(&tombstone.key[..]));
+            let end = upper.map_or(end, |upper| upper.min(end));
+            (start < end).then(|| Entry::range_delete(tombstone.timestamp, start.to_vec(), end.to_vec()))
+        })
+        .collect()
+}
+
+/// rough size of the entry in a table
+fn entry_bytes(entry: &Entry) -> u64 {
+    (entry.key.len() + entry.value.as_ref().map_or(0, Vec::len)) as u64
+}
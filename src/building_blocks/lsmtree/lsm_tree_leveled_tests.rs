@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::Path;
+use crate::building_blocks::{
+    sstable::{LSMTreeUnderlying, MF, SF},
+    Compression, Entry, EntryKind,
+};
+use super::{Compaction, LeveledCompaction, LSMTree, LSMTreeInterface};
+
+fn leveled() -> Compaction {
+    Compaction::Leveled(LeveledCompaction {
+        base_level_bytes: 2000,
+        multiplier: 2,
+        table_bytes: 500,
+    })
+}
+
+/// flushes tables of overlapping keys, every round overwrites the keys of the previous ones
+fn fill<S: LSMTreeUnderlying>(lsm: &mut LSMTree<S>, dir: &str, rounds: u128) where LSMTree<S>: LSMTreeInterface {
+    for round in 0..rounds {
+        let mut entries = (0..50)
+            .map(|i| Entry {
+                timestamp: round * 100 + i,
+                key: format!("key-{:03}", (round * 7 + i * 3) % 200).into_bytes(),
+                value: Some(format!("value-{round}").into_bytes()),
+                kind: EntryKind::Point,
+                expires_at: None,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+        S::write(dir, "memtable", entries, 0.01, 5, Compression::None).expect("writing table");
+        lsm.insert("memtable").expect("inserting table");
+    }
+}
+
+/// newest value of every key written by fill
+fn newest(rounds: u128) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut newest = BTreeMap::new();
+    for round in 0..rounds {
+        for i in 0..50 {
+            let key = format!("key-{:03}", (round * 7 + i * 3) % 200).into_bytes();
+            newest.insert(key, format!("value-{round}").into_bytes());
+        }
+    }
+    newest.into_iter().collect()
+}
+
+fn check_levels<S: LSMTreeUnderlying>(lsm: &LSMTree<S>, rounds: u128) where LSMTree<S>: LSMTreeInterface {
+    assert!(lsm.levels[0].nodes.len() < lsm.size_threshold);
+    assert!(lsm.levels[1..].iter().any(|level| level.nodes.len() > 1));
+
+    // below level 0 the key ranges of a level do not overlap
+    for level_num in 1..lsm.levels.len() {
+        let mut ranges = lsm.key_ranges(level_num).unwrap();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 < pair[1].0, "overlapping tables on level {level_num}");
+        }
+    }
+
+    // only the last level may go over its budget
+    let mut budget = 2000;
+    for level_num in 1..lsm.levels.len() - 1 {
+        assert!(lsm.level_bytes(level_num).unwrap() <= budget);
+        budget *= 2;
+    }
+
+    for (key, value) in newest(rounds) {
+        assert_eq!(lsm.get(key).unwrap().and_then(|entry| entry.value), Some(value));
+    }
+}
+
+fn clean(dir: &str) {
+    if Path::new(dir).is_dir() {
+        remove_dir_all(dir).expect("removing old data");
+    }
+    create_dir_all(dir).expect("creating data dir");
+}
+
+#[test]
+fn leveled_compaction_multi() {
+    let dir = "test-data/lsm-leveled-multi";
+    clean(dir);
+
+    let mut lsm = LSMTree::<MF>::new(0.01, 5, dir.to_string(), 3, 4).with_compaction(leveled());
+    fill(&mut lsm, dir, 30);
+    check_levels(&lsm, 30);
+
+    // tables are found again after a restart
+    let mut loaded = LSMTree::<MF>::new(0.01, 5, dir.to_string(), 3, 4).with_compaction(leveled());
+    loaded.load().unwrap();
+    assert_eq!(loaded.last_table, lsm.last_table);
+    check_levels(&loaded, 30);
+}
+
+#[test]
+fn leveled_compaction_single() {
+    let dir = "test-data/lsm-leveled-single";
+    clean(dir);
+
+    let mut lsm = LSMTree::<SF>::new(0.01, 5, dir.to_string(), 3, 4).with_compaction(leveled());
+    fill(&mut lsm, dir, 30);
+    check_levels(&lsm, 30);
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::Path;
+use proptest::prelude::*;
+use crate::building_blocks::{
+    sstable::{LSMTreeUnderlying, MF, SF},
+    Compression, Entry, EntryKind, MergeIterator,
+};
+use super::{Compaction, CompactionOutput, LeveledCompaction, LSMTree, LSMTreeInterface};
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// merge of a tiered tree, implemented for each file organization
+type Merge<S> = fn(&LSMTree<S>, usize, &str) -> anyhow::Result<CompactionOutput>;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Put(u8, u8),
+    Delete(u8),
+    /// deletes the keys in [start, end)
+    DeleteRange(u8, u8),
+    /// writes the pending entries as a table and inserts it, merges follow on their own
+    Flush,
+    /// merges a level of a tiered tree into the next one
+    Merge(usize),
+    Snapshot,
+    /// releases the oldest live snapshot
+    Release,
+}
+
+fn ops() -> impl Strategy<Value = Vec<Op>> {
+    let op = prop_oneof![
+        6 => (0..16u8, any::<u8>()).prop_map(|(key, value)| Op::Put(key, value)),
+        4 => (0..16u8).prop_map(Op::Delete),
+        1 => (0..16u8, 1..5u8).prop_map(|(start, len)| Op::DeleteRange(start, start + len)),
+        2 => Just(Op::Flush),
+        1 => (0..3usize).prop_map(Op::Merge),
+        1 => Just(Op::Snapshot),
+        1 => Just(Op::Release),
+    ];
+    prop::collection::vec(op, 1..120)
+}
+
+fn key(key: u8) -> Vec<u8> {
+    format!("key-{key:02}").into_bytes()
+}
+
+/// runs the operations against the tree and a BTreeMap, after every flush both have to agree
+/// on the newest values and on the values every live snapshot sees
+fn check<S: LSMTreeUnderlying>(mut lsm: LSMTree<S>, merge: Merge<S>, ops: Vec<Op>) -> Result<(), TestCaseError>
+where
+    LSMTree<S>: LSMTreeInterface,
+{
+    let dir = lsm.data_dir.clone();
+    let dir = &dir[..];
+    if Path::new(dir).is_dir() {
+        remove_dir_all(dir).expect("removing old data");
+    }
+    create_dir_all(dir).expect("creating data dir");
+
+    let mut model = Model::new();
+    let mut pending: BTreeMap<Vec<u8>, Entry> = BTreeMap::new();
+    let mut pending_ranges: Vec<Entry> = Vec::new();
+    let mut snapshots: Vec<(u64, u128, Model)> = Vec::new();
+    let mut timestamp = 0;
+    let mut took_snapshot = false;
+
+    let flush = |lsm: &mut LSMTree<S>, pending: &mut BTreeMap<Vec<u8>, Entry>, pending_ranges: &mut Vec<Entry>| {
+        if pending.is_empty() && pending_ranges.is_empty() {
+            return;
+        }
+        let mut entries = std::mem::take(pending).into_values().collect::<Vec<_>>();
+        entries.append(pending_ranges);
+        S::write(dir, "memtable", entries, 0.01, 2, Compression::None).expect("writing table");
+        lsm.insert("memtable").expect("inserting table");
+    };
+
+    for op in ops.into_iter().chain([Op::Flush]) {
+        match op {
+            Op::Put(k, value) => {
+                timestamp += 1;
+                pending.insert(key(k), Entry { timestamp, key: key(k), value: Some(vec![value]), kind: EntryKind::Point, expires_at: None });
+                model.insert(key(k), vec![value]);
+            }
+            Op::Delete(k) => {
+                timestamp += 1;
+                pending.insert(key(k), Entry { timestamp, key: key(k), value: None, kind: EntryKind::Point, expires_at: None });
+                model.remove(&key(k));
+            }
+            Op::DeleteRange(start, end) => {
+                timestamp += 1;
+                pending_ranges.push(Entry::range_delete(timestamp, key(start), key(end)));
+                model.retain(|k, _| *k < key(start) || *k >= key(end));
+            }
+            Op::Flush => flush(&mut lsm, &mut pending, &mut pending_ranges),
+            Op::Merge(level) => {
+                if lsm.compaction == Compaction::Tiered && !lsm.levels[level].nodes.is_empty() {
+                    let merged = merge(&lsm, level, dir).expect("merging level");
+                    lsm.apply(merged).expect("applying merge");
+                }
+            }
+            Op::Snapshot => {
+                // everything the snapshot sees is in the tree when it is checked
+                flush(&mut lsm, &mut pending, &mut pending_ranges);
+                let id = lsm.snapshots.register(timestamp);
+                took_snapshot = true;
+                snapshots.push((id, timestamp, model.clone()));
+            }
+            Op::Release => {
+                if !snapshots.is_empty() {
+                    let (id, _, _) = snapshots.remove(0);
+                    lsm.snapshots.release(id);
+                }
+            }
+        }
+
+        if !pending.is_empty() || !pending_ranges.is_empty() {
+            continue;
+        }
+
+        let views = snapshots.iter()
+            .map(|(_, timestamp, model)| (*timestamp, model))
+            .chain([(u128::MAX, &model)]);
+        for (timestamp, expected) in views {
+            for k in 0..16 {
+                let found = lsm.get_at(&key(k), timestamp).unwrap().and_then(|entry| entry.value);
+                prop_assert_eq!(found.as_ref(), expected.get(&key(k)), "key {} at {}", k, timestamp);
+            }
+
+            let sources = lsm.sources().expect("opening sources");
+            let range_tombstones = lsm.range_tombstones().expect("reading range tombstones");
+            let scanned = MergeIterator::new(sources, timestamp)
+                .with_range_tombstones(range_tombstones)
+                .collect::<anyhow::Result<Model>>()
+                .expect("scanning");
+            prop_assert_eq!(&scanned, expected);
+        }
+    }
+
+    // tombstones a snapshot needed at merge time stay in the last level, which is never merged again
+    if lsm.compaction != Compaction::Tiered || took_snapshot {
+        return Ok(());
+    }
+
+    // with everything merged down a tombstone is kept only while an older table can hold its key
+    for level in 0..lsm.levels.len() - 1 {
+        if !lsm.levels[level].nodes.is_empty() {
+            let merged = merge(&lsm, level, dir).expect("merging level");
+            lsm.apply(merged).expect("applying merge");
+        }
+    }
+
+    // tables in the order reads go through them, newest first
+    let tables = lsm.levels.iter()
+        .flat_map(|level| level.nodes.iter().rev())
+        .map(|table| format!("{dir}/{}", table.path))
+        .collect::<Vec<_>>();
+    let ranges = tables.iter()
+        .map(|table| S::key_range(table).expect("reading key range"))
+        .collect::<Vec<_>>();
+    for (i, table) in tables.iter().enumerate() {
+        let entries = S::entries(table)
+            .expect("reading table")
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("reading entries");
+        for tombstone in entries.iter().filter(|entry| entry.value.is_none()) {
+            let covered = ranges[i + 1..].iter().any(|(first, last)| *first <= tombstone.key && tombstone.key <= *last);
+            prop_assert!(covered, "useless tombstone for {:?} in {}", tombstone.key, table);
+        }
+    }
+    Ok(())
+}
+
+fn leveled() -> Compaction {
+    Compaction::Leveled(LeveledCompaction {
+        base_level_bytes: 40,
+        multiplier: 2,
+        table_bytes: 20,
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn tiered_multi_matches_model(ops in ops()) {
+        let lsm = LSMTree::<MF>::new(0.01, 2, "test-data/lsm-model-tiered-multi".to_string(), 2, 4);
+        check(lsm, LSMTree::<MF>::merge, ops)?;
+    }
+
+    #[test]
+    fn tiered_single_matches_model(ops in ops()) {
+        let lsm = LSMTree::<SF>::new(0.01, 2, "test-data/lsm-model-tiered-single".to_string(), 2, 4);
+        check(lsm, LSMTree::<SF>::merge, ops)?;
+    }
+
+    #[test]
+    fn leveled_multi_matches_model(ops in ops()) {
+        let lsm = LSMTree::<MF>::new(0.01, 2, "test-data/lsm-model-leveled-multi".to_string(), 2, 4)
+            .with_compaction(leveled());
+        check(lsm, LSMTree::<MF>::merge, ops)?;
+    }
+}
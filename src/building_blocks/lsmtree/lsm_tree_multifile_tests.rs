@@ -1,11 +1,14 @@
 use anyhow::{Result, Context};
+use bincode::Options;
+use std::collections::HashSet;
 use std::fs::{remove_dir_all, create_dir};
 use std::ops::Range;
 use std::path::Path;
 use crate::building_blocks::{
-    sstable::MF,
+    sstable::{MF, LSMTreeUnderlying},
+    Compression,
     SSTableBuilderMultiFile as SSTableBuilder,
-    Entry
+    Entry, EntryKind, BINCODE_OPTIONS
 };
 use super::{LSMTree, LSMTreeInterface};
 
@@ -29,7 +32,7 @@ fn insert_range(
             path = String::from("test-sstable-0-0");
         }
     } else {
-        path = String::from(format!("test-{base}-0-0"));
+        path = format!("test-{base}-0-0");
     }
 
     let mut builder = SSTableBuilder::new(dir, &path, 100, 0.1, 10)
@@ -52,6 +55,8 @@ fn insert_range(
             timestamp: timestmp,
             key: key.to_string().into_bytes(),
             value: val,
+            kind: EntryKind::Point,
+            expires_at: None,
         };
 
         builder
@@ -128,7 +133,7 @@ macro_rules! redo_dirs {
 macro_rules! are_tombstones {
     ($lsm:expr, $keys:expr, true) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
 
             let entry = out.unwrap();
@@ -139,7 +144,7 @@ macro_rules! are_tombstones {
 
     ($lsm:expr, $keys:expr, false) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
 
             let entry = out.unwrap();
@@ -152,14 +157,14 @@ macro_rules! are_tombstones {
 macro_rules! keys_exist {
     ($lsm:expr, $keys:expr, true) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
         }
     };
 
     ($lsm:expr, $keys:expr, false) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert_eq!(out, None);
         }
     };
@@ -251,7 +256,8 @@ fn lsm_merge_simple_multi() {
 
     insert_range(&mut (0..1000), test_path, &mut lsm, false, false, "").unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys: Vec<&str> = vec![
         "456", "789", "234", "567", "890", "901", "345", "678", "123", "432", "765", "210", "543",
@@ -339,7 +345,8 @@ fn lsm_merge_tombstones_multi() {
     // tombstones
     insert_range(&mut (501..600), test_path, &mut lsm, true, false, "").unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys = vec![
         "501", "589", "534", "567", "590", "501", "545", "578", "523", "532", "565", "510", "543",
@@ -399,7 +406,8 @@ fn lsm_merge_mix_tomb_multi() {
     )
     .unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys = vec![
         "501", "589", "534", "567", "590", "501", "545", "578", "523", "532", "565", "510", "543",
@@ -427,11 +435,11 @@ fn lsm_merge_mix_tomb_multi() {
     // both failed searches and found tombstones return None
     keys_exist!(lsm, keys.clone(), false);
 
-    assert_eq!(lsm.get(Vec::from("2002")), None);
+    assert_eq!(lsm.get(Vec::from("2002")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2012")), None);
+    assert_eq!(lsm.get(Vec::from("2012")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2021")), None);
+    assert_eq!(lsm.get(Vec::from("2021")).unwrap(), None);
 }
 
 #[test]
@@ -506,11 +514,11 @@ fn lsm_merge_mix_tomb_auto_multi() {
 
     keys_exist!(lsm, keys.clone(), false);
 
-    assert_eq!(lsm.get(Vec::from("2002")), None);
+    assert_eq!(lsm.get(Vec::from("2002")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2012")), None);
+    assert_eq!(lsm.get(Vec::from("2012")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2021")), None);
+    assert_eq!(lsm.get(Vec::from("2021")).unwrap(), None);
 }
 
 
@@ -577,3 +585,175 @@ fn lsm_load_multi() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn lsm_versions_across_blocks_multi() {
+    let test_path = "./test-data/lsm-versions-across-blocks-multi";
+    redo_dirs!(test_path);
+
+    // the versions of key-150 take up several blocks
+    let mut entries = Vec::new();
+    for i in 0..300 {
+        let key = format!("key-{i:03}").into_bytes();
+        let versions = if i == 150 { 200 } else { 1 };
+        for version in (0..versions).rev() {
+            entries.push(Entry {
+                timestamp: version + 1,
+                key: key.clone(),
+                value: Some(format!("value-{i}-{version:020}").into_bytes()),
+                kind: EntryKind::Point,
+                expires_at: None,
+            });
+        }
+    }
+    MF::write(test_path, "memtable", entries, 0.1, 2, Compression::None).unwrap();
+
+    let mut lsm = LSMTree::<MF>::new(0.1, 2, String::from(test_path), 3, 3);
+    lsm.insert("memtable").unwrap();
+
+    for i in 0..300 {
+        let entry = lsm.get(format!("key-{i:03}").into_bytes()).unwrap().unwrap();
+        let newest = if i == 150 { 199 } else { 0 };
+        assert_eq!(entry.value, Some(format!("value-{i}-{newest:020}").into_bytes()));
+    }
+    for version in 0..200 {
+        let entry = lsm.get_at(b"key-150", version + 1).unwrap().unwrap();
+        assert_eq!(entry.value, Some(format!("value-150-{version:020}").into_bytes()));
+    }
+    assert_eq!(lsm.get(b"key-150a".to_vec()).unwrap(), None);
+}
+
+#[test]
+fn lsm_mixed_compression_multi() {
+    let test_path = "./test-data/lsm-mixed-compression-multi";
+    redo_dirs!(test_path);
+
+    // the merge of the three tables writes a zstd one
+    let mut lsm = LSMTree::<MF>::new(0.1, 2, String::from(test_path), 3, 3)
+        .with_compression(Compression::Zstd);
+    let codecs = [Compression::None, Compression::Lz4, Compression::Snappy];
+    for (table, compression) in codecs.into_iter().enumerate() {
+        let entries = (0..100)
+            .map(|i| Entry {
+                timestamp: (table * 100 + i) as u128,
+                key: format!("key-{table}-{i:03}").into_bytes(),
+                value: Some(format!("value-{table}-{i}").into_bytes()),
+                kind: EntryKind::Point,
+                expires_at: None,
+            })
+            .collect();
+        MF::write(test_path, "memtable", entries, 0.1, 2, compression).unwrap();
+        lsm.insert("memtable").unwrap();
+
+        // tables of different codecs are read side by side before the merge
+        for written in 0..=table {
+            let entry = lsm.get(format!("key-{written}-050").into_bytes()).unwrap().unwrap();
+            assert_eq!(entry.value, Some(format!("value-{written}-50").into_bytes()));
+        }
+    }
+
+    assert!(lsm.levels[0].nodes.is_empty());
+    let merged = format!("{test_path}/{}", lsm.levels[1].nodes[0].path);
+    let codec: Compression = BINCODE_OPTIONS
+        .deserialize(&std::fs::read(format!("{merged}/compression")).unwrap())
+        .unwrap();
+    assert_eq!(codec, Compression::Zstd);
+
+    for table in 0..3 {
+        for i in 0..100 {
+            let entry = lsm.get(format!("key-{table}-{i:03}").into_bytes()).unwrap().unwrap();
+            assert_eq!(entry.value, Some(format!("value-{table}-{i}").into_bytes()));
+        }
+    }
+}
+
+#[test]
+fn lsm_reused_table_names_multi() {
+    let test_path = "./test-data/lsm-reused-table-names-multi";
+    redo_dirs!(test_path);
+
+    // level 1 is emptied by every second merge into it, its next table must not take the name of a removed one
+    let mut lsm = LSMTree::<MF>::new(0.1, 2, String::from(test_path), 2, 3);
+    let mut live = HashSet::new();
+    let mut removed = HashSet::new();
+    for table in 0..8 {
+        let entry = Entry {
+            timestamp: table + 1,
+            key: b"key".to_vec(),
+            value: Some(format!("value-{table}").into_bytes()),
+            kind: EntryKind::Point,
+            expires_at: None,
+        };
+        MF::write(test_path, "memtable", vec![entry], 0.1, 2, Compression::None).unwrap();
+        lsm.insert("memtable").unwrap();
+
+        // the readers opened here must not be used for the tables that replace them
+        let entry = lsm.get(b"key".to_vec()).unwrap().unwrap();
+        assert_eq!(entry.value, Some(format!("value-{table}").into_bytes()));
+
+        let current = lsm.levels.iter()
+            .flat_map(|level| &level.nodes)
+            .map(|node| node.path.clone())
+            .collect::<HashSet<_>>();
+        removed.extend(live.difference(&current).cloned());
+        assert!(current.is_disjoint(&removed));
+        live = current;
+    }
+    assert!(!removed.is_empty());
+}
+
+#[test]
+fn lsm_table_cache_capacity_multi() {
+    let test_path = "./test-data/lsm-table-cache-capacity-multi";
+    redo_dirs!(test_path);
+
+    // tables are never merged, so every lookup of an older key reopens an evicted table
+    let mut lsm = LSMTree::<MF>::new(0.1, 2, String::from(test_path), 10, 3)
+        .with_table_cache(2);
+    for table in 0..5u128 {
+        let entry = Entry {
+            timestamp: table + 1,
+            key: format!("key-{table}").into_bytes(),
+            value: Some(format!("value-{table}").into_bytes()),
+            kind: EntryKind::Point,
+            expires_at: None,
+        };
+        MF::write(test_path, "memtable", vec![entry], 0.1, 2, Compression::None).unwrap();
+        lsm.insert("memtable").unwrap();
+    }
+
+    for _ in 0..2 {
+        for table in 0..5 {
+            let entry = lsm.get(format!("key-{table}").into_bytes()).unwrap().unwrap();
+            assert_eq!(entry.value, Some(format!("value-{table}").into_bytes()));
+            assert!(lsm.tables.len().unwrap() <= 2);
+        }
+    }
+}
+
+#[test]
+fn lsm_merge_of_damaged_table_fails_multi() {
+    let test_path = "./test-data/lsm-merge-damaged-multi";
+    redo_dirs!(test_path);
+
+    let mut lsm = LSMTree::<MF>::new(0.1, 2, String::from(test_path), 2, 3);
+    let entry = |table: u128| Entry {
+        timestamp: table + 1,
+        key: format!("key-{table}").into_bytes(),
+        value: Some(b"value".to_vec()),
+        kind: EntryKind::Point,
+        expires_at: None,
+    };
+    MF::write(test_path, "memtable", vec![entry(0)], 0.1, 2, Compression::None).unwrap();
+    lsm.insert("memtable").unwrap();
+
+    let data = format!("{test_path}/sstable-0-1/data");
+    let mut bytes = std::fs::read(&data).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    std::fs::write(&data, bytes).unwrap();
+
+    // the second table starts a merge that reads the damaged one
+    MF::write(test_path, "memtable", vec![entry(1)], 0.1, 2, Compression::None).unwrap();
+    assert!(lsm.insert("memtable").is_err());
+}
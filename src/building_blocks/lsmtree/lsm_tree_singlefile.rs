@@ -1,11 +1,12 @@
 // TODO: replace the unwraps with context()? if I have the time
-use super::{LSMTree, LSMTreeInterface, Level, TableNode};
+use super::{LSMTree, LSMTreeInterface, Level, TableNode, resolve_versions, resolve_range_tombstones, Compaction, CompactionOutput, Manifest, TableCache, TABLE_CACHE_CAPACITY, compaction_table, lock};
 use crate::building_blocks::sstable::{
-    SSTableBuilderSingleFile as SSTableBuilder, SSTableReaderSingleFile as SSTableReader, SF,
+    SSTableBuilderSingleFile as SSTableBuilder, SF, Compression,
 };
-use crate::building_blocks::Entry;
-use anyhow::{Context, Result};
-use std::fs::{read_dir, remove_dir_all, rename};
+use crate::building_blocks::{CacheStats, Entry, EntryIterator, SnapshotList};
+use crate::utils::helpers::{get_timestamp, sync_dir};
+use anyhow::{Context, Result, anyhow};
+use std::fs::{remove_dir_all, rename};
 use std::rc::Rc;
 
 impl LSMTree<SF> {
@@ -21,6 +22,7 @@ impl LSMTree<SF> {
         for _ in 0..number_of_levels {
             levels.push(Level { nodes: vec![] });
         }
+        let manifest = Manifest::new(&data_dir);
         LSMTree {
             levels,
             fp_prob,
@@ -28,6 +30,14 @@ impl LSMTree<SF> {
             data_dir,
             size_threshold,
             last_table: 0,
+            flushed_sequence: 0,
+            snapshots: SnapshotList::new(),
+            compaction: Compaction::Tiered,
+            compression: Compression::None,
+            compaction_pointers: vec![],
+            manifest,
+            tables: TableCache::new(TABLE_CACHE_CAPACITY),
+            block_cache: None,
             marker,
         }
     }
@@ -39,93 +49,44 @@ impl LSMTree<SF> {
         };
         self.levels[0].nodes.push(node);
 
-        Ok(())
+        self.log_edit(vec![(0, String::from(path))], vec![])
+            .context("recording new table")
     }
 
-    fn _insert(&mut self, path: &str) -> Result<()> {
-        self.append_table(path).context("appending table")?;
+    /// renames the table into level 0 and records it, compaction is left to the caller
+    fn _insert(&mut self, table_name: &str) -> Result<()> {
+        let path = format!("{}/{}", self.data_dir, table_name);
 
-        let dir = { self.data_dir.clone() };
+        let new_idx = self.last_table + 1;
 
-        if self.levels[0].nodes.len() >= self.size_threshold {
-            self.merge(0, &dir).context("merging")?;
-        }
-        Ok(())
-    }
+        let new_name = format!("sstable-0-{}", new_idx);
+        let new_path = format!("{}/{}", self.data_dir, new_name);
+        rename(path, new_path).context("renaming sstable")?;
+        // the manifest can name the table only once the new name is durable
+        sync_dir(&self.data_dir)?;
 
-    /// Function to resolve a sequence of entries with the same key
-    ///
-    /// # NOTE:
-    /// Not memory efficient, could fill memory up indefinitely
-    fn resolve_entries(
-        &mut self,
-        entries: &mut Vec<Rc<Entry>>,
-        level_num: usize,
-    ) -> Option<Rc<Entry>> {
-        // Sort the entries by timestamp
-        entries.sort_by_key(|e| e.timestamp);
-
-        // if the level is not the last one, just return newest entry
-        // if there are no entries return None
-        if level_num < self.levels.len() {
-            let last = entries.last();
-            if let Some(entry) = last {
-                return Some(Rc::clone(entry));
-            }
-            return None;
-        }
+        self.last_table += 1;
 
-        // else, traverse the entries backwards
-        // and get the newest non-tombstone entry
-        for entry in entries.iter().rev() {
-            if entry.value.is_none() {
-                continue;
-            }
-            return Some(Rc::clone(entry));
-        }
-        None
+        self.append_table(&new_name).context("appending table")
     }
 
-
     /// Merges all sstables assigned to a specified level into
-    /// an sstable specified by filename
-    pub(super) fn merge(&mut self, level_num: usize, dirname: &str) -> Result<()> {
-        if level_num == self.levels.len() - 1 {
-            return Ok(());
-        }
-
-        let previous = self.levels[level_num + 1].nodes.last();
-        let mut last = -1;
-        if let Some(filename) = previous {
-            if let Some(num) = filename.path.split("-").last() {
-                last = num.parse().context("parsing last")?;
-            }
+    /// an sstable for the next level, the levels change once the output is applied
+    pub(super) fn merge(&self, level_num: usize, dirname: &str) -> Result<CompactionOutput> {
+        if level_num + 1 >= self.levels.len() {
+            return Err(anyhow!("level {level_num} has no level to merge into"));
         }
 
-        let tablename = &format!("sstable-{}-{}", level_num + 1, last + 1);
-
-        let mut iterators: Vec<_> = self.levels[level_num]
-            .nodes
-            .iter()
-            .map(|table| {
-                SSTableReader::load(&(format!("{}/{}", dirname, table.path)))
-                    .with_context(|| format!("loading {dirname}/{}", table.path))
-                    .unwrap()
-                    .iter()
-                    .unwrap()
-                    .into_iter()
-                    .peekable()
-            })
-            .collect();
+        let tablename = &compaction_table(0);
 
+        // the merged tables come from the table cache, the iterators open their own files
+        let mut iterators = Vec::new();
         let mut sum_item_counts = 0;
-        {
-            for table in &self.levels[level_num].nodes {
-                let sstable = SSTableReader::load(&(format!("{}/{}", dirname, table.path)));
-                let sstable = sstable.context("unwrapping for bf")?;
-                let bf = sstable.read_filter().context("unwrapping bf")?;
-                sum_item_counts += bf.item_count;
-            }
+        for table in &self.levels[level_num].nodes {
+            let reader = self.table(&table.path)?;
+            let reader = lock(&reader)?;
+            sum_item_counts += reader.filter.item_count;
+            iterators.push(reader.iter().with_context(|| format!("reading {dirname}/{}", table.path))?.peekable());
         }
 
         let mut builder = SSTableBuilder::new(
@@ -135,10 +96,22 @@ impl LSMTree<SF> {
             self.fp_prob,
             self.summary_nth,
         )
-        .context("creating builder")?;
+        .context("creating builder")?
+        .with_compression(self.compression);
+
+        // versions visible to live snapshots survive the merge
+        let snapshots = self.snapshots.timestamps();
+        // tables already on the next level and below are older than the merged ones
+        let older = self.older_ranges(level_num + 1).context("reading key ranges")?;
+        // a range tombstone deletes older versions in all of the merged tables
+        let range_tombstones = self.range_tombstones_of(self.levels[level_num].nodes.iter().map(|node| &node.path[..]))
+            .context("reading range tombstones")?;
+        // entries expired by now are dropped like tombstones
+        let now = get_timestamp()?;
 
         let mut last_key: Option<Vec<u8>> = None;
         let mut relevant_entries: Vec<Rc<Entry>> = Vec::new();
+        let mut written = 0;
 
         loop {
             // Get the smallest entry
@@ -146,12 +119,15 @@ impl LSMTree<SF> {
                 .iter_mut()
                 .enumerate()
                 .filter_map(|(idx, iter)| iter.peek().map(|value| (value, idx)))
-                .min_by_key(|&(value, _)| value.as_ref().unwrap().key.clone());
+                // an entry that could not be read comes first, so the merge fails on it
+                .min_by_key(|&(value, _)| value.as_ref().ok().map(|entry| entry.key.clone()));
 
             match smallest {
                 Some((_, idx)) => {
                     // Consume the value from the corresponding iterator
-                    let entry = iterators[idx].next().unwrap().unwrap();
+                    let entry = iterators[idx].next()
+                        .context("merged table ended early")?
+                        .context("reading merged table")?;
                     let key = { entry.key.clone() };
 
                     let entry_ref = Rc::new(entry);
@@ -160,10 +136,11 @@ impl LSMTree<SF> {
                     if last_key.as_ref() == Some(&key) {
                         relevant_entries.push(Rc::clone(&entry_ref));
                     } else {
-                        if let Some(resolved_entry) =
-                            self.resolve_entries(&mut relevant_entries, level_num)
+                        for resolved_entry in
+                            resolve_versions(&mut relevant_entries, &snapshots, &older, &range_tombstones, now)
                         {
-                            builder.insert((*resolved_entry).clone()).unwrap();
+                            builder.insert((*resolved_entry).clone()).context("inserting entry")?;
+                            written += 1;
                         }
                         relevant_entries.clear();
                         relevant_entries.push(Rc::clone(&entry_ref));
@@ -173,233 +150,52 @@ impl LSMTree<SF> {
                 None => {
                     // If there are no more entries, resolve the remaining entries
                     // println!("Resolving remaining entries...\n");
-                    if let Some(resolved_entry) =
-                        self.resolve_entries(&mut relevant_entries, level_num)
+                    for resolved_entry in
+                        resolve_versions(&mut relevant_entries, &snapshots, &older, &range_tombstones, now)
                     {
-                        builder.insert((*resolved_entry).clone()).unwrap();
+                        builder.insert((*resolved_entry).clone()).context("inserting entry")?;
+                        written += 1;
                     }
                     break; // Break when all iterators are exhausted
                 }
             }
         }
 
-        builder.finish_data().expect("finishing big sstable");
-
-        // clear level and remove from disk
-        self.levels[level_num].nodes.iter().for_each(|node| {
-            let filename = node.path.clone();
-            let path = format!("{dirname}/{filename}");
-            remove_dir_all(path)
-                .context("removing {node.path}")
-                .unwrap();
-        });
-
-        self.levels[level_num].nodes.clear();
-
-        self.levels[level_num + 1].nodes.push(TableNode {
-            path: String::from(tablename),
-        });
-
-        if self.levels[level_num + 1].nodes.len() >= self.size_threshold {
-            let msg = format!("MERGING RECURSE {level_num} -> {}", level_num + 1);
-            self.merge(level_num + 1, dirname).context(msg)?;
+        for tombstone in resolve_range_tombstones(range_tombstones, &snapshots, &older) {
+            builder.insert(tombstone).context("inserting range tombstone")?;
+            written += 1;
         }
 
-        Ok(())
+        // only dropped tombstones, there is no table to add
+        let outputs = if written > 0 {
+            builder.finish_data().context("finishing merged sstable")?;
+            vec![(level_num + 1, String::from(tablename))]
+        } else {
+            drop(builder);
+            remove_dir_all(format!("{dirname}/{tablename}"))
+                .context("removing empty sstable")?;
+            vec![]
+        };
+
+        let inputs = self.levels[level_num].nodes.iter().map(|node| (level_num, node.path.clone())).collect();
+        Ok(CompactionOutput { inputs, outputs, pointer: None })
     }
 }
 
 impl LSMTreeInterface for LSMTree<SF> {
-    /// Merges all sstables assigned to a specified level into
-    /// an sstable specified by filename
-    fn prefix_scan(&mut self, prefix: &str) -> Result<Vec<Entry>> {
-        let mut out_entries: Vec<Entry> = vec![];
-
-        // read all files in data dir
-        let files = read_dir(self.data_dir.clone()).context("reading data dir")?;
-
-        // get filenames and create a vec of table iterators from each file
-        let mut iterators: Vec<_> = files
-            .into_iter()
-            .map(|file| {
-                let filepath = file.context("unwrapping file").unwrap();
-                let filepath = filepath.path().to_str().unwrap().to_owned();
-
-                SSTableReader::load(&filepath)
-                    .with_context(|| format!("loading {}", filepath))
-                    .unwrap()
-                    .prefix_scan(prefix)
-                    .unwrap()
-                    .into_iter()
-                    .peekable()
-            })
-            .collect();
-
-        let mut last_key: Option<Vec<u8>> = None;
-        let mut relevant_entries: Vec<Rc<Entry>> = Vec::new();
-
-        loop {
-            // Get the smallest entry
-            let smallest = iterators
-                .iter_mut()
-                .enumerate()
-                .filter_map(|(idx, iter)| iter.peek().map(|value| (value, idx)))
-                .min_by_key(|&(value, _)| value.key.clone());
-
-            match smallest {
-                Some((_, idx)) => {
-                    // Consume the value from the corresponding iterator
-                    let entry = iterators[idx].next().unwrap();
-                    let key = { entry.key.clone() };
-
-                    let entry_ref = Rc::new(entry);
-
-                    // Check if this entry has the same key as the last key
-                    if last_key.as_ref() == Some(&key) {
-                        relevant_entries.push(Rc::clone(&entry_ref));
-                    } else {
-                        relevant_entries.sort_by_key(|e| e.timestamp);
-
-                        // traverse the entries backwards
-                        // and get the newest non-tombstone entry
-                        for entry in relevant_entries.iter().rev() {
-                            if entry.value.is_none() {
-                                continue;
-                            }
-
-                            // unpack the entry
-                            out_entries.push((**entry).clone());
-                            break;
-                        }
-
-                        relevant_entries.clear();
-
-                        relevant_entries.push(Rc::clone(&entry_ref));
-                        last_key = Some(key);
-                    }
-                }
-                None => {
-                    // If there are no more entries, resolve the remaining entries
-                    //
-                    // traverse the entries backwards
-                    // and get the newest non-tombstone entry
-                    for entry in relevant_entries.iter().rev() {
-                        if entry.value.is_none() {
-                            continue;
-                        }
-
-                        out_entries.push((**entry).clone());
-                    }
-
-                    relevant_entries.clear();
-                    break; // Break when all iterators are exhausted
-                }
-            }
-        }
-
-        Ok(out_entries)
+    /// lazy cursors over all tables, newest versions can be in any of them
+    fn sources(&self) -> Result<Vec<Box<dyn EntryIterator>>> {
+        self.table_sources()
     }
 
-
-
-    fn range_scan(&mut self, start_key: &str, end_key: &str) -> Result<Vec<Entry>> {
-        let mut out_entries: Vec<Entry> = vec![];
-
-        // read all files in data dir
-        let files = read_dir(self.data_dir.clone()).context("reading data dir")?;
-
-        // get filenames and create a vec of table iterators from each file
-        let mut iterators: Vec<_> = files
-            .into_iter()
-            .map(|file| {
-                let filepath = file.context("unwrapping file").unwrap();
-                let filepath = filepath.path().to_str().unwrap().to_owned();
-
-                SSTableReader::load(&filepath)
-                    .with_context(|| format!("loading {}", filepath))
-                    .unwrap()
-                    .range_scan(start_key.as_bytes(), end_key.as_bytes())
-                    .unwrap()
-                    .into_iter()
-                    .peekable()
-            })
-            .collect();
-
-        let mut last_key: Option<Vec<u8>> = None;
-        let mut relevant_entries: Vec<Rc<Entry>> = Vec::new();
-
-        loop {
-            // Get the smallest entry
-            let smallest = iterators
-                .iter_mut()
-                .enumerate()
-                .filter_map(|(idx, iter)| iter.peek().map(|value| (value, idx)))
-                .min_by_key(|&(value, _)| value.key.clone());
-
-            match smallest {
-                Some((_, idx)) => {
-                    // Consume the value from the corresponding iterator
-                    let entry = iterators[idx].next().unwrap();
-                    let key = { entry.key.clone() };
-
-                    let entry_ref = Rc::new(entry);
-
-                    // Check if this entry has the same key as the last key
-                    if last_key.as_ref() == Some(&key) {
-                        relevant_entries.push(Rc::clone(&entry_ref));
-                    } else {
-                        relevant_entries.sort_by_key(|e| e.timestamp);
-
-                        // traverse the entries backwards
-                        // and get the newest non-tombstone entry
-                        for entry in relevant_entries.iter().rev() {
-                            if entry.value.is_none() {
-                                continue;
-                            }
-
-                            // unpack the entry
-                            out_entries.push((**entry).clone());
-                        }
-
-                        relevant_entries.clear();
-
-                        relevant_entries.push(Rc::clone(&entry_ref));
-                        last_key = Some(key);
-                    }
-                }
-                None => {
-                    // If there are no more entries, resolve the remaining entries
-                    //
-                    // traverse the entries backwards
-                    // and get the newest non-tombstone entry
-                    for entry in relevant_entries.iter().rev() {
-                        if entry.value.is_none() {
-                            continue;
-                        }
-
-                        out_entries.push((**entry).clone());
-                        break;
-                    }
-
-                    relevant_entries.clear();
-                    break; // Break when all iterators are exhausted
-                }
-            }
-        }
-
-        Ok(out_entries)
-    }
-
-
-
-    // NOTE:?
-    // Can't use ? if func returns Option<T>
     /// Tries to find an `Entry` base on the `key`
+    /// ignoring versions newer than `timestamp`
     ///
     /// Returns None if it encounters a tombstone
     /// Returns None if it finds nothing even after a full traversal
+    /// Fails if a table can not be read
     /// # Examples:
-    /// ```
+    /// ```ignore
     /// let dir = String::from("data");
     ///
     /// let mut lsm = LSMTree::new(
@@ -412,137 +208,55 @@ impl LSMTreeInterface for LSMTree<SF> {
     /// lsm.insert("new_sstable").unwrap();
     ///
     /// let key: Vec<u8> = Vec::from("joe");
-    /// let out = lsm.get(key);
+    /// let out = lsm.get(key).unwrap();
     /// assert!(out.is_some());
     ///
     /// let key: Vec<u8> = Vec::from("mama");
-    /// let out = lsm.get(key);
+    /// let out = lsm.get(key).unwrap();
     /// assert!(out.is_some());
     ///
     /// ```
-    fn get(&self, key: Vec<u8>) -> Option<Entry> {
-        for level in &self.levels {
-            for table in &level.nodes.iter().rev().collect::<Vec<&TableNode>>() {
-                let path = format!("{}/{}", self.data_dir, table.path);
-                let msg = format!("Failed to open file {path}");
-                let reader = SSTableReader::load(&path).context(msg).unwrap();
-                let filter = reader.read_filter().unwrap();
-
-                let mut sum_offset: Option<u64> = None;
-
-                // if filter says no just go on
-                if !filter.check(&key).unwrap() {
-                    continue;
-                }
-
-                let mut index = reader.index_iter().unwrap();
-                let (sum_iter, sum_range) = reader.summary_iter().unwrap();
-
-                if sum_range.first_key > key {
-                    continue;
-                }
-                if sum_range.last_key < key {
-                    continue;
-                }
-
-                for e in sum_iter {
-                    let sum_entry = e.unwrap();
-
-                    if sum_entry.first_key <= key {
-                        if sum_entry.last_key >= key {
-                            sum_offset = Some(sum_entry.offset);
-                            break;
-                        }
-                    }
-                }
-
-                if sum_offset.is_none() {
-                    return None;
-                }
-
-                let entries = reader.iter();
-                assert!(entries.is_ok());
-                let mut entries = entries.unwrap();
-
-                assert!(sum_offset.is_some());
-                let offset = sum_offset.unwrap();
-                index.move_iter(offset).unwrap();
-                for entry in index {
-                    let entry = entry.unwrap();
-                    let offset = entry.offset;
-                    if entry.key != key {
-                        continue;
-                    }
+    fn get_at(&self, key: &[u8], timestamp: u128) -> Result<Option<Entry>> {
+        self.get_from_tables(key, timestamp)
+    }
 
-                    entries.move_iter(offset).unwrap();
-                    let entry_ok = entries.next().unwrap().unwrap();
+    fn range_tombstones(&self) -> Result<Vec<Entry>> {
+        let tables = self.levels.iter().flat_map(|level| &level.nodes).map(|node| &node.path[..]);
+        self.range_tombstones_of(tables)
+    }
 
-                    // if it's a tombstone, assume byebye
-                    if entry_ok.value.is_none() {
-                        return None;
-                    }
+    fn set_snapshots(&mut self, snapshots: SnapshotList) {
+        self.snapshots = snapshots;
+    }
 
-                    return Some(entry_ok);
-                }
-                return None;
-            }
-        }
-        None
+    fn block_cache_stats(&self) -> Result<Option<CacheStats>> {
+        self.block_cache.as_ref().map(|cache| cache.stats()).transpose()
     }
 
     fn load(&mut self) -> Result<()> {
-        let paths =
-            std::fs::read_dir(self.data_dir.clone()).context("reading directory contents")?;
-
-        for file in paths {
-            let filepath = file.context("reading filename").unwrap().path();
-
-            let dir_name = filepath
-                .file_name()
-                .and_then(|name| name.to_str())
-                .expect("Failed to convert OsStr to String");
-
-            let mut tokens: Vec<&str> = dir_name.split("-").collect();
-            tokens.reverse();
-            let level = tokens[1].parse::<usize>().context("parsing level num")?;
-
-            if self.levels.len() > level {
-                self.levels[level].nodes.push(TableNode {
-                    path: String::from(dir_name.clone()),
-                });
-
-                self.levels[level]
-                    .nodes
-                    .sort_by_key(|node| node.path.clone());
-            } else {
-                self.levels.push(Level {
-                    nodes: vec![TableNode {
-                        path: String::from(dir_name.clone()),
-                    }],
-                });
-            }
-        }
+        self.recover()
+    }
 
-        // read all files in data dir
-        let files = read_dir(self.data_dir.clone()).context("reading data dir")?;
+    fn insert_flushed(&mut self, table_name: &str, sequence: u64) -> Result<()> {
+        self.flushed_sequence = self.flushed_sequence.max(sequence);
+        self._insert(table_name)
+    }
 
-        let mut filenames = vec![];
-        for file in files {
-            let filepath = file.context("unwrapping file").unwrap();
-            let filepath = filepath.path();
-            let filepath = filepath.display();
-            filenames.push(filepath.to_string());
+    fn build_compaction(&self) -> Result<Option<CompactionOutput>> {
+        if let Compaction::Leveled(options) = &self.compaction {
+            return self.build_leveled(options).context("compacting levels");
         }
+        self.tiered_level()
+            .map(|level_num| self.merge(level_num, &self.data_dir).with_context(|| format!("merging level {level_num}")))
+            .transpose()
+    }
 
-        filenames.sort();
-        if let Some(filename) = filenames.pop() {
-            let tokens = filename.split("-").last();
-                if let Some(index) = tokens {
-                    self.last_table = index.parse::<usize>().context("parsing last table index")?;
-                }
-        }
+    fn apply_compaction(&mut self, output: CompactionOutput) -> Result<()> {
+        self.apply(output)
+    }
 
-        Ok(())
+    fn flushed_sequence(&self) -> u64 {
+        self.flushed_sequence
     }
 
     /// Inserts a new sstable into the LSM structure by passing a filepath
@@ -556,7 +270,7 @@ impl LSMTreeInterface for LSMTree<SF> {
     /// Pass anything else
     ///
     /// # Examples:
-    /// ```
+    /// ```ignore
     /// let dir = String::from("data");
     ///
     /// let mut lsm = LSMTree::new(
@@ -568,26 +282,16 @@ impl LSMTreeInterface for LSMTree<SF> {
     /// lsm.insert("new_sstable").unwrap();
     //?/
     /// let key: Vec<u8> = Vec::from("joe");
-    /// let out = lsm.get(key);
+    /// let out = lsm.get(key).unwrap();
     /// assert!(out.is_some());
     ///
     /// let key: Vec<u8> = Vec::from("mama");
-    /// let out = lsm.get(key);
+    /// let out = lsm.get(key).unwrap();
     /// assert!(out.is_some());
     ///
     /// ```
     fn insert(&mut self, table_name: &str) -> Result<()> {
-        let path = format!("{}/{}", self.data_dir, table_name);
-
-        let new_idx = self.last_table + 1;
-
-        let new_name = format!("sstable-0-{}", new_idx);
-        let new_path = format!("{}/{}", self.data_dir, new_name);
-
-        rename(path, new_path).context("renaming sstable")?;
-
-        self.last_table += 1;
-
-        self._insert(&new_name)
+        self._insert(table_name)?;
+        self.compact().context("compacting")
     }
 }
@@ -3,9 +3,10 @@ use std::fs::{remove_dir_all, create_dir};
 use std::ops::Range;
 use std::path::Path;
 use crate::building_blocks::{
-    sstable::SF,
+    sstable::{SF, LSMTreeUnderlying},
+    Compression,
     SSTableBuilderSingleFile as SSTableBuilder,
-    Entry
+    Entry, EntryKind
 };
 use super::{LSMTree, LSMTreeInterface};
 
@@ -24,7 +25,7 @@ macro_rules! redo_dirs {
 macro_rules! are_tombstones {
     ($lsm:expr, $keys:expr, true) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
 
             let entry = out.unwrap();
@@ -35,7 +36,7 @@ macro_rules! are_tombstones {
 
     ($lsm:expr, $keys:expr, false) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
 
             let entry = out.unwrap();
@@ -48,14 +49,14 @@ macro_rules! are_tombstones {
 macro_rules! keys_exist {
     ($lsm:expr, $keys:expr, true) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert!(out.is_some());
         }
     };
 
     ($lsm:expr, $keys:expr, false) => {
         for key in $keys {
-            let out = $lsm.get(Vec::from(key));
+            let out = $lsm.get(Vec::from(key)).unwrap();
             assert_eq!(out, None);
         }
     };
@@ -82,7 +83,7 @@ fn insert_range(
             path = String::from("test-sstable-0-0");
         }
     } else {
-        path = String::from(format!("test-{base}-0-0"));
+        path = format!("test-{base}-0-0");
     }
 
     let mut builder = SSTableBuilder::new(dir, &path, 100, 0.1, 10)
@@ -105,6 +106,8 @@ fn insert_range(
             timestamp: timestmp,
             key: key.to_string().into_bytes(),
             value: val,
+            kind: EntryKind::Point,
+            expires_at: None,
         };
 
         builder
@@ -252,7 +255,8 @@ fn lsm_merge_simple_single() {
 
     insert_range(&mut (0..1000), test_path, &mut lsm, false, false, "").unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys: Vec<&str> = vec![
         "456", "789", "234", "567", "890", "901", "345", "678", "123", "432", "765", "210", "543",
@@ -332,7 +336,8 @@ fn lsm_merge_tombstones_single() {
     // tombstones
     insert_range(&mut (501..600), test_path, &mut lsm, true, false, "").unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys = vec![
         "501", "589", "534", "567", "590", "501", "545", "578", "523", "532", "565", "510", "543",
@@ -384,7 +389,8 @@ fn lsm_merge_mix_tomb_single() {
     )
     .unwrap();
 
-    lsm.merge(0, test_path).unwrap();
+    let merged = lsm.merge(0, test_path).unwrap();
+    lsm.apply(merged).unwrap();
 
     let keys = vec![
         "501", "589", "534", "567", "590", "501", "545", "578", "523", "532", "565", "510", "543",
@@ -412,11 +418,11 @@ fn lsm_merge_mix_tomb_single() {
     // both failed searches and found tombstones return None
     keys_exist!(lsm, keys.clone(), false);
 
-    assert_eq!(lsm.get(Vec::from("2002")), None);
+    assert_eq!(lsm.get(Vec::from("2002")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2012")), None);
+    assert_eq!(lsm.get(Vec::from("2012")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2021")), None);
+    assert_eq!(lsm.get(Vec::from("2021")).unwrap(), None);
 }
 
 #[test]
@@ -488,11 +494,11 @@ fn lsm_merge_mix_tomb_auto_single() {
 
     keys_exist!(lsm, keys.clone(), false);
 
-    assert_eq!(lsm.get(Vec::from("2002")), None);
+    assert_eq!(lsm.get(Vec::from("2002")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2012")), None);
+    assert_eq!(lsm.get(Vec::from("2012")).unwrap(), None);
 
-    assert_eq!(lsm.get(Vec::from("2021")), None);
+    assert_eq!(lsm.get(Vec::from("2021")).unwrap(), None);
 }
 
 
@@ -560,3 +566,93 @@ fn lsm_load_single() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn lsm_versions_across_blocks_single() {
+    let test_path = "./test-data/lsm-versions-across-blocks-single";
+    redo_dirs!(test_path);
+
+    // the versions of key-150 take up several blocks
+    let mut entries = Vec::new();
+    for i in 0..300 {
+        let key = format!("key-{i:03}").into_bytes();
+        let versions = if i == 150 { 200 } else { 1 };
+        for version in (0..versions).rev() {
+            entries.push(Entry {
+                timestamp: version + 1,
+                key: key.clone(),
+                value: Some(format!("value-{i}-{version:020}").into_bytes()),
+                kind: EntryKind::Point,
+                expires_at: None,
+            });
+        }
+    }
+    SF::write(test_path, "memtable", entries, 0.1, 2, Compression::None).unwrap();
+
+    let mut lsm = LSMTree::<SF>::new(0.1, 2, String::from(test_path), 3, 3);
+    lsm.insert("memtable").unwrap();
+
+    for i in 0..300 {
+        let entry = lsm.get(format!("key-{i:03}").into_bytes()).unwrap().unwrap();
+        let newest = if i == 150 { 199 } else { 0 };
+        assert_eq!(entry.value, Some(format!("value-{i}-{newest:020}").into_bytes()));
+    }
+    for version in 0..200 {
+        let entry = lsm.get_at(b"key-150", version + 1).unwrap().unwrap();
+        assert_eq!(entry.value, Some(format!("value-150-{version:020}").into_bytes()));
+    }
+    assert_eq!(lsm.get(b"key-150a".to_vec()).unwrap(), None);
+}
+
+#[test]
+fn lsm_reused_table_names_single() {
+    let test_path = "./test-data/lsm-reused-table-names-single";
+    redo_dirs!(test_path);
+
+    // level 1 is emptied by every second merge into it, so its next table gets the name of a removed one
+    let mut lsm = LSMTree::<SF>::new(0.1, 2, String::from(test_path), 2, 3);
+    for table in 0..8 {
+        let entry = Entry {
+            timestamp: table + 1,
+            key: b"key".to_vec(),
+            value: Some(format!("value-{table}").into_bytes()),
+            kind: EntryKind::Point,
+            expires_at: None,
+        };
+        SF::write(test_path, "memtable", vec![entry], 0.1, 2, Compression::None).unwrap();
+        lsm.insert("memtable").unwrap();
+
+        // the readers opened here must not be used for the tables that replace them
+        let entry = lsm.get(b"key".to_vec()).unwrap().unwrap();
+        assert_eq!(entry.value, Some(format!("value-{table}").into_bytes()));
+    }
+}
+
+#[test]
+fn lsm_table_cache_capacity_single() {
+    let test_path = "./test-data/lsm-table-cache-capacity-single";
+    redo_dirs!(test_path);
+
+    // tables are never merged, so every lookup of an older key reopens an evicted table
+    let mut lsm = LSMTree::<SF>::new(0.1, 2, String::from(test_path), 10, 3)
+        .with_table_cache(2);
+    for table in 0..5u128 {
+        let entry = Entry {
+            timestamp: table + 1,
+            key: format!("key-{table}").into_bytes(),
+            value: Some(format!("value-{table}").into_bytes()),
+            kind: EntryKind::Point,
+            expires_at: None,
+        };
+        SF::write(test_path, "memtable", vec![entry], 0.1, 2, Compression::None).unwrap();
+        lsm.insert("memtable").unwrap();
+    }
+
+    for _ in 0..2 {
+        for table in 0..5 {
+            let entry = lsm.get(format!("key-{table}").into_bytes()).unwrap().unwrap();
+            assert_eq!(entry.value, Some(format!("value-{table}").into_bytes()));
+            assert!(lsm.tables.len().unwrap() <= 2);
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::{Context, Result, anyhow};
+use bincode::Options;
+use crc::{Crc, CRC_32_JAMCRC};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_dir_all, rename, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use crate::building_blocks::{sstable::LSMTreeUnderlying, BINCODE_OPTIONS};
+use super::{LSMTree, CompactionOutput, Level, TableNode, compaction_table, table_number};
+
+pub(super) const MANIFEST_NAME: &str = "MANIFEST";
+
+/// tables added to and removed from the levels by one insert or merge
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(super) struct VersionEdit {
+    /// level and name of each new table
+    pub added: Vec<(usize, String)>,
+    pub removed: Vec<String>,
+    /// number of the newest table, names are never reused
+    pub last_table: usize,
+    /// highest WAL sequence whose records are in the tables
+    pub flushed_sequence: u64,
+}
+
+/// append only log of version edits in the data dir, the levels are rebuilt by replaying it
+///
+/// record format: |Len(u64)|CRC(u32)|VersionEdit|
+pub(super) struct Manifest {
+    path: String,
+}
+
+impl Manifest {
+    pub fn new(data_dir: &str) -> Self {
+        Manifest { path: format!("{data_dir}/{MANIFEST_NAME}") }
+    }
+
+    pub fn exists(&self) -> bool {
+        Path::new(&self.path).is_file()
+    }
+
+    /// the edit is durable once this returns
+    pub fn append(&self, edit: &VersionEdit) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("opening manifest")?;
+        file.write_all(&serialize(edit)?)
+            .context("writing version edit")?;
+        file.sync_all().context("syncing manifest")
+    }
+
+    /// replaces the log with a single edit holding the whole state
+    pub fn rewrite(&self, edit: &VersionEdit) -> Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp).context("creating new manifest")?;
+        file.write_all(&serialize(edit)?)
+            .context("writing version edit")?;
+        file.sync_all().context("syncing new manifest")?;
+        rename(&tmp, &self.path).context("replacing manifest")
+    }
+
+    /// all complete edits in the order they were written
+    /// a torn record at the end is a write that never finished and is ignored
+    pub fn replay(&self) -> Result<Vec<VersionEdit>> {
+        let mut bytes = Vec::new();
+        File::open(&self.path)
+            .context("opening manifest")?
+            .read_to_end(&mut bytes)
+            .context("reading manifest")?;
+
+        let mut edits = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let Some(record) = record_at(&bytes[offset..])? else {
+                break;
+            };
+            let end = offset + 12 + record.len();
+
+            let crc: u32 = BINCODE_OPTIONS
+                .deserialize(&bytes[offset + 8..offset + 12])
+                .context("deserializing crc")?;
+            if Crc::<u32>::new(&CRC_32_JAMCRC).checksum(record) != crc {
+                if end == bytes.len() {
+                    break;
+                }
+                return Err(anyhow!("crc does not match at offset {offset}").context("replaying manifest"));
+            }
+
+            let edit = BINCODE_OPTIONS
+                .deserialize(record)
+                .context("deserializing version edit")?;
+            edits.push(edit);
+            offset = end;
+        }
+        Ok(edits)
+    }
+}
+
+/// body of the record starting at the beginning of the slice, None if it is cut off
+fn record_at(bytes: &[u8]) -> Result<Option<&[u8]>> {
+    if bytes.len() < 12 {
+        return Ok(None);
+    }
+    let len: u64 = BINCODE_OPTIONS
+        .deserialize(&bytes[..8])
+        .context("deserializing record len")?;
+    let end = usize::try_from(len).ok().and_then(|len| len.checked_add(12));
+    Ok(end.and_then(|end| bytes.get(12..end)))
+}
+
+fn serialize(edit: &VersionEdit) -> Result<Vec<u8>> {
+    let edit_ser = BINCODE_OPTIONS
+        .serialize(edit)
+        .context("serializing version edit")?;
+    let crc = Crc::<u32>::new(&CRC_32_JAMCRC).checksum(&edit_ser);
+
+    let mut record = BINCODE_OPTIONS
+        .serialize(&(edit_ser.len() as u64))
+        .context("serializing record len")?;
+    record.extend(BINCODE_OPTIONS.serialize(&crc).context("serializing crc")?);
+    record.extend(edit_ser);
+    Ok(record)
+}
+
+impl<S: LSMTreeUnderlying> LSMTree<S> {
+    /// has to be called before any of the removed tables is deleted,
+    /// a crash in between leaves only unreferenced directories behind
+    /// readers of the removed tables are dropped
+    pub(super) fn log_edit(&self, added: Vec<(usize, String)>, removed: Vec<String>) -> Result<()> {
+        self.forget_tables(&removed).context("dropping readers of removed tables")?;
+        let edit = VersionEdit { added, removed, last_table: self.last_table, flushed_sequence: self.flushed_sequence };
+        self.manifest.append(&edit).context("appending version edit")
+    }
+
+    /// rebuilds the levels from the manifest, data written before the manifest existed
+    /// is found by the table names, directories no level refers to are removed
+    pub(super) fn recover(&mut self) -> Result<()> {
+        for level in &mut self.levels {
+            level.nodes.clear();
+        }
+        self.tables.clear()?;
+        self.flushed_sequence = 0;
+
+        if self.manifest.exists() {
+            for edit in self.manifest.replay().context("replaying manifest")? {
+                self.apply_edit(edit);
+            }
+        } else {
+            self.levels_from_names().context("reading table names")?;
+        }
+
+        let newest = self.levels
+            .iter()
+            .flat_map(|level| &level.nodes)
+            .map(|node| table_number(&node.path))
+            .max()
+            .unwrap_or(0);
+        self.last_table = self.last_table.max(newest);
+
+        // one edit with the whole state keeps the log short
+        let added = self.levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level_num, level)| level.nodes.iter().map(move |node| (level_num, node.path.clone())))
+            .collect();
+        let current = VersionEdit {
+            added,
+            removed: vec![],
+            last_table: self.last_table,
+            flushed_sequence: self.flushed_sequence,
+        };
+        self.manifest.rewrite(&current).context("rewriting manifest")?;
+
+        self.remove_unreferenced().context("removing unreferenced tables")?;
+        self.upgrade_tables().context("upgrading tables of format version 1")
+    }
+
+    /// rewrites the tables written before tables recorded their format version, which can not be read otherwise
+    /// a level is swapped in one edit so the order of its tables is kept, a crash leaves the old tables in place
+    fn upgrade_tables(&mut self) -> Result<()> {
+        for level_num in 0..self.levels.len() {
+            let mut inputs = Vec::new();
+            let mut outputs = Vec::new();
+            for node in &self.levels[level_num].nodes {
+                let dir = format!("{}/{}", self.data_dir, node.path);
+                let entries = S::legacy_entries(&dir)
+                    .with_context(|| format!("reading {}", node.path))?;
+                if let Some(entries) = entries {
+                    let name = compaction_table(outputs.len());
+                    S::write(&self.data_dir, &name, entries, self.fp_prob, self.summary_nth, self.compression)
+                        .with_context(|| format!("rewriting {}", node.path))?;
+                    inputs.push((level_num, node.path.clone()));
+                    outputs.push((level_num, name));
+                }
+            }
+
+            if inputs.is_empty() {
+                continue;
+            }
+            // only a crash between levels leaves upgraded tables behind, those are on other levels
+            if inputs.len() != self.levels[level_num].nodes.len() {
+                return Err(anyhow!("level {level_num} mixes tables of format version 1 with newer ones"));
+            }
+            self.apply(CompactionOutput { inputs, outputs, pointer: None })
+                .with_context(|| format!("swapping upgraded tables of level {level_num}"))?;
+        }
+        Ok(())
+    }
+
+    fn apply_edit(&mut self, edit: VersionEdit) {
+        for level in &mut self.levels {
+            level.nodes.retain(|node| !edit.removed.contains(&node.path));
+        }
+        for (level_num, path) in edit.added {
+            if self.levels.len() <= level_num {
+                self.levels.resize_with(level_num + 1, || Level { nodes: vec![] });
+            }
+            self.levels[level_num].nodes.push(TableNode { path });
+        }
+        self.last_table = edit.last_table;
+        self.flushed_sequence = edit.flushed_sequence;
+    }
+
+    /// level is the middle number of "sstable-<level>-<number>", tables of a level are ordered by number
+    fn levels_from_names(&mut self) -> Result<()> {
+        let paths = read_dir(&self.data_dir).context("reading directory contents")?;
+        for file in paths {
+            let file = file.context("reading filename")?;
+            let name = file.file_name().to_string_lossy().into_owned();
+            let mut tokens = name.rsplit('-');
+            let level = tokens.nth(1).and_then(|level| level.parse::<usize>().ok());
+            let (Some(level_num), true) = (level, file.path().is_dir()) else {
+                continue;
+            };
+
+            if self.levels.len() <= level_num {
+                self.levels.resize_with(level_num + 1, || Level { nodes: vec![] });
+            }
+            self.levels[level_num].nodes.push(TableNode { path: name });
+        }
+
+        for level in &mut self.levels {
+            level.nodes.sort_by_key(|node| table_number(&node.path));
+        }
+        Ok(())
+    }
+
+    /// tables written by a merge that never made it into the manifest, inputs of a finished merge
+    /// and memtables whose flush did not finish, the WAL still holds their entries
+    fn remove_unreferenced(&self) -> Result<()> {
+        let referenced: HashSet<&str> = self.levels
+            .iter()
+            .flat_map(|level| &level.nodes)
+            .map(|node| &node.path[..])
+            .collect();
+
+        for file in read_dir(&self.data_dir).context("reading directory contents")? {
+            let path = file.context("reading filename")?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+            if path.is_dir() && !name.is_some_and(|name| referenced.contains(&name[..])) {
+                remove_dir_all(&path)
+                    .with_context(|| format!("removing {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, remove_file, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+    use bincode::Options;
+    use crc::{Crc, CRC_32_JAMCRC};
+    use crate::building_blocks::{sstable::{LSMTreeUnderlying, MF, SF}, Compression, Entry, EntryKind, LSMTree, LSMTreeInterface, BINCODE_OPTIONS};
+    use super::{Manifest, VersionEdit};
+
+    fn clean(dir: &str) {
+        if Path::new(dir).is_dir() {
+            remove_dir_all(dir).expect("removing old data");
+        }
+        create_dir_all(dir).expect("creating data dir");
+    }
+
+    fn edit(level: usize, name: &str) -> VersionEdit {
+        VersionEdit { added: vec![(level, name.to_string())], removed: vec![], last_table: 1, flushed_sequence: 0 }
+    }
+
+    #[test]
+    fn replay_stops_at_torn_tail() {
+        let dir = "test-data/manifest-torn";
+        clean(dir);
+        let manifest = Manifest::new(dir);
+        manifest.append(&edit(0, "sstable-0-1")).unwrap();
+        manifest.append(&edit(1, "sstable-1-2")).unwrap();
+
+        // a write cut off by a crash
+        let mut file = OpenOptions::new().append(true).open(&manifest.path).unwrap();
+        file.write_all(&[40, 0, 0, 0, 0, 0, 0, 0, 1, 2]).unwrap();
+
+        let edits = manifest.replay().unwrap();
+        assert_eq!(edits, vec![edit(0, "sstable-0-1"), edit(1, "sstable-1-2")]);
+    }
+
+    #[test]
+    fn replay_rejects_corrupted_record() {
+        let dir = "test-data/manifest-corrupted";
+        clean(dir);
+        let manifest = Manifest::new(dir);
+        manifest.append(&edit(0, "sstable-0-1")).unwrap();
+        manifest.append(&edit(1, "sstable-1-2")).unwrap();
+
+        let mut bytes = std::fs::read(&manifest.path).unwrap();
+        bytes[14] ^= 0xff;
+        std::fs::write(&manifest.path, bytes).unwrap();
+
+        assert!(manifest.replay().is_err());
+    }
+
+    fn write_table(dir: &str, name: &str, keys: &[&str]) {
+        let entries = keys.iter()
+            .map(|key| Entry { timestamp: 1, key: key.as_bytes().to_vec(), value: Some(b"value".to_vec()), kind: EntryKind::Point, expires_at: None })
+            .collect();
+        MF::write(dir, name, entries, 0.01, 2, Compression::None).unwrap();
+    }
+
+    #[test]
+    fn recover_removes_unreferenced_tables() {
+        let dir = "test-data/manifest-recover";
+        clean(dir);
+
+        let mut lsm = LSMTree::<MF>::new(0.01, 2, dir.to_string(), 10, 3);
+        write_table(dir, "memtable", &["a", "b"]);
+        lsm.insert("memtable").unwrap();
+        write_table(dir, "memtable", &["c", "d"]);
+        lsm.insert("memtable").unwrap();
+
+        // output of a merge that crashed before it was recorded and a memtable that was never inserted
+        write_table(dir, "sstable-1-1", &["a", "b", "c", "d"]);
+        write_table(dir, "memtable", &["e"]);
+
+        let mut loaded = LSMTree::<MF>::new(0.01, 2, dir.to_string(), 10, 3);
+        loaded.load().unwrap();
+        let names = |lsm: &LSMTree<MF>| lsm.levels
+            .iter()
+            .map(|level| level.nodes.iter().map(|node| node.path.clone()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(names(&loaded), names(&lsm));
+        assert_eq!(loaded.last_table, 2);
+        assert!(!Path::new(&format!("{dir}/sstable-1-1")).exists());
+        assert!(!Path::new(&format!("{dir}/memtable")).exists());
+        assert!(loaded.get(b"c".to_vec()).unwrap().is_some());
+
+        // the sequence of the flushed WAL records is recorded with the table
+        write_table(dir, "memtable", &["f"]);
+        lsm.insert_flushed("memtable", 7).unwrap();
+        let mut loaded = LSMTree::<MF>::new(0.01, 2, dir.to_string(), 10, 3);
+        loaded.load().unwrap();
+        assert_eq!(loaded.flushed_sequence(), 7);
+
+        // without a manifest the levels come from the table names and a manifest is written
+        remove_file(format!("{dir}/MANIFEST")).unwrap();
+        let mut legacy = LSMTree::<MF>::new(0.01, 2, dir.to_string(), 10, 3);
+        legacy.load().unwrap();
+        assert_eq!(names(&legacy), names(&lsm));
+        assert!(Path::new(&format!("{dir}/MANIFEST")).is_file());
+    }
+
+    /// |Len(u64)|CRC(u32)|timestamp, key, value| records the tables were written with before they recorded a version
+    fn legacy_records(entries: &[(&str, Option<&str>)], timestamp: u128) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (key, value) in entries {
+            let entry = (timestamp, key.as_bytes().to_vec(), value.map(|value| value.as_bytes().to_vec()));
+            let entry_ser = BINCODE_OPTIONS.serialize(&entry).unwrap();
+            data.extend(BINCODE_OPTIONS.serialize(&(entry_ser.len() as u64)).unwrap());
+            data.extend(BINCODE_OPTIONS.serialize(&Crc::<u32>::new(&CRC_32_JAMCRC).checksum(&entry_ser)).unwrap());
+            data.extend(entry_ser);
+        }
+        data
+    }
+
+    fn check_upgraded<S: LSMTreeUnderlying>(dir: &str, new: impl Fn() -> LSMTree<S>)
+    where
+        LSMTree<S>: LSMTreeInterface,
+    {
+        let mut lsm = new();
+        lsm.load().unwrap();
+        // the newer table of the level still wins
+        assert_eq!(lsm.get(b"a".to_vec()).unwrap().unwrap().value, Some(b"new".to_vec()));
+        assert_eq!(lsm.get(b"b".to_vec()).unwrap().unwrap().value, Some(b"old".to_vec()));
+        assert!(lsm.get(b"c".to_vec()).unwrap().is_none());
+        assert!(lsm.get(b"d".to_vec()).unwrap().is_some());
+        assert_eq!(lsm.levels[0].nodes.len(), 2);
+        assert!(lsm.levels[0].nodes.iter().all(|node| S::legacy_entries(&format!("{dir}/{}", node.path)).unwrap().is_none()));
+        assert!(!Path::new(&format!("{dir}/sstable-0-1")).exists());
+
+        let mut loaded = new();
+        loaded.load().unwrap();
+        assert_eq!(loaded.get(b"a".to_vec()).unwrap().unwrap().value, Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn recover_upgrades_tables_of_format_version_1() {
+        let older = [("a", Some("old")), ("b", Some("old")), ("c", Some("old"))];
+        let newer = [("a", Some("new")), ("c", None), ("d", Some("new"))];
+
+        let dir = "test-data/manifest-upgrade-multi";
+        clean(dir);
+        for (name, entries, timestamp) in [("sstable-0-1", &older, 1), ("sstable-0-2", &newer, 2)] {
+            create_dir_all(format!("{dir}/{name}")).unwrap();
+            std::fs::write(format!("{dir}/{name}/data"), legacy_records(entries, timestamp)).unwrap();
+        }
+        check_upgraded(dir, || LSMTree::<MF>::new(0.01, 2, dir.to_string(), 10, 3));
+
+        // the single file starts with the offsets of the data, filter, index, summary and metadata
+        let dir = "test-data/manifest-upgrade-single";
+        clean(dir);
+        for (name, entries, timestamp) in [("sstable-0-1", &older, 1), ("sstable-0-2", &newer, 2)] {
+            let records = legacy_records(entries, timestamp);
+            let filter_offset = 40 + records.len() as u64;
+            let mut data = BINCODE_OPTIONS.serialize(&[40, filter_offset, filter_offset, filter_offset, filter_offset]).unwrap();
+            data.extend(records);
+            create_dir_all(format!("{dir}/{name}")).unwrap();
+            std::fs::write(format!("{dir}/{name}/data"), data).unwrap();
+        }
+        check_upgraded(dir, || LSMTree::<SF>::new(0.01, 2, dir.to_string(), 10, 3));
+    }
+}
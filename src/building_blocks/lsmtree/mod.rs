@@ -1,6 +1,9 @@
 mod lsm_tree_singlefile;
 mod lsm_tree_multifile;
+mod lsm_tree_leveled;
 mod lsm_tree;
+mod manifest;
+mod table_cache;
 
 #[cfg(test)]
 mod lsm_tree_singlefile_tests;
@@ -8,4 +11,13 @@ mod lsm_tree_singlefile_tests;
 #[cfg(test)]
 mod lsm_tree_multifile_tests;
 
-pub use lsm_tree::{Level, TableNode, LSMTree, LSMTreeInterface};
+#[cfg(test)]
+mod lsm_tree_leveled_tests;
+
+#[cfg(test)]
+mod lsm_tree_model_tests;
+
+pub use lsm_tree::{Level, TableNode, LSMTree, LSMTreeInterface, CompactionOutput, Compaction, LeveledCompaction, TABLE_CACHE_CAPACITY};
+use lsm_tree::{resolve_versions, resolve_range_tombstones, table_number, compaction_table, lock};
+use manifest::Manifest;
+use table_cache::TableCache;
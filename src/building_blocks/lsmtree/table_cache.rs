@@ -0,0 +1,146 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex, MutexGuard}};
+use anyhow::{Result, anyhow};
+
+/// opened tables by their path, the least recently used one is dropped once there are more than capacity
+/// a reader is shared behind a lock since its iterators and the reader itself use the same file offsets
+/// a dropped reader stays usable for whoever still holds it
+pub struct TableCache<R> {
+    capacity: usize,
+    inner: Mutex<TableCacheInner<R>>,
+}
+
+struct TableCacheInner<R> {
+    /// reader and the tick it was last used at
+    tables: HashMap<String, (Arc<Mutex<R>>, u64)>,
+    /// path by the tick it was last used at, the first one is the least recently used
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl<R> TableCache<R> {
+    pub fn new(capacity: usize) -> Self {
+        let inner = TableCacheInner { tables: HashMap::new(), recency: BTreeMap::new(), tick: 0 };
+        TableCache { capacity, inner: Mutex::new(inner) }
+    }
+
+    /// reader of the table at the path, opened with load if it is not cached
+    /// the lock is not held while loading, a table loaded twice at the same time is kept once
+    pub fn get(&self, path: &str, load: impl FnOnce() -> Result<R>) -> Result<Arc<Mutex<R>>> {
+        if let Some(reader) = self.lock()?.touch(path) {
+            return Ok(reader);
+        }
+
+        let reader = Arc::new(Mutex::new(load()?));
+        let mut inner = self.lock()?;
+        if let Some(reader) = inner.touch(path) {
+            return Ok(reader);
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.tables.insert(path.to_string(), (Arc::clone(&reader), tick));
+        inner.recency.insert(tick, path.to_string());
+        while inner.tables.len() > self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.tables.remove(&oldest);
+        }
+        Ok(reader)
+    }
+
+    /// the reader is dropped from the cache, whoever holds it can still use it
+    pub fn remove(&self, path: &str) -> Result<()> {
+        let mut inner = self.lock()?;
+        if let Some((_, tick)) = inner.tables.remove(path) {
+            inner.recency.remove(&tick);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        inner.tables.clear();
+        inner.recency.clear();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.lock()?.tables.len())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, TableCacheInner<R>>> {
+        self.inner.lock().map_err(|_| anyhow!("table cache lock poisoned"))
+    }
+}
+
+impl<R> TableCacheInner<R> {
+    /// cached reader of the table, marked as the most recently used one
+    fn touch(&mut self, path: &str) -> Option<Arc<Mutex<R>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (reader, used) = self.tables.get_mut(path)?;
+        self.recency.remove(used);
+        *used = tick;
+        let reader = Arc::clone(reader);
+        self.recency.insert(tick, path.to_string());
+        Some(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use super::TableCache;
+
+    #[test]
+    fn least_recently_used_is_dropped() {
+        let loads = Cell::new(0);
+        let cache = TableCache::new(2);
+        let get = |path: &str| {
+            let reader = cache.get(path, || {
+                loads.set(loads.get() + 1);
+                Ok(path.to_string())
+            }).unwrap();
+            let reader = reader.lock().unwrap().clone();
+            reader
+        };
+
+        assert_eq!(get("a"), "a");
+        assert_eq!(get("b"), "b");
+        assert_eq!(get("a"), "a");
+        assert_eq!(loads.get(), 2);
+
+        // b was used before a
+        get("c");
+        assert_eq!(cache.len().unwrap(), 2);
+        get("a");
+        assert_eq!(loads.get(), 3);
+        get("b");
+        assert_eq!(loads.get(), 4);
+
+        cache.remove("b").unwrap();
+        get("b");
+        assert_eq!(loads.get(), 5);
+
+        cache.clear().unwrap();
+        assert_eq!(cache.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn failed_loads_are_not_cached() {
+        let cache: TableCache<String> = TableCache::new(2);
+        assert!(cache.get("a", || Err(anyhow::anyhow!("missing table"))).is_err());
+        assert_eq!(cache.len().unwrap(), 0);
+        assert!(cache.get("a", || Ok(String::from("a"))).is_ok());
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let cache = TableCache::new(0);
+        let reader = cache.get("a", || Ok(1)).unwrap();
+        assert_eq!(*reader.lock().unwrap(), 1);
+        assert_eq!(cache.len().unwrap(), 0);
+    }
+}
@@ -1,155 +1,315 @@
-use std::{cell::RefCell, rc::Rc};
-use crate::{building_blocks::{FileOrganization, SSTableBuilderSingleFile, Entry, SSTableBuilderMultiFile}, utils::merge_sort::merge_sort};
+use crate::{building_blocks::{Compression, FileOrganization, SSTableBuilderSingleFile, Entry, EntryIterator, EntryVecIterator, SSTableBuilderMultiFile}, utils::merge_sort::merge_sort};
 use anyhow::{Context, Result};
+use std::{ops::Bound, sync::Arc};
 use super::{
     MemtableEntry, StorageCRUD
 };
 
-/// memetable flushes to the disk only when the amount of data inside memtable
-/// is greater or equal to capacity, in order to avoid checking multiple times for one action
-/// create, update and delete return the path to the new sstable if flush happened
+/// memtable is frozen once the amount of data inside it is greater or equal to capacity,
+/// the caller checks `is_full` after each write and replaces it with a fresh one by calling `freeze`
 /// sstable created when flushing memtable is always called "memtable"
 pub struct Memtable
 {
-    storage: Box<dyn StorageCRUD>,
+    /// shared with the views handed to iterators and snapshots,
+    /// a write copies it first if a view still holds it, the whole storage of up to capacity entries
+    storage: Arc<Box<dyn StorageCRUD>>,
 
-    /// number of entries in the memtable
+    /// kept apart from the storage, they are not tied to a single key
+    range_tombstones: Arc<Vec<Entry>>,
+
+    /// number of entries in the memtable, range tombstones included
     pub len: u64,
 
     /// max number of entries to be placed inside memtable
     pub capacity: u64,
 
+    table: TableSettings,
+}
+
+/// how a flushed memtable is written to the disk
+#[derive(Clone)]
+struct TableSettings {
     sstable_type: FileOrganization,
     fp_prob: f64,
     summary_nth: u64,
     data_folder: String,
+    compression: Compression,
+}
+
+/// full memtable waiting to be written to an sstable, it keeps serving reads until then
+pub struct ImmutableMemtable {
+    /// sorted by key, one version per key, iterators share it
+    entries: Arc<Vec<Entry>>,
+    range_tombstones: Vec<Entry>,
+    table: TableSettings,
 }
 
 impl Memtable
 {
     pub fn new(storage: Box<dyn StorageCRUD>, capacity: u64, sstable_type: FileOrganization, fp_prob: f64, summary_nth: u64, data_folder: String) -> Self {
         Memtable{
-            storage,
+            storage: Arc::new(storage),
+            range_tombstones: Arc::new(Vec::new()),
             len: 0,
             capacity,
-            sstable_type,
-            fp_prob,
-            summary_nth,
-            data_folder
+            table: TableSettings {
+                sstable_type,
+                fp_prob,
+                summary_nth,
+                data_folder,
+                compression: Compression::None,
+            },
         }
     }
 
-    pub fn create(&mut self, entry: MemtableEntry) -> Option<Result<()>> {
-        if self.storage.read(entry.key.clone()).is_none() {
-            self.len += 1;
-        }
-        self.storage.create(entry);
+    /// codec of the tables the memtable is flushed to
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.table.compression = compression;
+        self
+    }
 
-        assert!(self.len <= self.capacity);
-        if self.len == self.capacity {
-            return Some(self.flush());
+    pub fn create(&mut self, entry: MemtableEntry) {
+        if self.storage.read(&entry.key).is_none() {
+            self.len += 1;
         }
-        None
+        self.storage_mut().create(entry);
     }
 
-    pub fn read(&mut self, key: String) -> Option<Rc<RefCell<MemtableEntry>>> {
+    pub fn read(&self, key: &[u8]) -> Option<MemtableEntry> {
         self.storage.read(key)
     }
 
-    pub fn update(&mut self, entry: MemtableEntry) -> Option<Result<()>> {
-        if self.storage.read(entry.key.clone()).is_none() {
+    pub fn update(&mut self, entry: MemtableEntry) {
+        if self.storage.read(&entry.key).is_none() {
             self.len += 1;
         }
-        self.storage.update(entry);
-
-        assert!(self.len <= self.capacity);
-        if self.len == self.capacity {
-            return Some(self.flush());
-        }
-        None
+        self.storage_mut().update(entry);
     }
 
-    pub fn delete(&mut self, entry: MemtableEntry) -> Option<Result<()>> {
-        if self.storage.read(entry.key.clone()).is_none() {
+    pub fn delete(&mut self, entry: MemtableEntry) {
+        if self.storage.read(&entry.key).is_none() {
             self.len += 1;
         }
-        self.storage.delete(entry);
+        self.storage_mut().delete(entry);
+    }
 
-        assert!(self.len <= self.capacity);
-        if self.len == self.capacity {
-            return Some(self.flush());
-        }
-        None
+    pub fn delete_range(&mut self, tombstone: Entry) {
+        self.len += 1;
+        Arc::make_mut(&mut self.range_tombstones).push(tombstone);
     }
 
-    pub fn prefix_scan(&mut self, prefix: String) -> Vec<Rc<RefCell<MemtableEntry>>> {
-        let mut res = Vec::new();
+    /// applies all entries before the capacity is checked so a flush never lands in the middle of a batch
+    /// entries without a value are applied as tombstones
+    pub fn apply_batch(&mut self, entries: Vec<Entry>) {
+        for entry in entries {
+            if entry.is_range_delete() {
+                self.delete_range(entry);
+                continue;
+            }
+
+            let entry = MemtableEntry::new(entry.timestamp, entry.key, entry.value).with_expiry(entry.expires_at);
+            if self.storage.read(&entry.key).is_none() {
+                self.len += 1;
+            }
 
-        for entry in self.storage.entries() {
-            let borrowed_entry = entry.borrow();
-            if borrowed_entry.key.starts_with(&prefix) && borrowed_entry.value.is_some() {
-                res.push(Rc::clone(&entry));
+            if entry.value.is_some() {
+                self.storage_mut().create(entry);
+            } else {
+                self.storage_mut().delete(entry);
             }
         }
+    }
 
-        res
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity
     }
 
-    pub fn range_scan(&mut self, start: String, end: String) -> Vec<Rc<RefCell<MemtableEntry>>> {
-        let mut res = Vec::new();
+    /// range tombstones in the order they were written
+    pub fn range_tombstones(&self) -> &[Entry] {
+        &self.range_tombstones
+    }
 
-        for entry in self.storage.entries() {
-            let borrowed_entry = entry.borrow();
-            if borrowed_entry.key >= start && borrowed_entry.key <= end {
-                res.push(Rc::clone(&entry));
-            }
-        }
+    /// all entries including tombstones, range tombstones are not included
+    pub fn entries(&self) -> Vec<Entry> {
+        self.storage.entries()
+            .iter()
+            .map(Entry::from)
+            .collect()
+    }
+
+    /// moves all entries into an immutable memtable and leaves this one empty
+    pub fn freeze(&mut self) -> ImmutableMemtable {
+        let owned_entries = self.storage.entries();
+        let mut entries = owned_entries.iter().collect::<Vec<_>>();
+        merge_sort(&mut entries);
+
+        let immutable = ImmutableMemtable {
+            entries: Arc::new(entries.into_iter().map(Entry::from).collect()),
+            range_tombstones: Arc::unwrap_or_clone(std::mem::take(&mut self.range_tombstones)),
+            table: self.table.clone(),
+        };
 
-        res
+        self.len = 0;
+        self.storage_mut().clear();
+        immutable
     }
 
+    /// writes all entries to an sstable right away and empties the memtable
     pub fn flush(&mut self) -> Result<()> {
-        let wrapped_entries = self.storage.entries();
-        let ref_entries = wrapped_entries
-            .iter()
-            .map(|e| e.as_ref().borrow()).collect::<Vec<_>>();
-        let mut entries = ref_entries
-            .iter()
-            .map(|e| &**e).collect::<Vec<_>>();
-        merge_sort(&mut entries);
+        self.freeze().flush()
+    }
+
+    /// the entries as they are now, later writes do not change the view
+    /// the first write while a view is alive copies the storage, views taken between two writes share one copy
+    pub fn view(&self) -> MemtableView {
+        MemtableView {
+            storage: Arc::clone(&self.storage),
+            range_tombstones: Arc::clone(&self.range_tombstones),
+        }
+    }
+
+    fn storage_mut(&mut self) -> &mut Box<dyn StorageCRUD> {
+        Arc::make_mut(&mut self.storage)
+    }
+}
+
+/// memtable as it was when the view was taken, the storage is shared instead of copied
+#[derive(Clone)]
+pub struct MemtableView {
+    storage: Arc<Box<dyn StorageCRUD>>,
+    range_tombstones: Arc<Vec<Entry>>,
+}
+
+impl MemtableView {
+    pub fn read(&self, key: &[u8]) -> Option<Entry> {
+        self.storage.read(key).map(|entry| Entry::from(&entry))
+    }
 
-        if self.sstable_type == FileOrganization::SingleFile(()) {
+    pub fn range_tombstones(&self) -> &[Entry] {
+        &self.range_tombstones
+    }
+
+    /// true if no write came between the two views, they keep a single copy of the storage
+    pub fn shares_storage(&self, other: &MemtableView) -> bool {
+        Arc::ptr_eq(&self.storage, &other.storage)
+    }
+
+    /// entries in key order including tombstones, range tombstones are not included
+    pub fn iter(&self) -> MemtableIterator {
+        MemtableIterator {
+            storage: Arc::clone(&self.storage),
+            front: Bound::Unbounded,
+            back: None,
+        }
+    }
+}
+
+/// walks the shared storage one lookup per entry
+pub struct MemtableIterator {
+    storage: Arc<Box<dyn StorageCRUD>>,
+
+    /// entries up to it were returned from the front or skipped by a seek
+    front: Bound<Vec<u8>>,
+
+    /// entries from it on were returned from the back or skipped by a seek
+    back: Option<Vec<u8>>,
+}
+
+impl Iterator for MemtableIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.storage.first_from(self.front.as_ref().map(Vec::as_slice))?;
+        if self.back.as_ref().is_some_and(|back| &entry.key >= back) {
+            return None;
+        }
+        self.front = Bound::Excluded(entry.key.clone());
+        Some(Ok(Entry::from(&entry)))
+    }
+}
+
+impl DoubleEndedIterator for MemtableIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.storage.last_before(self.back.as_deref())?;
+        let past_front = match &self.front {
+            Bound::Included(front) => &entry.key < front,
+            Bound::Excluded(front) => &entry.key <= front,
+            Bound::Unbounded => false,
+        };
+        if past_front {
+            return None;
+        }
+        self.back = Some(entry.key.clone());
+        Some(Ok(Entry::from(&entry)))
+    }
+}
+
+impl EntryIterator for MemtableIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.front = Bound::Included(key.to_vec());
+        Ok(())
+    }
+
+    fn seek_back(&mut self, key: &[u8]) -> Result<()> {
+        self.back = Some(key.to_vec());
+        Ok(())
+    }
+}
+
+impl ImmutableMemtable {
+    pub fn read(&self, key: &[u8]) -> Option<&Entry> {
+        self.entries
+            .binary_search_by(|entry| entry.key[..].cmp(key))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// all entries including tombstones, sorted by key
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// iterator over the entries that shares them instead of copying
+    pub fn iter(&self) -> EntryVecIterator {
+        EntryVecIterator::shared(Arc::clone(&self.entries))
+    }
+
+    pub fn range_tombstones(&self) -> &[Entry] {
+        &self.range_tombstones
+    }
+
+    /// writes the entries to an sstable called "memtable" in the data folder
+    pub fn flush(&self) -> Result<()> {
+        let table = &self.table;
+        if table.sstable_type == FileOrganization::SingleFile(()) {
             let mut builder = SSTableBuilderSingleFile::new(
-                &self.data_folder,
-                "memtable", entries.len() as u64,
-                self.fp_prob, self.summary_nth)
-                .context("creating single file builder")?;
-
-            for entry in entries {
-                let entry = Entry::from(entry);
-                builder.insert(entry)
+                &table.data_folder,
+                "memtable", self.entries.len() as u64,
+                table.fp_prob, table.summary_nth)
+                .context("creating single file builder")?
+                .with_compression(table.compression);
+
+            for entry in self.entries.iter().chain(&self.range_tombstones) {
+                builder.insert(entry.clone())
                     .context("inserting entry")?;
             }
             builder.finish_data()
                 .context("finishing singlefile builder")?;
         } else {
             let mut builder = SSTableBuilderMultiFile::new(
-                &self.data_folder,
-                "memtable", entries.len() as u64,
-                self.fp_prob, self.summary_nth)
-                .context("creating multifile builder")?;
-
-            for entry in entries {
-                let entry = Entry::from(entry);
-                builder.insert(entry)
+                &table.data_folder,
+                "memtable", self.entries.len() as u64,
+                table.fp_prob, table.summary_nth)
+                .context("creating multifile builder")?
+                .with_compression(table.compression);
+
+            for entry in self.entries.iter().chain(&self.range_tombstones) {
+                builder.insert(entry.clone())
                     .context("inserting entry")?;
             }
             builder.finish()
                 .context("finishing multifile builder")?;
         }
-
-        self.len = 0;
-        self.storage.clear();
         Ok(())
     }
 }
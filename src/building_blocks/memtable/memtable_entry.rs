@@ -1,49 +1,58 @@
 use std::cmp::Ordering;
 
-#[derive(Ord, Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct MemtableEntry {
     /// nanos
     pub timestamp: u128,
 
-    pub key: String,
+    pub key: Vec<u8>,
 
     /// its value is None it means its a tombstone
-    pub value: Option<Vec<u8>>
+    pub value: Option<Vec<u8>>,
+
+    /// nanos, None if the entry never expires
+    pub expires_at: Option<u128>,
 }
 
 impl MemtableEntry {
     pub fn new_string(timestamp: u128, key: String, value: Option<String>) -> Self {
-        let value = if let Some(value) = value {
-            Some(value.into_bytes())
-        } else {
-            None
-        };
+        let value = value.map(|value| value.into_bytes());
         MemtableEntry {
             timestamp,
-            key,
-            value
+            key: key.into_bytes(),
+            value,
+            expires_at: None,
         }
     }
 
-    pub fn new(timestamp: u128, key: String, value: Option<Vec<u8>>) -> Self {
+    pub fn new(timestamp: u128, key: Vec<u8>, value: Option<Vec<u8>>) -> Self {
         MemtableEntry {
             timestamp,
             key,
-            value
+            value,
+            expires_at: None,
         }
     }
 
+    /// the entry reads as a tombstone from `expires_at` on
+    pub fn with_expiry(mut self, expires_at: Option<u128>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
     /// delete == tombstone
-    /// there is no need for timestamp to be updated since all actions happen in memtable inplace
-    /// and are older than the entry in the lsm tree if there is one
-    pub fn delete(&mut self) {
+    /// timestamp is updated so snapshots taken before the delete still see the old value
+    pub fn delete(&mut self, timestamp: u128) {
+        self.timestamp = timestamp;
         self.value = None;
+        self.expires_at = None;
     }
 
-    /// there is no need for timestamp to be updated since all actions happen in memtable inplace
-    /// and are older than the entry in the lsm tree if there is one
-    pub fn update(&mut self, value: Option<Vec<u8>>) {
+    /// timestamp is updated so snapshots taken before the update still see the old value
+    pub fn update(&mut self, timestamp: u128, value: Option<Vec<u8>>, expires_at: Option<u128>) {
+        self.timestamp = timestamp;
         self.value = value;
+        self.expires_at = expires_at;
     }
 }
 
@@ -54,42 +63,20 @@ impl PartialEq for MemtableEntry {
 }
 impl Eq for MemtableEntry {}
 
-impl Default for MemtableEntry {
-    fn default() -> Self {
-        MemtableEntry {
-            timestamp: 0,
-            key: "".to_string(),
-            value: None
-        }
+impl Ord for MemtableEntry {
+    fn cmp(&self, other: &MemtableEntry) -> Ordering {
+        self.key.cmp(&other.key)
     }
 }
 
-// TODO: merge sort has to be refactored to work with this
 impl PartialOrd for MemtableEntry {
     fn partial_cmp(&self, other: &MemtableEntry) -> Option<Ordering> {
-        if self.lt(other) { Some(Ordering::Less) }
-        else if self.gt(other) { Some(Ordering::Greater) }
-        else { Some(Ordering::Equal) }
-    }
-
-    fn lt(&self, other: &MemtableEntry) -> bool {
-        self.key < other.key
-    }
-
-    fn le(&self, other: &MemtableEntry) -> bool {
-        self.key <= other.key
-    }
-
-    fn gt(&self, other: &MemtableEntry) -> bool {
-        self.key > other.key
-    }
-
-    fn ge(&self, other: &MemtableEntry) -> bool {
-        self.key >= other.key
+        Some(self.cmp(other))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::nonminimal_bool)]
 mod tests {
     use super::MemtableEntry;
 
@@ -116,7 +103,7 @@ mod tests {
         assert!(right >= left);
         assert!(!(left >= right));
 
-        left.key = "baa".to_string();
+        left.key = b"baa".to_vec();
         assert!(right >= left);
         assert!(left >= right);
     }
@@ -128,7 +115,7 @@ mod tests {
         assert!(left <= right);
         assert!(!(right <= left));
 
-        left.key = "baa".to_string();
+        left.key = b"baa".to_vec();
         assert!(left <= right);
         assert!(right <= left);
     }
@@ -139,7 +126,7 @@ mod tests {
         let right = MemtableEntry::new_string(1, "baa".to_string(), None);
         assert!(right != left);
 
-        left.key = "baa".to_string();
+        left.key = b"baa".to_vec();
         assert!(!(left != right));
     }
 }
@@ -1,8 +1,5 @@
-use std::{
-    cell::RefCell,
-    rc::Rc, path::Path, fs::remove_dir_all
-};
-use crate::building_blocks::{BTree, FileOrganization};
+use std::{path::Path, fs::remove_dir_all, ops::Bound};
+use crate::building_blocks::{BTree, Entry, EntryIterator, FileOrganization};
 
 use super::{
     Memtable,
@@ -10,40 +7,35 @@ use super::{
     StorageCRUD
 };
 
-impl StorageCRUD for Vec<Rc<RefCell<MemtableEntry>>> {
+impl StorageCRUD for Vec<MemtableEntry> {
     fn create(&mut self, item: MemtableEntry) {
-        let res = self.iter().find(|entry| entry.borrow().key == item.key);
+        let res = self.iter_mut().find(|entry| entry.key == item.key);
         if let Some(old_item) = res {
-            _ = old_item.replace(item);
+            *old_item = item;
         } else {
-            let item = RefCell::new(item);
-            let item = Rc::new(item);
             self.push(item)
         }
     }
 
-    fn read(&mut self, key: String) -> Option<Rc<RefCell<MemtableEntry>>> {
-        let res = self.iter().find(|entry| entry.borrow().key == key);
-        if res.is_some() {
-            Some(Rc::clone(res.unwrap()))
-        } else {
-            None
-        }
+    fn read(&self, key: &[u8]) -> Option<MemtableEntry> {
+        self.iter()
+            .find(|entry| entry.key == key)
+            .cloned()
     }
 
     fn update(&mut self, item: MemtableEntry) {
-        let old_item = self.iter().find(|entry| entry.borrow().key == item.key);
+        let old_item = self.iter_mut().find(|entry| entry.key == item.key);
         if let Some(old_item) = old_item {
-            _ = old_item.borrow_mut().update(item.value);
+            old_item.update(item.timestamp, item.value, item.expires_at);
         } else {
             self.create(item);
         }
     }
 
     fn delete(&mut self, item: MemtableEntry) {
-        let index = self.iter().position(|entry| entry.borrow().key == item.key);
+        let index = self.iter().position(|entry| entry.key == item.key);
         if let Some(index) = index {
-            _ = self.get(index).unwrap().borrow_mut().delete();
+            self[index].delete(item.timestamp);
         } else {
             self.create(item);
         }
@@ -53,132 +45,95 @@ impl StorageCRUD for Vec<Rc<RefCell<MemtableEntry>>> {
         self.clear();
     }
 
-    fn entries(&self) -> Vec<Rc<RefCell<MemtableEntry>>> {
-        self.iter()
-            .map(|item| Rc::clone(item))
-            .collect()
+    fn entries(&self) -> Vec<MemtableEntry> {
+        let mut entries = self.clone();
+        entries.sort();
+        entries
+    }
+
+    fn first_from(&self, from: Bound<&[u8]>) -> Option<MemtableEntry> {
+        self.entries()
+            .into_iter()
+            .find(|entry| match from {
+                Bound::Included(key) => &entry.key[..] >= key,
+                Bound::Excluded(key) => &entry.key[..] > key,
+                Bound::Unbounded => true,
+            })
+    }
+
+    fn last_before(&self, before: Option<&[u8]>) -> Option<MemtableEntry> {
+        self.entries()
+            .into_iter()
+            .rev()
+            .find(|entry| before.is_none_or(|key| &entry.key[..] < key))
+    }
+
+    fn boxed_clone(&self) -> Box<dyn StorageCRUD> {
+        Box::new(self.clone())
     }
 }
 
 #[test]
 fn create() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
     let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
 
-    assert!(memtable.read("0".to_string()).is_none());
+    assert!(memtable.read(b"0").is_none());
 
     let entry = MemtableEntry::new_string(0, "0".to_string(), Some("0".to_string()));
     memtable.create(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry.clone()))));
+    assert_eq!(memtable.read(b"0"), Some(entry.clone()));
 
     memtable.create(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry))));
+    assert_eq!(memtable.read(b"0"), Some(entry));
 }
 
 #[test]
 fn update() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
     let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
 
     let mut entry = MemtableEntry::new_string(0, "0".to_string(), Some("0".to_string()));
     memtable.create(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry.clone()))));
+    assert_eq!(memtable.read(b"0"), Some(entry.clone()));
 
     entry.value = Some("1".to_string().into_bytes());
     memtable.update(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry))));
+    assert_eq!(memtable.read(b"0"), Some(entry));
 }
 
 #[test]
 fn delete() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
     let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
 
-    assert!(memtable.read("0".to_string()).is_none());
+    assert!(memtable.read(b"0").is_none());
 
     let mut entry = MemtableEntry::new_string(0, "0".to_string(), Some("0".to_string()));
     memtable.create(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry.clone()))));
+    assert_eq!(memtable.read(b"0"), Some(entry.clone()));
 
     // deleting existing one
     entry.value = None;
     memtable.delete(entry.clone());
 
-    assert_eq!(memtable.read("0".to_string()), Some(Rc::new(RefCell::new(entry.clone()))));
+    assert_eq!(memtable.read(b"0"), Some(entry.clone()));
 
     // new tombsone
-    entry.key = "1".to_string();
+    entry.key = b"1".to_vec();
     memtable.delete(entry.clone());
 
-    assert_eq!(memtable.read("1".to_string()), Some(Rc::new(RefCell::new(entry.clone()))));
-}
-
-#[test]
-fn prefix_scan() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
-    let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
-
-    let mut entry = MemtableEntry::new_string(0, "aabc".to_string(), Some("0".to_string()));
-    memtable.create(entry.clone());
-
-    entry.key = "aaaa".into();
-    memtable.create(entry.clone());
-
-    entry.key = "bcasd".into();
-    memtable.create(entry.clone());
-
-    let entries = memtable.prefix_scan("aa".into());
-    assert_eq!(entries.len(), 2);
-
-    let entries = memtable.prefix_scan("bc".into());
-    assert_eq!(entries.len(), 1);
-
-    let entries = memtable.prefix_scan("da".into());
-    assert_eq!(entries.len(), 0);
-
-    // ignores tombstones
-    entry.value = None;
-    entry.key = "da".into();
-    memtable.create(entry.clone());
-    let entries = memtable.prefix_scan("da".into());
-    assert_eq!(entries.len(), 0);
-}
-
-#[test]
-fn range_scan() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
-    let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
-
-    let mut entry = MemtableEntry::new_string(0, "aabc".to_string(), Some("0".to_string()));
-    memtable.create(entry.clone());
-
-    entry.key = "accc".into();
-    memtable.create(entry.clone());
-
-    let entries = memtable.range_scan("aaaa".into(), "cccc".into());
-    assert_eq!(entries.len(), 2);
-
-    entry.key = "cccd".into();
-    memtable.create(entry.clone());
-
-    let entries = memtable.range_scan("aaaa".into(), "cccc".into());
-    assert_eq!(entries.len(), 2);
-
-    entry.key = "cccb".into();
-    memtable.create(entry.clone());
-
-    let entries = memtable.range_scan("aaaa".into(), "cccc".into());
-    assert_eq!(entries.len(), 3);
+    assert_eq!(memtable.read(b"1"), Some(entry.clone()));
 }
 
 #[test]
 fn len() {
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
     let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
 
     // create
@@ -202,14 +157,69 @@ fn flushing() {
     let exists = Path::new("./test-data/memtable").is_dir();
     if exists { remove_dir_all("./test-data/memtable").expect("removing old writen memtable"); }
 
-    let items: BTree<String, Rc<RefCell<MemtableEntry>>> = BTree::new();
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
     let mut memtable = Memtable::new(Box::new(items), 2, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
 
     let mut entry = MemtableEntry::new_string(0, "aabc".to_string(), Some("0".to_string()));
     memtable.create(entry.clone());
+    assert!(!memtable.is_full());
     entry.key = "aaaa".into();
-    let res = memtable.create(entry);
+    memtable.create(entry);
+    assert!(memtable.is_full());
+
+    assert!(memtable.flush().is_ok());
+    assert_eq!(memtable.len, 0);
+    assert!(memtable.entries().is_empty());
+}
+
+#[test]
+fn freezing() {
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
+    let mut memtable = Memtable::new(Box::new(items), 2, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
+
+    let mut entry = MemtableEntry::new_string(0, "b".to_string(), Some("0".to_string()));
+    memtable.create(entry.clone());
+    entry.key = "a".into();
+    entry.value = None;
+    memtable.delete(entry);
+
+    let immutable = memtable.freeze();
+    assert!(memtable.read(b"a").is_none());
+    assert_eq!(memtable.len, 0);
+
+    let keys = immutable.entries().iter().map(|entry| entry.key.clone()).collect::<Vec<_>>();
+    assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+    assert_eq!(immutable.read(b"a").unwrap().value, None);
+    assert_eq!(immutable.read(b"b").unwrap().value, Some(b"0".to_vec()));
+    assert!(immutable.read(b"c").is_none());
+}
+
+#[test]
+fn view_is_not_changed_by_writes() {
+    let items: BTree<Vec<u8>, MemtableEntry> = BTree::new();
+    let mut memtable = Memtable::new(Box::new(items), 256, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
+    for key in ["a", "b", "c", "d"] {
+        memtable.create(MemtableEntry::new_string(1, key.to_string(), Some("old".to_string())));
+    }
 
-    assert!(res.is_some());
-    assert!(res.unwrap().is_ok());
+    let view = memtable.view();
+    memtable.update(MemtableEntry::new_string(2, "b".to_string(), Some("new".to_string())));
+    memtable.create(MemtableEntry::new_string(2, "bb".to_string(), Some("new".to_string())));
+    assert_eq!(view.read(b"b").unwrap().value, Some(b"old".to_vec()));
+    assert!(view.read(b"bb").is_none());
+    assert_eq!(memtable.read(b"b").unwrap().value, Some(b"new".to_vec()));
+
+    let keys = |iter: &mut dyn Iterator<Item = anyhow::Result<Entry>>| {
+        iter.map(|entry| entry.unwrap().key).collect::<Vec<_>>()
+    };
+    assert_eq!(keys(&mut view.iter()), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+    assert_eq!(keys(&mut view.iter().rev()), vec![b"d".to_vec(), b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]);
+
+    let mut iter = view.iter();
+    iter.seek(b"b").unwrap();
+    iter.seek_back(b"d").unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().key, b"b".to_vec());
+    assert_eq!(iter.next_back().unwrap().unwrap().key, b"c".to_vec());
+    assert!(iter.next().is_none());
+    assert!(iter.next_back().is_none());
 }
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod memtable;
 mod memtable_entry;
 mod storage_trait;
@@ -5,6 +6,6 @@ mod storage_trait;
 #[cfg(test)]
 mod memtable_tests;
 
-pub use memtable::Memtable;
+pub use memtable::{Memtable, ImmutableMemtable, MemtableIterator, MemtableView};
 pub use memtable_entry::MemtableEntry;
 pub use storage_trait::StorageCRUD;
@@ -1,11 +1,12 @@
-use std::{cell::RefCell, rc::Rc};
+use std::ops::Bound;
 use super::MemtableEntry;
 
 /// the underlying structure used for storing memtable_entry implements given trait
-pub trait StorageCRUD {
+/// storages are shared between threads behind the memtable lock, reads hand out copies
+pub trait StorageCRUD: Send + Sync {
     fn create(&mut self, item: MemtableEntry);
 
-    fn read(&mut self, key: String) -> Option<Rc<RefCell<MemtableEntry>>>;
+    fn read(&self, key: &[u8]) -> Option<MemtableEntry>;
 
     /// updates an existing item, otherwise creates a new one
     fn update(&mut self, item: MemtableEntry);
@@ -19,6 +20,20 @@ pub trait StorageCRUD {
 
     // TODO: ideally this would return an Iterator
     // possible solution is for structs implementing this trait to have a common iteartor struct
-    /// returns all entries
-    fn entries(&self) -> Vec<Rc<RefCell<MemtableEntry>>>;
+    /// returns all entries sorted by key
+    fn entries(&self) -> Vec<MemtableEntry>;
+
+    /// first entry after the bound in key order
+    fn first_from(&self, from: Bound<&[u8]>) -> Option<MemtableEntry>;
+
+    /// last entry with key < before, the last entry of all without it
+    fn last_before(&self, before: Option<&[u8]>) -> Option<MemtableEntry>;
+
+    fn boxed_clone(&self) -> Box<dyn StorageCRUD>;
+}
+
+impl Clone for Box<dyn StorageCRUD> {
+    fn clone(&self) -> Self {
+        self.boxed_clone()
+    }
 }
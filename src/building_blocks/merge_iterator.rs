@@ -0,0 +1,411 @@
+use anyhow::Result;
+use std::sync::Arc;
+use super::{Entry, newest_range_delete};
+
+/// sorted source of entries that can be consumed from both ends
+/// versions of the same key are next to each other
+/// sources are `Send` so iterators can be handed to other threads
+pub trait EntryIterator: DoubleEndedIterator<Item = Result<Entry>> + Send {
+    /// the front moves to the first entry with key >= key
+    fn seek(&mut self, key: &[u8]) -> Result<()>;
+
+    /// the back moves to the last entry with key < key
+    fn seek_back(&mut self, key: &[u8]) -> Result<()>;
+}
+
+/// iterator over entries that are already in memory, sorts them by key
+pub struct EntryVecIterator {
+    entries: Arc<Vec<Entry>>,
+
+    /// next entry to be returned from the front
+    front: usize,
+
+    /// one past the next entry to be returned from the back
+    back: usize,
+}
+
+impl EntryVecIterator {
+    pub fn new(mut entries: Vec<Entry>) -> Self {
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Self::shared(Arc::new(entries))
+    }
+
+    /// entries have to be sorted by key already
+    pub fn shared(entries: Arc<Vec<Entry>>) -> Self {
+        let back = entries.len();
+        EntryVecIterator { entries, front: 0, back }
+    }
+}
+
+impl Iterator for EntryVecIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.front += 1;
+        Some(Ok(self.entries[self.front - 1].clone()))
+    }
+}
+
+impl DoubleEndedIterator for EntryVecIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(Ok(self.entries[self.back].clone()))
+    }
+}
+
+impl EntryIterator for EntryVecIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.front = self.entries.partition_point(|entry| &entry.key[..] < key);
+        Ok(())
+    }
+
+    fn seek_back(&mut self, key: &[u8]) -> Result<()> {
+        self.back = self.entries.partition_point(|entry| &entry.key[..] < key);
+        Ok(())
+    }
+}
+
+/// source with the entries taken out of it but not yet returned
+struct Peekable {
+    iter: Box<dyn EntryIterator>,
+    front: Option<Entry>,
+    back: Option<Entry>,
+}
+
+impl Peekable {
+    fn peek_front(&mut self) -> Result<Option<&Entry>> {
+        if self.front.is_none() {
+            self.front = self.iter.next().transpose()?;
+        }
+
+        // the source is exhausted from the front but the back still holds an entry
+        Ok(self.front.as_ref().or(self.back.as_ref()))
+    }
+
+    fn peek_back(&mut self) -> Result<Option<&Entry>> {
+        if self.back.is_none() {
+            self.back = self.iter.next_back().transpose()?;
+        }
+        Ok(self.back.as_ref().or(self.front.as_ref()))
+    }
+
+    fn take_front(&mut self) -> Option<Entry> {
+        self.front.take().or_else(|| self.back.take())
+    }
+
+    fn take_back(&mut self) -> Option<Entry> {
+        self.back.take().or_else(|| self.front.take())
+    }
+}
+
+/// lazily merges sorted sources (memtable and sstables) into one ordered stream of key-value pairs
+/// of all versions of a key the newest one not newer than the timestamp wins,
+/// keys whose winning version is a tombstone, has expired by the timestamp
+/// or is deleted by a newer range tombstone are skipped
+/// only a few entries per source are held in memory at a time
+pub struct MergeIterator {
+    sources: Vec<Peekable>,
+    timestamp: u128,
+
+    /// range tombstones of all sources, they are few so all of them are held in memory
+    range_tombstones: Vec<Entry>,
+
+    /// inclusive lower bound
+    lower: Option<Vec<u8>>,
+
+    /// exclusive upper bound
+    upper: Option<Vec<u8>>,
+}
+
+impl MergeIterator {
+    pub fn new(sources: Vec<Box<dyn EntryIterator>>, timestamp: u128) -> Self {
+        let sources = sources
+            .into_iter()
+            .map(|iter| Peekable { iter, front: None, back: None })
+            .collect();
+
+        MergeIterator { sources, timestamp, range_tombstones: Vec::new(), lower: None, upper: None }
+    }
+
+    /// keys deleted by the range tombstones are hidden
+    pub fn with_range_tombstones(mut self, tombstones: Vec<Entry>) -> Self {
+        self.range_tombstones = tombstones;
+        self
+    }
+
+    /// limits the iterator to keys in [lower, upper)
+    pub fn with_bounds(mut self, lower: Option<Vec<u8>>, upper: Option<Vec<u8>>) -> Result<Self> {
+        if let Some(lower) = lower.as_ref() {
+            for source in self.sources.iter_mut() {
+                source.iter.seek(lower)?;
+            }
+        }
+        if let Some(upper) = upper.as_ref() {
+            for source in self.sources.iter_mut() {
+                source.iter.seek_back(upper)?;
+            }
+        }
+
+        self.lower = lower;
+        self.upper = upper;
+        Ok(self)
+    }
+
+    /// limits the iterator to keys starting with the prefix
+    pub fn with_prefix(self, prefix: &[u8]) -> Result<Self> {
+        self.with_bounds(Some(prefix.to_vec()), prefix_end(prefix))
+    }
+
+    /// limits the iterator to keys in [start, end]
+    pub fn with_range(self, start: &[u8], end: &[u8]) -> Result<Self> {
+        // smallest key bigger than end
+        let mut upper = end.to_vec();
+        upper.push(0);
+        self.with_bounds(Some(start.to_vec()), Some(upper))
+    }
+
+    /// moves the front to the first key >= key, keys already returned from the back stay returned
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let key = match self.lower.as_ref() {
+            Some(lower) if lower.as_slice() > key => lower.clone(),
+            _ => key.to_vec(),
+        };
+
+        for source in self.sources.iter_mut() {
+            source.front = None;
+            if source.back.as_ref().is_some_and(|entry| entry.key < key) {
+                source.back = None;
+            }
+            source.iter.seek(&key)?;
+        }
+        Ok(())
+    }
+
+    fn next_front(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let mut smallest: Option<Vec<u8>> = None;
+            for source in self.sources.iter_mut() {
+                if let Some(entry) = source.peek_front()? {
+                    if smallest.as_ref().is_none_or(|key| &entry.key < key) {
+                        smallest = Some(entry.key.clone());
+                    }
+                }
+            }
+
+            let Some(key) = smallest else {
+                return Ok(None);
+            };
+
+            let mut versions = Vec::new();
+            for source in self.sources.iter_mut() {
+                while source.peek_front()?.is_some_and(|entry| entry.key == key) {
+                    versions.push(source.take_front().unwrap());
+                }
+            }
+
+            let range_delete = newest_range_delete(&self.range_tombstones, &key, self.timestamp);
+            if let Some(value) = resolve_visible(versions, self.timestamp, range_delete) {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+
+    fn next_back_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let mut biggest: Option<Vec<u8>> = None;
+            for source in self.sources.iter_mut() {
+                if let Some(entry) = source.peek_back()? {
+                    if biggest.as_ref().is_none_or(|key| &entry.key > key) {
+                        biggest = Some(entry.key.clone());
+                    }
+                }
+            }
+
+            let Some(key) = biggest else {
+                return Ok(None);
+            };
+
+            let mut versions = Vec::new();
+            for source in self.sources.iter_mut() {
+                while source.peek_back()?.is_some_and(|entry| entry.key == key) {
+                    versions.push(source.take_back().unwrap());
+                }
+            }
+
+            let range_delete = newest_range_delete(&self.range_tombstones, &key, self.timestamp);
+            if let Some(value) = resolve_visible(versions, self.timestamp, range_delete) {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+}
+
+impl Iterator for MergeIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_front().transpose()
+    }
+}
+
+impl DoubleEndedIterator for MergeIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_back_inner().transpose()
+    }
+}
+
+/// value of the newest version not newer than the timestamp
+/// None if there is no such version, it is a tombstone, it has expired by the timestamp
+/// or the range tombstone at `range_delete` is newer
+pub fn resolve_visible(versions: Vec<Entry>, timestamp: u128, range_delete: Option<u128>) -> Option<Vec<u8>> {
+    versions.into_iter()
+        .filter(|entry| entry.timestamp <= timestamp)
+        .max_by_key(|entry| entry.timestamp)
+        .filter(|entry| range_delete.is_none_or(|deleted| deleted <= entry.timestamp))
+        .filter(|entry| !entry.is_expired(timestamp))
+        .and_then(|entry| entry.value)
+}
+
+/// smallest key that is bigger than all keys with the prefix, None if there is no such key
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::building_blocks::{Entry, EntryKind};
+    use super::{EntryIterator, EntryVecIterator, MergeIterator, prefix_end, resolve_visible};
+
+    fn entry(key: &str, timestamp: u128, value: Option<&str>) -> Entry {
+        Entry {
+            timestamp,
+            key: key.as_bytes().to_vec(),
+            value: value.map(|value| value.as_bytes().to_vec()),
+            kind: EntryKind::Point,
+            expires_at: None,
+        }
+    }
+
+    fn sources() -> Vec<Box<dyn EntryIterator>> {
+        let newer = vec![
+            entry("b", 20, None),
+            entry("c", 20, Some("c2")),
+            entry("e", 20, Some("e2")),
+        ];
+        let older = vec![
+            entry("a", 10, Some("a1")),
+            entry("b", 10, Some("b1")),
+            entry("c", 10, Some("c1")),
+            entry("d", 10, Some("d1")),
+        ];
+        vec![Box::new(EntryVecIterator::new(newer)), Box::new(EntryVecIterator::new(older))]
+    }
+
+    fn keys(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<String> {
+        pairs.into_iter()
+            .map(|(key, value)| format!("{}={}", String::from_utf8(key).unwrap(), String::from_utf8(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn newest_wins_and_tombstones_hidden() {
+        let iter = MergeIterator::new(sources(), u128::MAX);
+        let pairs = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["a=a1", "c=c2", "d=d1", "e=e2"]);
+    }
+
+    #[test]
+    fn reverse() {
+        let iter = MergeIterator::new(sources(), u128::MAX);
+        let pairs = iter.rev().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["e=e2", "d=d1", "c=c2", "a=a1"]);
+    }
+
+    #[test]
+    fn both_ends() {
+        let mut iter = MergeIterator::new(sources(), u128::MAX);
+        assert_eq!(keys(vec![iter.next().unwrap().unwrap()]), vec!["a=a1"]);
+        assert_eq!(keys(vec![iter.next_back().unwrap().unwrap()]), vec!["e=e2"]);
+        assert_eq!(keys(vec![iter.next_back().unwrap().unwrap()]), vec!["d=d1"]);
+        assert_eq!(keys(vec![iter.next().unwrap().unwrap()]), vec!["c=c2"]);
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
+
+    #[test]
+    fn as_of_timestamp() {
+        let iter = MergeIterator::new(sources(), 15);
+        let pairs = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["a=a1", "b=b1", "c=c1", "d=d1"]);
+    }
+
+    #[test]
+    fn bounds_and_seek() {
+        let iter = MergeIterator::new(sources(), u128::MAX).with_range(b"b", b"d").unwrap();
+        let pairs = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["c=c2", "d=d1"]);
+
+        let mut iter = MergeIterator::new(sources(), u128::MAX).with_prefix(b"c").unwrap();
+        let pairs = iter.by_ref().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["c=c2"]);
+
+        let mut iter = MergeIterator::new(sources(), u128::MAX);
+        iter.seek(b"cc").unwrap();
+        let pairs = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["d=d1", "e=e2"]);
+    }
+
+    #[test]
+    fn range_tombstones_hide_older_versions() {
+        let tombstones = vec![
+            Entry::range_delete(15, b"a".to_vec(), b"c".to_vec()),
+            Entry::range_delete(25, b"c".to_vec(), b"e".to_vec()),
+        ];
+        let iter = MergeIterator::new(sources(), u128::MAX).with_range_tombstones(tombstones.clone());
+        let pairs = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["e=e2"]);
+
+        // the tombstones newer than the read are ignored
+        let iter = MergeIterator::new(sources(), 20).with_range_tombstones(tombstones.clone());
+        let pairs = iter.rev().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(keys(pairs), vec!["e=e2", "d=d1", "c=c2"]);
+    }
+
+    #[test]
+    fn prefix_ends() {
+        assert_eq!(prefix_end(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_end(&[1, 0xff]), Some(vec![2]));
+        assert_eq!(prefix_end(&[0xff]), None);
+    }
+
+    #[test]
+    fn visible_version() {
+        let versions = || vec![entry("k", 10, Some("1")), entry("k", 20, None), entry("k", 30, Some("3"))];
+        assert_eq!(resolve_visible(versions(), 5, None), None);
+        assert_eq!(resolve_visible(versions(), 15, None), Some(b"1".to_vec()));
+        assert_eq!(resolve_visible(versions(), 25, None), None);
+        assert_eq!(resolve_visible(versions(), u128::MAX, None), Some(b"3".to_vec()));
+        assert_eq!(resolve_visible(versions(), 15, Some(12)), None);
+        assert_eq!(resolve_visible(versions(), u128::MAX, Some(25)), Some(b"3".to_vec()));
+
+        // an expired version hides the older ones like a tombstone
+        let mut expiring = versions();
+        expiring[2].expires_at = Some(40);
+        assert_eq!(resolve_visible(expiring.clone(), 35, None), Some(b"3".to_vec()));
+        assert_eq!(resolve_visible(expiring.clone(), 40, None), None);
+        assert_eq!(resolve_visible(expiring, 15, None), Some(b"1".to_vec()));
+    }
+}
@@ -78,15 +78,15 @@ impl MerkleRoot {
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .serialize(&self)
-            .context("serializing merkle tree")?)
+            .context("serializing merkle tree")
     }
 
     pub fn deserialize(data: &[u8]) -> Result<MerkleRoot> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .deserialize(data)
-            .context("deserializing merkle tree")?)
+            .context("deserializing merkle tree")
     }
 }
 
@@ -119,7 +119,7 @@ mod tests {
         ];
 
         let merkle_root = MerkleRoot::new(data.clone());
-        assert_eq!(merkle_root.root.is_some(), true);
+        assert!(merkle_root.root.is_some());
     }
 
     #[test]
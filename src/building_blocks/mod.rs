@@ -24,6 +24,12 @@ pub static MAX_KEY_LEN: u64 = 64*1024;
 /// max lenth of the key is 512mb
 pub static MAX_VAL_LEN: u64 = 512*1024*1024;
 
+/// version of the on-disk format of entries, every WAL segment and sstable records the one it was written in
+/// 1 - no version recorded, 2 - entries can expire, 3 - sstable data in prefix compressed blocks,
+/// 4 - blocks compressed with the codec the table records, 5 - fixed-width index, 6 - WAL records carry sequences
+/// tables and WAL segments of version 1 are rewritten in the current format when the database is opened
+pub static FORMAT_VERSION: u32 = 6;
+
 mod entry;
 mod bloomfilter;
 mod hyperloglog;
@@ -38,23 +44,29 @@ mod lsmtree;
 mod simhash;
 mod wal;
 mod cache;
+mod write_batch;
+mod snapshot;
+mod merge_iterator;
+mod page;
 
-pub use entry::Entry;
+pub use entry::{Entry, EntryKind, newest_range_delete};
 pub use bloomfilter::BloomFilter;
 pub use hyperloglog::HyperLogLog;
 pub use count_min_sketch::CountMinSketch;
 pub use token_bucket::TokenBucket;
-pub use memtable::Memtable;
+pub use memtable::{Memtable, ImmutableMemtable, MemtableIterator, MemtableView};
 pub use memtable::StorageCRUD;
 pub use memtable::MemtableEntry;
 pub use sstable::{
     IndexBuilder, IndexIterator, IndexEntry,
     SummaryBuilder, SummaryEntry, SummaryIterator,
-    SSTableConfig, FileOrganization,
+    SSTableConfig, FileOrganization, Compression,
     SSTableBuilderMultiFile, SSTableReaderMultiFile, SSTableIteratorMultiFile,
     SSTableBuilderSingleFile, SSTableReaderSingleFile, SSTableIteratorSingleFile,
-    SF, MF, LSMTreeUnderlying
+    SSTableCursor, SSTableRanges, BlockCache,
+    SF, MF, LSMTreeUnderlying, TableReader
 };
+pub(crate) use sstable::legacy_records;
 
 
 pub use skip_list::SkipList;
@@ -66,6 +78,14 @@ pub use simhash::SimHash;
 pub use simhash::similarity;
 pub use simhash::hamming_distance;
 pub use wal::WriteAheadLog;
-pub use wal::WriteAheadLogReader;
-pub use cache::Cache;
-pub use lsmtree::{LSMTree, LSMTreeInterface};
+pub use wal::{WriteAheadLogReader, RecoveryMode, RecoveryReport, DroppedRange};
+pub use wal::WalRecord;
+pub use wal::{SyncMode, WalSync};
+pub(crate) use wal::upgrade_segments;
+pub use write_batch::WriteBatch;
+pub(crate) use write_batch::range_end;
+pub use snapshot::{Snapshot, SnapshotList};
+pub use page::{Page, PageToken};
+pub use merge_iterator::{EntryIterator, EntryVecIterator, MergeIterator};
+pub use cache::{Cache, CacheStats};
+pub use lsmtree::{LSMTree, LSMTreeInterface, CompactionOutput, Compaction, LeveledCompaction, TABLE_CACHE_CAPACITY};
@@ -0,0 +1,51 @@
+use anyhow::{Result, Context};
+use bincode::Options;
+use serde::{Serialize, Deserialize};
+use super::BINCODE_OPTIONS;
+
+/// opaque continuation of a paginated scan
+/// the next page starts right after the last returned key and reads the same snapshot
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PageToken {
+    pub(crate) last_key: Vec<u8>,
+    pub(crate) snapshot_id: u64,
+}
+
+impl PageToken {
+    /// hex string that can be handed out to clients
+    pub fn encode(&self) -> Result<String> {
+        let token_ser = BINCODE_OPTIONS
+            .serialize(self)
+            .context("serializing page token")?;
+        Ok(hex::encode(token_ser))
+    }
+
+    pub fn decode(token: &str) -> Result<PageToken> {
+        let token_ser = hex::decode(token)
+            .context("decoding page token hex")?;
+        BINCODE_OPTIONS
+            .deserialize(&token_ser)
+            .context("deserializing page token")
+    }
+}
+
+/// one page of a scan, next is None once the scan is exhausted
+#[derive(Debug)]
+pub struct Page {
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    pub next: Option<PageToken>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageToken;
+
+    #[test]
+    fn encode_decode() {
+        let token = PageToken { last_key: vec![0, 0xff, b'a'], snapshot_id: 7 };
+        let encoded = token.encode().unwrap();
+        assert_eq!(PageToken::decode(&encoded).unwrap(), token);
+        assert!(PageToken::decode("not hex").is_err());
+        assert!(PageToken::decode(&encoded[..4]).is_err());
+    }
+}
@@ -58,21 +58,21 @@ impl SimHash {
         format!("{:064b}", hasher.finish())
     }
 
-    pub fn update_weighted_bits(&mut self, weighted_bits: &mut Vec<i32>, hash_chars: &[char], count: i32) {
-        for bit in 0..64 {
-            if hash_chars[bit] == '1' {
-                weighted_bits[bit] += count;
+    pub fn update_weighted_bits(&mut self, weighted_bits: &mut [i32], hash_chars: &[char], count: i32) {
+        for (weight, hash_char) in weighted_bits.iter_mut().zip(hash_chars).take(64) {
+            if *hash_char == '1' {
+                *weight += count;
             } else {
-                weighted_bits[bit] -= count;
+                *weight -= count;
             }
         }
     }
 
-    pub fn calculate_fingerprint(&mut self, weighted_bits: &mut Vec<i32>) {
+    pub fn calculate_fingerprint(&mut self, weighted_bits: &mut [i32]) {
         // Apply threshold to get b-bit fingerprint
         self.simhash = 0;
-        for bit in 0..64 {
-            if weighted_bits[bit] > 0 {
+        for (bit, weight) in weighted_bits.iter().enumerate().take(64) {
+            if *weight > 0 {
                 self.simhash |= 1 << bit;
             }
         }
@@ -88,15 +88,15 @@ impl SimHash {
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .serialize(self)
-            .context("serializing simahsh")?)
+            .context("serializing simahsh")
     }
 
     pub fn deserialize(input: &[u8]) -> Result<Self> {
-        Ok(BINCODE_OPTIONS
+        BINCODE_OPTIONS
             .deserialize(input)
-            .context("deserializing simhash")?)
+            .context("deserializing simhash")
     }
 }
 
@@ -123,9 +123,7 @@ mod tests {
 
     #[test]
     fn test_update_weighted_bits() {
-        let stopwords = vec![
-            "this", "is", "a", "with", "to", "the", "some",
-        ]
+        let stopwords = ["this", "is", "a", "with", "to", "the", "some"]
         .iter()
         .map(|&word| word.to_string())
         .collect();
@@ -251,6 +249,6 @@ mod tests {
 
         // Calculate similarity based on Hamming distance
         let similarity = similarity(fingerprint1, fingerprint2);
-        assert!(similarity >= 0.0 && similarity <= 1.0);
+        assert!((0.0..=1.0).contains(&similarity));
     }
 }
@@ -1,60 +1,49 @@
 use rand::Rng;
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::default::Default;
+use std::ops::Bound;
 
 use super::{StorageCRUD, MemtableEntry};
 
+/// nodes live in the arena of the skip list and point to each other by index
+/// so the list holds no shared pointers and can be sent between threads
+#[derive(Clone)]
 pub struct SkipListNode<T> {
     value: T,
-    next_nodes: Vec<Option<Rc<RefCell<SkipListNode<T>>>>>,
+    next_nodes: Vec<Option<usize>>,
 }
 
+/// the head is always the first node in the arena
+/// deleted nodes stay in the arena until the list is cleared
+#[derive(Clone)]
 pub struct SkipList<T> {
-    head: Rc<RefCell<SkipListNode<T>>>,
+    nodes: Vec<SkipListNode<T>>,
     max_level: usize,
 }
 
+const HEAD: usize = 0;
+
 impl<T: Ord + Default> SkipList<T> {
     pub fn new(max_level: usize) -> Self {
-
-        let head = Rc::new(RefCell::new(SkipListNode {
+        let head = SkipListNode {
             value: Default::default(),
             next_nodes: vec![None; max_level],
-        }));
+        };
 
-        SkipList { head, max_level }
+        SkipList { nodes: vec![head], max_level }
     }
 
     pub fn insert(&mut self, value: T) {
         let level = self.roll();
+        let update = self.predecessors(&value);
 
-        let new_node = Rc::new(RefCell::new(SkipListNode {
-            value,
-            next_nodes: vec![None; level],
-        }));
-
-        let mut current = Rc::clone(&self.head);
-        let mut update = vec![Rc::clone(&self.head); level];
-
-        for i in (0..level).rev() {
-            let mut next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
-            while let Some(next) = next_reference {
-                let next_value = &next.borrow().value;
-                if next_value < &new_node.borrow().value {
-                    current = Rc::clone(&next);
-                    next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
-                } else {
-                    break;
-                }
-            }
-            update[i] = Rc::clone(&current);
-        }
+        let new_node = self.nodes.len();
+        let next_nodes = (0..level)
+            .map(|i| self.nodes[update[i]].next_nodes[i])
+            .collect();
+        self.nodes.push(SkipListNode { value, next_nodes });
 
-        for i in 0..level {
-            let mut new_node_borrow_mut = new_node.borrow_mut();
-            new_node_borrow_mut.next_nodes[i] = update[i].borrow_mut().next_nodes[i].take();
-            update[i].borrow_mut().next_nodes[i] = Some(Rc::clone(&new_node));
+        for (i, previous) in update.into_iter().enumerate().take(level) {
+            self.nodes[previous].next_nodes[i] = Some(new_node);
         }
     }
 
@@ -62,74 +51,74 @@ impl<T: Ord + Default> SkipList<T> {
         self.search(value).is_some()
     }
 
-    pub fn search(&self, value: T)  -> Option<Rc<RefCell<SkipListNode<T>>>> {
-        let mut current = Rc::clone(&self.head);
+    pub fn search(&self, value: T) -> Option<&T> {
+        self.find(&value).map(|node| &self.nodes[node].value)
+    }
 
-        for i in (0..self.max_level).rev() {
-                let mut next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
-                while let Some(next) = next_reference {
-                    let next_value = &next.borrow().value;
-                    if next_value < &value {
-                        current = Rc::clone(&next);
-                        next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
-                    } else if next_value == &value {
-                        return Some(Rc::clone(&next));
-                    } else {
-                        break;
-                    }
-                }
+    pub fn delete(&mut self, value: T) {
+        let update = self.predecessors(&value);
+
+        let Some(node_to_delete) = self.nodes[update[0]].next_nodes[0] else {
+            return;
+        };
+        if self.nodes[node_to_delete].value != value {
+            return;
+        }
+
+        for (i, previous) in update.into_iter().enumerate() {
+            if self.nodes[previous].next_nodes[i] == Some(node_to_delete) {
+                self.nodes[previous].next_nodes[i] = self.nodes[node_to_delete].next_nodes[i];
             }
-        None
+        }
     }
 
+    pub fn get_first_row_nodes(&self) -> Vec<&T> {
+        let mut first_row_nodes = Vec::new();
+        let mut current = self.nodes[HEAD].next_nodes[0];
 
-    pub fn delete(&mut self, value: T) {
-        let mut current = Rc::clone(&self.head);
-        let mut update = vec![Rc::clone(&self.head); self.max_level];
+        while let Some(node) = current {
+            first_row_nodes.push(&self.nodes[node].value);
+            current = self.nodes[node].next_nodes[0];
+        }
+
+        first_row_nodes
+    }
+
+    /// index of the node holding the value
+    fn find(&self, value: &T) -> Option<usize> {
+        let update = self.predecessors(value);
+        self.nodes[update[0]].next_nodes[0]
+            .filter(|&node| &self.nodes[node].value == value)
+    }
+
+    /// for every level the last node whose value is smaller than the given one
+    fn predecessors(&self, value: &T) -> Vec<usize> {
+        let mut current = HEAD;
+        let mut update = vec![HEAD; self.max_level];
 
         for i in (0..self.max_level).rev() {
-            let mut next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
-            while let Some(next) = next_reference {
-                let next_value = &next.borrow().value;
-                if next_value < &value {
-                    current = Rc::clone(&next);
-                    next_reference = current.borrow().next_nodes[i].as_ref().map(Rc::clone);
+            while let Some(next) = self.nodes[current].next_nodes[i] {
+                if &self.nodes[next].value < value {
+                    current = next;
                 } else {
                     break;
                 }
             }
-            update[i] = Rc::clone(&current);
-        }
-
-        let target = current.borrow().next_nodes[0].as_ref().map(Rc::clone);
-        if let Some(node_to_delete) = target {
-            if node_to_delete.borrow().value == value {
-                for i in 0..self.max_level {
-                    let previous = &update[i];
-                    let mut previous_next = previous.borrow_mut().next_nodes[i].take();
-
-                    if let Some(next) = &previous_next {
-                        if Rc::ptr_eq(&node_to_delete, next) {
-                            previous_next = node_to_delete.borrow_mut().next_nodes[i].take();
-                        }
-                    }
-                    previous.borrow_mut().next_nodes[i] = previous_next;
-                }
-            }
+            update[i] = current;
         }
+        update
     }
 
-    pub fn get_first_row_nodes(&self) -> Vec<Rc<RefCell<SkipListNode<T>>>> {
-            let mut first_row_nodes = Vec::new();
-            let mut current = self.head.borrow().next_nodes[0].as_ref().map(Rc::clone);
-
-            while let Some(node) = current {
-                first_row_nodes.push(node.clone());
-                current = node.borrow().next_nodes[0].as_ref().map(Rc::clone);
+    /// index of the last node, the head if the list is empty
+    fn last(&self) -> usize {
+        let mut current = HEAD;
+        for i in (0..self.max_level).rev() {
+            while let Some(next) = self.nodes[current].next_nodes[i] {
+                current = next;
             }
-
-            first_row_nodes
         }
+        current
+    }
 
     fn roll(&mut self) -> usize {
         let mut level = 1;
@@ -145,68 +134,86 @@ impl<T: Ord + Default> SkipList<T> {
 }
 
 impl StorageCRUD for SkipList<MemtableEntry> {
+    /// replaces the entry with the same key
     fn create(&mut self, item: MemtableEntry) {
-        self.insert(item);
+        match self.find(&item) {
+            Some(node) => self.nodes[node].value = item,
+            None => self.insert(item),
+        }
     }
 
-    fn read(&mut self, key: String) -> Option<Rc<RefCell<MemtableEntry>>> {
-        let search_result = self.search(MemtableEntry::new_string(0, key.clone(), None));
-        if let Some(node) = search_result {
-            let value = node.borrow().value.clone();
-            let entry = MemtableEntry::new(value.timestamp, value.key.clone(), value.value.clone());
-            Some(Rc::new(RefCell::new(entry)))
-        } else {
-            None
-        }
+    fn read(&self, key: &[u8]) -> Option<MemtableEntry> {
+        self.search(MemtableEntry::new(0, key.to_vec(), None)).cloned()
     }
 
     fn update(&mut self, item: MemtableEntry) {
-            if let Some(existing_node) = self.search(item.clone()) {
-                let existing_entry = existing_node.borrow_mut();
-                let existing_value = existing_entry.value.clone();
-                self.delete(existing_value.clone());
-            }
-            self.insert(item);
+        match self.find(&item) {
+            Some(node) => self.nodes[node].value.update(item.timestamp, item.value, item.expires_at),
+            None => self.insert(item),
         }
+    }
 
+    /// the entry stays in the list as a tombstone so it shadows older versions on disk
     fn delete(&mut self, item: MemtableEntry) {
-        self.delete(item);
+        match self.find(&item) {
+            Some(node) => self.nodes[node].value.delete(item.timestamp),
+            None => self.insert(item),
+        }
     }
 
     fn clear(&mut self) {
+        self.nodes.truncate(1);
         for i in 0..self.max_level {
-            self.head.borrow_mut().next_nodes[i] = None;
+            self.nodes[HEAD].next_nodes[i] = None;
         }
     }
 
-    fn entries(&self) -> Vec<Rc<RefCell<MemtableEntry>>> {
+    fn entries(&self) -> Vec<MemtableEntry> {
         self.get_first_row_nodes()
-            .iter()
-            .map(|node| {
-                let value = node.borrow().value.clone();
-                let entry = MemtableEntry::new(value.timestamp, value.key.clone(), value.value.clone());
-                Rc::new(RefCell::new(entry))
-            })
+            .into_iter()
+            .cloned()
             .collect()
     }
+
+    fn first_from(&self, from: Bound<&[u8]>) -> Option<MemtableEntry> {
+        let node = match from {
+            Bound::Unbounded => self.nodes[HEAD].next_nodes[0],
+            Bound::Included(key) | Bound::Excluded(key) => {
+                let probe = MemtableEntry::new(0, key.to_vec(), None);
+                let next = self.nodes[self.predecessors(&probe)[0]].next_nodes[0];
+                match next {
+                    Some(node) if matches!(from, Bound::Excluded(_)) && self.nodes[node].value == probe => {
+                        self.nodes[node].next_nodes[0]
+                    }
+                    _ => next,
+                }
+            }
+        };
+        node.map(|node| self.nodes[node].value.clone())
+    }
+
+    fn last_before(&self, before: Option<&[u8]>) -> Option<MemtableEntry> {
+        let node = match before {
+            Some(key) => self.predecessors(&MemtableEntry::new(0, key.to_vec(), None))[0],
+            None => self.last(),
+        };
+        (node != HEAD).then(|| self.nodes[node].value.clone())
+    }
+
+    fn boxed_clone(&self) -> Box<dyn StorageCRUD> {
+        Box::new(self.clone())
+    }
 }
 
 impl<T: std::fmt::Debug> SkipList<T> {
     pub fn print(&self) {
         for level in (0..self.max_level).rev() {
-            let mut current = Rc::clone(&self.head);
+            let mut current = self.nodes[HEAD].next_nodes[level];
             let mut nodes = Vec::new();
 
-            loop {
-                let next_reference = current.borrow().next_nodes[level].as_ref().map(Rc::clone);
-
-                match next_reference {
-                    Some(next) => {
-                        nodes.push(format!("{:?}", next.borrow().value));
-                        current = Rc::clone(&next);
-                    }
-                    None => break,
-                }
+            while let Some(node) = current {
+                nodes.push(format!("{:?}", self.nodes[node].value));
+                current = self.nodes[node].next_nodes[level];
             }
 
             if !nodes.is_empty() {
@@ -226,7 +233,7 @@ mod tests {
     fn new_correct_initial_state_test() {
         let skip_list: SkipList<i32> = SkipList::new(10);
 
-        let head_node = skip_list.head.borrow();
+        let head_node = &skip_list.nodes[HEAD];
         assert_eq!(head_node.next_nodes.len(), 10);
 
         for next_node in &head_node.next_nodes {
@@ -245,10 +252,10 @@ mod tests {
         skip_list.insert(1);
         skip_list.insert(9);
 
-        assert_eq!(skip_list.contains(5), false);
-        assert_eq!(skip_list.contains(7),true);
-        assert_eq!(skip_list.contains(11), false);
-        assert_eq!(skip_list.contains(1), true);
+        assert!(!skip_list.contains(5));
+        assert!(skip_list.contains(7));
+        assert!(!skip_list.contains(11));
+        assert!(skip_list.contains(1));
     }
 
     #[test]
@@ -263,10 +270,10 @@ mod tests {
         skip_list.delete(7);
         skip_list.delete(1);
 
-        assert_eq!(skip_list.contains(7), false);
-        assert_eq!(skip_list.contains(3), true);
-        assert_eq!(skip_list.contains(1), false);
-        assert_eq!(skip_list.contains(9), true);
+        assert!(!skip_list.contains(7));
+        assert!(skip_list.contains(3));
+        assert!(!skip_list.contains(1));
+        assert!(skip_list.contains(9));
     }
 
     #[test]
@@ -276,9 +283,9 @@ mod tests {
         skip_list.insert(15);
         skip_list.insert(20);
 
-        assert_eq!(skip_list.search(10).map(|node| node.borrow().value), Some(10));
-        assert_eq!(skip_list.search(15).map(|node| node.borrow().value), Some(15));
-        assert_eq!(skip_list.search(20).map(|node| node.borrow().value), Some(20));
+        assert_eq!(skip_list.search(10).copied(), Some(10));
+        assert_eq!(skip_list.search(15).copied(), Some(15));
+        assert_eq!(skip_list.search(20).copied(), Some(20));
     }
 
     #[test]
@@ -288,9 +295,9 @@ mod tests {
         skip_list.insert(15);
         skip_list.insert(20);
 
-        assert_eq!(skip_list.search(5).map(|node| node.borrow().value), None);
-        assert_eq!(skip_list.search(25).map(|node| node.borrow().value), None);
-        assert_eq!(skip_list.search(100).map(|node| node.borrow().value), None);
+        assert_eq!(skip_list.search(5).copied(), None);
+        assert_eq!(skip_list.search(25).copied(), None);
+        assert_eq!(skip_list.search(100).copied(), None);
     }
 
     #[test]
@@ -305,7 +312,47 @@ mod tests {
 
             assert_eq!(first_row_nodes.len(), 4);
 
-            let values: Vec<_> = first_row_nodes.iter().map(|node| node.borrow().value).collect();
+            let values: Vec<_> = first_row_nodes.into_iter().copied().collect();
             assert_eq!(values, vec![1, 3, 7, 9]);
         }
+
+    #[test]
+    fn storage_replaces_and_keeps_tombstones() {
+        let mut skip_list: SkipList<MemtableEntry> = SkipList::new(10);
+        StorageCRUD::create(&mut skip_list, MemtableEntry::new(1, b"a".to_vec(), Some(b"1".to_vec())));
+        StorageCRUD::create(&mut skip_list, MemtableEntry::new(2, b"a".to_vec(), Some(b"2".to_vec())));
+        StorageCRUD::delete(&mut skip_list, MemtableEntry::new(3, b"b".to_vec(), None));
+
+        let entries = skip_list.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].value, Some(b"2".to_vec()));
+        assert_eq!(entries[1].value, None);
+
+        StorageCRUD::delete(&mut skip_list, MemtableEntry::new(4, b"a".to_vec(), None));
+        let entry = skip_list.read(b"a").unwrap();
+        assert_eq!((entry.timestamp, entry.value), (4, None));
+
+        StorageCRUD::clear(&mut skip_list);
+        assert!(skip_list.entries().is_empty());
+    }
+
+    #[test]
+    fn storage_walks_by_key() {
+        let mut skip_list: SkipList<MemtableEntry> = SkipList::new(10);
+        for key in [b"b", b"d", b"a", b"c"] {
+            StorageCRUD::create(&mut skip_list, MemtableEntry::new(1, key.to_vec(), Some(b"v".to_vec())));
+        }
+        let key = |entry: Option<MemtableEntry>| entry.map(|entry| entry.key);
+
+        assert_eq!(key(skip_list.first_from(Bound::Unbounded)), Some(b"a".to_vec()));
+        assert_eq!(key(skip_list.first_from(Bound::Included(b"b"))), Some(b"b".to_vec()));
+        assert_eq!(key(skip_list.first_from(Bound::Excluded(b"b"))), Some(b"c".to_vec()));
+        assert_eq!(key(skip_list.first_from(Bound::Included(b"bb"))), Some(b"c".to_vec()));
+        assert_eq!(key(skip_list.first_from(Bound::Excluded(b"d"))), None);
+
+        assert_eq!(key(skip_list.last_before(None)), Some(b"d".to_vec()));
+        assert_eq!(key(skip_list.last_before(Some(b"c"))), Some(b"b".to_vec()));
+        assert_eq!(key(skip_list.last_before(Some(b"cc"))), Some(b"c".to_vec()));
+        assert_eq!(key(skip_list.last_before(Some(b"a"))), None);
+    }
 }
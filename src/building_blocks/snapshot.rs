@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, sync::{Arc, Mutex, MutexGuard}};
+use super::MemtableView;
+
+/// registry of live snapshots shared between the database and the lsm tree
+/// maps snapshot id to the timestamp it reads at
+#[derive(Clone, Default)]
+pub struct SnapshotList {
+    inner: Arc<Mutex<SnapshotListInner>>,
+}
+
+#[derive(Default)]
+struct SnapshotListInner {
+    next_id: u64,
+    live: BTreeMap<u64, u128>,
+}
+
+impl SnapshotList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// returns the id of the new snapshot
+    pub fn register(&self, timestamp: u128) -> u64 {
+        let mut inner = self.inner();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.live.insert(id, timestamp);
+        id
+    }
+
+    pub fn release(&self, id: u64) {
+        self.inner().live.remove(&id);
+    }
+
+    /// timestamp of a live snapshot
+    pub fn get(&self, id: u64) -> Option<u128> {
+        self.inner().live.get(&id).copied()
+    }
+
+    /// timestamps of all live snapshots, oldest first
+    pub fn timestamps(&self) -> Vec<u128> {
+        let mut timestamps = self.inner().live.values().copied().collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        timestamps
+    }
+
+    fn inner(&self) -> MutexGuard<'_, SnapshotListInner> {
+        // the registry is only touched by short infallible sections, a poisoned lock holds valid data
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// consistent view of the database at the moment it was taken
+/// the snapshot is released when dropped, after that compaction can drop the versions it needed
+pub struct Snapshot {
+    id: u64,
+    timestamp: u128,
+
+    /// the memtable copies its entries before a write overwrites a version the snapshot needs
+    pub(crate) memtable: MemtableView,
+
+    list: SnapshotList,
+}
+
+impl Snapshot {
+    pub(crate) fn new(timestamp: u128, memtable: MemtableView, list: &SnapshotList) -> Self {
+        let id = list.register(timestamp);
+        Snapshot { id, timestamp, memtable, list: list.clone() }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// entries newer than this are not visible through the snapshot
+    pub fn timestamp(&self) -> u128 {
+        self.timestamp
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::building_blocks::{BTree, FileOrganization, Memtable};
+    use super::{Snapshot, SnapshotList};
+
+    #[test]
+    fn register_and_release() {
+        let list = SnapshotList::new();
+        let memtable = Memtable::new(Box::new(BTree::new()), 16, FileOrganization::SingleFile(()), 0.01, 50, "test-data/".into());
+        let first = Snapshot::new(20, memtable.view(), &list);
+        let second = Snapshot::new(10, memtable.view(), &list);
+        assert_eq!(list.timestamps(), vec![10, 20]);
+        assert_eq!(list.get(first.id()), Some(20));
+
+        drop(first);
+        assert_eq!(list.timestamps(), vec![10]);
+
+        drop(second);
+        assert!(list.timestamps().is_empty());
+    }
+}
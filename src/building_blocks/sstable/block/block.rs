@@ -0,0 +1,154 @@
+use std::{fs::File, io::Read};
+use anyhow::{Result, Context, anyhow};
+use bincode::Options;
+use crc::{Crc, CRC_32_JAMCRC};
+use crate::building_blocks::{Entry, BINCODE_OPTIONS, MAX_KEY_LEN, MAX_VAL_LEN};
+use crate::building_blocks::sstable::Compression;
+use super::{BlockEntry, BLOCK_SIZE};
+
+/// decoded block as written by the BlockBuilder
+pub struct Block {
+    /// encoded entries without the restart offsets
+    data: Vec<u8>,
+    restarts: Vec<u32>,
+}
+
+impl Block {
+    /// expected slice: crc(4b)+compressed block
+    pub fn deserialize(block: &[u8], compression: Compression) -> Result<Block> {
+        if block.len() < 4 {
+            return Err(anyhow!("block too short"));
+        }
+
+        let crc_deser: u32 = BINCODE_OPTIONS
+            .deserialize(&block[..4])
+            .context("deserializing block crc")?;
+        let computed_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+            .checksum(&block[4..]);
+        if crc_deser != computed_crc {
+            return Err(anyhow!("crc does not match").context("deserializing block"));
+        }
+
+        let block = compression.decompress(block[4..].to_vec())
+            .context("decompressing block")?;
+        if block.len() < 4 {
+            return Err(anyhow!("block too short"));
+        }
+        let count: u32 = BINCODE_OPTIONS
+            .deserialize(&block[block.len() - 4..])
+            .context("deserializing restart count")?;
+        let restarts_start = (count as usize)
+            .checked_mul(4)
+            .and_then(|len| (block.len() - 4).checked_sub(len))
+            .ok_or(anyhow!("corrupted restart count"))?;
+
+        let restarts = block[restarts_start..block.len() - 4]
+            .chunks(4)
+            .map(|restart| BINCODE_OPTIONS.deserialize(restart).context("deserializing restart offset"))
+            .collect::<Result<Vec<u32>>>()?;
+        if restarts.iter().any(|restart| *restart as usize >= restarts_start) {
+            return Err(anyhow!("corrupted restart offset"));
+        }
+
+        Ok(Block { data: block[..restarts_start].to_vec(), restarts })
+    }
+
+    /// reads the block at the current position of the file, None at the end of the file
+    /// returns the block and the number of bytes read
+    pub fn read(file: &mut File, compression: Compression) -> Option<Result<(Block, u64)>> {
+        let mut len_ser = vec![0; 8];
+        if let Err(e) = file.read_exact(&mut len_ser) {
+            return match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => None,
+                _ => Some(Err(anyhow!(e).context("reading length of the block"))),
+            };
+        }
+        Some(Block::read_body(file, &len_ser, compression))
+    }
+
+    fn read_body(file: &mut File, len_ser: &[u8], compression: Compression) -> Result<(Block, u64)> {
+        let len: u64 = BINCODE_OPTIONS
+            .deserialize(len_ser)
+            .context("deserializing block len")?;
+
+        // a block is cut after the entry that takes it over the size
+        if len > BLOCK_SIZE as u64 + MAX_KEY_LEN + MAX_VAL_LEN + 1024 {
+            return Err(anyhow!("corrupted block len"));
+        }
+
+        // +4 for crc
+        let mut block_ser = vec![0; (len + 4) as usize];
+        file.read_exact(&mut block_ser)
+            .context("reading block")?;
+
+        let block = Block::deserialize(&block_ser, compression)?;
+        Ok((block, 8 + 4 + len))
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        self.entries_from(0)
+    }
+
+    /// entries starting at the first one whose key is not smaller than the given key,
+    /// the restart points are binary searched so only the entries after the last smaller one are decoded
+    pub fn seek(&self, key: &[u8]) -> Result<Vec<Entry>> {
+        let (mut low, mut high) = (0, self.restarts.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            let (entry, _) = self.entry_at(self.restarts[mid] as usize, &[])?;
+            if &entry.key[..] < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        // every key before restart point low is smaller
+        let start = low.checked_sub(1).map_or(0, |restart| self.restarts[restart] as usize);
+        let mut entries = self.entries_from(start)?;
+        let smaller = entries.partition_point(|entry| &entry.key[..] < key);
+        entries.drain(..smaller);
+        Ok(entries)
+    }
+
+    /// offset has to be a restart point
+    fn entries_from(&self, mut offset: usize) -> Result<Vec<Entry>> {
+        let mut entries: Vec<Entry> = Vec::new();
+        while offset < self.data.len() {
+            let prev_key = entries.last().map_or(&[][..], |entry| &entry.key[..]);
+            let (entry, next) = self.entry_at(offset, prev_key)?;
+            entries.push(entry);
+            offset = next;
+        }
+        Ok(entries)
+    }
+
+    /// entry at the offset and the offset of the next one
+    fn entry_at(&self, offset: usize, prev_key: &[u8]) -> Result<(Entry, usize)> {
+        let entry_start = offset + 4;
+        let len: u32 = BINCODE_OPTIONS
+            .deserialize(self.data.get(offset..entry_start).context("block entry len out of bounds")?)
+            .context("deserializing block entry len")?;
+        let entry_end = entry_start + len as usize;
+
+        let block_entry: BlockEntry = BINCODE_OPTIONS
+            .deserialize(self.data.get(entry_start..entry_end).context("block entry out of bounds")?)
+            .context("deserializing block entry")?;
+
+        let shared = block_entry.shared as usize;
+        if shared > prev_key.len() {
+            return Err(anyhow!("corrupted shared key len"));
+        }
+        let mut key = prev_key[..shared].to_vec();
+        key.extend(block_entry.suffix);
+
+        let entry = Entry {
+            timestamp: block_entry.timestamp,
+            key,
+            value: block_entry.value,
+            kind: block_entry.kind,
+            expires_at: block_entry.expires_at,
+        };
+        Ok((entry, entry_end))
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Result, Context};
+use bincode::Options;
+use crc::{Crc, CRC_32_JAMCRC};
+use crate::building_blocks::{Entry, BINCODE_OPTIONS};
+use crate::building_blocks::sstable::Compression;
+use super::{BlockEntry, BLOCK_SIZE, RESTART_INTERVAL};
+
+/// collects sorted entries into a block, keys share their prefix with the previous key
+/// except at restart points where they are stored whole
+///
+/// block: |entries|restart offsets(u32 each)|restart count(u32)|
+/// entry: |len(u32)|BlockEntry|
+/// written as: |len(u64)|crc(u32)|compressed block|
+pub struct BlockBuilder {
+    compression: Compression,
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    first_key: Vec<u8>,
+    last_key: Vec<u8>,
+    count: usize,
+}
+
+impl BlockBuilder {
+    pub fn new(compression: Compression) -> Self {
+        BlockBuilder { compression, buf: Vec::new(), restarts: Vec::new(), first_key: Vec::new(), last_key: Vec::new(), count: 0 }
+    }
+
+    pub fn add(&mut self, entry: &Entry) -> Result<()> {
+        let shared = if self.count.is_multiple_of(RESTART_INTERVAL) {
+            self.restarts.push(self.buf.len() as u32);
+            0
+        } else {
+            self.last_key.iter()
+                .zip(&entry.key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+
+        let block_entry = BlockEntry {
+            shared: shared as u32,
+            suffix: entry.key[shared..].to_vec(),
+            timestamp: entry.timestamp,
+            value: entry.value.clone(),
+            kind: entry.kind.clone(),
+            expires_at: entry.expires_at,
+        };
+        let entry_ser = BINCODE_OPTIONS
+            .serialize(&block_entry)
+            .context("serializing block entry")?;
+        let len_ser = BINCODE_OPTIONS
+            .serialize(&(entry_ser.len() as u32))
+            .context("serializing block entry len")?;
+        self.buf.extend(len_ser);
+        self.buf.extend(entry_ser);
+
+        if self.count == 0 {
+            self.first_key = entry.key.clone();
+        }
+        self.last_key = entry.key.clone();
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// true once the block should be written, a single large entry can take it over the size
+    pub fn is_full(&self) -> bool {
+        self.len() >= BLOCK_SIZE
+    }
+
+    /// size of the block if it was finished now, before compression
+    pub fn len(&self) -> usize {
+        self.buf.len() + 4 * self.restarts.len() + 4
+    }
+
+    pub fn first_key(&self) -> &[u8] {
+        &self.first_key
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// serialized block with its length and crc, the builder is empty afterwards
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        let mut block = std::mem::take(&mut self.buf);
+        for restart in &self.restarts {
+            block.extend(BINCODE_OPTIONS.serialize(restart).context("serializing restart offset")?);
+        }
+        block.extend(BINCODE_OPTIONS
+            .serialize(&(self.restarts.len() as u32))
+            .context("serializing restart count")?);
+        let block = self.compression.compress(block)?;
+
+        let crc = Crc::<u32>::new(&CRC_32_JAMCRC).checksum(&block);
+        let mut block_ser = BINCODE_OPTIONS
+            .serialize(&(block.len() as u64))
+            .context("serializing block len")?;
+        block_ser.extend(BINCODE_OPTIONS.serialize(&crc).context("serializing block crc")?);
+        block_ser.extend(block);
+
+        self.restarts.clear();
+        self.count = 0;
+        Ok(block_ser)
+    }
+}
+
+/// entries written as blocks one after another, used for small sets like range tombstones
+pub fn serialize_blocks(entries: &[Entry], compression: Compression) -> Result<Vec<u8>> {
+    let mut blocks = Vec::new();
+    let mut builder = BlockBuilder::new(compression);
+    for entry in entries {
+        builder.add(entry)?;
+        if builder.is_full() {
+            blocks.extend(builder.finish()?);
+        }
+    }
+    if !builder.is_empty() {
+        blocks.extend(builder.finish()?);
+    }
+    Ok(blocks)
+}
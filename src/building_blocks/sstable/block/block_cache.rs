@@ -0,0 +1,71 @@
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, MutexGuard};
+use anyhow::{Result, Context, anyhow};
+use crate::building_blocks::{Cache, CacheStats, Entry, SSTableIteratorMultiFile};
+
+/// decoded blocks by the table they are in and their offset, shared by all readers of an lsm tree
+/// a table gets a new id every time it is loaded, so a new table with the name of a removed one
+/// never sees its blocks, they are evicted once they are not used
+pub struct BlockCache {
+    cache: Mutex<Cache<(u64, u64), CachedBlock>>,
+    next_table: AtomicU64,
+}
+
+#[derive(Clone)]
+struct CachedBlock {
+    entries: Arc<Vec<Entry>>,
+    /// offset of the block after it
+    next_offset: u64,
+}
+
+impl BlockCache {
+    /// capacity is in bytes of keys and values
+    pub fn new(capacity: usize) -> Self {
+        BlockCache { cache: Mutex::new(Cache::new(capacity)), next_table: AtomicU64::new(0) }
+    }
+
+    /// id the blocks of a loaded table are cached under
+    pub fn table_id(&self) -> u64 {
+        self.next_table.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        Ok(self.lock()?.stats())
+    }
+
+    /// versions of the key from the block at the offset on, blocks that are not cached are read with the iterator
+    pub fn versions(&self, table: u64, iter: &mut SSTableIteratorMultiFile, mut offset: u64, key: &[u8]) -> Result<Vec<Entry>> {
+        let mut versions = Vec::new();
+        loop {
+            let cached = self.lock()?.find(&(table, offset)).cloned();
+            let block = match cached {
+                Some(block) => block,
+                None => {
+                    iter.move_iter(offset)?;
+                    let Some(entries) = iter.next_block() else {
+                        return Ok(versions);
+                    };
+                    let entries = entries.context("reading block")?;
+                    let size = entries.iter()
+                        .map(|entry| entry.key.len() + entry.value.as_ref().map_or(0, Vec::len))
+                        .sum();
+                    let block = CachedBlock { entries: Arc::new(entries), next_offset: iter.current_offset };
+                    self.lock()?.add((table, offset), block.clone(), size);
+                    block
+                }
+            };
+
+            let start = block.entries.partition_point(|entry| &entry.key[..] < key);
+            for entry in &block.entries[start..] {
+                if entry.key != key {
+                    return Ok(versions);
+                }
+                versions.push(entry.clone());
+            }
+            offset = block.next_offset;
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Cache<(u64, u64), CachedBlock>>> {
+        self.cache.lock().map_err(|_| anyhow!("block cache lock poisoned"))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::building_blocks::{Entry, EntryKind};
+use crate::building_blocks::sstable::Compression;
+use super::{Block, BlockBuilder, RESTART_INTERVAL};
+
+fn entry(i: usize) -> Entry {
+    Entry {
+        timestamp: i as u128,
+        key: format!("key-{i:03}").into_bytes(),
+        value: Some(i.to_string().into_bytes()),
+        kind: EntryKind::Point,
+        expires_at: None,
+    }
+}
+
+fn build(count: usize, compression: Compression) -> Block {
+    let mut builder = BlockBuilder::new(compression);
+    for i in 0..count {
+        builder.add(&entry(i)).expect("adding entry");
+    }
+    let block_ser = builder.finish().expect("finishing block");
+    Block::deserialize(&block_ser[8..], compression).expect("deserializing block")
+}
+
+#[test]
+fn entries_round_trip() {
+    for compression in [Compression::None, Compression::Lz4, Compression::Snappy, Compression::Zstd] {
+        let block = build(40, compression);
+        let entries = block.entries().expect("reading entries");
+        assert_eq!(entries, (0..40).map(entry).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn keys_share_prefixes() {
+    let mut builder = BlockBuilder::new(Compression::None);
+    let mut framed = 0;
+    for i in 0..RESTART_INTERVAL * 2 {
+        builder.add(&entry(i)).expect("adding entry");
+        framed += entry(i).serialize().expect("serializing entry").len();
+    }
+    assert_eq!(builder.first_key(), b"key-000");
+    assert_eq!(builder.last_key(), format!("key-{:03}", RESTART_INTERVAL * 2 - 1).as_bytes());
+    let block_ser = builder.finish().expect("finishing block");
+    assert!(block_ser.len() < framed);
+    assert!(builder.is_empty());
+}
+
+#[test]
+fn seek() {
+    let block = build(40, Compression::None);
+    for i in 0..40 {
+        let key = format!("key-{i:03}").into_bytes();
+        let entries = block.seek(&key).expect("seeking");
+        assert_eq!(entries, (i..40).map(entry).collect::<Vec<_>>());
+    }
+
+    let entries = block.seek(b"key-0105").expect("seeking between keys");
+    assert_eq!(entries[0], entry(11));
+    assert_eq!(block.seek(b"a").expect("seeking before keys").len(), 40);
+    assert!(block.seek(b"z").expect("seeking after keys").is_empty());
+}
+
+#[test]
+fn corrupted_block() {
+    let mut builder = BlockBuilder::new(Compression::Lz4);
+    builder.add(&entry(0)).expect("adding entry");
+    let mut block_ser = builder.finish().expect("finishing block");
+    block_ser[20] ^= 0xff;
+    assert!(Block::deserialize(&block_ser[8..], Compression::Lz4).is_err());
+}
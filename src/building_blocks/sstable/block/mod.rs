@@ -0,0 +1,31 @@
+#[allow(clippy::module_inception)]
+mod block;
+mod block_builder;
+mod block_cache;
+
+#[cfg(test)]
+mod block_tests;
+
+use serde::{Serialize, Deserialize};
+use crate::building_blocks::EntryKind;
+
+pub use block::Block;
+pub use block_builder::{BlockBuilder, serialize_blocks};
+pub use block_cache::BlockCache;
+
+/// blocks are cut once they grow past this many bytes
+pub static BLOCK_SIZE: usize = 4096;
+
+/// every nth key of a block is stored whole, lookups binary search over these
+pub static RESTART_INTERVAL: usize = 16;
+
+/// entry with the part of the key it shares with the previous entry cut off
+#[derive(Serialize, Deserialize)]
+struct BlockEntry {
+    shared: u32,
+    suffix: Vec<u8>,
+    timestamp: u128,
+    value: Option<Vec<u8>>,
+    kind: EntryKind,
+    expires_at: Option<u128>,
+}
@@ -0,0 +1,69 @@
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+/// codec sstable blocks are compressed with, every table records the one it was written with
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Snappy,
+    Zstd,
+}
+
+/// zstd level, the default trades speed for ratio the same way the library does
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    pub fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(&data)),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(&data)
+                .context("compressing with snappy"),
+            Compression::Zstd => zstd::bulk::compress(&data, ZSTD_LEVEL)
+                .context("compressing with zstd"),
+        }
+    }
+
+    pub fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(&data)
+                .context("decompressing with lz4"),
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(&data)
+                .context("decompressing with snappy"),
+            Compression::Zstd => zstd::decode_all(&data[..])
+                .context("decompressing with zstd"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+
+    #[test]
+    fn round_trip() {
+        let data = b"key-000key-001key-002key-003".repeat(100);
+        for compression in [Compression::None, Compression::Lz4, Compression::Snappy, Compression::Zstd] {
+            let compressed = compression.compress(data.clone()).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len(), "{compression:?} did not compress");
+            }
+            assert_eq!(compression.decompress(compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn corrupted_data() {
+        // a small length up front keeps lz4 from allocating a huge buffer
+        let mut garbage = vec![16, 0, 0, 0];
+        garbage.extend([0xff; 16]);
+        for compression in [Compression::Lz4, Compression::Snappy, Compression::Zstd] {
+            assert!(compression.decompress(garbage.clone()).is_err(), "{compression:?} accepted garbage");
+        }
+    }
+}
@@ -14,59 +14,66 @@ pub struct IndexEntry {
     pub offset: u64,
 }
 
-/// every entry is individually encoded
-/// each entry is written in format: serialized length(8b) + crc(4b) + encoded entry
+/// every entry takes the same space so the index can be binary searched, keys are padded to the longest one
+/// index: |key width(u32)|entries|
+/// entry: |crc(4b)|key len(u32)|key padded to the width|offset(8b)|, crc covers the rest of the entry
+/// there is one entry per block so they are kept in memory until the index is finished
 pub struct IndexBuilder {
     file: File,
-
-    /// tracks at which offset each index entry is written
-    pub (in crate::building_blocks::sstable) index_offset: u64,
+    entries: Vec<IndexEntry>,
 }
 
 impl IndexBuilder {
     pub fn new(file: File) -> Self {
-        IndexBuilder { file, index_offset: 0 }
+        IndexBuilder { file, entries: Vec::new() }
     }
 
-    pub fn add(&mut self, key: &Vec<u8>, offset: u64) -> Result<()> {
-        let entry = IndexEntry { key: key.clone(), offset };
-        let entry_ser = BINCODE_OPTIONS
-            .serialize(&entry)
-            .context("serializing index entry")?;
-
-        let crc = Crc::<u32>::new(&CRC_32_JAMCRC)
-            .checksum(&entry_ser[..]);
-
-        let crc_ser = BINCODE_OPTIONS
-            .serialize(&crc)
-            .context("serializing crc for index entry")?;
-
-        let entry_len: u64 = entry_ser.len() as u64;
-        let len_ser = BINCODE_OPTIONS
-            .serialize(&entry_len)
-            .context("serializing index entry len")?;
+    pub fn add(&mut self, key: &[u8], offset: u64) -> Result<()> {
+        self.entries.push(IndexEntry { key: key.to_vec(), offset });
+        Ok(())
+    }
 
-        self.file.write_all(&len_ser)
-            .context("writing index entry len to the file")?;
+    /// number of entries added so far, the next one is written at this position
+    pub fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
 
-        self.file.write_all(&crc_ser)
-            .context("writing index entry crc to the file")?;
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
 
-        self.file.write_all(&entry_ser)
-            .context("writing index entry to the file")?;
+    /// writes the index, nothing is written before
+    pub fn finish(&mut self) -> Result<()> {
+        let key_width = self.entries.iter()
+            .map(|entry| entry.key.len())
+            .max()
+            .unwrap_or(0) as u32;
 
-        // offset moved by a single entry len
-        self.index_offset +=
-            len_ser.len() as u64 +
-            crc_ser.len() as u64 +
-            entry_ser.len() as u64;
+        let mut index_ser = BINCODE_OPTIONS
+            .serialize(&key_width)
+            .context("serializing index key width")?;
+        for entry in &self.entries {
+            let mut entry_ser = BINCODE_OPTIONS
+                .serialize(&(entry.key.len() as u32))
+                .context("serializing index entry key len")?;
+            entry_ser.extend(&entry.key);
+            entry_ser.resize(4 + key_width as usize, 0);
+            entry_ser.extend(BINCODE_OPTIONS
+                .serialize(&entry.offset)
+                .context("serializing index entry offset")?);
 
-        Ok(())
-    }
+            let crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+                .checksum(&entry_ser[..]);
+            index_ser.extend(BINCODE_OPTIONS
+                .serialize(&crc)
+                .context("serializing crc for index entry")?);
+            index_ser.extend(entry_ser);
+        }
 
-    pub fn finish(&mut self) -> Result<()> {
-        self.file.flush()
-            .context("flushing index file")?;
+        self.file.write_all(&index_ser)
+            .context("writing index to the file")?;
+        self.file.sync_all()
+            .context("syncing index file")?;
         Ok(())
     }
 }
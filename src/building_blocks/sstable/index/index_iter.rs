@@ -8,89 +8,150 @@ use crc::{Crc, CRC_32_JAMCRC};
 use crate::building_blocks::{BINCODE_OPTIONS, MAX_KEY_LEN};
 use super::IndexEntry;
 
+/// reads the fixed-width index written by the IndexBuilder, entries are addressed by their position
 pub struct IndexIterator {
     file: File,
-    pub (in crate::building_blocks::sstable) current_offset: u64,
+
+    /// offset of the first entry in the file
+    start: u64,
+
+    /// keys of all entries are padded to this len
+    key_width: u64,
+
+    /// number of entries in the index
+    len: u64,
+
+    /// position of the entry returned next
+    pub (in crate::building_blocks::sstable) position: u64,
 }
 
 impl IndexIterator {
-    pub fn iter(file: File) -> Self {
-        IndexIterator { file, current_offset: 0 }
+    /// the index takes [start, end) of the file, the rest of the file if end is None
+    pub fn iter(mut file: File, start: u64, end: Option<u64>) -> Result<Self> {
+        let end = match end {
+            Some(end) => end,
+            None => file.metadata().context("getting index file metadata")?.len(),
+        };
+
+        file.seek(SeekFrom::Start(start))
+            .context("seeking to the index")?;
+        let mut key_width_ser = vec![0; 4];
+        file.read_exact(&mut key_width_ser)
+            .context("reading index key width")?;
+        let key_width: u32 = BINCODE_OPTIONS
+            .deserialize(&key_width_ser)
+            .context("deserializing index key width")?;
+        if key_width as u64 > MAX_KEY_LEN {
+            return Err(anyhow!("corrupted index key width"));
+        }
+
+        let mut iter = IndexIterator { file, start: start + 4, key_width: key_width as u64, len: 0, position: 0 };
+        let entries_len = end.checked_sub(iter.start)
+            .ok_or(anyhow!("index ends before its key width"))?;
+        if entries_len % iter.entry_width() != 0 {
+            return Err(anyhow!("corrupted index len"));
+        }
+        iter.len = entries_len / iter.entry_width();
+        Ok(iter)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn rewind(&mut self) -> Result<()> {
-        self.file.rewind()
-            .context("rewiding index file")?;
-        self.current_offset = 0;
-        Ok(())
+        self.move_iter(0)
     }
 
-    /// offset from the beginning of the file
-    pub fn move_iter(&mut self, offset: u64) -> Result<()> {
-        self.file.seek(SeekFrom::Start(offset))
+    /// position of the entry in the index, not the offset in the file
+    pub fn move_iter(&mut self, position: u64) -> Result<()> {
+        if position > self.len {
+            return Err(anyhow!("index position {position} is past the {} entries", self.len));
+        }
+        self.file.seek(SeekFrom::Start(self.start + position * self.entry_width()))
             .context("seeking index file")?;
-        self.current_offset = offset;
+        self.position = position;
         Ok(())
     }
-}
-
-impl Iterator for IndexIterator {
-    type Item = Result<IndexEntry>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut len_ser = vec![0; 8];
-        let res = self.file.read_exact(&mut len_ser);
-        if let Err(e) = res.as_ref() {
-            return match e.kind() {
-                std::io::ErrorKind::UnexpectedEof => None,
-                _ => Some(
-                    Err(res.context("reading index entry len").err().unwrap()))
-            };
+    /// first entry in positions [start, end) whose key is not smaller than the given key,
+    /// the entries are read at once and binary searched
+    pub fn search(&mut self, start: u64, end: u64, key: &[u8]) -> Result<Option<IndexEntry>> {
+        if start > end || end > self.len {
+            return Err(anyhow!("index positions [{start}, {end}) are not in the {} entries", self.len));
         }
+        self.move_iter(start)?;
 
-        let len = BINCODE_OPTIONS
-            .deserialize(&len_ser)
-            .context("deserializing index entry len");
+        let width = self.entry_width() as usize;
+        let mut entries_ser = vec![0; (end - start) as usize * width];
+        self.file.read_exact(&mut entries_ser)
+            .context("reading index entries")?;
+        self.position = end;
 
-        if let Err(e) = len { return Some(Err(e)); }
-        let len: u64 = len.unwrap();
+        let (mut low, mut high) = (0, entries_ser.len() / width);
+        let mut found = None;
+        while low < high {
+            let mid = (low + high) / 2;
+            let entry = self.deserialize(&entries_ser[mid * width..(mid + 1) * width])?;
+            if &entry.key[..] < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+                found = Some(entry);
+            }
+        }
+        Ok(found)
+    }
 
-        // 64kb at most for the key and 8 bytes more for offset
-        if len > MAX_KEY_LEN+8 {
-            let e = anyhow!("corrupted index entry len");
-            return Some(Err(e));
+    /// crc(4b) + key len(4b) + padded key + offset(8b)
+    fn entry_width(&self) -> u64 {
+        16 + self.key_width
+    }
+
+    fn deserialize(&self, entry_ser: &[u8]) -> Result<IndexEntry> {
+        let crc_file: u32 = BINCODE_OPTIONS
+            .deserialize(&entry_ser[..4])
+            .context("deserializing crc for index entry")?;
+        let computed_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+            .checksum(&entry_ser[4..]);
+        if computed_crc != crc_file {
+            return Err(anyhow!("crc does not match for index entry")
+                .context("deserializing index entry"));
         }
 
-        let mut crc_ser = vec![0; 4];
-        let res = self.file.read_exact(&mut crc_ser)
-            .context("reading crc for index entry");
-        if let Err(e) = res { return Some(Err(e)) }
+        let key_len: u32 = BINCODE_OPTIONS
+            .deserialize(&entry_ser[4..8])
+            .context("deserializing index entry key len")?;
+        if key_len as u64 > self.key_width {
+            return Err(anyhow!("corrupted index entry key len"));
+        }
 
-        let crc_file = BINCODE_OPTIONS
-            .deserialize(&crc_ser)
-            .context("deserializing crc for index entry");
-        if let Err(e) = crc_file { return Some(Err(e)) }
-        let crc_file: u32 = crc_file.unwrap();
+        let key_end = 8 + self.key_width as usize;
+        let offset = BINCODE_OPTIONS
+            .deserialize(&entry_ser[key_end..])
+            .context("deserializing index entry offset")?;
+        Ok(IndexEntry { key: entry_ser[8..8 + key_len as usize].to_vec(), offset })
+    }
+}
 
-        let mut entry_ser = vec![0; len as usize];
-        let res = self.file.read_exact(&mut entry_ser)
-            .context("reading index entry");
-        if let Err(e) = res { return Some(Err(e)) }
+impl Iterator for IndexIterator {
+    type Item = Result<IndexEntry>;
 
-        let computed_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
-            .checksum(&entry_ser[..]);
-        if computed_crc != crc_file {
-            let e = anyhow!("crc does not match for index entry")
-                .context("deserializing index entry");
-            return Some(Err(e));
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.len {
+            return None;
         }
 
-        let entry = BINCODE_OPTIONS
-            .deserialize(&entry_ser)
-            .context("deserializing index entry");
-        if let Err(e) = entry { return Some(Err(e)); }
+        let mut entry_ser = vec![0; self.entry_width() as usize];
+        if let Err(e) = self.file.read_exact(&mut entry_ser) {
+            return Some(Err(anyhow!(e).context("reading index entry")));
+        }
 
-        self.current_offset += 12 + len;
-        Some(entry)
+        self.position += 1;
+        Some(self.deserialize(&entry_ser))
     }
 }
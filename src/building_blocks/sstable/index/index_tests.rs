@@ -1,7 +1,4 @@
-use std::{
-    fs::OpenOptions,
-    io::Seek
-};
+use std::fs::OpenOptions;
 use super::{IndexBuilder, IndexIterator};
 
 #[test]
@@ -19,17 +16,16 @@ fn writing() {
         index_builder.add(&i.to_string().into_bytes(), i)
             .expect("error adding index entry");
     }
+    index_builder.finish().expect("error finishing index builder");
 
     // read by offsets
-    let mut file = OpenOptions::new()
+    let file = OpenOptions::new()
         .read(true)
         .open("test-data/valid-index-write")
         .expect("error opening 'valid-index-write'");
 
-    // seek to the last offset
-    file.seek(std::io::SeekFrom::Start(0)).expect("seeking in the 'valid-index-write'");
-
-    let index_iter = IndexIterator::iter(file);
+    let index_iter = IndexIterator::iter(file, 0, None).expect("reading index");
+    assert_eq!(index_iter.len(), 10);
 
     for (i, entry) in index_iter.enumerate() {
         let entry = entry.expect("reading index entry");
@@ -45,15 +41,13 @@ fn read_valid() {
         .open("test-data/valid-index-read")
         .expect("error opening 'valid-index-read'");
 
-    let index_iter = IndexIterator::iter(file);
+    let index_iter = IndexIterator::iter(file, 0, None).expect("reading index");
 
-    let mut index = 0;
-    for entry in index_iter {
+    for (index, entry) in index_iter.enumerate() {
         assert!(entry.is_ok());
         let entry = entry.unwrap();
         assert_eq!(entry.key, index.to_string().into_bytes());
-        assert_eq!(entry.offset, index);
-        index += 1;
+        assert_eq!(entry.offset, index as u64);
     }
 }
 
@@ -64,14 +58,47 @@ fn read_invalid() {
         .open("test-data/invalid-index-read")
         .expect("error opening 'invalid-index-read'");
 
-    let index_iter = IndexIterator::iter(file);
+    let corrupted = match IndexIterator::iter(file, 0, None) {
+        Ok(mut index_iter) => index_iter.any(|entry| entry.is_err()),
+        Err(_) => true,
+    };
+    assert!(corrupted);
+}
+
+#[test]
+fn search() {
+    let file = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .read(true)
+        .create(true)
+        .open("test-data/search-index")
+        .expect("error opening 'search-index'");
+
+    // keys of different lengths are padded to the longest one
+    let keys = ["a", "bb", "bbb", "c", "dddd", "e"];
+    let mut index_builder = IndexBuilder::new(file.try_clone().unwrap());
+    for (i, key) in keys.iter().enumerate() {
+        index_builder.add(key.as_bytes(), i as u64).expect("error adding index entry");
+    }
+    index_builder.finish().expect("error finishing index builder");
+
+    let mut index_iter = IndexIterator::iter(file, 0, None).expect("reading index");
+    let found = |index_iter: &mut IndexIterator, start, end, key: &[u8]| index_iter
+        .search(start, end, key)
+        .expect("searching index")
+        .map(|entry| entry.offset);
 
-    let mut corrupted = false;
-    for entry in index_iter {
-        if entry.is_err() {
-            corrupted = true;
-            break;
-        }
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(found(&mut index_iter, 0, 6, key.as_bytes()), Some(i as u64));
     }
-    assert!(corrupted);
+    assert_eq!(found(&mut index_iter, 0, 6, b"b"), Some(1));
+    assert_eq!(found(&mut index_iter, 0, 6, b"cc"), Some(4));
+    assert_eq!(found(&mut index_iter, 0, 6, b"f"), None);
+    assert_eq!(found(&mut index_iter, 2, 4, b"a"), Some(2));
+    assert_eq!(found(&mut index_iter, 2, 4, b"d"), None);
+    assert!(index_iter.search(2, 7, b"a").is_err());
+
+    // iteration goes on after the searched entries
+    assert_eq!(index_iter.next().unwrap().expect("reading index entry").key, b"dddd");
 }
@@ -0,0 +1,109 @@
+use std::{fs::{read, File}, io::Read, path::Path};
+use anyhow::{Context, Result, anyhow};
+use bincode::Options;
+use crc::{Crc, CRC_32_JAMCRC};
+use serde::Deserialize;
+use crate::building_blocks::{Entry, EntryKind, BINCODE_OPTIONS};
+
+/// header of a single file table of format version 1, five offsets with the data offset first
+const HEADER_SIZE: usize = 40;
+
+/// entry of format version 1, there was no expiry and no range delete
+#[derive(Deserialize)]
+struct EntryV1 {
+    timestamp: u128,
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+/// entries of a multi file table of format version 1, None if the table records its version
+pub(super) fn multi_file_entries(table_dir: &str) -> Result<Option<Vec<Entry>>> {
+    if Path::new(&format!("{table_dir}/version")).is_file() {
+        return Ok(None);
+    }
+    let data = read(format!("{table_dir}/data")).context("reading data file")?;
+    entries(&data).map(Some)
+}
+
+/// entries of a single file table of format version 1, None if the table records its version
+/// the data is between the header and the filter
+pub(super) fn single_file_entries(table_dir: &str) -> Result<Option<Vec<Entry>>> {
+    let path = format!("{table_dir}/data");
+    let mut header = [0; HEADER_SIZE];
+    File::open(&path)
+        .context("opening data file")?
+        .read_exact(&mut header)
+        .context("reading header")?;
+    let offsets: [u64; 5] = BINCODE_OPTIONS
+        .deserialize(&header)
+        .context("deserializing header")?;
+    // a versioned header starts with the version and not with the offset of the data right after the header
+    if offsets[0] != HEADER_SIZE as u64 {
+        return Ok(None);
+    }
+
+    let file = read(&path).context("reading data file")?;
+    let data = usize::try_from(offsets[1]).ok()
+        .and_then(|filter_offset| file.get(HEADER_SIZE..filter_offset))
+        .ok_or_else(|| anyhow!("filter offset {} is out of the table", offsets[1]))?;
+    entries(data).map(Some)
+}
+
+/// |Len(u64)|CRC(u32)|EntryV1| records back to back, sorted by key
+fn entries(data: &[u8]) -> Result<Vec<Entry>> {
+    let mut entries = records(data).collect::<Result<Vec<_>>>()?;
+    // keys were cut to a byte per char, tables with other than ascii keys may be out of order
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(entries)
+}
+
+/// |Len(u64)|CRC(u32)|EntryV1| records in the order they were written, up to the end of the data or len 0
+/// the records of sstables and WAL segments of format version 1 are the same
+pub(crate) fn records(data: &[u8]) -> impl Iterator<Item = Result<Entry>> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let record = match data.get(offset..offset + 12) {
+            // zeros after the last record are the end of a preallocated segment
+            Some(record) if record[..8] == [0; 8] => return None,
+            Some(record) => record,
+            None if offset >= data.len() => return None,
+            None => return Some(Err(anyhow!("entry at offset {offset} is cut off"))),
+        };
+        let entry = record_at(data, offset, record);
+        offset = match &entry {
+            Ok((_, end)) => *end,
+            // nothing after a damaged record can be found
+            Err(_) => data.len(),
+        };
+        Some(entry.map(|(entry, _)| entry))
+    })
+}
+
+/// the entry of the record at the offset and the offset after it
+fn record_at(data: &[u8], offset: usize, record: &[u8]) -> Result<(Entry, usize)> {
+    let len: u64 = BINCODE_OPTIONS
+        .deserialize(&record[..8])
+        .context("deserializing entry len")?;
+    let crc: u32 = BINCODE_OPTIONS
+        .deserialize(&record[8..])
+        .context("deserializing entry crc")?;
+
+    let end = usize::try_from(len).ok().and_then(|len| (offset + 12).checked_add(len));
+    let entry_ser = end.and_then(|end| data.get(offset + 12..end))
+        .ok_or_else(|| anyhow!("entry at offset {offset} is cut off"))?;
+    if Crc::<u32>::new(&CRC_32_JAMCRC).checksum(entry_ser) != crc {
+        return Err(anyhow!("crc does not match at offset {offset}"));
+    }
+
+    let entry: EntryV1 = BINCODE_OPTIONS
+        .deserialize(entry_ser)
+        .context("deserializing entry")?;
+    let entry = Entry {
+        timestamp: entry.timestamp,
+        key: entry.key,
+        value: entry.value,
+        kind: EntryKind::Point,
+        expires_at: None,
+    };
+    Ok((entry, offset + 12 + entry_ser.len()))
+}
@@ -1,20 +1,28 @@
+mod block;
+mod compression;
 mod index;
+mod legacy;
 mod sstable_config;
 mod sstable_multifile;
 mod sstable_singlefile;
 mod summary;
+mod table_cursor;
 
+pub use block::BlockCache;
+pub use compression::Compression;
 pub use index::IndexBuilder;
 pub use index::IndexEntry;
 pub use index::IndexIterator;
-pub use sstable_config::{FileOrganization, LSMTreeUnderlying, SSTableConfig, MF, SF};
+pub use sstable_config::{FileOrganization, LSMTreeUnderlying, SSTableConfig, TableReader, MF, SF};
 pub use sstable_multifile::{
     SSTableBuilderMultiFile, SSTableIteratorMultiFile, SSTableReaderMultiFile,
 };
 pub use sstable_singlefile::{
-    IndexIteratorSingleFile, SSTableBuilderSingleFile, SSTableIteratorSingleFile,
+    SSTableBuilderSingleFile, SSTableIteratorSingleFile,
     SSTableReaderSingleFile,
 };
 pub use summary::SummaryBuilder;
 pub use summary::SummaryEntry;
 pub use summary::SummaryIterator;
+pub use table_cursor::{SSTableCursor, SSTableRanges};
+pub(crate) use legacy::records as legacy_records;
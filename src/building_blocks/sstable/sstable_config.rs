@@ -1,4 +1,12 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
 use serde::{Serialize,Deserialize};
+use crate::building_blocks::{BloomFilter, Entry, IndexIterator, MerkleRoot};
+use super::{
+    legacy, BlockCache, Compression, SSTableRanges,
+    SSTableBuilderMultiFile, SSTableBuilderSingleFile,
+    SSTableReaderMultiFile, SSTableReaderSingleFile,
+};
 // TODO: derive serialization for RON?
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -6,11 +14,15 @@ pub struct SSTableConfig {
     pub file_organization: FileOrganization,
 
     // TODO: assert that this is > 2
-    /// every n key make an entry in the summary
+    /// every n blocks make an entry in the summary
     pub summary_nth: u64,
 
     /// filter false positive probability
     pub filter_fp_prob: f64,
+
+    /// codec of the blocks
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 #[derive(Clone)]
@@ -27,6 +39,211 @@ pub struct SF(());
 #[derive(PartialEq)]
 pub struct MF(());
 
-pub trait LSMTreeUnderlying {}
-impl LSMTreeUnderlying for SF {}
-impl LSMTreeUnderlying for MF {}
+/// opened table the lsm tree keeps between lookups, scans and merges
+pub trait TableReader: SSTableRanges + Sized + 'static {
+    fn load(table_dir: &str) -> Result<Self>;
+
+    fn filter(&self) -> &BloomFilter;
+
+    /// first and last key of the table, including the ends of the range tombstones
+    fn range(&self) -> (&[u8], &[u8]);
+
+    /// range tombstones of the table, read when it is loaded
+    fn range_tombstones(&self) -> &[Entry];
+
+    /// versions of the key in the table newest first
+    fn get(&mut self, key: &[u8]) -> Result<Vec<Entry>>;
+
+    /// all entries of the table in the order they are stored, the iterator does not use the files of the reader
+    fn entries(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>>;
+
+    /// point lookups read their blocks through the cache
+    fn with_block_cache(self, cache: Arc<BlockCache>) -> Self;
+
+    /// one entry per data block, with the first key of the block and its offset
+    fn index(&self) -> Result<IndexIterator>;
+
+    /// root of the merkle tree over the values, written with the table
+    fn merkle_root(&self) -> Result<MerkleRoot>;
+
+    /// codec of the data and range tombstone blocks
+    fn compression(&self) -> Compression;
+}
+
+impl TableReader for SSTableReaderSingleFile {
+    fn load(table_dir: &str) -> Result<Self> {
+        SSTableReaderSingleFile::load(table_dir)
+    }
+
+    fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+
+    fn range(&self) -> (&[u8], &[u8]) {
+        SSTableReaderSingleFile::range(self)
+    }
+
+    fn range_tombstones(&self) -> &[Entry] {
+        SSTableReaderSingleFile::range_tombstones(self)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Vec<Entry>> {
+        SSTableReaderSingleFile::get(self, key)
+    }
+
+    fn entries(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        Ok(Box::new(self.iter()?))
+    }
+
+    fn with_block_cache(self, cache: Arc<BlockCache>) -> Self {
+        SSTableReaderSingleFile::with_block_cache(self, cache)
+    }
+
+    fn index(&self) -> Result<IndexIterator> {
+        self.index_iter()
+    }
+
+    fn merkle_root(&self) -> Result<MerkleRoot> {
+        SSTableReaderSingleFile::merkle_root(self)
+    }
+
+    fn compression(&self) -> Compression {
+        self.header.compression
+    }
+}
+
+impl TableReader for SSTableReaderMultiFile {
+    fn load(table_dir: &str) -> Result<Self> {
+        SSTableReaderMultiFile::load(table_dir)
+    }
+
+    fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+
+    fn range(&self) -> (&[u8], &[u8]) {
+        SSTableReaderMultiFile::range(self)
+    }
+
+    fn range_tombstones(&self) -> &[Entry] {
+        SSTableReaderMultiFile::range_tombstones(self)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Vec<Entry>> {
+        SSTableReaderMultiFile::get(self, key)
+    }
+
+    fn entries(&self) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        Ok(Box::new(self.iter()?))
+    }
+
+    fn with_block_cache(self, cache: Arc<BlockCache>) -> Self {
+        SSTableReaderMultiFile::with_block_cache(self, cache)
+    }
+
+    fn index(&self) -> Result<IndexIterator> {
+        self.index_iter()
+    }
+
+    fn merkle_root(&self) -> Result<MerkleRoot> {
+        SSTableReaderMultiFile::merkle_root(self)
+    }
+
+    fn compression(&self) -> Compression {
+        SSTableReaderMultiFile::compression(self)
+    }
+}
+
+/// table operations the lsm tree needs independent of the file organization
+pub trait LSMTreeUnderlying {
+    type Reader: TableReader;
+
+    /// all entries of the table in the order they are stored, range tombstones are not included
+    fn entries(table_dir: &str) -> Result<Box<dyn Iterator<Item = Result<Entry>>>>;
+
+    fn range_tombstones(table_dir: &str) -> Result<Vec<Entry>>;
+
+    /// first and last key of the table, including the ends of the range tombstones
+    fn key_range(table_dir: &str) -> Result<(Vec<u8>, Vec<u8>)>;
+
+    /// writes entries sorted by key into a new table in the data dir
+    /// range tombstones can be anywhere between them
+    fn write(data_dir: &str, name: &str, entries: Vec<Entry>, fp_prob: f64, summary_nth: u64, compression: Compression) -> Result<()>;
+
+    /// entries of a table written before tables recorded their format version, None if it records one
+    fn legacy_entries(table_dir: &str) -> Result<Option<Vec<Entry>>>;
+}
+
+impl LSMTreeUnderlying for SF {
+    type Reader = SSTableReaderSingleFile;
+
+    fn entries(table_dir: &str) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        let reader = SSTableReaderSingleFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        Ok(Box::new(reader.iter()?))
+    }
+
+    fn range_tombstones(table_dir: &str) -> Result<Vec<Entry>> {
+        let reader = SSTableReaderSingleFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        Ok(reader.range_tombstones().to_vec())
+    }
+
+    fn key_range(table_dir: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let reader = SSTableReaderSingleFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        let (first_key, last_key) = reader.range();
+        Ok((first_key.to_vec(), last_key.to_vec()))
+    }
+
+    fn write(data_dir: &str, name: &str, entries: Vec<Entry>, fp_prob: f64, summary_nth: u64, compression: Compression) -> Result<()> {
+        let mut builder = SSTableBuilderSingleFile::new(data_dir, name, entries.len() as u64, fp_prob, summary_nth)
+            .context("creating single file builder")?
+            .with_compression(compression);
+        for entry in entries {
+            builder.insert(entry).context("inserting entry")?;
+        }
+        builder.finish_data().context("finishing singlefile builder")
+    }
+
+    fn legacy_entries(table_dir: &str) -> Result<Option<Vec<Entry>>> {
+        legacy::single_file_entries(table_dir)
+    }
+}
+
+impl LSMTreeUnderlying for MF {
+    type Reader = SSTableReaderMultiFile;
+
+    fn entries(table_dir: &str) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+        let reader = SSTableReaderMultiFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        Ok(Box::new(reader.iter()?))
+    }
+
+    fn range_tombstones(table_dir: &str) -> Result<Vec<Entry>> {
+        let reader = SSTableReaderMultiFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        Ok(reader.range_tombstones().to_vec())
+    }
+
+    fn key_range(table_dir: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let reader = SSTableReaderMultiFile::load(table_dir)
+            .with_context(|| format!("loading {table_dir}"))?;
+        let (first_key, last_key) = reader.range();
+        Ok((first_key.to_vec(), last_key.to_vec()))
+    }
+
+    fn write(data_dir: &str, name: &str, entries: Vec<Entry>, fp_prob: f64, summary_nth: u64, compression: Compression) -> Result<()> {
+        let mut builder = SSTableBuilderMultiFile::new(data_dir, name, entries.len() as u64, fp_prob, summary_nth)
+            .context("creating multifile builder")?
+            .with_compression(compression);
+        for entry in entries {
+            builder.insert(entry).context("inserting entry")?;
+        }
+        builder.finish().context("finishing multifile builder")
+    }
+
+    fn legacy_entries(table_dir: &str) -> Result<Option<Vec<Entry>>> {
+        legacy::multi_file_entries(table_dir)
+    }
+}
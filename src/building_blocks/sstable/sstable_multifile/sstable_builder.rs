@@ -4,36 +4,56 @@ use std::{
         create_dir,
         OpenOptions
     },
-    io::{Write, Seek},
-    rc::Rc
+    io::{Write, Seek}
 };
 use anyhow::{Result, Context};
+use bincode::Options;
 use crate::building_blocks::{
     SummaryBuilder,
     IndexBuilder,
-    Entry, BloomFilter, MerkleRoot
+    Entry, BloomFilter, MerkleRoot,
+    BINCODE_OPTIONS, FORMAT_VERSION
 };
+use crate::building_blocks::sstable::{block::{BlockBuilder, serialize_blocks}, Compression};
+use crate::building_blocks::sstable::summary::table_range;
+use crate::utils::helpers::sync_dir;
 use super::SSTableIteratorMultiFile;
 
 /// SSTable builder where aiding structures are in a separate files
 pub struct SSTableBuilderMultiFile {
+    /// directory of the table and the one it is in, synced once all files of the table are
+    data_dir: String,
+    dir: String,
     index: IndexBuilder,
     summary: SummaryBuilder,
     metadata_file: File,
     filter: BloomFilter,
     filter_file: File,
     sstable_file: File,
+    range_tombstones_file: File,
+    compression_file: File,
+
+    /// codec of the data and range tombstone blocks
+    compression: Compression,
+
+    /// block currently being filled with entries
+    block: BlockBuilder,
+
+    /// written to their own file when the table is finished
+    range_tombstones: Vec<Entry>,
     sstable_offset: u64,
+
+    /// index position the current summary range starts at
     summary_offset: u64,
     summary_nth: u64,
-    entries_written: u64,
+    blocks_written: u64,
 
-    // the first entry in the current range of the summary
-    first_entry_range: Option<Rc<Entry>>,
+    // the first key in the current range of the summary
+    first_key_range: Option<Vec<u8>>,
 
-    // need to keep track in order to be able to write total range and last entry in the current range
-    first_entry_written: Option<Rc<Entry>>,
-    last_entry_written: Option<Rc<Entry>>,
+    // need to keep track in order to be able to write total range and last key in the current range
+    first_key_written: Option<Vec<u8>>,
+    last_key_written: Option<Vec<u8>>,
 }
 
 impl SSTableBuilderMultiFile {
@@ -41,7 +61,7 @@ impl SSTableBuilderMultiFile {
     /// data_dir - directory where SSTables are stored
     /// generation - generation of the SSTable (name of the directory where this SSTable is going to be written to)
     /// filter_fp_prob - filter false positive probability
-    /// summary_nth - from SSTable config - how many entries should summary have
+    /// summary_nth - from SSTable config - how many blocks each summary entry covers
     pub fn new(data_dir: &str, generation: &str, item_count: u64, filter_fp_prob: f64, summary_nth: u64) -> Result<Self> {
         let dir_path = format!("{}/{}", data_dir, generation);
         create_dir(&dir_path)
@@ -52,108 +72,169 @@ impl SSTableBuilderMultiFile {
         let summary_file = create_file(&dir_path, "summary")?;
         let filter_file = create_file(&dir_path, "filter")?;
         let metadata_file = create_file(&dir_path, "metadata")?;
+        let range_tombstones_file = create_file(&dir_path, "range_tombstones")?;
+        let compression_file = create_file(&dir_path, "compression")?;
+
+        let version_ser = BINCODE_OPTIONS
+            .serialize(&FORMAT_VERSION)
+            .context("serializing format version")?;
+        let mut version_file = create_file(&dir_path, "version")?;
+        version_file.write_all(&version_ser)
+            .context("writing format version")?;
+        version_file.sync_all()
+            .context("syncing format version")?;
 
         let index = IndexBuilder::new(index_file);
         let summary = SummaryBuilder::new(summary_file);
         let filter = BloomFilter::new(item_count, filter_fp_prob);
 
         Ok(SSTableBuilderMultiFile {
+            data_dir: data_dir.to_string(),
+            dir: dir_path,
             index,
             summary,
             metadata_file,
             filter,
             filter_file,
             sstable_file,
+            range_tombstones_file,
+            compression_file,
+            compression: Compression::None,
+            block: BlockBuilder::new(Compression::None),
+            range_tombstones: Vec::new(),
             sstable_offset: 0,
             summary_offset: 0,
             summary_nth,
-            entries_written: 0,
-            first_entry_range: None,
-            first_entry_written: None,
-            last_entry_written: None,
+            blocks_written: 0,
+            first_key_range: None,
+            first_key_written: None,
+            last_key_written: None,
         })
     }
 
+    /// codec the blocks are compressed with, has to be set before any entry is inserted
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self.block = BlockBuilder::new(compression);
+        self
+    }
+
+    /// range tombstones can be inserted in any order
     pub fn insert(&mut self, entry: Entry) -> Result<()> {
-        self.entries_written += 1;
-        let entry = Rc::new(entry);
-        let entry_ser = entry.serialize()?;
+        if entry.is_range_delete() {
+            self.range_tombstones.push(entry);
+            return Ok(());
+        }
+
+        self.filter.add(&entry.key)?;
+
+        self.block.add(&entry)
+            .context("adding entry to the block")?;
 
         // only happens once
-        if self.first_entry_written.is_none() {
-            self.first_entry_written = Some(Rc::clone(&entry));
+        if self.first_key_written.is_none() {
+            self.first_key_written = Some(entry.key.clone());
         }
+        self.last_key_written = Some(entry.key);
 
-        self.sstable_file.write_all(&entry_ser)
-            .context("writing entry into the sstable file")?;
+        if self.block.is_full() {
+            self.write_block()?;
+        }
 
-        self.filter.add(&entry.key)?;
+        Ok(())
+    }
 
-        self.index.add(&entry.key, self.sstable_offset)
-            .context("adding index entry")?;
+    /// the index entry of a block holds its last key
+    fn write_block(&mut self) -> Result<()> {
+        if self.first_key_range.is_none() {
+            self.first_key_range = Some(self.block.first_key().to_vec());
+        }
+        let last_key = self.block.last_key().to_vec();
+        let block_ser = self.block.finish()?;
 
-        self.sstable_offset += entry_ser.len() as u64;
+        self.sstable_file.write_all(&block_ser)
+            .context("writing block into the sstable file")?;
 
-        self.last_entry_written = Some(Rc::clone(&entry));
+        self.index.add(&last_key, self.sstable_offset)
+            .context("adding index entry")?;
 
-        if self.first_entry_range.is_none() {
-            self.first_entry_range = Some(Rc::clone(&entry));
-        }
+        self.sstable_offset += block_ser.len() as u64;
+        self.blocks_written += 1;
 
-        if self.entries_written as u64 % self.summary_nth == 0 {
+        if self.blocks_written.is_multiple_of(self.summary_nth) {
             self.summary.add(
-                &self.first_entry_range.as_ref().unwrap().key,
-                &self.last_entry_written.as_ref().unwrap().key,
+                &self.first_key_range.take().unwrap(),
+                &last_key,
                 self.summary_offset)
                 .context("adding summary entry")?;
-            self.first_entry_range = None;
-            self.summary_offset = self.index.index_offset;
+            self.summary_offset = self.index.len();
         }
 
         Ok(())
     }
 
     /// write the last incomplete entry in the summary and the total range
-    /// flush the filter to the file
+    /// write the filter, the range tombstones and the compression to the files
+    /// every file and the directory are synced, the table survives a crash once this returns
     pub fn finish(&mut self) -> Result<()> {
-        if self.summary_offset != self.index.index_offset {
-            assert!(self.first_entry_range.is_some());
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+
+        if self.summary_offset != self.index.len() {
+            assert!(self.first_key_range.is_some());
             self.summary.add(
-                &self.first_entry_range.as_ref().unwrap().key,
-                &self.last_entry_written.as_ref().unwrap().key,
+                self.first_key_range.as_ref().unwrap(),
+                self.last_key_written.as_ref().unwrap(),
                 self.summary_offset)
                 .context("adding incomplete last summary entry")?;
         }
 
-        assert!(self.first_entry_written.is_some());
-        assert!(self.last_entry_written.is_some());
-        self.summary.total_range(&self.first_entry_written.as_ref().unwrap().key, &self.last_entry_written.as_ref().unwrap().key)
+        let points = self.first_key_written.as_deref()
+            .zip(self.last_key_written.as_deref());
+        let (first_key, last_key) = table_range(points, &self.range_tombstones)
+            .context("sstable without entries")?;
+        self.summary.total_range(&first_key, &last_key)
             .context("writing total range and last entry in the current range")?;
 
+        self.range_tombstones_file.write_all(&serialize_blocks(&self.range_tombstones, self.compression)?)
+            .context("writing range tombstones")?;
+        self.range_tombstones_file.sync_all()
+            .context("syncing range tombstones")?;
+
+        let compression_ser = BINCODE_OPTIONS
+            .serialize(&self.compression)
+            .context("serializing compression")?;
+        self.compression_file.write_all(&compression_ser)
+            .context("writing compression")?;
+        self.compression_file.sync_all()
+            .context("syncing compression")?;
+
         self.filter.write_to_file(&mut self.filter_file)
             .context("writing filter to the file")?;
 
         self.generate_meta()
             .context("generating meta")?;
 
-        self.metadata_file.flush()
-            .context("flushing metadata to the file")?;
+        self.metadata_file.sync_all()
+            .context("syncing metadata")?;
 
-        self.sstable_file.flush()
-            .context("flushing sstable to the file")?;
+        self.sstable_file.sync_all()
+            .context("syncing sstable")?;
 
-        self.filter_file.flush()
-            .context("flushing filter to the file")?;
+        self.filter_file.sync_all()
+            .context("syncing filter")?;
 
         self.index.finish()?;
 
-        Ok(())
+        sync_dir(&self.dir)?;
+        sync_dir(&self.data_dir)
     }
 
     fn generate_meta(&mut self) -> Result<()> {
         self.sstable_file.rewind().context("rewinding sstable file")?;
         let iter_fd = self.sstable_file.try_clone().context("cloning sstable file")?;
-        let iter = SSTableIteratorMultiFile::iter(iter_fd);
+        let iter = SSTableIteratorMultiFile::iter(iter_fd, self.compression);
 
         let mut data = Vec::with_capacity(self.filter.item_count as usize);
         for entry in iter {
@@ -1,74 +1,82 @@
 use std::{
     fs::File,
-    io::{Seek, SeekFrom, Read}
+    io::{Seek, SeekFrom},
+    vec
 };
-use anyhow::{Result, Context, anyhow};
-use bincode::Options;
-use crate::building_blocks::{Entry, BINCODE_OPTIONS, MAX_KEY_LEN, MAX_VAL_LEN};
+use anyhow::{Result, Context};
+use crate::building_blocks::Entry;
+use crate::building_blocks::sstable::{block::Block, Compression};
 
+/// reads the data a block at a time, entries of the last block read are buffered
 pub struct SSTableIteratorMultiFile {
     sstable_file: File,
+    compression: Compression,
+    /// offset of the next block to be read
     pub (in crate::building_blocks::sstable) current_offset: u64,
+    /// blocks are not read past this offset, the end of the file if None
+    pub (in crate::building_blocks::sstable) end_offset: Option<u64>,
+    block: vec::IntoIter<Entry>,
 }
 
 impl SSTableIteratorMultiFile {
-    pub fn iter(file: File) -> Self {
-        SSTableIteratorMultiFile { sstable_file: file, current_offset: 0 }
+    /// the compression has to be the one the table was written with
+    pub fn iter(file: File, compression: Compression) -> Self {
+        SSTableIteratorMultiFile { sstable_file: file, compression, current_offset: 0, end_offset: None, block: Vec::new().into_iter() }
     }
 
     /// move to the beginning of the file
     pub fn rewind(&mut self) -> Result<()> {
-        self.sstable_file.rewind()
-            .context("rewinding sstable file")?;
-        self.current_offset = 0;
-        Ok(())
+        self.move_iter(0)
     }
 
-    /// offset from the beginning of the file
+    /// offset from the beginning of the file, has to be the start of a block
     pub fn move_iter(&mut self, offset: u64) -> Result<()> {
         self.sstable_file.seek(SeekFrom::Start(offset))
             .context("seeking sstable file")?;
         self.current_offset = offset;
+        self.block = Vec::new().into_iter();
+        Ok(())
+    }
+
+    /// moves to the block at the offset and skips its entries smaller than the key
+    pub fn seek(&mut self, offset: u64, key: &[u8]) -> Result<()> {
+        self.move_iter(offset)?;
+        if let Some(block) = self.read_block() {
+            self.block = block?.seek(key)?.into_iter();
+        }
         Ok(())
     }
+
+    /// all entries of the next block, the entries of the current one that were not returned are skipped
+    pub fn next_block(&mut self) -> Option<Result<Vec<Entry>>> {
+        self.block = Vec::new().into_iter();
+        self.read_block().map(|block| block?.entries())
+    }
+
+    fn read_block(&mut self) -> Option<Result<Block>> {
+        if self.end_offset.is_some_and(|end| self.current_offset >= end) {
+            return None;
+        }
+        let res = Block::read(&mut self.sstable_file, self.compression)?;
+        Some(res.map(|(block, len)| {
+            self.current_offset += len;
+            block
+        }))
+    }
 }
 
 impl Iterator for SSTableIteratorMultiFile {
     type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut len_ser = vec![0; 8];
-        let res = self.sstable_file.read_exact(&mut len_ser);
-        if let Err(e) = res.as_ref() {
-            return match e.kind() {
-                std::io::ErrorKind::UnexpectedEof => return None,
-                _ => Some(Err(res.context("reading length of the entry").err().unwrap()))
+        loop {
+            if let Some(entry) = self.block.next() {
+                return Some(Ok(entry));
+            }
+            match self.next_block()? {
+                Ok(entries) => self.block = entries.into_iter(),
+                Err(e) => return Some(Err(e)),
             }
         }
-
-        let len = BINCODE_OPTIONS
-            .deserialize(&len_ser[..])
-            .context("deserializing entry len");
-        if let Err(e) = len { return Some(Err(e)); }
-        let len: u64 = len.unwrap();
-
-        if len > (MAX_KEY_LEN+4+MAX_VAL_LEN) {
-            let e = anyhow!("corrupted entry len");
-            return Some(Err(e));
-        }
-
-        // +4 for crc
-        let mut entry_ser = vec![0; (len+4) as usize];
-        let res = self.sstable_file.read_exact(&mut entry_ser)
-            .context("reading entry");
-        if let Err(e) = res { return Some(Err(e)); }
-
-        let entry = Entry::deserialize(&entry_ser[..])
-            .context("deserializing entry");
-        if let Err(e) = entry { return Some(Err(e)); }
-        let entry = entry.unwrap();
-
-        self.current_offset += 8 + 4 + len;
-        Some(Ok(entry))
     }
 }
@@ -1,21 +1,38 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::Seek
+    fs::{read, File, OpenOptions},
+    io::{Read, Seek},
+    path::Path,
+    sync::Arc
 };
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, anyhow};
+use bincode::Options;
 use super::SSTableIteratorMultiFile;
-use crate::building_blocks::{IndexIterator, SummaryIterator, SummaryEntry, BloomFilter, Entry};
+use crate::building_blocks::sstable::{summary::find_range, BlockCache, Compression};
+use crate::building_blocks::{IndexIterator, SummaryIterator, SummaryEntry, BloomFilter, Entry, MerkleRoot, BINCODE_OPTIONS, FORMAT_VERSION};
 
+/// the filter, the summary and the range tombstones are read when the table is loaded and kept in memory
 pub struct SSTableReaderMultiFile {
+    dir: String,
     pub filter: BloomFilter,
     index_file: File,
+    /// used by point lookups, scans get their own
+    index: IndexIterator,
     summary_file: File,
     metadata_file: File,
     sstable_file: File,
+    compression: Compression,
+    summary: Vec<SummaryEntry>,
+    range: SummaryEntry,
+    range_tombstones: Vec<Entry>,
+    /// blocks read by point lookups are kept there with the id of the table
+    block_cache: Option<(Arc<BlockCache>, u64)>,
 }
 
 impl SSTableReaderMultiFile {
+    /// tables of another format version can not be read
     pub fn load(sstabel_dir: &str) -> Result<Self> {
+        check_version(sstabel_dir)?;
+
         let filter_file = open_file(sstabel_dir, "filter")
             .context("opening filter file")?;
 
@@ -34,28 +51,124 @@ impl SSTableReaderMultiFile {
         let metadata_file = open_file(sstabel_dir, "metadata")
             .context("opening metadata file")?;
 
+        let range_tombstones_file = open_file(sstabel_dir, "range_tombstones")
+            .context("opening range tombstones file")?;
+
+        let compression = BINCODE_OPTIONS
+            .deserialize(&read(format!("{sstabel_dir}/compression")).context("reading compression")?)
+            .context("deserializing compression")?;
+
+        let index = IndexIterator::iter(index_file.try_clone().context("cloning index fd")?, 0, None)
+            .context("reading index")?;
+
+        let (summary_iter, range) = SummaryIterator::iter(summary_file.try_clone().context("cloning summary fd")?)
+            .context("reading summary")?;
+        let summary = summary_iter.collect::<Result<Vec<_>>>()
+            .context("reading summary entry")?;
+
+        let range_tombstones = SSTableIteratorMultiFile::iter(range_tombstones_file, compression)
+            .collect::<Result<Vec<_>>>()
+            .context("reading range tombstone")?;
 
         Ok(SSTableReaderMultiFile {
+            dir: sstabel_dir.to_string(),
             filter,
             index_file,
+            index,
             summary_file,
             sstable_file,
-            metadata_file
+            metadata_file,
+            compression,
+            summary,
+            range,
+            range_tombstones,
+            block_cache: None,
         })
     }
 
+    /// point lookups read their blocks through the cache
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        let table = cache.table_id();
+        self.block_cache = Some((cache, table));
+        self
+    }
+
+    /// all range tombstones of the table, they are not part of the data
+    pub fn range_tombstones(&self) -> &[Entry] {
+        &self.range_tombstones
+    }
+
+    /// codec of the data and range tombstone blocks
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// root of the merkle tree over the values, the metadata file holds only it
+    pub fn merkle_root(&self) -> Result<MerkleRoot> {
+        let mut fd = self.metadata_file.try_clone()
+            .context("cloning metadata fd")?;
+        fd.rewind().context("rewinding metadata fd")?;
+        let mut meta = Vec::new();
+        fd.read_to_end(&mut meta)
+            .context("reading metadata")?;
+        MerkleRoot::deserialize(&meta)
+    }
+
+    /// first and last key of the table
+    pub fn range(&self) -> (&[u8], &[u8]) {
+        (&self.range.first_key, &self.range.last_key)
+    }
+
+    /// versions of the key newest first, the summary and the index are binary searched
+    /// so only the block holding the key is read, and the next ones if its versions go on there
+    pub fn get(&mut self, key: &[u8]) -> Result<Vec<Entry>> {
+        if !self.filter.check(key).context("checking filter")? {
+            return Ok(vec![]);
+        }
+        let Some((start, end)) = find_range(&self.summary, key) else {
+            return Ok(vec![]);
+        };
+        let end = end.unwrap_or(self.index.len());
+        let Some(index_entry) = self.index.search(start, end, key).context("searching index")? else {
+            return Ok(vec![]);
+        };
+
+        let mut iter = self.shared_iter().context("getting sstable iter")?;
+        if let Some((cache, table)) = &self.block_cache {
+            return cache.versions(*table, &mut iter, index_entry.offset, key)
+                .context("reading versions through the block cache");
+        }
+        iter.seek(index_entry.offset, key).context("seeking sstable iter")?;
+        let mut versions = Vec::new();
+        for entry in iter {
+            let entry = entry.context("reading sstable entry")?;
+            if entry.key != key {
+                break;
+            }
+            versions.push(entry);
+        }
+        Ok(versions)
+    }
+
+    /// the iterator opens the data file again, so it does not share the file offset with the reader
     pub fn iter(&self) -> Result<SSTableIteratorMultiFile> {
+        let fd = open_file(&self.dir, "data")
+            .context("opening data file for sstable iter")?;
+        Ok(SSTableIteratorMultiFile::iter(fd, self.compression))
+    }
+
+    /// iterator over the fd of the reader, the reader can not be used by anything else while it is read
+    fn shared_iter(&self) -> Result<SSTableIteratorMultiFile> {
         let mut fd = self.sstable_file.try_clone()
             .context("cloning data fd for sstable iter")?;
         fd.rewind().context("rewinding data fd for sstable iter")?;
-        Ok(SSTableIteratorMultiFile::iter(fd))
+        Ok(SSTableIteratorMultiFile::iter(fd, self.compression))
     }
 
     pub fn index_iter(&self) -> Result<IndexIterator> {
-        let mut fd = self.index_file.try_clone()
+        let fd = self.index_file.try_clone()
             .context("cloning index fd for index iter")?;
-        fd.rewind().context("rewinding index fd for index iter")?;
-        Ok(IndexIterator::iter(fd))
+        IndexIterator::iter(fd, 0, None)
     }
 
     /// returns the iterator and the global range
@@ -66,70 +179,42 @@ impl SSTableReaderMultiFile {
         SummaryIterator::iter(fd)
     }
 
-    pub fn prefix_scan(&self, prefix: &str) -> Result<Vec<Entry>> {
-        let (summary_iter, range) = self.summary_iter().context("reading summary")?;
-        if !prefix_intersects(prefix.as_bytes(), &range.first_key[..], &range.last_key[..]) {
-            return Ok(vec![]);
-        }
+    /// all summary ranges without the global one, each covers summary_nth blocks
+    pub fn ranges(&self) -> Result<Vec<SummaryEntry>> {
+        Ok(self.summary.clone())
+    }
 
-        let mut index_offset = None;
-        for entry in summary_iter {
-            let entry = entry.context("reading summary entry")?;
-            if prefix_intersects(prefix.as_bytes(), &entry.first_key[..], &entry.last_key[..]) {
-                index_offset = Some(entry.offset);
-            }
-        }
+    /// all entries covered by the range at position i in ranges
+    pub fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>> {
+        let mut index_iter = self.index_iter().context("getting index iterator")?;
+        index_iter.move_iter(ranges[i].offset).context("moving index iter")?;
 
-        if index_offset.is_some() {
-            let mut res = Vec::new();
-            let mut index_iter = self.index_iter().context("getting index iterator")?;
-            index_iter.move_iter(index_offset.unwrap()).context("")?;
-            let index_entry = index_iter.next().unwrap().context("reading index entry")?;
-            let mut iter = self.iter().context("getting sstable iter")?;
-            iter.move_iter(index_entry.offset).context("moving sstable iter")?;
-
-            for entry in iter {
-                let entry = entry.context("reading sstable entry")?;
-                if vector_prefix(prefix.as_bytes(), &entry.key[..]) { res.push(entry); }
-                else if end(prefix.as_bytes(), &entry.key[..]) { break; }
-            }
-            Ok(res)
+        // the range ends where the next one starts
+        let end = ranges.get(i + 1).map_or(index_iter.len(), |range| range.offset);
+        let offsets = index_iter
+            .take(end.saturating_sub(ranges[i].offset) as usize)
+            .map(|entry| entry.map(|entry| entry.offset))
+            .collect::<Result<Vec<_>>>()
+            .context("reading index entry")?;
 
-        } else {
-            Ok(vec![])
-        }
-    }
-
-    pub fn range_scan(&self, start: &[u8], end: &[u8]) -> Result<Vec<Entry>> {
-        let (summary_iter, range) = self.summary_iter().context("reading summary")?;
-        if !(&range.first_key[..] <= end && &range.last_key[..] >= start) { return Ok(vec![]); }
+        let Some(first_offset) = offsets.first() else {
+            return Ok(vec![]);
+        };
 
-        let mut index_offset = None;
-        for entry in summary_iter {
-            let entry = entry.context("reading summary entry")?;
-            if &entry.first_key[..] <= end && &range.last_key[..] >= start {
-                index_offset = Some(entry.offset);
-            }
-        }
+        let mut iter = self.shared_iter().context("getting sstable iter")?;
+        iter.move_iter(*first_offset).context("moving sstable iter")?;
 
-        if index_offset.is_some() {
-            let mut res = Vec::new();
-            let mut index_iter = self.index_iter().context("getting index iterator")?;
-            index_iter.move_iter(index_offset.unwrap()).context("")?;
-            let index_entry = index_iter.next().unwrap().context("reading index entry")?;
-            let mut iter = self.iter().context("getting sstable iter")?;
-            iter.move_iter(index_entry.offset).context("moving sstable iter")?;
-
-            for entry in iter {
-                let entry = entry.context("reading sstable entry")?;
-                if &entry.key[..] >= start && &entry.key[..] <= end { res.push(entry); }
-                else if &entry.key[..] >= end { break; }
+        // every index entry points at a block
+        let mut entries = Vec::new();
+        for _ in 0..offsets.len() {
+            match iter.next_block() {
+                Some(block) => entries.extend(block.context("reading sstable block")?),
+                None => break,
             }
-            Ok(res)
-        } else {
-            Ok(vec![])
         }
+        Ok(entries)
     }
+
 }
 
 pub fn open_file(dir: &str, name: &str) -> Result<File> {
@@ -141,18 +226,18 @@ pub fn open_file(dir: &str, name: &str) -> Result<File> {
     Ok(file)
 }
 
-fn prefix_intersects(prefix: &[u8], start: &[u8], end: &[u8]) -> bool {
-    let prefix_len = prefix.len();
-    let start_prefix = &start[..prefix_len];
-    let end_prefix = &end[..prefix_len];
-    prefix >= start_prefix && prefix <= end_prefix
-}
-
-fn vector_prefix(prefix: &[u8], key: &[u8]) -> bool {
-    prefix == &key[..prefix.len()]
-
-}
+/// the version file holds the format version(u32), tables without one are version 1
+fn check_version(dir: &str) -> Result<()> {
+    let version_path = format!("{dir}/version");
+    if !Path::new(&version_path).is_file() {
+        return Err(anyhow!("sstable has format version 1, expected {FORMAT_VERSION}"));
+    }
 
-fn end(prefix: &[u8], key: &[u8]) -> bool {
-    prefix > &key[..prefix.len()]
+    let version: u32 = BINCODE_OPTIONS
+        .deserialize(&read(&version_path).context("reading format version")?)
+        .context("deserializing format version")?;
+    if version != FORMAT_VERSION {
+        return Err(anyhow!("sstable has format version {version}, expected {FORMAT_VERSION}"));
+    }
+    Ok(())
 }
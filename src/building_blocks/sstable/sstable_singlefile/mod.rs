@@ -2,14 +2,12 @@ mod sstable_builder;
 mod sstable_header;
 mod sstable_reader;
 mod sstable_iter;
-mod index_iter;
 
 #[cfg(test)]
 mod sstable_tests;
 
 pub use sstable_reader::SSTableReaderSingleFile;
 pub use sstable_iter::SSTableIteratorSingleFile;
-pub use index_iter::IndexIteratorSingleFile;
 pub use sstable_header::SSTableHeader;
 pub use sstable_header::HEADER_SIZE;
 pub use sstable_builder::SSTableBuilderSingleFile;
@@ -1,20 +1,25 @@
 use std::{
     fs::{File, OpenOptions, create_dir},
-    io::{Write, Seek, SeekFrom}, rc::Rc
+    io::{Write, Seek, SeekFrom}
 };
 use anyhow::{Result, Context};
 use crate::building_blocks::{Entry, IndexBuilder, SummaryBuilder, BloomFilter, MerkleRoot};
-use super::{SSTableHeader, SSTableIteratorSingleFile, IndexIteratorSingleFile};
+use crate::building_blocks::sstable::{block::{BlockBuilder, serialize_blocks}, Compression};
+use crate::building_blocks::sstable::summary::table_range;
+use crate::utils::helpers::sync_dir;
+use super::{SSTableHeader, SSTableIteratorSingleFile};
 
 /// SSTable builder where aiding structures are in the same file as the data itself
 /// singlefile sstable is built in steps
-/// 1. pass - write all sstable entries and the filter
-/// 2. pass - write index by reading previously written entries
-/// 3. pass - write summary by reading previously written index entries
+/// 1. pass - write all sstable entries in blocks and the filter
+/// 2. pass - write index by reading previously written blocks, the summary ranges are collected on the way
+/// 3. pass - write summary
+///
 /// file layout:
 /// ----------------------
 /// sstable header
 /// data
+/// range tombstones
 /// filter
 /// metadata
 /// index
@@ -25,6 +30,10 @@ use super::{SSTableHeader, SSTableIteratorSingleFile, IndexIteratorSingleFile};
 pub struct SSTableBuilderSingleFile {
     header: SSTableHeader,
 
+    /// directory of the table and the one it is in, synced once the file is
+    data_dir: String,
+    dir: String,
+
     /// used for reading previously written data
     reader_file: File,
 
@@ -33,17 +42,28 @@ pub struct SSTableBuilderSingleFile {
 
     filter: BloomFilter,
 
-    /// last key written, used for generating summary
+    /// block currently being filled with entries
+    block: BlockBuilder,
+
+    /// written after the data once all entries are inserted
+    range_tombstones: Vec<Entry>,
+
+    /// first and last key written, used for generating summary
+    first_key_global: Option<Vec<u8>>,
     last_key_global: Option<Vec<u8>>,
     summary_nth: u64,
+
+    /// first key, last key and first index position of each summary range
+    summary_ranges: Vec<(Vec<u8>, Vec<u8>, u64)>,
 }
 
 impl SSTableBuilderSingleFile {
     pub fn new(data_dir: &str, generation: &str, item_count: u64, filter_fp_prob: f64, summary_nth: u64) -> Result<Self> {
-        create_dir(format!("{}/{}", data_dir, generation))
+        let dir = format!("{}/{}", data_dir, generation);
+        create_dir(&dir)
             .context("creating the generation directory")?;
 
-        let file_name = format!("{}/{}/data", data_dir, generation);
+        let file_name = format!("{}/data", dir);
         let mut writer_file = OpenOptions::new()
             .create(true)
             .truncate(true)
@@ -67,26 +87,70 @@ impl SSTableBuilderSingleFile {
 
         header.data_offset = header_ser.len() as u64;
 
-        Ok(Self { header, reader_file, writer_file, filter, summary_nth, last_key_global: None})
+        Ok(Self {
+            header,
+            data_dir: data_dir.to_string(),
+            dir,
+            reader_file,
+            writer_file,
+            filter,
+            block: BlockBuilder::new(Compression::None),
+            summary_nth,
+            summary_ranges: Vec::new(),
+            range_tombstones: Vec::new(),
+            first_key_global: None,
+            last_key_global: None,
+        })
     }
 
+    /// codec the blocks are compressed with, has to be set before any entry is inserted
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.header.compression = compression;
+        self.block = BlockBuilder::new(compression);
+        self
+    }
+
+    /// range tombstones can be inserted in any order
     pub fn insert(&mut self, entry: Entry) -> Result<()> {
+        if entry.is_range_delete() {
+            self.range_tombstones.push(entry);
+            return Ok(());
+        }
         self.insert_data(entry)
     }
 
     fn insert_data(&mut self, entry: Entry) -> Result<()> {
-        let entry_ser = entry.serialize()?;
-
-        self.writer_file.write_all(&entry_ser)
-            .context("writing the sstable entry")?;
+        self.block.add(&entry)
+            .context("adding entry to the block")?;
 
         self.filter.add(&entry.key)
             .context("adding entry to the filter")?;
 
+        if self.block.is_full() {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        let block_ser = self.block.finish()?;
+        self.writer_file.write_all(&block_ser)
+            .context("writing the sstable block")?;
         Ok(())
     }
 
     pub fn finish_data(&mut self) -> Result<()> {
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+
+        let range_tombstones_offset = self.writer_file.stream_position()
+            .context("getting current file position")?;
+        self.header.range_tombstones_offset = range_tombstones_offset;
+
+        self.writer_file.write_all(&serialize_blocks(&self.range_tombstones, self.header.compression)?)
+            .context("writing range tombstones")?;
+
         let filter_offset = self.writer_file.stream_position()
             .context("getting current file position")?;
         self.header.filter_offset = filter_offset;
@@ -100,7 +164,7 @@ impl SSTableBuilderSingleFile {
         self.generate_index()
             .context("generating the index")?;
 
-        self.generate_summary(self.summary_nth)
+        self.generate_summary()
             .context("generating the summary")?;
 
         self.finish()
@@ -119,7 +183,7 @@ impl SSTableBuilderSingleFile {
         let reader_fd = self.reader_file.try_clone()
             .context("cloning the reader file fd for data")?;
 
-        let data_iter = SSTableIteratorSingleFile::iter(reader_fd, self.header.data_offset, self.header.filter_offset);
+        let data_iter = SSTableIteratorSingleFile::iter(reader_fd, self.header.data_offset, self.header.range_tombstones_offset, self.header.compression);
         let mut data = Vec::with_capacity(self.filter.item_count as usize);
         for entry in data_iter {
             let entry = entry.context("reading sstable entry")?;
@@ -136,7 +200,8 @@ impl SSTableBuilderSingleFile {
         self.writer_file.sync_all()
             .context("syncing the sstable file")?;
 
-        Ok(())
+        sync_dir(&self.dir)?;
+        sync_dir(&self.data_dir)
     }
 
     fn generate_index(&mut self) -> Result<()> {
@@ -154,87 +219,79 @@ impl SSTableBuilderSingleFile {
         let reader_fd = self.reader_file.try_clone()
             .context("cloning the reader file fd for data")?;
 
-        let mut data_iter = SSTableIteratorSingleFile::iter(reader_fd, self.header.data_offset, self.header.filter_offset);
-        let mut index_offset = self.header.data_offset;
-        let mut next_entry;
-
-        // first iteration outside of a loop in order for entry to be initialized for
-        // self.last_key_global assignement
-        let mut entry = data_iter.next().expect("there is always atleast one entry")?;
-        index_builder.add(&entry.key, index_offset)
-            .context("adding index entry")?;
-        index_offset = data_iter.iter.current_offset;
-        loop {
-            next_entry = data_iter.next();
-            if next_entry.is_none() { break; }
-            entry = next_entry.unwrap()?;
-            index_builder.add(&entry.key, index_offset)
+        let mut data_iter = SSTableIteratorSingleFile::iter(reader_fd, self.header.data_offset, self.header.range_tombstones_offset, self.header.compression);
+        let mut block_offset = self.header.data_offset;
+
+        // first key and first index position of the current summary range
+        let mut range_start: Option<(Vec<u8>, u64)> = None;
+        let mut blocks = 0;
+
+        // a table can hold only range tombstones
+        while let Some(block) = data_iter.next_block() {
+            let block = block?;
+            let (Some(first), Some(last)) = (block.first(), block.last()) else {
+                continue;
+            };
+
+            if range_start.is_none() {
+                range_start = Some((first.key.clone(), index_builder.len()));
+            }
+
+            // the block holds the keys up to its last one
+            index_builder.add(&last.key, block_offset)
                 .context("adding index entry")?;
-            index_offset = data_iter.iter.current_offset;
+            block_offset = data_iter.iter.current_offset;
+
+            blocks += 1;
+            if blocks % self.summary_nth == 0 {
+                let (first_key, offset) = range_start.take().unwrap();
+                self.summary_ranges.push((first_key, last.key.clone(), offset));
+            }
+
+            if self.first_key_global.is_none() {
+                self.first_key_global = Some(first.key.clone());
+            }
+            self.last_key_global = Some(last.key.clone());
         }
 
-        self.last_key_global = Some(entry.key);
+        // the last range is incomplete
+        if let (Some((first_key, offset)), Some(last_key)) = (range_start, &self.last_key_global) {
+            self.summary_ranges.push((first_key, last_key.clone(), offset));
+        }
+
+        index_builder.finish()
+            .context("writing the index")?;
+
         self.writer_file.sync_all()
             .context("syncing the sstable file")?;
         Ok(())
     }
 
-    fn generate_summary(&mut self, summary_nth: u64) -> Result<()> {
-        assert!(self.last_key_global.is_some());
+    fn generate_summary(&mut self) -> Result<()> {
         let summary_offset = self.writer_file.stream_position()
             .context("getting current file position after filter writing filter")?;
         self.header.summary_offset = summary_offset;
 
-        self.reader_file.seek(SeekFrom::Start(self.header.index_offset))
-            .context("seeking to the index")?;
-        let reader_fd = self.reader_file.try_clone()
-            .context("cloning the reader file fd for index")?;
-        let mut index_iter = IndexIteratorSingleFile::iter(reader_fd, self.header.index_offset, self.header.summary_offset);
-
         let summary_fd = self.writer_file
             .try_clone()
             .context("cloning the writer file fd for summary")?;
         let mut summary_builder = SummaryBuilder::new(summary_fd);
 
-        let first_entry = Rc::new(index_iter.next().expect("there is always atleast first entry")?);
-        let first_key_global = first_entry.key.clone();
-
-        // keeps the first entry in the current range
-        let mut first_key_range = Some(Rc::clone(&first_entry));
-        // keeps the last written entry
-        let mut last_key_range = if let Some(entry) = index_iter.next() {
-            Rc::new(entry?)
-        } else {
-            Rc::clone(&first_entry)
-        };
-
-        let mut counter = 2;
-        let mut current_range_offset = self.header.index_offset;
-
-        while let Some(entry) = index_iter.next() {
-            last_key_range = Rc::new(entry?);
-            if first_key_range.is_none() { first_key_range = Some(Rc::clone(&last_key_range)); }
-            counter += 1;
-            if counter % summary_nth == 0 {
-                summary_builder.add(&first_key_range.unwrap().key, &last_key_range.key, current_range_offset)?;
-                current_range_offset = index_iter.iter.current_offset;
-                first_key_range = None;
-            }
-        }
-
-        if counter % summary_nth != 0 {
-            assert!(first_key_range.is_some());
-            summary_builder.add(&first_key_range.as_ref().unwrap().key, &last_key_range.key, current_range_offset)?
+        for (first_key, last_key, offset) in &self.summary_ranges {
+            summary_builder.add(first_key, last_key, *offset)?;
         }
 
-        summary_builder.total_range(&first_key_global, &self.last_key_global.as_ref().unwrap())?;
+        let points = self.first_key_global.as_deref().zip(self.last_key_global.as_deref());
+        let (first_key, last_key) = table_range(points, &self.range_tombstones)
+            .context("sstable without entries")?;
+        summary_builder.total_range(&first_key, &last_key)?;
 
         self.writer_file.sync_all()
             .context("syncing the sstable file")?;
         Ok(())
     }
 
-    /// writes the sstable header
+    /// writes the sstable header, the table survives a crash once this returns
     fn finish(&mut self) -> Result<()> {
         self.writer_file.rewind()
             .context("rewiding sstable file")?;
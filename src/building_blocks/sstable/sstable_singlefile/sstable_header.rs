@@ -1,30 +1,43 @@
 use bincode::Options;
 use serde::{Serialize, Deserialize};
-use anyhow::{Result, Context};
-use crate::building_blocks::BINCODE_OPTIONS;
+use anyhow::{Result, Context, anyhow};
+use crate::building_blocks::{BINCODE_OPTIONS, FORMAT_VERSION};
+use crate::building_blocks::sstable::Compression;
 
-pub static HEADER_SIZE: u64 = 40;
+pub static HEADER_SIZE: u64 = 56;
 
 /// all the offsets are the offsets from the beginning from the file including the header
 /// itself
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SSTableHeader {
+    /// format of the entries, tables without a version start with the data offset
+    pub version: u32,
+
+    /// codec of the data and range tombstone blocks
+    pub compression: Compression,
+
     /// data_offset is just the size of the SSTableHeader length
     pub data_offset: u64,
     pub filter_offset: u64,
     pub index_offset: u64,
     pub summary_offset: u64,
     pub meta_offset: u64,
+
+    /// range tombstones follow the data, the filter starts where they end
+    pub range_tombstones_offset: u64,
 }
 
 impl SSTableHeader {
     pub fn new() -> Self {
         SSTableHeader {
+            version: FORMAT_VERSION,
+            compression: Compression::None,
             data_offset: 0,
             filter_offset: 0,
             index_offset: 0,
             summary_offset: 0,
-            meta_offset: 0
+            meta_offset: 0,
+            range_tombstones_offset: 0,
         }
     }
 
@@ -36,10 +49,14 @@ impl SSTableHeader {
         Ok(header_ser)
     }
 
+    /// tables of another format version can not be read
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        let header = BINCODE_OPTIONS
+        let header: SSTableHeader = BINCODE_OPTIONS
             .deserialize(data)
             .context("deserializing header")?;
+        if header.version != FORMAT_VERSION {
+            return Err(anyhow!("sstable has format version {}, expected {FORMAT_VERSION}", header.version));
+        }
         Ok(header)
     }
 }
@@ -1,6 +1,7 @@
 use std::fs::File;
 use anyhow::{Result, anyhow};
 use crate::building_blocks::{SSTableIteratorMultiFile, Entry};
+use crate::building_blocks::sstable::Compression;
 
 pub struct SSTableIteratorSingleFile {
     start_offset: u64,
@@ -11,9 +12,10 @@ pub struct SSTableIteratorSingleFile {
 /// wrapper around SSTableIteratorMultiFile that tracks the file cursor position
 impl SSTableIteratorSingleFile {
     /// end offset is the offset at which data ends (filter starts)
-    pub fn iter(file: File, start_offset: u64, end_offset: u64) -> Self {
-        let mut iter = SSTableIteratorMultiFile::iter(file);
+    pub fn iter(file: File, start_offset: u64, end_offset: u64, compression: Compression) -> Self {
+        let mut iter = SSTableIteratorMultiFile::iter(file, compression);
         iter.current_offset = start_offset;
+        iter.end_offset = Some(end_offset);
         SSTableIteratorSingleFile { start_offset, end_offset, iter }
     }
 
@@ -33,15 +35,23 @@ impl SSTableIteratorSingleFile {
         self.iter.move_iter(offset)?;
         Ok(())
     }
+
+    /// moves to the block at the offset and skips its entries smaller than the key
+    pub fn seek(&mut self, offset: u64, key: &[u8]) -> Result<()> {
+        self.move_iter(offset)?;
+        self.iter.seek(offset, key)
+    }
+
+    /// all entries of the next block
+    pub fn next_block(&mut self) -> Option<Result<Vec<Entry>>> {
+        self.iter.next_block()
+    }
 }
 
 impl Iterator for SSTableIteratorSingleFile {
     type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.iter.current_offset >= self.end_offset {
-            return None;
-        }
         self.iter.next()
     }
 }
@@ -1,14 +1,26 @@
 use std::{
     fs::{File, OpenOptions},
-    io::{Read, SeekFrom, Seek}
+    io::{Read, SeekFrom, Seek},
+    sync::Arc
 };
 use anyhow::{Result, Context};
-use crate::building_blocks::{SummaryIterator, SummaryEntry, BloomFilter, Entry};
-use super::{SSTableHeader, HEADER_SIZE, SSTableIteratorSingleFile, IndexIteratorSingleFile};
+use crate::building_blocks::sstable::{summary::find_range, BlockCache};
+use crate::building_blocks::{IndexIterator, SummaryIterator, SummaryEntry, BloomFilter, Entry, MerkleRoot};
+use super::{SSTableHeader, HEADER_SIZE, SSTableIteratorSingleFile};
 
+/// the filter, the summary and the range tombstones are read when the table is loaded and kept in memory
 pub struct SSTableReaderSingleFile {
+    dir: String,
     pub header: SSTableHeader,
+    pub filter: BloomFilter,
     file: File,
+    /// used by point lookups, scans get their own
+    index: IndexIterator,
+    summary: Vec<SummaryEntry>,
+    range: SummaryEntry,
+    range_tombstones: Vec<Entry>,
+    /// blocks read by point lookups are kept there with the id of the table
+    block_cache: Option<(Arc<BlockCache>, u64)>,
 }
 
 /// each iter uses a same fd, therefore it is not safe to have multiple iterator iterate at the same time
@@ -19,10 +31,10 @@ impl SSTableReaderSingleFile {
             .write(true)
             .open(format!("{}/data", sstabel_dir))
             .context("opening sstable file")?;
-        SSTableReaderSingleFile::read_sstable(file)
+        SSTableReaderSingleFile::read_sstable(sstabel_dir, file)
     }
 
-    fn read_sstable(mut file: File) -> Result<Self> {
+    fn read_sstable(dir: &str, mut file: File) -> Result<Self> {
         let mut header_ser = vec![0; HEADER_SIZE as usize];
         file.read_exact(&mut header_ser)
             .context("reading sstable header")?;
@@ -30,36 +42,120 @@ impl SSTableReaderSingleFile {
         let header = SSTableHeader::deserialize(&header_ser[..])
             .context("deserializing sstable header")?;
 
-        Ok(Self { header, file })
+        let fd = file.try_clone()
+            .context("cloning fd")?;
+        let index = IndexIterator::iter(fd, header.index_offset, Some(header.summary_offset))
+            .context("reading index")?;
+
+        let mut fd = file.try_clone()
+            .context("cloning fd")?;
+        fd.seek(SeekFrom::Start(header.filter_offset))
+            .context("seeking to filter")?;
+        let filter = BloomFilter::read_from_file(fd)
+            .context("reading filter")?;
+
+        let (summary_iter, range) = summary_iter(&file, &header)
+            .context("reading summary")?;
+        let summary = summary_iter.collect::<Result<Vec<_>>>()
+            .context("reading summary entry")?;
+
+        let mut fd = file.try_clone()
+            .context("cloning fd")?;
+        fd.seek(SeekFrom::Start(header.range_tombstones_offset))
+            .context("seeking to range tombstones")?;
+        let range_tombstones = SSTableIteratorSingleFile::iter(fd, header.range_tombstones_offset, header.filter_offset, header.compression)
+            .collect::<Result<Vec<_>>>()
+            .context("reading range tombstone")?;
+
+        Ok(Self { dir: dir.to_string(), header, filter, file, index, summary, range, range_tombstones, block_cache: None })
+    }
+
+    /// point lookups read their blocks through the cache
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        let table = cache.table_id();
+        self.block_cache = Some((cache, table));
+        self
     }
 
+    /// versions of the key newest first, the summary and the index are binary searched
+    /// so only the block holding the key is read, and the next ones if its versions go on there
+    pub fn get(&mut self, key: &[u8]) -> Result<Vec<Entry>> {
+        if !self.filter.check(key).context("checking filter")? {
+            return Ok(vec![]);
+        }
+        let Some((start, end)) = find_range(&self.summary, key) else {
+            return Ok(vec![]);
+        };
+        let end = end.unwrap_or(self.index.len());
+        let Some(index_entry) = self.index.search(start, end, key).context("searching index")? else {
+            return Ok(vec![]);
+        };
+
+        let mut iter = self.shared_iter().context("getting sstable iter")?;
+        if let Some((cache, table)) = &self.block_cache {
+            return cache.versions(*table, &mut iter.iter, index_entry.offset, key)
+                .context("reading versions through the block cache");
+        }
+        iter.seek(index_entry.offset, key).context("seeking sstable iter")?;
+        let mut versions = Vec::new();
+        for entry in iter {
+            let entry = entry.context("reading sstable entry")?;
+            if entry.key != key {
+                break;
+            }
+            versions.push(entry);
+        }
+        Ok(versions)
+    }
+
+    /// first and last key of the table
+    pub fn range(&self) -> (&[u8], &[u8]) {
+        (&self.range.first_key, &self.range.last_key)
+    }
+
+    /// the iterator opens the file again, so it does not share the file offset with the reader
     pub fn iter(&self) -> Result<SSTableIteratorSingleFile> {
+        let mut fd = File::open(format!("{}/data", self.dir))
+            .context("opening sstable file for sstable iter")?;
+        fd.seek(SeekFrom::Start(self.header.data_offset))
+            .context("seeking to data")?;
+        Ok(SSTableIteratorSingleFile::iter(fd, self.header.data_offset, self.header.range_tombstones_offset, self.header.compression))
+    }
+
+    /// iterator over the fd of the reader, the reader can not be used by anything else while it is read
+    fn shared_iter(&self) -> Result<SSTableIteratorSingleFile> {
         let mut fd = self.file.try_clone()
             .context("cloning fd")?;
         fd.seek(SeekFrom::Start(self.header.data_offset))
             .context("seeking to data")?;
-        Ok(SSTableIteratorSingleFile::iter(fd, self.header.data_offset, self.header.filter_offset))
+        Ok(SSTableIteratorSingleFile::iter(fd, self.header.data_offset, self.header.range_tombstones_offset, self.header.compression))
     }
 
-    pub fn index_iter(&self) -> Result<IndexIteratorSingleFile> {
-        let mut fd = self.file.try_clone()
+    /// all range tombstones of the table, they are not part of the data
+    pub fn range_tombstones(&self) -> &[Entry] {
+        &self.range_tombstones
+    }
+
+    pub fn index_iter(&self) -> Result<IndexIterator> {
+        let fd = self.file.try_clone()
             .context("cloning fd")?;
-        fd.seek(SeekFrom::Start(self.header.index_offset))
-            .context("seeking to index")?;
-        Ok(IndexIteratorSingleFile::iter(fd, self.header.index_offset, self.header.summary_offset))
+        IndexIterator::iter(fd, self.header.index_offset, Some(self.header.summary_offset))
     }
 
     pub fn summary_iter(&self) -> Result<(SummaryIterator, SummaryEntry)> {
-        let fd = self.file.try_clone()
+        summary_iter(&self.file, &self.header)
+    }
+
+    /// root of the merkle tree over the values, stored between the filter and the index
+    pub fn merkle_root(&self) -> Result<MerkleRoot> {
+        let mut fd = self.file.try_clone()
             .context("cloning fd")?;
-        let (mut iter, range) = SummaryIterator::iter(fd)?;
-
-        // amount_to_be_read is set to filesize-totalrange, subtracting the summary offset leaves
-        // the actual number of bytes to be read
-        iter.amount_to_be_read -= self.header.summary_offset as i64;
-        iter.file.seek(SeekFrom::Start(self.header.summary_offset))
-            .context("seeking to summary")?;
-        Ok((iter, range))
+        fd.seek(SeekFrom::Start(self.header.meta_offset))
+            .context("seeking to metadata")?;
+        let mut meta = vec![0; self.header.index_offset.saturating_sub(self.header.meta_offset) as usize];
+        fd.read_exact(&mut meta)
+            .context("reading metadata")?;
+        MerkleRoot::deserialize(&meta)
     }
 
     pub fn read_filter(&self) -> Result<BloomFilter> {
@@ -67,87 +163,56 @@ impl SSTableReaderSingleFile {
             .context("cloning fd")?;
         fd.seek(SeekFrom::Start(self.header.filter_offset))
             .context("seeking to filter")?;
-        Ok(BloomFilter::read_from_file(fd)?)
+        BloomFilter::read_from_file(fd)
     }
 
-    pub fn prefix_scan(&self, prefix: &str) -> Result<Vec<Entry>> {
-        let (summary_iter, range) = self.summary_iter().context("reading summary")?;
-        if !prefix_intersects(prefix.as_bytes(), &range.first_key[..], &range.last_key[..]) {
-            return Ok(vec![]);
-        }
-
-        let mut index_offset = None;
-        for entry in summary_iter {
-            let entry = entry.context("reading summary entry")?;
-            if prefix_intersects(prefix.as_bytes(), &entry.first_key[..], &entry.last_key[..]) {
-                index_offset = Some(entry.offset);
-            }
-        }
+    /// all summary ranges without the global one, each covers summary_nth blocks
+    pub fn ranges(&self) -> Result<Vec<SummaryEntry>> {
+        Ok(self.summary.clone())
+    }
 
-        if index_offset.is_some() {
-            let mut res = Vec::new();
-            let mut index_iter = self.index_iter().context("getting index iterator")?;
-            index_iter.move_iter(index_offset.unwrap()).context("")?;
-            let index_entry = index_iter.next().unwrap().context("reading index entry")?;
-            let mut iter = self.iter().context("getting sstable iter")?;
-            iter.move_iter(index_entry.offset).context("moving sstable iter")?;
-
-            for entry in iter {
-                let entry = entry.context("reading sstable entry")?;
-                if vector_prefix(prefix.as_bytes(), &entry.key[..]) { res.push(entry); }
-                else if end(prefix.as_bytes(), &entry.key[..]) { break; }
-            }
-            Ok(res)
+    /// all entries covered by the range at position i in ranges
+    pub fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>> {
+        let mut index_iter = self.index_iter().context("getting index iterator")?;
+        index_iter.move_iter(ranges[i].offset).context("moving index iter")?;
 
-        } else {
-            Ok(vec![])
-        }
-    }
+        // the range ends where the next one starts
+        let end = ranges.get(i + 1).map_or(index_iter.len(), |range| range.offset);
+        let offsets = index_iter
+            .take(end.saturating_sub(ranges[i].offset) as usize)
+            .map(|entry| entry.map(|entry| entry.offset))
+            .collect::<Result<Vec<_>>>()
+            .context("reading index entry")?;
 
-    pub fn range_scan(&self, start: &[u8], end: &[u8]) -> Result<Vec<Entry>> {
-        let (summary_iter, range) = self.summary_iter().context("reading summary")?;
-        if !(&range.first_key[..] <= end && &range.last_key[..] >= start) { return Ok(vec![]); }
+        let Some(first_offset) = offsets.first() else {
+            return Ok(vec![]);
+        };
 
-        let mut index_offset = None;
-        for entry in summary_iter {
-            let entry = entry.context("reading summary entry")?;
-            if &entry.first_key[..] <= end && &range.last_key[..] >= start {
-                index_offset = Some(entry.offset);
-            }
-        }
+        let mut iter = self.shared_iter().context("getting sstable iter")?;
+        iter.move_iter(*first_offset).context("moving sstable iter")?;
 
-        if index_offset.is_some() {
-            let mut res = Vec::new();
-            let mut index_iter = self.index_iter().context("getting index iterator")?;
-            index_iter.move_iter(index_offset.unwrap()).context("")?;
-            let index_entry = index_iter.next().unwrap().context("reading index entry")?;
-            let mut iter = self.iter().context("getting sstable iter")?;
-            iter.move_iter(index_entry.offset).context("moving sstable iter")?;
-
-            for entry in iter {
-                let entry = entry.context("reading sstable entry")?;
-                if &entry.key[..] >= start && &entry.key[..] <= end { res.push(entry); }
-                else if &entry.key[..] >= end { break; }
+        // every index entry points at a block
+        let mut entries = Vec::new();
+        for _ in 0..offsets.len() {
+            match iter.next_block() {
+                Some(block) => entries.extend(block.context("reading sstable block")?),
+                None => break,
             }
-            Ok(res)
-        } else {
-            Ok(vec![])
         }
+        Ok(entries)
     }
-}
-
-fn prefix_intersects(prefix: &[u8], start: &[u8], end: &[u8]) -> bool {
-    let prefix_len = prefix.len();
-    let start_prefix = &start[..prefix_len];
-    let end_prefix = &end[..prefix_len];
-    prefix >= start_prefix && prefix <= end_prefix
-}
-
-fn vector_prefix(prefix: &[u8], key: &[u8]) -> bool {
-    prefix == &key[..prefix.len()]
 
 }
 
-fn end(prefix: &[u8], key: &[u8]) -> bool {
-    prefix > &key[..prefix.len()]
+fn summary_iter(file: &File, header: &SSTableHeader) -> Result<(SummaryIterator, SummaryEntry)> {
+    let fd = file.try_clone()
+        .context("cloning fd")?;
+    let (mut iter, range) = SummaryIterator::iter(fd)?;
+
+    // amount_to_be_read is set to filesize-totalrange, subtracting the summary offset leaves
+    // the actual number of bytes to be read
+    iter.amount_to_be_read -= header.summary_offset as i64;
+    iter.file.seek(SeekFrom::Start(header.summary_offset))
+        .context("seeking to summary")?;
+    Ok((iter, range))
 }
@@ -1,5 +1,5 @@
-use std::{path::Path, fs::remove_dir_all};
-use crate::building_blocks::Entry;
+use std::{path::Path, fs::remove_dir_all, sync::Arc};
+use crate::building_blocks::{BlockCache, Compression, Entry, EntryKind};
 use super::{SSTableReaderSingleFile, sstable_builder::SSTableBuilderSingleFile};
 
 #[test]
@@ -15,6 +15,8 @@ fn write_sstable_singlefile() {
             timestamp: i,
             key: i.to_string().into_bytes(),
             value: Some(i.to_string().into_bytes()),
+            kind: EntryKind::Point,
+            expires_at: None,
         };
 
         sstable.insert(entry).expect("inserting entry into the sstable");
@@ -29,52 +31,56 @@ fn read_valid_sstable_singlefile() {
         .expect("reading sstable");
 
     // test sstable entries
-    let mut i = 0;
-    for entry in sstable_reader.iter().expect("getting sstable iter") {
+    for (i, entry) in sstable_reader.iter().expect("getting sstable iter").enumerate() {
         let entry = entry.expect("reading entry");
         let expected_entry = Entry {
             key: i.to_string().into_bytes(),
-            value: Some(i.to_string().into_bytes()), timestamp: i,
+            value: Some(i.to_string().into_bytes()), timestamp: i as u128,
+            kind: EntryKind::Point,
+            expires_at: None,
         };
 
         assert_eq!(entry, expected_entry);
-        i += 1;
     }
 
-    // test index
-    let mut data_iter = sstable_reader.iter().expect("getting data iter");
-    let random_entry_index = sstable_reader.index_iter().expect("getting index iter")
-        .nth(10)
-        .unwrap()
-        .expect("reading eleventh entry in the index");
+    // test index, every block starts right after the last key of the previous one
+    let mut index_iter = sstable_reader.index_iter().expect("getting index iter");
+    let first_block = index_iter.next().unwrap().expect("reading first entry in the index");
+    let second_block = index_iter.next().unwrap().expect("reading second entry in the index");
 
-    data_iter.move_iter(random_entry_index.offset)
+    let mut data_iter = sstable_reader.iter().expect("getting data iter");
+    data_iter.move_iter(second_block.offset)
         .expect("moving sstable iter");
 
-    let random_entry_read = data_iter.next().unwrap().expect("reading random sstable entry");
-    assert_eq!(random_entry_read.key, 10.to_string().into_bytes());
+    let block_start = data_iter.next().unwrap().expect("reading first entry of the second block");
+    let last_key: u32 = String::from_utf8(first_block.key).unwrap().parse().unwrap();
+    assert_eq!(block_start.key, (last_key + 1).to_string().into_bytes());
 
-    // test summary
+    // test summary, the second range starts at the eleventh block
     let (mut summary_iter, _) = sstable_reader
         .summary_iter()
         .expect("getting summary iter");
 
-    let random_entry_summary = summary_iter
-        .nth(5)
+    let second_range = summary_iter
+        .nth(1)
         .unwrap()
-        .expect("getting fifth entry in the summary");
+        .expect("getting second entry in the summary");
 
     let mut index_iter = sstable_reader.index_iter().expect("getting index iter");
-    index_iter.move_iter(random_entry_summary.offset).expect("moving index iter");
+    index_iter.move_iter(second_range.offset).expect("moving index iter");
+    let index_entry = index_iter.next().unwrap().expect("reading index entry of the range");
 
-    let index_entry = index_iter.next().unwrap().expect("reading random index entry");
-    assert_eq!(index_entry.key, 50.to_string().into_bytes());
+    let eleventh_block = sstable_reader.index_iter().expect("getting index iter")
+        .nth(10)
+        .unwrap()
+        .expect("reading eleventh entry in the index");
+    assert_eq!(index_entry.key, eleventh_block.key);
 
     let filter = sstable_reader.read_filter().expect("getting filter");
     // test filter
-    for i in 0..100 {
+    for i in 0..1000 {
         let check = filter.check(&i.to_string().into_bytes()).expect("checking key in the filter");
-        assert_eq!(check, true);
+        assert!(check);
     }
 }
 
@@ -93,3 +99,99 @@ fn read_invalid_sstable_singlefile() {
     }
     assert!(corrupted);
 }
+
+#[test]
+fn read_compressed_sstable_singlefile() {
+    let mut sizes = Vec::new();
+    for compression in [Compression::None, Compression::Lz4, Compression::Snappy, Compression::Zstd] {
+        let name = format!("compressed-sstable-singlefile-{compression:?}");
+        let dir = format!("test-data/{name}");
+        if Path::new(&dir).is_dir() { remove_dir_all(&dir).expect("removing old sstable"); }
+
+        let mut sstable = SSTableBuilderSingleFile::new("test-data", &name, 1000, 0.1, 2)
+            .expect("creating a sstable")
+            .with_compression(compression);
+        let entries = (0..1000)
+            .map(|i| Entry {
+                key: format!("key-{i:04}").into_bytes(),
+                value: Some(format!("value-{i:04}").repeat(4).into_bytes()),
+                timestamp: i,
+                kind: EntryKind::Point,
+                expires_at: None,
+            })
+            .collect::<Vec<_>>();
+        for entry in entries.clone() {
+            sstable.insert(entry).expect("inserting entry into the sstable");
+        }
+        let tombstone = Entry::range_delete(1000, b"key-0100".to_vec(), b"key-0200".to_vec());
+        sstable.insert(tombstone.clone()).expect("inserting range tombstone");
+        sstable.finish_data().expect("finishing sstable");
+        sizes.push(std::fs::metadata(format!("{dir}/data")).unwrap().len());
+
+        let sstable_reader = SSTableReaderSingleFile::load(&dir).expect("reading sstable");
+        let read = sstable_reader.iter().unwrap().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, entries);
+        assert_eq!(sstable_reader.range_tombstones(), [tombstone]);
+
+        let ranges = sstable_reader.ranges().unwrap();
+        let read = (0..ranges.len())
+            .map(|i| sstable_reader.read_range(&ranges, i))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read.concat(), entries);
+    }
+
+    // the values repeat themselves so every codec shrinks the data
+    assert!(sizes[1..].iter().all(|size| *size < sizes[0]), "sizes {sizes:?}");
+}
+
+#[test]
+fn get_sstable_singlefile() {
+    let dir = "test-data/get-sstable-singlefile";
+    if Path::new(dir).is_dir() { remove_dir_all(dir).expect("removing old sstable"); }
+
+    // keys of different lengths with a few versions each, newest first, some of them go on in the next block
+    let mut entries = (0..600u128)
+        .flat_map(|i| (0..=i % 4).rev().map(move |version| Entry {
+            key: format!("key-{}", i * 3).into_bytes(),
+            value: Some(vec![b'v'; 40]),
+            timestamp: version,
+            kind: EntryKind::Point,
+            expires_at: None,
+        }))
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.timestamp.cmp(&a.timestamp)));
+    let mut keys = entries.iter().map(|entry| entry.key.clone()).collect::<Vec<_>>();
+    keys.dedup();
+
+    let mut sstable = SSTableBuilderSingleFile::new("test-data", "get-sstable-singlefile", entries.len() as u64, 0.01, 3)
+        .expect("creating a sstable");
+    for entry in entries.clone() {
+        sstable.insert(entry).expect("inserting entry into the sstable");
+    }
+    sstable.finish_data().expect("finishing sstable");
+
+    let mut sstable_reader = SSTableReaderSingleFile::load(dir).expect("reading sstable");
+    for key in &keys {
+        let expected = entries.iter().filter(|entry| &entry.key == key).cloned().collect::<Vec<_>>();
+        assert_eq!(sstable_reader.get(key).expect("getting key"), expected);
+    }
+
+    for missing in ["a", "key-1", "key-10", "key-5", "z"] {
+        assert!(sstable_reader.get(missing.as_bytes()).expect("getting missing key").is_empty());
+    }
+
+    // the same versions through a block cache, the second round finds every block there
+    let cache = Arc::new(BlockCache::new(1 << 20));
+    let mut sstable_reader = SSTableReaderSingleFile::load(dir).expect("reading sstable")
+        .with_block_cache(Arc::clone(&cache));
+    for _ in 0..2 {
+        for key in &keys {
+            let expected = entries.iter().filter(|entry| &entry.key == key).cloned().collect::<Vec<_>>();
+            assert_eq!(sstable_reader.get(key).expect("getting key through the block cache"), expected);
+        }
+    }
+    let stats = cache.stats().expect("reading block cache stats");
+    assert!(stats.hits > stats.misses);
+    assert!(sstable_reader.get(b"key-5").expect("getting missing key").is_empty());
+}
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod summary;
 mod summary_iter;
 
@@ -6,5 +7,7 @@ mod summary_tests;
 
 pub use summary::SummaryBuilder;
 pub use summary::SummaryEntry;
+pub use summary::table_range;
+pub use summary::find_range;
 pub use summary::MAX_SUMMARY_ENTRY_LEN;
 pub use summary_iter::SummaryIterator;
@@ -6,18 +6,41 @@ use bincode::Options;
 use crc::{Crc, CRC_32_JAMCRC};
 use serde::{Serialize, Deserialize};
 use anyhow::{Result, Context};
-use crate::building_blocks::{MAX_KEY_LEN, BINCODE_OPTIONS};
+use crate::building_blocks::{MAX_KEY_LEN, BINCODE_OPTIONS, Entry};
 
-/// two keys max len + 8bytes for offset
-pub static MAX_SUMMARY_ENTRY_LEN: u64 = 2 * MAX_KEY_LEN + 8;
+/// two keys of max len with their lengths + 8bytes for offset
+pub static MAX_SUMMARY_ENTRY_LEN: u64 = 2 * (MAX_KEY_LEN + 8) + 8;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SummaryEntry {
     pub first_key: Vec<u8>,
     pub last_key: Vec<u8>,
+    /// position of the first index entry of the range
     pub offset: u64,
 }
 
+/// index positions [start, end) of the only range that can hold the key, end is None for the last range
+/// ranges are binary searched, none is returned if the key falls outside of all of them
+pub fn find_range(ranges: &[SummaryEntry], key: &[u8]) -> Option<(u64, Option<u64>)> {
+    let i = ranges.partition_point(|range| &range.last_key[..] < key);
+    let range = ranges.get(i)?;
+    if &range.first_key[..] > key {
+        return None;
+    }
+    Some((range.offset, ranges.get(i + 1).map(|next| next.offset)))
+}
+
+/// first and last key of the table, widened to include the ranges of the range tombstones
+/// the exclusive end of a range is used as its last key
+pub fn table_range(points: Option<(&[u8], &[u8])>, range_tombstones: &[Entry]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let ranges = range_tombstones.iter()
+        .filter_map(|tombstone| Some((&tombstone.key[..], tombstone.range_end()?)));
+    points.into_iter()
+        .chain(ranges)
+        .map(|(first, last)| (first.to_vec(), last.to_vec()))
+        .reduce(|(first, last), (other_first, other_last)| (first.min(other_first), last.max(other_last)))
+}
+
 /// every entry is individually encoded
 /// each entry is written in format: serialized length(8b) + crc(4b) + encoded entry
 /// total range of the keys is written at the end, in the format: encoded_entry + crc(4b) + serialized len(8b)
@@ -30,8 +53,8 @@ impl SummaryBuilder {
         SummaryBuilder { file }
     }
 
-    pub fn add(&mut self, first_key: &Vec<u8>, last_key: &Vec<u8>, offset: u64) -> Result<()> {
-        let entry = SummaryEntry { first_key: first_key.clone(), last_key: last_key.clone(), offset };
+    pub fn add(&mut self, first_key: &[u8], last_key: &[u8], offset: u64) -> Result<()> {
+        let entry = SummaryEntry { first_key: first_key.to_vec(), last_key: last_key.to_vec(), offset };
         let entry_ser = BINCODE_OPTIONS
             .serialize(&entry)
             .context("serializing summary entry")?;
@@ -63,8 +86,8 @@ impl SummaryBuilder {
     /// this method is expected to be called at the end and no more entries are expected to be written,
     /// otherwise summary won't be readable
     /// length last in order to be able to read from the back of the fail
-    pub fn total_range(&mut self, first_key: &Vec<u8>, last_key: &Vec<u8>) -> Result<()> {
-        let entry = SummaryEntry { first_key: first_key.clone(), last_key: last_key.clone(), offset: 0};
+    pub fn total_range(&mut self, first_key: &[u8], last_key: &[u8]) -> Result<()> {
+        let entry = SummaryEntry { first_key: first_key.to_vec(), last_key: last_key.to_vec(), offset: 0};
         let entry_ser = BINCODE_OPTIONS
             .serialize(&entry)
             .context("serializing summary entry")?;
@@ -90,8 +113,8 @@ impl SummaryBuilder {
         self.file.write_all(&len_ser[..])
             .context("writing summary entry len")?;
 
-        self.file.flush()
-            .context("flushing summary to the file")?;
+        self.file.sync_all()
+            .context("syncing summary file")?;
         Ok(())
     }
 }
@@ -6,7 +6,7 @@ use anyhow::{Result, Context, anyhow};
 use bincode::Options;
 use crc::{Crc, CRC_32_JAMCRC};
 use super::{SummaryEntry, MAX_SUMMARY_ENTRY_LEN};
-use crate::building_blocks::{BINCODE_OPTIONS, MAX_KEY_LEN};
+use crate::building_blocks::BINCODE_OPTIONS;
 
 pub struct SummaryIterator {
     pub (in crate::building_blocks::sstable) file: File,
@@ -53,11 +53,6 @@ impl SummaryIterator {
             .context("reading length of the total range")?;
         let len = deserialize_len(&len_ser[..])?;
 
-        if len > MAX_KEY_LEN+8 {
-            let e = anyhow!("corrupted inedex entry len");
-            return Err(e);
-        }
-
         self.file.seek(SeekFrom::End(-(12+len as i64)))
             .context("seeking to the total range")?;
         let mut entry_ser = vec![0; len as usize];
@@ -148,14 +143,14 @@ fn deserialize_len(len_ser: &[u8]) -> Result<u64> {
         .context("deserializing summary entry len")?;
     if len > MAX_SUMMARY_ENTRY_LEN {
         let e = anyhow!("corrupted summary entry len");
-        return Err(e);
+        Err(e)
     } else {
         Ok(len)
     }
 }
 
 fn deserialize_entry(entry_ser: &[u8]) -> Result<SummaryEntry> {
-    Ok(BINCODE_OPTIONS
-        .deserialize(&entry_ser)
-        .context("deserializing summary entry")?)
+    BINCODE_OPTIONS
+        .deserialize(entry_ser)
+        .context("deserializing summary entry")
 }
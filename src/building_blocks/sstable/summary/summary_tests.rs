@@ -1,5 +1,5 @@
 use std::fs::OpenOptions;
-use super::{SummaryBuilder, SummaryIterator};
+use super::{SummaryBuilder, SummaryEntry, SummaryIterator, find_range};
 
 #[test]
 fn writing() {
@@ -73,3 +73,23 @@ fn read_invalid_total_range() {
     let iter = SummaryIterator::iter(file);
     assert!(iter.is_err());
 }
+
+#[test]
+fn find_ranges() {
+    let ranges: Vec<SummaryEntry> = [("b", "d", 0), ("f", "h", 10), ("h", "k", 20)]
+        .into_iter()
+        .map(|(first, last, offset)| SummaryEntry { first_key: first.into(), last_key: last.into(), offset })
+        .collect();
+
+    assert_eq!(find_range(&ranges, b"b"), Some((0, Some(10))));
+    assert_eq!(find_range(&ranges, b"c"), Some((0, Some(10))));
+    assert_eq!(find_range(&ranges, b"g"), Some((10, Some(20))));
+    // a key the range ends with can go on in the next one, its newest versions are in the first
+    assert_eq!(find_range(&ranges, b"h"), Some((10, Some(20))));
+    assert_eq!(find_range(&ranges, b"k"), Some((20, None)));
+
+    assert_eq!(find_range(&ranges, b"a"), None);
+    assert_eq!(find_range(&ranges, b"e"), None);
+    assert_eq!(find_range(&ranges, b"l"), None);
+    assert_eq!(find_range(&[], b"a"), None);
+}
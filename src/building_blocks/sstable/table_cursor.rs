@@ -0,0 +1,163 @@
+use std::sync::{Arc, Mutex};
+use anyhow::{Result, Context, anyhow};
+use crate::building_blocks::{Entry, EntryIterator, SummaryEntry};
+use super::{SSTableReaderSingleFile, SSTableReaderMultiFile};
+
+/// sstable that can be read one summary range at a time
+pub trait SSTableRanges: Send {
+    fn ranges(&self) -> Result<Vec<SummaryEntry>>;
+    fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>>;
+}
+
+impl SSTableRanges for SSTableReaderSingleFile {
+    fn ranges(&self) -> Result<Vec<SummaryEntry>> {
+        SSTableReaderSingleFile::ranges(self)
+    }
+
+    fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>> {
+        SSTableReaderSingleFile::read_range(self, ranges, i)
+    }
+}
+
+impl SSTableRanges for SSTableReaderMultiFile {
+    fn ranges(&self) -> Result<Vec<SummaryEntry>> {
+        SSTableReaderMultiFile::ranges(self)
+    }
+
+    fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>> {
+        SSTableReaderMultiFile::read_range(self, ranges, i)
+    }
+}
+
+/// a reader shared through the table cache is locked for every read, its reads share the file offsets
+impl<R: SSTableRanges> SSTableRanges for Arc<Mutex<R>> {
+    fn ranges(&self) -> Result<Vec<SummaryEntry>> {
+        self.lock()
+            .map_err(|_| anyhow!("sstable reader lock poisoned"))?
+            .ranges()
+    }
+
+    fn read_range(&self, ranges: &[SummaryEntry], i: usize) -> Result<Vec<Entry>> {
+        self.lock()
+            .map_err(|_| anyhow!("sstable reader lock poisoned"))?
+            .read_range(ranges, i)
+    }
+}
+
+/// (range, entry in the range)
+type Position = (usize, usize);
+
+/// lazy iterator over an sstable that can be consumed from both ends
+/// only the summary and at most one range per end are kept in memory
+pub struct SSTableCursor<R: SSTableRanges> {
+    reader: R,
+    ranges: Vec<SummaryEntry>,
+
+    /// next entry to be returned from the front
+    front: Position,
+
+    /// entries before this one are still to be returned from the back
+    back: Position,
+
+    front_block: Option<(usize, Vec<Entry>)>,
+    back_block: Option<(usize, Vec<Entry>)>,
+}
+
+impl<R: SSTableRanges> SSTableCursor<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        let ranges = reader.ranges().context("reading sstable ranges")?;
+        let back = (ranges.len(), 0);
+        Ok(SSTableCursor { reader, ranges, front: (0, 0), back, front_block: None, back_block: None })
+    }
+
+    /// position of the first entry with key >= key
+    fn position(&mut self, key: &[u8]) -> Result<Position> {
+        let range = self.ranges.partition_point(|range| &range.last_key[..] < key);
+        if range == self.ranges.len() {
+            return Ok((range, 0));
+        }
+
+        let block = load(&mut self.front_block, &self.reader, &self.ranges, range)?;
+        Ok((range, block.partition_point(|entry| &entry.key[..] < key)))
+    }
+}
+
+/// entries of the range, reading them only if the slot holds a different one
+fn load<'a, R: SSTableRanges>(
+    slot: &'a mut Option<(usize, Vec<Entry>)>,
+    reader: &R,
+    ranges: &[SummaryEntry],
+    range: usize,
+) -> Result<&'a [Entry]> {
+    if slot.as_ref().is_none_or(|(loaded, _)| *loaded != range) {
+        let entries = reader.read_range(ranges, range)
+            .context("reading sstable range")?;
+        *slot = Some((range, entries));
+    }
+    Ok(&slot.as_ref().unwrap().1)
+}
+
+impl<R: SSTableRanges> Iterator for SSTableCursor<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.front >= self.back {
+                return None;
+            }
+
+            let (range, pos) = self.front;
+            let block = match load(&mut self.front_block, &self.reader, &self.ranges, range) {
+                Ok(block) => block,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if pos >= block.len() {
+                self.front = (range + 1, 0);
+                continue;
+            }
+
+            self.front = (range, pos + 1);
+            return Some(Ok(block[pos].clone()));
+        }
+    }
+}
+
+impl<R: SSTableRanges> DoubleEndedIterator for SSTableCursor<R> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.back <= self.front {
+                return None;
+            }
+
+            let (range, pos) = self.back;
+            if pos == 0 {
+                let block = match load(&mut self.back_block, &self.reader, &self.ranges, range - 1) {
+                    Ok(block) => block,
+                    Err(e) => return Some(Err(e)),
+                };
+                self.back = (range - 1, block.len());
+                continue;
+            }
+
+            let block = match load(&mut self.back_block, &self.reader, &self.ranges, range) {
+                Ok(block) => block,
+                Err(e) => return Some(Err(e)),
+            };
+            self.back = (range, pos - 1);
+            return Some(Ok(block[pos - 1].clone()));
+        }
+    }
+}
+
+impl<R: SSTableRanges> EntryIterator for SSTableCursor<R> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.front = self.position(key)?;
+        Ok(())
+    }
+
+    fn seek_back(&mut self, key: &[u8]) -> Result<()> {
+        self.back = self.position(key)?;
+        Ok(())
+    }
+}
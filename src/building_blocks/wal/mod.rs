@@ -1,11 +1,22 @@
+#[allow(clippy::module_inception)]
 mod wal;
 mod utils;
 mod wal_reader;
+mod wal_record;
+mod wal_sync;
+
+#[cfg(test)]
+mod wal_tests;
 
 pub use wal::WriteAheadLog;
 pub(super) use utils::get_next_index;
 pub(super) use utils::create_file;
 pub(super) use utils::purge_all_files;
-pub(super) use utils::get_next_index_avaiable;
+pub(super) use utils::purge_files_through;
+pub(super) use utils::read_header;
 pub(super) use utils::get_valid_path_names;
-pub use wal_reader::WriteAheadLogReader;
+pub(super) use utils::SEGMENT_HEADER_SIZE;
+pub(crate) use utils::upgrade_segments;
+pub use wal_reader::{WriteAheadLogReader, RecoveryMode, RecoveryReport, DroppedRange};
+pub use wal_record::WalRecord;
+pub use wal_sync::{SyncMode, WalSync};
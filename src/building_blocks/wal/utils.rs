@@ -1,7 +1,18 @@
 use anyhow::{Result, Context, anyhow};
-use std::fs::{read_dir, ReadDir, remove_file};
+use bincode::Options;
+use std::fs::{read, read_dir, rename, write, File, ReadDir, remove_file};
+use std::io::{Read, Write};
 use regex::Regex;
-use memmap2::MmapMut;
+use memmap2::MmapRaw;
+use crate::building_blocks::{legacy_records, BINCODE_OPTIONS, FORMAT_VERSION};
+use crate::utils::helpers::sync_dir;
+use super::{WalRecord, WriteAheadLogReader};
+
+/// every segment starts with the format version(u32) of its records and the sequence(u64) of its first record
+pub const SEGMENT_HEADER_SIZE: usize = 12;
+
+/// a segment of format version 1 is written here before it replaces the old one
+const UPGRADE_SUFFIX: &str = ".upgrade";
 
 /// reads the wal folder, checks that all files have a valid name
 /// returns index of the last file written + 1
@@ -40,52 +51,57 @@ pub fn get_valid_path_names(input: ReadDir) -> Result<Vec<String>> {
     Ok(file_names)
 }
 
-pub fn get_next_index_avaiable(input: &Vec<String>) -> Result<usize> {
+pub fn get_next_index_avaiable(input: &[String]) -> Result<usize> {
     let mut indices: Vec<_> = input
         .iter()
         .map(|path| {
-            let parts = path.split('-');
+            let mut parts = path.split('-');
             // its safe to unwrap since regex guarantees that there is exactly one '-'
             // and a number after it
-            parts.last().unwrap().parse::<usize>().unwrap()
+            parts.next_back().unwrap().parse::<usize>().unwrap()
         })
     .collect();
     indices.sort_unstable();
 
-    // check if all indices exist
-    for (i, index) in indices.iter().enumerate() {
-        if *index != i {
-            let e = anyhow!("segment with index '{i}' is missing");
-            return Err(e);
-        }
-    }
+    // missing segments are left to the recovery of the log
 
-    if indices.len() >= 1 {
+    if !indices.is_empty() {
         Ok(*indices.last().unwrap()+1)
     } else {
         Ok(0)
     }
 }
 
-pub fn create_file(dir: &str, index: usize, file_size: u64) -> Result<MmapMut> {
+/// the segment is created with its header already written, its records start at the first sequence
+/// with sync its header, its len and its name in the folder reach the disk before it is used
+pub fn create_file(dir: &str, index: usize, file_size: u64, first_sequence: u64, sync: bool) -> Result<MmapRaw> {
     let file_name = format!("{dir}/segment-{index}");
 
-    let file = std::fs::OpenOptions::new()
+    let mut file = std::fs::OpenOptions::new()
         .create_new(true)
         .write(true)
         .read(true)
         .open(file_name)
         .context("creating and opening file '{file_name}'")?;
 
-    file.set_len(file_size)
+    let header_ser = BINCODE_OPTIONS
+        .serialize(&(FORMAT_VERSION, first_sequence))
+        .context("serializing segment header")?;
+    file.write_all(&header_ser)
+        .context("writing segment header")?;
+
+    file.set_len(file_size.max(SEGMENT_HEADER_SIZE as u64))
         .context("setting length of the file '{file_name}'")?;
 
-    let map = unsafe {
-        MmapMut::map_mut(&file)
-            .context("mapping file '{file_name}'")?
-    };
+    if sync {
+        file.sync_all().context("syncing segment")?;
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .context("syncing wal folder")?;
+    }
 
-    Ok(map)
+    MmapRaw::map_raw(&file)
+        .context("mapping file '{file_name}'")
 }
 
 /// the currently opened file has to be closed before calling this
@@ -99,3 +115,124 @@ pub fn purge_all_files(dir: &str) -> Result<()> {
     }
     Ok(())
 }
+
+/// removes the segments whose records all have a sequence up to the given one
+/// a segment is only known to end where the segment after it starts, the newest one is always kept
+pub fn purge_files_through(dir: &str, sequence: u64) -> Result<()> {
+    let paths = read_dir(dir)
+        .context("reading wal folder")?;
+    let mut indices = get_valid_path_names(paths)?
+        .iter()
+        // its safe to unwrap since the names were validated
+        .map(|name| name.split('-').next_back().unwrap().parse::<usize>().unwrap())
+        .collect::<Vec<_>>();
+    indices.sort_unstable();
+
+    for (index, next) in indices.iter().zip(indices.iter().skip(1)) {
+        let mut header = [0; SEGMENT_HEADER_SIZE];
+        let read = File::open(format!("{dir}/segment-{next}"))
+            .and_then(|mut file| file.read_exact(&mut header));
+        // a header that was never written says nothing about the segment before it
+        let next_first = match read {
+            Ok(()) => read_header(&header)?,
+            Err(_) => None,
+        };
+        if next_first.is_none_or(|first| first > sequence + 1) {
+            break;
+        }
+        remove_file(format!("{dir}/segment-{index}"))
+            .with_context(|| format!("removing segment {index}"))?;
+    }
+    Ok(())
+}
+
+/// sequence of the first record in the segment, None if the header was never written
+/// segments of another format version can not be read
+pub fn read_header(bytes: &[u8]) -> Result<Option<u64>> {
+    if bytes.len() < SEGMENT_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let (version, first_sequence): (u32, u64) = BINCODE_OPTIONS
+        .deserialize(&bytes[..SEGMENT_HEADER_SIZE])
+        .context("deserializing segment header")?;
+    match version {
+        0 => Ok(None),
+        version if version == FORMAT_VERSION => Ok(Some(first_sequence)),
+        version => Err(anyhow!("segment has format version {version}, expected {FORMAT_VERSION}")),
+    }
+}
+
+/// rewrites the segments of format version 1 in the current format, their records get sequences after the given one
+/// segments of version 1 have no header and start with the len(u64) of their first record,
+/// they were all removed once the memtable was flushed, so all of their records are unflushed
+/// a segment is replaced whole, one left over from an interrupted upgrade is removed
+pub fn upgrade_segments(dir: &str, mut sequence: u64) -> Result<()> {
+    for path in read_dir(dir).context("reading wal folder")? {
+        let path = path.context("reading a path in wal folder")?.path();
+        if path.to_str().is_some_and(|path| path.ends_with(UPGRADE_SUFFIX)) {
+            remove_file(&path)
+                .with_context(|| format!("removing '{}'", path.display()))?;
+        }
+    }
+
+    let paths = read_dir(dir)
+        .context("reading wal folder")?;
+    let mut indices = get_valid_path_names(paths)?
+        .iter()
+        // its safe to unwrap since the names were validated
+        .map(|name| name.split('-').next_back().unwrap().parse::<usize>().unwrap())
+        .collect::<Vec<_>>();
+    indices.sort_unstable();
+
+    for index in indices {
+        let file_name = format!("{dir}/segment-{index}");
+        let bytes = read(&file_name)
+            .with_context(|| format!("reading '{file_name}'"))?;
+        if !is_legacy(&bytes) {
+            // an upgrade can be interrupted after some of the segments were replaced
+            let records = WriteAheadLogReader::iter(dir)?
+                .with_segment(index)
+                .next()
+                .transpose()?
+                .unwrap_or_default();
+            sequence = records.iter().map(|(sequence, _)| *sequence).fold(sequence, u64::max);
+            continue;
+        }
+
+        let mut segment = BINCODE_OPTIONS
+            .serialize(&(FORMAT_VERSION, sequence + 1))
+            .context("serializing segment header")?;
+        for entry in legacy_records(&bytes) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("warning: segment {index} of format version 1 is damaged, the records after it are dropped: {e:#}");
+                    break;
+                }
+            };
+            sequence += 1;
+            segment.extend(WalRecord::Entry(entry).serialize(sequence)?);
+        }
+
+        let upgraded = format!("{file_name}{UPGRADE_SUFFIX}");
+        write(&upgraded, &segment)
+            .and_then(|()| File::open(&upgraded)?.sync_all())
+            .with_context(|| format!("writing '{upgraded}'"))?;
+        rename(&upgraded, &file_name)
+            .with_context(|| format!("replacing '{file_name}'"))?;
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// a segment of format version 1 starts with a record instead of the header
+/// a record is longer than any version, so its len can not be taken for the current one
+fn is_legacy(bytes: &[u8]) -> bool {
+    let version = bytes.get(..4)
+        .and_then(|version| BINCODE_OPTIONS.deserialize::<u32>(version).ok());
+    if version.is_none_or(|version| version == 0 || version == FORMAT_VERSION) {
+        return false;
+    }
+    matches!(legacy_records(bytes).next(), Some(Ok(_)))
+}
@@ -1,70 +1,145 @@
-use std::io::Write;
-use anyhow::{Result, Context};
-use memmap2::MmapMut;
-use crate::building_blocks::Entry;
-use super::{get_next_index, create_file, purge_all_files};
+use std::sync::Arc;
+use anyhow::{Result, Context, anyhow};
+use memmap2::MmapRaw;
+use crate::building_blocks::{Entry, WriteBatch};
+use super::{get_next_index, create_file, purge_all_files, purge_files_through, SyncMode, WalRecord, WalSync, SEGMENT_HEADER_SIZE};
 
 pub struct WriteAheadLog {
-    current_file: Option<MmapMut>,
+    current_file: Option<Arc<MmapRaw>>,
 
-    // amount of bytes written to the current file
+    // amount of bytes written to the current file, the header included
     current_file_len: usize,
 
+    /// index of the next segment, tracked here since old segments are removed while the wal is written
+    next_index: usize,
+
     /// in bytes
     segment_size: u64,
 
+    /// sequence of the last record added, sequences only grow, also across restarts
+    last_sequence: u64,
+
     // path to the wal segments
-    path: String
+    path: String,
+
+    /// shared with the writers waiting for their records to be synced
+    sync: Arc<WalSync>,
 }
 
 impl WriteAheadLog {
     /// wal_folder - where the segments are stored
     /// segment_size - size of each segment in bytes
+    /// the first segment is created by the first record, so it can start at any sequence
     pub fn new(wal_folder: &str, segment_size: u64) -> Result<Self> {
-        let mut s = WriteAheadLog {
+        let next_index = get_next_index(wal_folder)
+            .context("getting the next index available")?;
+        Ok(WriteAheadLog {
             current_file: None,
             current_file_len: 0,
+            next_index,
             segment_size,
-            path: wal_folder.to_owned()
-        };
-        s.generate_next_file().context("creating a segment")?;
-        Ok(s)
+            last_sequence: 0,
+            path: wal_folder.to_owned(),
+            sync: WalSync::new(SyncMode::default())?,
+        })
+    }
+
+    /// records get sequences after the given one, the highest sequence that was ever written or flushed
+    pub fn with_last_sequence(mut self, sequence: u64) -> Self {
+        self.last_sequence = sequence;
+        self
+    }
+
+    /// group commit by default
+    pub fn with_sync_mode(mut self, mode: SyncMode) -> Result<Self> {
+        self.sync = WalSync::new(mode)?;
+        self.sync.switch(self.current_file.clone(), self.current_file_len, false)?;
+        Ok(self)
+    }
+
+    /// writers wait on it for their records once they let go of the wal
+    pub fn sync_handle(&self) -> Arc<WalSync> {
+        Arc::clone(&self.sync)
+    }
+
+    /// sequence of the last record added
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
     }
 
-    pub fn add(&mut self, entry: &Entry) -> Result<()> {
-        let entry_ser = entry.serialize()?;
+    /// returns the position of the record, with every-write sync it is already on the disk
+    pub fn add(&mut self, entry: &Entry) -> Result<u64> {
+        self.add_record(&WalRecord::Entry(entry.clone()))
+    }
+
+    /// the whole batch is written as a single record
+    pub fn add_batch(&mut self, batch: &WriteBatch) -> Result<u64> {
+        self.add_record(&WalRecord::Batch(batch.clone()))
+    }
+
+    fn add_record(&mut self, record: &WalRecord) -> Result<u64> {
+        let sequence = self.last_sequence + 1;
+        let entry_ser = record.serialize(sequence)?;
 
         if (entry_ser.len() + self.current_file_len) as u64 > self.segment_size || self.current_file.is_none() {
-            self.generate_next_file().context("creating a new segment")?;
+            // entries bigger than the segment size get a segment of their own
+            let file_size = self.segment_size.max((SEGMENT_HEADER_SIZE + entry_ser.len()) as u64);
+            self.generate_next_file(file_size).context("creating a new segment")?;
         }
 
-        if let Some(file) = self.current_file.as_mut() {
-            (&mut file[self.current_file_len..])
-                .write_all(&entry_ser)
-                .context("writing entry")?;
-        } else {unreachable!()}
+        let Some(file) = self.current_file.as_ref() else { unreachable!() };
+        if self.current_file_len + entry_ser.len() > file.len() {
+            return Err(anyhow!("record does not fit in the segment"));
+        }
+        // only the wal writes to the segment and never over what it already wrote,
+        // syncs of the written part can run at the same time
+        unsafe {
+            std::ptr::copy_nonoverlapping(entry_ser.as_ptr(), file.as_mut_ptr().add(self.current_file_len), entry_ser.len());
+        }
 
         self.current_file_len += entry_ser.len();
-        Ok(())
+        self.last_sequence = sequence;
+        let position = self.sync.append(self.current_file_len)?;
+        if self.sync.mode() == SyncMode::EveryWrite {
+            self.sync.sync(position).context("syncing record")?;
+        }
+        Ok(position)
     }
 
     /// remove all files on the disk inlcuding the one that is mapped to mem
     /// only to be called once the memtable is flushed successfully
     pub fn purge(&mut self) -> Result<()> {
+        self.sync.switch(None, 0, true)?;
         self.current_file = None;
         self.current_file_len = 0;
-        Ok(purge_all_files(&self.path)?)
+        self.next_index = 0;
+        purge_all_files(&self.path)
     }
 
-    fn generate_next_file(&mut self) -> Result<()> {
-        let next_index = get_next_index(&self.path)
-            .context("getting the next index available")?;
+    /// closes the current segment and starts a new one
+    /// returns the index of the new segment, older segments only hold records written before the call
+    pub fn rotate(&mut self) -> Result<usize> {
+        self.generate_next_file(self.segment_size)
+            .context("creating a new segment")
+    }
 
-        let current_file = create_file(&self.path, next_index, self.segment_size)
+    /// removes the segments that only hold records up to the sequence
+    /// only to be called once the tables with those records are recorded in the manifest
+    pub fn purge_through(wal_folder: &str, sequence: u64) -> Result<()> {
+        purge_files_through(wal_folder, sequence)
+    }
+
+    /// returns the index of the new file, the records of the old one are synced first
+    fn generate_next_file(&mut self, file_size: u64) -> Result<usize> {
+        let index = self.next_index;
+        let current_file = create_file(&self.path, index, file_size, self.last_sequence + 1, self.sync.mode() != SyncMode::None)
                 .context("creating a new file")?;
+        let current_file = Arc::new(current_file);
+        self.sync.switch(Some(Arc::clone(&current_file)), SEGMENT_HEADER_SIZE, false)?;
 
         self.current_file = Some(current_file);
-        self.current_file_len = 0;
-        Ok(())
+        self.current_file_len = SEGMENT_HEADER_SIZE;
+        self.next_index += 1;
+        Ok(index)
     }
 }
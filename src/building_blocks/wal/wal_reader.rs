@@ -1,15 +1,61 @@
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, anyhow};
 use bincode::Options;
-use std::{fs::{read_dir, File, OpenOptions}, io::Read};
-use crate::building_blocks::{Entry, BINCODE_OPTIONS};
-use super::{get_next_index_avaiable, get_valid_path_names};
+use serde::{Serialize, Deserialize};
+use std::{fs::{read, read_dir, remove_file, OpenOptions}, io::{Seek, SeekFrom, Write}, path::Path};
+use crate::building_blocks::BINCODE_OPTIONS;
+use super::{get_valid_path_names, read_header, WalRecord, SEGMENT_HEADER_SIZE};
+
+/// len(8b) + crc(4b) in front of every record
+const RECORD_HEADER_SIZE: usize = 12;
+
+/// what recovery does with a record that is torn or fails its crc
+/// segments are preallocated and zero filled, zeros after the last record are the clean end of a segment
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryMode {
+    /// any damaged record or missing segment fails the recovery
+    Strict,
+    /// the log ends at the first damaged record or missing segment, everything after it is dropped
+    #[default]
+    TruncateAtCorruption,
+    /// damaged records and missing segments are dropped, reading goes on after them
+    /// a record whose len is damaged takes the rest of its segment with it
+    SkipCorrupt,
+}
+
+/// part of the log recovery left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedRange {
+    pub segment: usize,
+    /// offset in the segment, 0 for a missing segment
+    pub offset: u64,
+    /// bytes dropped, 0 for a missing segment
+    pub len: u64,
+    pub reason: String,
+}
 
-/// reads the wal folders and yields a vector of entries for each segment
-/// going from oldest to the newest segment, but entries themsevles are
-/// going from newest to the oldest
+/// what was read from the log and what was dropped
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RecoveryReport {
+    /// records read from the log
+    pub records: usize,
+    /// per segment read, the offset after its last good record
+    pub segment_ends: Vec<(usize, u64)>,
+    pub dropped: Vec<DroppedRange>,
+}
+
+/// reads the wal folders and yields a vector of records with their sequences for each segment
+/// going from oldest to the newest segment, records inside a segment are in the order they were written
+/// damaged records are handled by the recovery mode and reported
 pub struct WriteAheadLogReader {
-    files: Vec<String>,
+    /// indices of the segments left to read, newest first
+    segments: Vec<usize>,
     path: String,
+    mode: RecoveryMode,
+    report: RecoveryReport,
+    /// index of the segment that follows the last one read
+    next_segment: Option<usize>,
+    /// with truncation, the log ended in this segment at this offset
+    truncated_at: Option<(usize, u64)>,
 }
 
 impl WriteAheadLogReader {
@@ -18,83 +64,186 @@ impl WriteAheadLogReader {
         let paths = read_dir(path)
             .context("reading wal folder")?;
 
-        let mut file_names = get_valid_path_names(paths)?;
-        _ = get_next_index_avaiable(&file_names)
-            .context("check if there are files missing")?;
-
-        file_names.sort_unstable_by(|a, b| {
-            let a_index = a.split('-').last().unwrap().parse::<u64>().unwrap();
-            let b_index = b.split('-').last().unwrap().parse::<u64>().unwrap();
-            a_index.partial_cmp(&b_index).unwrap()
-        });
-        file_names.reverse();
+        let mut segments = get_valid_path_names(paths)?
+            .iter()
+            // its safe to unwrap since the names were validated
+            .map(|name| name.split('-').next_back().unwrap().parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        segments.sort_unstable_by(|a, b| b.cmp(a));
 
         Ok(WriteAheadLogReader {
-            files: file_names,
-            path: path.to_owned()
+            segments,
+            path: path.to_owned(),
+            mode: RecoveryMode::default(),
+            report: RecoveryReport::default(),
+            next_segment: None,
+            truncated_at: None,
         })
     }
-}
 
-impl Iterator for WriteAheadLogReader {
-    type Item = Result<Vec<Entry>>;
+    pub fn with_recovery_mode(mut self, mode: RecoveryMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut entries = Vec::new();
-        let file_name = if let Some(_) = self.files.last() {
-            self.files.pop().unwrap()
-        } else {
-            return None;
+    /// reads only the segment with the index, the others are not looked at
+    pub fn with_segment(mut self, segment: usize) -> Self {
+        self.segments.retain(|index| *index == segment);
+        self
+    }
+
+    /// complete once the reader is exhausted
+    pub fn report(&self) -> &RecoveryReport {
+        &self.report
+    }
+
+    /// removes what truncation dropped from the disk, the damaged tail of the segment the log ended in
+    /// is zeroed and the segments after it are removed, so records written later are not dropped with it
+    /// only to be called once the reader is exhausted
+    pub fn truncate(&self) -> Result<()> {
+        let Some((segment, offset)) = self.truncated_at else {
+            return Ok(());
         };
 
-        let file_name = format!("{}/{}", self.path, file_name);
-        let file = OpenOptions::new()
-            .read(true)
-            .open(file_name)
-            .context("opening file '{file_name}'");
-        if let Err(e) = file { return Some(Err(e)); }
-        let mut file = file.unwrap();
+        // the log can also end at a missing segment
+        let file_name = format!("{}/segment-{segment}", self.path);
+        if Path::new(&file_name).exists() {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&file_name)
+                .with_context(|| format!("opening '{file_name}'"))?;
+            let len = file.metadata().context("getting segment metadata")?.len();
+            file.seek(SeekFrom::Start(offset)).context("seeking to the damaged tail")?;
+            file.write_all(&vec![0; len.saturating_sub(offset) as usize])
+                .context("zeroing the damaged tail")?;
+            file.sync_all().context("syncing segment")?;
+        }
 
-        loop {
-            let entry = read_entry(&mut file)
-                .context("reading entry");
-            if let Err(e) = entry { return Some(Err(e)); }
-            else {
-                let entry = entry.unwrap();
-                if entry.is_none() { break; }
-                else { entries.push(entry.unwrap()); }
+        for dropped in self.report.dropped.iter().filter(|dropped| dropped.segment > segment) {
+            let file_name = format!("{}/segment-{}", self.path, dropped.segment);
+            if Path::new(&file_name).exists() {
+                remove_file(&file_name)
+                    .with_context(|| format!("removing '{file_name}'"))?;
             }
         }
-
-        Some(Ok(entries))
+        Ok(())
     }
-}
 
-/// reads until it reaches end of the file or len 0
-fn read_entry(file: &mut File) -> Result<Option<Entry>> {
-    let mut len_ser = vec![0; 8];
-    let res = file.read_exact(&mut len_ser);
-    if let Err(e) = res.as_ref() {
-        return match e.kind() {
-            std::io::ErrorKind::UnexpectedEof => Ok(None),
-            _ => Err(res.context("reading index entry len").err().unwrap())
-        };
-    }
+    /// records of the segment, None once the log is truncated
+    fn read_segment(&mut self, segment: usize) -> Result<Option<Vec<(u64, WalRecord)>>> {
+        if self.truncated_at.is_some() {
+            self.drop_segment(segment, "after the point the log was truncated at")?;
+            return Ok(None);
+        }
+
+        // segments of flushed memtables are removed from the front, all indices after the first one have to exist
+        if let Some(expected) = self.next_segment.filter(|expected| *expected < segment) {
+            for missing in expected..segment {
+                self.damaged(missing, 0, 0, "segment is missing")?;
+            }
+            if self.truncated_at.is_some() {
+                self.drop_segment(segment, "after a missing segment")?;
+                return Ok(None);
+            }
+        }
+        self.next_segment = Some(segment + 1);
+
+        let file_name = format!("{}/segment-{segment}", self.path);
+        let bytes = read(&file_name)
+            .with_context(|| format!("reading '{file_name}'"))?;
+
+        let mut records = Vec::new();
+        // a segment whose header was never written holds no records
+        if read_header(&bytes)?.is_none() {
+            if bytes.iter().any(|byte| *byte != 0) {
+                self.damaged(segment, 0, bytes.len() as u64, "segment header is missing")?;
+            }
+            self.report.segment_ends.push((segment, 0));
+            return Ok(Some(records));
+        }
+
+        // records go on while a byte after them is not zero, found once instead of after every record
+        let data_end = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+        let mut offset = SEGMENT_HEADER_SIZE;
+        let mut end = offset;
+        while offset < data_end {
+            let rest = (bytes.len() - offset) as u64;
+            if bytes.len() - offset < RECORD_HEADER_SIZE {
+                self.damaged(segment, offset as u64, rest, "record header is torn")?;
+                break;
+            }
+
+            let len: u64 = BINCODE_OPTIONS
+                .deserialize(&bytes[offset..offset + 8])
+                .context("deserializing record len")?;
+            // a torn len can point past the end of the segment, nothing after it can be found
+            if len == 0 || len > rest - RECORD_HEADER_SIZE as u64 {
+                self.damaged(segment, offset as u64, rest, "record len is damaged")?;
+                break;
+            }
+
+            let record_end = offset + RECORD_HEADER_SIZE + len as usize;
+            let record_ser = &bytes[offset + 8..record_end];
+            if !WalRecord::is_intact(record_ser) {
+                let len = (record_end - offset) as u64;
+                let skipped = self.mode == RecoveryMode::SkipCorrupt;
+                self.damaged(segment, offset as u64, if skipped { len } else { rest }, "record crc does not match")?;
+                if !skipped {
+                    break;
+                }
+                offset = record_end;
+                continue;
+            }
+
+            let record = WalRecord::deserialize(record_ser)
+                .with_context(|| format!("deserializing record at offset {offset} of segment {segment}"))?;
+            records.push(record);
+            offset = record_end;
+            end = offset;
+        }
 
-    let len: u64 = BINCODE_OPTIONS
-        .deserialize(&len_ser)
-        .context("deserializing entry len")?;
+        self.report.records += records.len();
+        self.report.segment_ends.push((segment, end as u64));
+        Ok(Some(records))
+    }
 
-    if len == 0 {
-        return Ok(None);
+    /// the whole segment is left out
+    fn drop_segment(&mut self, segment: usize, reason: &str) -> Result<()> {
+        let file_name = format!("{}/segment-{segment}", self.path);
+        let len = std::fs::metadata(&file_name)
+            .with_context(|| format!("getting metadata of '{file_name}'"))?
+            .len();
+        self.report.dropped.push(DroppedRange { segment, offset: 0, len, reason: reason.to_string() });
+        Ok(())
     }
 
-    let mut entry_ser = vec![0; (len + 4) as usize];
-    file.read_exact(&mut entry_ser[..])
-        .context("reading entry")?;
+    /// fails in strict mode, otherwise the range is reported and with truncation the log ends here
+    fn damaged(&mut self, segment: usize, offset: u64, len: u64, reason: &str) -> Result<()> {
+        if self.mode == RecoveryMode::Strict {
+            return Err(anyhow!("segment {segment} is damaged at offset {offset}: {reason}"));
+        }
+        self.report.dropped.push(DroppedRange { segment, offset, len, reason: reason.to_string() });
+        if self.mode == RecoveryMode::TruncateAtCorruption && self.truncated_at.is_none() {
+            self.truncated_at = Some((segment, offset));
+        }
+        Ok(())
+    }
+}
 
-    let entry = Entry::deserialize(&entry_ser[..])
-        .context("deserializing entry")?;
+impl Iterator for WriteAheadLogReader {
+    type Item = Result<Vec<(u64, WalRecord)>>;
 
-    Ok(Some(entry))
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let segment = self.segments.pop()?;
+            match self.read_segment(segment) {
+                Ok(Some(records)) => return Some(Ok(records)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.segments.clear();
+                    return Some(Err(e.context(format!("reading segment {segment}"))));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use anyhow::{Result, Context, anyhow};
+use crc::{Crc, CRC_32_JAMCRC};
+use serde::{Serialize, Deserialize};
+use bincode::Options;
+use crate::building_blocks::{Entry, WriteBatch, BINCODE_OPTIONS};
+
+/// a single framed record in the WAL
+/// |len(u64),CRC(u32),sequence(u64),record|
+/// the crc covers the sequence and the whole record so a batch is either read whole or detected as torn
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum WalRecord {
+    Entry(Entry),
+    Batch(WriteBatch),
+}
+
+impl WalRecord {
+    /// record is serialized in format:
+    /// length of the record(8b)+crc(4b)+sequence(8b)+record
+    pub fn serialize(&self, sequence: u64) -> Result<Vec<u8>> {
+        let record_ser = BINCODE_OPTIONS
+            .serialize(&(sequence, self))
+            .context("serializing wal record")?;
+
+        let record_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+            .checksum(&record_ser[..]);
+
+        let mut len_ser = BINCODE_OPTIONS
+            .serialize(&(record_ser.len() as u64))
+            .context("serializing wal record len")?;
+
+        let crc_ser = BINCODE_OPTIONS
+            .serialize(&record_crc)
+            .context("serializing wal record crc")?;
+
+        len_ser.extend_from_slice(&crc_ser[..]);
+        len_ser.extend_from_slice(&record_ser[..]);
+        Ok(len_ser)
+    }
+
+    /// false if the record was only partially written or got corrupted afterwards
+    /// expected slice: crc(4b)+sequence(8b)+record
+    pub fn is_intact(record: &[u8]) -> bool {
+        if record.len() < 4 {
+            return false;
+        }
+
+        let crc_deser: Result<u32, _> = BINCODE_OPTIONS.deserialize(&record[..4]);
+        let computed_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+            .checksum(&record[4..]);
+        matches!(crc_deser, Ok(crc) if crc == computed_crc)
+    }
+
+    /// returns the sequence and the record
+    /// expected slice: crc(4b)+sequence(8b)+record
+    pub fn deserialize(record: &[u8]) -> Result<(u64, WalRecord)> {
+        let crc_deser: u32 = BINCODE_OPTIONS
+            .deserialize(&record[..4])
+            .context("deserializing crc")?;
+
+        let computed_crc = Crc::<u32>::new(&CRC_32_JAMCRC)
+            .checksum(&record[4..]);
+
+        if crc_deser != computed_crc {
+            let e = anyhow!("crc does not match")
+                .context("deserializing wal record");
+            return Err(e);
+        }
+
+        BINCODE_OPTIONS
+            .deserialize(&record[4..])
+            .context("deserializing wal record")
+    }
+
+    /// entries in the order they have to be applied
+    pub fn into_entries(self) -> Vec<Entry> {
+        match self {
+            WalRecord::Entry(entry) => vec![entry],
+            WalRecord::Batch(batch) => batch.entries,
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration
+};
+use anyhow::{Result, Context, anyhow};
+use memmap2::MmapRaw;
+use serde::{Serialize, Deserialize};
+
+/// when the records written to the WAL reach the disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// left to the OS, a crash of the machine can lose writes that already returned
+    None,
+    /// every record is synced before its write returns, writers sync one after another
+    EveryWrite,
+    /// a background thread syncs every this many milliseconds,
+    /// a crash loses at most the writes since the last sync
+    Interval(u64),
+    /// a write returns once its record is synced, writers waiting at the same time share one sync
+    #[default]
+    Group,
+}
+
+impl SyncMode {
+    /// an interval of 0 would keep the sync thread spinning
+    pub fn validate(&self) -> Result<()> {
+        if *self == SyncMode::Interval(0) {
+            return Err(anyhow!("sync interval has to be at least 1 ms, EveryWrite syncs every record"));
+        }
+        Ok(())
+    }
+}
+
+/// how much of the WAL is written and how much of it is on the disk,
+/// shared by the WAL, the writers waiting for their records and the sync thread
+pub struct WalSync {
+    mode: SyncMode,
+    state: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+struct SyncState {
+    /// segment being written, None once the WAL is purged
+    segment: Option<Arc<MmapRaw>>,
+
+    /// bytes of the segment that are written, the header included
+    written: usize,
+
+    /// bytes of the segment that are on the disk
+    synced: usize,
+
+    /// records added since the WAL was opened, the position of a record is the count after adding it
+    appended: u64,
+
+    /// records known to be on the disk
+    durable: u64,
+
+    /// a writer is syncing, the others wait for it and sync only if their records were not covered
+    syncing: bool,
+
+    /// the sync thread stops at its first error, writes fail from then on
+    error: Option<String>,
+}
+
+impl WalSync {
+    pub fn new(mode: SyncMode) -> Result<Arc<Self>> {
+        mode.validate()?;
+        let state = SyncState {
+            segment: None,
+            written: 0,
+            synced: 0,
+            appended: 0,
+            durable: 0,
+            syncing: false,
+            error: None,
+        };
+        let sync = Arc::new(WalSync { mode, state: Mutex::new(state), synced: Condvar::new() });
+        if let SyncMode::Interval(millis) = mode {
+            start_sync_thread(Arc::downgrade(&sync), Duration::from_millis(millis))
+                .context("starting wal sync thread")?;
+        }
+        Ok(sync)
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    /// returns once the record at the position is as durable as the mode promises,
+    /// with group commit the caller syncs for everybody waiting or waits for the one who does
+    pub fn wait(&self, position: u64) -> Result<()> {
+        match self.mode {
+            SyncMode::Group => self.sync(position),
+            _ => Ok(()),
+        }
+    }
+
+    /// number of records known to be on the disk
+    pub fn durable(&self) -> Result<u64> {
+        Ok(self.lock()?.durable)
+    }
+
+    /// the WAL wrote a record, returns its position
+    pub(super) fn append(&self, written: usize) -> Result<u64> {
+        let mut state = self.lock()?;
+        if let Some(error) = state.error.as_ref() {
+            return Err(anyhow!("syncing wal in the background: {error}"));
+        }
+        state.written = written;
+        state.appended += 1;
+        Ok(state.appended)
+    }
+
+    /// the WAL moved to a new segment, the records of the old one are synced first unless the mode is None
+    /// purged records do not have to be synced, they are in the sstables
+    pub(super) fn switch(&self, segment: Option<Arc<MmapRaw>>, written: usize, purged: bool) -> Result<()> {
+        let mut state = self.lock()?;
+        let unsynced = state.written > state.synced && !purged && self.mode != SyncMode::None;
+        if let (Some(old), true) = (state.segment.as_ref(), unsynced) {
+            old.flush_range(state.synced, state.written - state.synced)
+                .context("syncing wal segment")?;
+        }
+        if self.mode != SyncMode::None {
+            state.durable = state.appended;
+        }
+        // the first sync of the segment covers its header too
+        state.segment = segment;
+        state.written = written;
+        state.synced = 0;
+        self.synced.notify_all();
+        Ok(())
+    }
+
+    /// syncs everything written so far unless the record at the position is already synced
+    pub(super) fn sync(&self, position: u64) -> Result<()> {
+        let mut state = self.lock()?;
+        loop {
+            if state.durable >= position {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state)
+                .map_err(|_| anyhow!("wal sync lock poisoned"))?;
+        }
+
+        // records written while this sync runs are left for the next one
+        state.syncing = true;
+        let segment = state.segment.clone();
+        let (from, to, target) = (state.synced, state.written, state.appended);
+        drop(state);
+
+        let res = match segment.as_ref() {
+            Some(segment) if to > from => segment.flush_range(from, to - from).context("syncing wal segment"),
+            _ => Ok(()),
+        };
+
+        let mut state = self.lock()?;
+        state.syncing = false;
+        if res.is_ok() {
+            state.durable = state.durable.max(target);
+            // the segment could have been switched in the meantime, the new one is not synced
+            let same_segment = match (segment.as_ref(), state.segment.as_ref()) {
+                (Some(synced), Some(current)) => Arc::ptr_eq(synced, current),
+                _ => false,
+            };
+            if same_segment {
+                state.synced = state.synced.max(to);
+            }
+        }
+        self.synced.notify_all();
+        res
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, SyncState>> {
+        self.state.lock().map_err(|_| anyhow!("wal sync lock poisoned"))
+    }
+}
+
+/// syncs the WAL every interval until it is dropped
+fn start_sync_thread(sync: Weak<WalSync>, every: Duration) -> Result<()> {
+    thread::Builder::new()
+        .name(String::from("nebula-wal-sync"))
+        .spawn(move || loop {
+            thread::sleep(every);
+            let Some(sync) = sync.upgrade() else {
+                return;
+            };
+            let res = sync.lock()
+                .map(|state| state.appended)
+                .and_then(|appended| sync.sync(appended));
+            if let Err(e) = res {
+                if let Ok(mut state) = sync.lock() {
+                    state.error = Some(format!("{e:#}"));
+                }
+                return;
+            }
+        })?;
+    Ok(())
+}
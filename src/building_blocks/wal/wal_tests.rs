@@ -0,0 +1,499 @@
+use std::{
+    fs::{create_dir_all, remove_dir_all, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant}
+};
+use crate::building_blocks::{Entry, EntryKind, WriteBatch};
+use super::{WriteAheadLog, WriteAheadLogReader, WalRecord, SyncMode, RecoveryMode, RecoveryReport, SEGMENT_HEADER_SIZE};
+
+fn clean_dir(path: &str) {
+    if Path::new(path).is_dir() {
+        remove_dir_all(path).expect("removing old wal");
+    }
+    create_dir_all(path).expect("creating wal dir");
+}
+
+fn entry(key: &str, value: Option<&str>) -> Entry {
+    Entry {
+        timestamp: 1,
+        key: key.as_bytes().to_vec(),
+        value: value.map(|value| value.as_bytes().to_vec()),
+        kind: EntryKind::Point,
+        expires_at: None,
+    }
+}
+
+fn read_all(path: &str) -> Vec<WalRecord> {
+    WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .flat_map(|records| records.expect("reading segment"))
+        .map(|(_, record)| record)
+        .collect()
+}
+
+fn read_with(path: &str, mode: RecoveryMode) -> (Vec<WalRecord>, RecoveryReport) {
+    let mut reader = WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .with_recovery_mode(mode);
+    let records = reader.by_ref()
+        .flat_map(|records| records.expect("reading segment"))
+        .map(|(_, record)| record)
+        .collect();
+    (records, reader.report().clone())
+}
+
+fn records(keys: &[&str]) -> Vec<WalRecord> {
+    keys.iter().map(|key| WalRecord::Entry(entry(key, Some("0")))).collect()
+}
+
+/// every key in a segment of its own
+fn wal_of_segments(path: &str, keys: &[&str]) {
+    clean_dir(path);
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            wal.rotate().expect("rotating wal");
+        }
+        wal.add(&entry(key, Some("0"))).expect("adding entry");
+    }
+}
+
+/// flips the last byte of the first record in the segment, its crc does not match anymore
+fn corrupt_first_record(path: &str, segment: usize) {
+    let record_len = WalRecord::Entry(entry("0", Some("0"))).serialize(0).unwrap().len();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-{segment}"))
+        .expect("opening segment");
+    file.seek(SeekFrom::Start((SEGMENT_HEADER_SIZE + record_len - 1) as u64)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+}
+
+#[test]
+fn batch_is_one_record() {
+    let path = "test-data/wal-batch";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1").delete(b"b").put(b"c", b"3");
+
+    wal.add(&entry("x", Some("0"))).expect("adding entry");
+    wal.add_batch(&batch).expect("adding batch");
+
+    let records = read_all(path);
+    assert_eq!(records, vec![WalRecord::Entry(entry("x", Some("0"))), WalRecord::Batch(batch)]);
+}
+
+#[test]
+fn torn_batch_is_skipped() {
+    let path = "test-data/wal-torn-batch";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("x", Some("0"))).expect("adding entry");
+    let entry_len = WalRecord::Entry(entry("x", Some("0"))).serialize(0).unwrap().len();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1").put(b"b", b"2");
+    wal.add_batch(&batch).expect("adding batch");
+    let batch_len = WalRecord::Batch(batch).serialize(0).unwrap().len();
+    drop(wal);
+
+    // only the first half of the batch made it to the disk
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    let torn_offset = SEGMENT_HEADER_SIZE + entry_len + batch_len / 2;
+    file.seek(SeekFrom::Start(torn_offset as u64)).unwrap();
+    file.write_all(&vec![0; batch_len - batch_len / 2]).unwrap();
+
+    let records = read_all(path);
+    assert_eq!(records, vec![WalRecord::Entry(entry("x", Some("0")))]);
+}
+
+#[test]
+fn torn_len_is_skipped() {
+    let path = "test-data/wal-torn-len";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 100).expect("creating wal");
+    wal.add(&entry("x", Some("0"))).expect("adding entry");
+    let entry_len = WalRecord::Entry(entry("x", Some("0"))).serialize(0).unwrap().len();
+    drop(wal);
+
+    // len of the next record points past the end of the segment
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    file.seek(SeekFrom::Start((SEGMENT_HEADER_SIZE + entry_len) as u64)).unwrap();
+    file.write_all(&u64::MAX.to_le_bytes()).unwrap();
+
+    let records = read_all(path);
+    assert_eq!(records, vec![WalRecord::Entry(entry("x", Some("0")))]);
+}
+
+#[test]
+fn rotate_and_purge_through() {
+    let path = "test-data/wal-rotate";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("a", Some("0"))).expect("adding entry");
+    let segment = wal.rotate().expect("rotating wal");
+    assert_eq!(segment, 1);
+    wal.add(&entry("b", Some("1"))).expect("adding entry");
+
+    // the record of the first segment is not flushed yet
+    WriteAheadLog::purge_through(path, 0).expect("purging old segments");
+    assert!(Path::new(&format!("{path}/segment-0")).exists());
+
+    WriteAheadLog::purge_through(path, 1).expect("purging old segments");
+    assert!(!Path::new(&format!("{path}/segment-0")).exists());
+
+    // the newest segment is kept, nothing says where it ends
+    WriteAheadLog::purge_through(path, 2).expect("purging old segments");
+    assert!(Path::new(&format!("{path}/segment-1")).exists());
+
+    // the log does not have to start at the first segment
+    let records = read_all(path);
+    assert_eq!(records, vec![WalRecord::Entry(entry("b", Some("1")))]);
+
+    assert_eq!(wal.rotate().expect("rotating wal"), 2);
+}
+
+#[test]
+fn other_format_version_is_rejected() {
+    let path = "test-data/wal-format-version";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("a", Some("0"))).expect("adding entry");
+    drop(wal);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    file.write_all(&1u32.to_le_bytes()).unwrap();
+
+    let segments = WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .collect::<Vec<_>>();
+    assert!(segments[0].is_err());
+}
+
+#[test]
+fn every_write_is_synced_before_add_returns() {
+    let path = "test-data/wal-sync-every-write";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::EveryWrite).expect("setting sync mode");
+    let sync = wal.sync_handle();
+    for i in 0..5 {
+        let position = wal.add(&entry(&i.to_string(), Some("0"))).expect("adding entry");
+        assert_eq!(sync.durable().unwrap(), position);
+    }
+}
+
+#[test]
+fn none_and_group_leave_syncing_to_the_caller() {
+    let path = "test-data/wal-sync-none";
+    clean_dir(path);
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::None).expect("setting sync mode");
+    let position = wal.add(&entry("a", Some("0"))).expect("adding entry");
+    wal.sync_handle().wait(position).expect("waiting for the record");
+    assert_eq!(wal.sync_handle().durable().unwrap(), 0);
+
+    let path = "test-data/wal-sync-group";
+    clean_dir(path);
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    let position = wal.add(&entry("a", Some("0"))).expect("adding entry");
+    assert_eq!(wal.sync_handle().durable().unwrap(), 0);
+    wal.sync_handle().wait(position).expect("waiting for the record");
+    assert_eq!(wal.sync_handle().durable().unwrap(), position);
+}
+
+#[test]
+fn rotation_syncs_the_old_segment() {
+    let path = "test-data/wal-sync-rotation";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::Interval(60_000)).expect("setting sync mode");
+    let position = wal.add(&entry("a", Some("0"))).expect("adding entry");
+    assert_eq!(wal.sync_handle().durable().unwrap(), 0);
+    wal.rotate().expect("rotating wal");
+    assert_eq!(wal.sync_handle().durable().unwrap(), position);
+}
+
+#[test]
+fn interval_syncs_in_the_background() {
+    let path = "test-data/wal-sync-interval";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::Interval(5)).expect("setting sync mode");
+    let position = wal.add(&entry("a", Some("0"))).expect("adding entry");
+    let sync = wal.sync_handle();
+    let start = Instant::now();
+    while sync.durable().unwrap() < position {
+        assert!(start.elapsed() < Duration::from_secs(10), "record was never synced");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn zero_interval_is_rejected() {
+    let path = "test-data/wal-sync-zero-interval";
+    clean_dir(path);
+
+    let wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    assert!(wal.with_sync_mode(SyncMode::Interval(0)).is_err());
+}
+
+#[test]
+fn group_commit_concurrent_writers() {
+    let path = "test-data/wal-group-commit";
+    clean_dir(path);
+
+    let wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::Group).expect("setting sync mode");
+    let sync = wal.sync_handle();
+    let wal = Arc::new(Mutex::new(wal));
+    let writers = (0..8).map(|writer| {
+        let wal = Arc::clone(&wal);
+        let sync = Arc::clone(&sync);
+        thread::spawn(move || {
+            for i in 0..25 {
+                let key = format!("{writer}-{i}");
+                let position = wal.lock().unwrap().add(&entry(&key, Some("0"))).expect("adding entry");
+                // the wal is not locked while waiting, other writers can add their records to the same sync
+                sync.wait(position).expect("waiting for the record");
+                assert!(sync.durable().unwrap() >= position);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for writer in writers {
+        writer.join().expect("joining writer");
+    }
+
+    assert_eq!(sync.durable().unwrap(), 200);
+    assert_eq!(read_all(path).len(), 200);
+}
+
+#[test]
+fn crash_truncates_the_segment() {
+    let path = "test-data/wal-crash-truncated";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::EveryWrite).expect("setting sync mode");
+    let record_len = WalRecord::Entry(entry("0", Some("0"))).serialize(0).unwrap().len();
+    for i in 0..5 {
+        wal.add(&entry(&i.to_string(), Some("0"))).expect("adding entry");
+    }
+    drop(wal);
+
+    // the machine went down while the fourth record was written, nothing after it reached the disk
+    let segment = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    segment.set_len((SEGMENT_HEADER_SIZE + 3 * record_len + record_len / 2) as u64).unwrap();
+
+    let records = read_all(path);
+    let expected = (0..3).map(|i| WalRecord::Entry(entry(&i.to_string(), Some("0")))).collect::<Vec<_>>();
+    assert_eq!(records, expected);
+
+    // only the header made it
+    segment.set_len(SEGMENT_HEADER_SIZE as u64).unwrap();
+    assert!(read_all(path).is_empty());
+
+    // not even the header
+    segment.set_len(0).unwrap();
+    assert!(read_all(path).is_empty());
+}
+
+#[test]
+fn crash_corrupts_a_record() {
+    let path = "test-data/wal-crash-corrupted";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_sync_mode(SyncMode::EveryWrite).expect("setting sync mode");
+    let record_len = WalRecord::Entry(entry("0", Some("0"))).serialize(0).unwrap().len();
+    for i in 0..5 {
+        wal.add(&entry(&i.to_string(), Some("0"))).expect("adding entry");
+    }
+    drop(wal);
+
+    // a byte of the third record's value was never written, its crc does not match
+    let mut segment = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    segment.seek(SeekFrom::Start((SEGMENT_HEADER_SIZE + 3 * record_len - 1) as u64)).unwrap();
+    segment.write_all(&[0xff]).unwrap();
+
+    let records = read_all(path);
+    let expected = (0..2).map(|i| WalRecord::Entry(entry(&i.to_string(), Some("0")))).collect::<Vec<_>>();
+    assert_eq!(records, expected);
+}
+
+#[test]
+fn strict_recovery_fails_at_damage() {
+    let path = "test-data/wal-recovery-strict";
+    wal_of_segments(path, &["a", "b"]);
+    corrupt_first_record(path, 1);
+
+    let segments = WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .with_recovery_mode(RecoveryMode::Strict)
+        .collect::<Vec<_>>();
+    assert!(segments[0].is_ok());
+    assert!(segments[1].is_err());
+}
+
+#[test]
+fn skip_corrupt_reads_past_a_bad_record() {
+    let path = "test-data/wal-recovery-skip";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    for key in ["a", "b", "c"] {
+        wal.add(&entry(key, Some("0"))).expect("adding entry");
+    }
+    drop(wal);
+    corrupt_first_record(path, 0);
+
+    let (read, report) = read_with(path, RecoveryMode::SkipCorrupt);
+    assert_eq!(read, records(&["b", "c"]));
+    let record_len = WalRecord::Entry(entry("0", Some("0"))).serialize(0).unwrap().len() as u64;
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!((report.dropped[0].offset, report.dropped[0].len), (SEGMENT_HEADER_SIZE as u64, record_len));
+    assert_eq!(report.segment_ends, vec![(0, SEGMENT_HEADER_SIZE as u64 + 3 * record_len)]);
+
+    let (read, _) = read_with(path, RecoveryMode::TruncateAtCorruption);
+    assert!(read.is_empty());
+}
+
+#[test]
+fn truncation_drops_the_later_segments() {
+    let path = "test-data/wal-recovery-truncate";
+    wal_of_segments(path, &["a", "b", "c"]);
+    corrupt_first_record(path, 1);
+
+    let mut reader = WriteAheadLogReader::iter(path).expect("getting wal reader");
+    let read = reader.by_ref()
+        .flat_map(|records| records.expect("reading segment"))
+        .map(|(_, record)| record)
+        .collect::<Vec<_>>();
+    assert_eq!(read, records(&["a"]));
+    let dropped = reader.report().dropped.iter()
+        .map(|dropped| (dropped.segment, dropped.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(dropped, vec![(1, SEGMENT_HEADER_SIZE as u64), (2, 0)]);
+
+    // the damage is cut off the disk, reading again finds a clean log
+    reader.truncate().expect("truncating wal");
+    assert!(!Path::new(&format!("{path}/segment-2")).exists());
+    let (read, report) = read_with(path, RecoveryMode::Strict);
+    assert_eq!(read, records(&["a"]));
+    assert!(report.dropped.is_empty());
+    assert_eq!(report.segment_ends[1], (1, SEGMENT_HEADER_SIZE as u64));
+
+    // new segments follow the ones left
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("d", Some("0"))).expect("adding entry");
+    assert_eq!(read_with(path, RecoveryMode::Strict).0, records(&["a", "d"]));
+}
+
+#[test]
+fn missing_segment_in_every_mode() {
+    let path = "test-data/wal-recovery-missing";
+    wal_of_segments(path, &["a", "b", "c"]);
+    std::fs::remove_file(format!("{path}/segment-1")).unwrap();
+
+    let segments = WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .with_recovery_mode(RecoveryMode::Strict)
+        .collect::<Vec<_>>();
+    assert!(segments.iter().any(Result::is_err));
+
+    let (read, report) = read_with(path, RecoveryMode::SkipCorrupt);
+    assert_eq!(read, records(&["a", "c"]));
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!((report.dropped[0].segment, report.dropped[0].len), (1, 0));
+
+    let (read, report) = read_with(path, RecoveryMode::TruncateAtCorruption);
+    assert_eq!(read, records(&["a"]));
+    let dropped = report.dropped.iter().map(|dropped| dropped.segment).collect::<Vec<_>>();
+    assert_eq!(dropped, vec![1, 2]);
+}
+
+#[test]
+fn garbage_after_the_last_record_is_reported() {
+    let path = "test-data/wal-recovery-garbage";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("a", Some("0"))).expect("adding entry");
+    drop(wal);
+
+    // the len after the record reads as 0, but the segment is not zero from there on
+    let end = (SEGMENT_HEADER_SIZE + WalRecord::Entry(entry("a", Some("0"))).serialize(0).unwrap().len()) as u64;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(format!("{path}/segment-0"))
+        .expect("opening segment");
+    file.seek(SeekFrom::Start(end + 20)).unwrap();
+    file.write_all(&[0xab; 4]).unwrap();
+
+    let (read, report) = read_with(path, RecoveryMode::TruncateAtCorruption);
+    assert_eq!(read, records(&["a"]));
+    assert_eq!(report.records, 1);
+    assert_eq!(report.segment_ends, vec![(0, end)]);
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!((report.dropped[0].offset, report.dropped[0].len), (end, 1000 - end));
+}
+
+#[test]
+fn sequences_grow_across_segments_and_restarts() {
+    let path = "test-data/wal-sequences";
+    clean_dir(path);
+
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+    wal.add(&entry("a", Some("0"))).expect("adding entry");
+    wal.rotate().expect("rotating wal");
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"1").put(b"c", b"2");
+    wal.add_batch(&batch).expect("adding batch");
+    assert_eq!(wal.last_sequence(), 2);
+    drop(wal);
+
+    // sequences go on after the highest one written before
+    let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal")
+        .with_last_sequence(10);
+    wal.add(&entry("d", Some("0"))).expect("adding entry");
+
+    let sequences = WriteAheadLogReader::iter(path)
+        .expect("getting wal reader")
+        .flat_map(|records| records.expect("reading segment"))
+        .map(|(sequence, _)| sequence)
+        .collect::<Vec<_>>();
+    assert_eq!(sequences, vec![1, 2, 11]);
+
+    // a segment is only known to end where the next one starts
+    WriteAheadLog::purge_through(path, 2).expect("purging old segments");
+    assert_eq!(read_all(path), vec![WalRecord::Batch(batch), WalRecord::Entry(entry("d", Some("0")))]);
+    WriteAheadLog::purge_through(path, 10).expect("purging old segments");
+    assert_eq!(read_all(path), vec![WalRecord::Entry(entry("d", Some("0")))]);
+}
@@ -0,0 +1,72 @@
+use serde::{Serialize, Deserialize};
+use super::{Entry, EntryKind};
+
+/// group of puts and deletes that become durable and visible all together or not at all
+/// the whole batch is written to the WAL as a single record
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct WriteBatch {
+    /// operations in the order they were added, tombstones have no value
+    /// range tombstones hold the exclusive end of the range
+    pub(crate) entries: Vec<Entry>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch { entries: Vec::new() }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.push(key, Some(value.to_vec()))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.push(key, None)
+    }
+
+    /// deletes the keys for which start <= key <= end, puts of the same batch are not deleted
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) -> &mut Self {
+        self.entries.push(Entry::range_delete(0, start.to_vec(), range_end(end)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// all operations in a batch share the timestamp of the moment it was written
+    pub(crate) fn set_timestamp(&mut self, timestamp: u128) {
+        for entry in self.entries.iter_mut() {
+            entry.timestamp = timestamp;
+        }
+    }
+
+    fn push(&mut self, key: &[u8], value: Option<Vec<u8>>) -> &mut Self {
+        self.entries.push(Entry {
+            timestamp: 0,
+            key: key.to_vec(),
+            value,
+            kind: EntryKind::Point,
+            expires_at: None,
+        });
+        self
+    }
+}
+
+/// smallest key bigger than end, range tombstones store an exclusive end
+pub(crate) fn range_end(end: &[u8]) -> Vec<u8> {
+    let mut end = end.to_vec();
+    end.push(0);
+    end
+}
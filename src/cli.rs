@@ -1,5 +1,5 @@
 use std::path::PathBuf;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[command(name = "nebula")]
@@ -13,9 +13,82 @@ pub enum CliCommands {
     /// start the database
     Start,
 
+    /// start the database as a server speaking the redis protocol (RESP2)
+    Serve {
+        #[arg(long, default_value_t = 6379)]
+        port: u16,
+
+        /// address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+    },
+
+    /// start the database as an HTTP server with a JSON api
+    Http {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        /// address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+    },
+
+    /// inspects the write-ahead log
+    Wal {
+        #[command(subcommand)]
+        command: WalCommands,
+    },
+
+    /// inspects sstables
+    Sstable {
+        #[command(subcommand)]
+        command: SSTableCommands,
+    },
+
     /// generates test data
     GenerateTestData,
 
     /// executes query from a prepared file against the database
     DummyData { file_name: PathBuf }
 }
+
+#[derive(Debug, Subcommand)]
+pub enum WalCommands {
+    /// decodes the segments of a WAL folder, or a single segment file, and prints their records
+    Dump(DumpArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SSTableCommands {
+    /// decodes an sstable directory and prints its header, filter, summary, index, data and merkle root
+    Dump(DumpArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct DumpArgs {
+    pub path: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = DumpFormat::Text)]
+    pub format: DumpFormat,
+
+    /// how keys and values are printed
+    #[arg(long, value_enum, default_value_t = Encoding::Utf8)]
+    pub encoding: Encoding,
+
+    /// fails at the first checksum that does not match, otherwise the damage is reported and skipped
+    #[arg(long)]
+    pub verify: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DumpFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Encoding {
+    /// invalid sequences are replaced
+    Utf8,
+    Hex,
+}
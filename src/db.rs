@@ -0,0 +1,1895 @@
+use crate::building_blocks::{
+    BTree, Cache, CacheStats, Entry, ImmutableMemtable, LSMTree, LSMTreeInterface, Memtable, MemtableEntry, WriteAheadLog, WalSync,
+    WriteAheadLogReader, RecoveryReport, WriteBatch, Snapshot, newest_range_delete, range_end, EntryKind, Page, PageToken, EntryIterator, MergeIterator, MemtableIterator, SnapshotList, SF, BloomFilter, HyperLogLog, CountMinSketch, SimHash, SkipList, MF, BINCODE_OPTIONS, MAX_KEY_LEN, MAX_VAL_LEN, similarity, upgrade_segments,
+};
+use crate::building_blocks::FileOrganization::{SingleFile, MultiFile};
+use crate::flusher::{self, Flusher, Immutables, SharedLsm};
+use crate::utils::config::{Config, MemtableStorage};
+use crate::utils::helpers::get_timestamp;
+use anyhow::{Context, Result, anyhow};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use bincode::Options;
+
+/// values read from the tables by their key, a cached None is a deleted key
+type ValueCache = Cache<Vec<u8>, Option<Vec<u8>>>;
+
+/// handle to an opened database
+///
+/// all operations return their results instead of printing them,
+/// the REPL and the CLI are just clients of this type
+///
+/// the handle is `Send + Sync` and can be shared between threads,
+/// readers run in parallel while writers are serialized by the WAL lock
+/// locks are always taken in the order wal, memtable, flusher, immutables, lsm, cache, wal sync
+///
+/// a full memtable is frozen and written to an sstable by a background thread,
+/// until then it keeps serving reads and new writes land in a fresh memtable
+pub struct Db {
+    /// held for the whole write so entries reach the memtable in timestamp order
+    wal: Mutex<WriteAheadLog>,
+    /// writers wait on it for their records once they let go of the locks
+    wal_sync: Arc<WalSync>,
+    memtable: RwLock<Memtable>,
+
+    /// frozen memtables waiting to be flushed, newest first
+    immutables: Immutables,
+    flusher: Mutex<Flusher>,
+    lsm: SharedLsm,
+    /// written keys are dropped from it while the memtable is locked,
+    /// so a read that started before the write can not put the old value back
+    cache: Mutex<ValueCache>,
+    snapshots: SnapshotList,
+
+    /// snapshots of paginated scans that have more pages, by snapshot id
+    cursors: Mutex<BTreeMap<u64, Arc<Snapshot>>>,
+    recovery: RecoveryReport,
+    config: Config,
+}
+
+/// paginated scans that were never finished are dropped oldest first after this many
+const MAX_OPEN_CURSORS: usize = 64;
+
+/// the memtable is copied by the first write after a scan opens, each open scan can keep a copy of its own
+/// scans are dropped oldest first once they keep more copies than this
+const MAX_CURSOR_MEMTABLES: usize = 8;
+
+/// keys of the probabilistic structures start with one of these,
+/// the plain key-value commands of the front ends can not touch them
+pub const RESERVED_PREFIXES: [&str; 4] = ["bf_", "cms_", "hll_", "sh_"];
+
+impl Db {
+    /// opens the database rooted at `path`, creating the required directories if they are missing
+    /// relative directories in the config are resolved against `path`
+    /// if the WAL contains entries that were not flushed the memtable is rebuilt from them
+    pub fn open<P: AsRef<Path>>(path: P, mut config: Config) -> Result<Self> {
+        config.validate()
+            .context("validating config")?;
+        config.resolve_paths(path.as_ref())
+            .context("resolving config paths")?;
+
+        let memtable_vars = config.memtable.get_values();
+        let lsm_vars = config.lsm.get_values();
+        let wal_vars = config.wal.get_values();
+
+        create_dir_all(&memtable_vars.5)
+            .context("creating memtable data dir")?;
+        create_dir_all(&lsm_vars.3)
+            .context("creating lsm data dir")?;
+        create_dir_all(&wal_vars.0)
+            .context("creating wal dir")?;
+
+        let memtable = match memtable_vars.0 {
+            MemtableStorage::BTree => {
+                let storage: BTree<Vec<u8>, MemtableEntry> = BTree::new();
+
+                Memtable::new(
+                    Box::new(storage),
+                    memtable_vars.1,
+                    memtable_vars.2,
+                    memtable_vars.3,
+                    memtable_vars.4,
+                    memtable_vars.5,
+                )
+                .with_compression(memtable_vars.6)
+            }
+            MemtableStorage::SkipList => {
+                let storage: SkipList<MemtableEntry> = SkipList::new(config.skiplist.get_values());
+
+                Memtable::new(
+                    Box::new(storage),
+                    memtable_vars.1,
+                    memtable_vars.2,
+                    memtable_vars.3,
+                    memtable_vars.4,
+                    memtable_vars.5,
+                )
+                .with_compression(memtable_vars.6)
+            }
+        };
+
+        let cache_vars = config.cache.get_values();
+        let cache = Cache::new(cache_vars.0);
+        let mut lsm: Box<dyn LSMTreeInterface> = match lsm_vars.0 {
+            SingleFile(()) => Box::new(LSMTree::<SF>::new(lsm_vars.1, lsm_vars.2, lsm_vars.3.clone(), lsm_vars.4, lsm_vars.5)
+                .with_compaction(lsm_vars.6)
+                .with_compression(lsm_vars.7)
+                .with_table_cache(lsm_vars.8)
+                .with_block_cache(cache_vars.1)),
+            MultiFile(()) => Box::new(LSMTree::<MF>::new(lsm_vars.1, lsm_vars.2, lsm_vars.3.clone(), lsm_vars.4, lsm_vars.5)
+                .with_compaction(lsm_vars.6)
+                .with_compression(lsm_vars.7)
+                .with_table_cache(lsm_vars.8)
+                .with_block_cache(cache_vars.1)),
+        };
+
+        let snapshots = SnapshotList::new();
+        lsm.set_snapshots(snapshots.clone());
+
+        // load data if found
+        lsm.load().context("loading data into lsm")?;
+
+        // records up to the flushed sequence are already in the tables
+        let flushed = lsm.flushed_sequence();
+
+        // records of segments of format version 1 follow the flushed ones
+        upgrade_segments(&wal_vars.0, flushed).context("upgrading WAL segments")?;
+        // the damaged tail is cut off before the WAL writes after it
+        let mut wal_reader = WriteAheadLogReader::iter(&wal_vars.0)
+            .context("getting wal_reader iter")?
+            .with_recovery_mode(wal_vars.3);
+        let wal_records = wal_reader.by_ref()
+            .collect::<Result<Vec<_>>>()
+            .context("recovering WAL")?;
+        wal_reader.truncate().context("truncating WAL")?;
+        let recovery = wal_reader.report().clone();
+        let last_sequence = wal_records.iter()
+            .flatten()
+            .map(|(sequence, _)| *sequence)
+            .fold(flushed, u64::max);
+        let wal = WriteAheadLog::new(&wal_vars.0, wal_vars.1).context("creating WAL")?
+            .with_sync_mode(wal_vars.2).context("setting WAL sync mode")?
+            .with_last_sequence(last_sequence);
+        let wal_sync = wal.sync_handle();
+
+        let lsm = Arc::new(RwLock::new(lsm));
+        let immutables = Arc::new(RwLock::new(VecDeque::new()));
+        let flusher = Flusher::start(Arc::clone(&lsm), Arc::clone(&immutables), wal_vars.0.clone())
+            .context("starting flush thread")?;
+
+        let db = Db {
+            wal: Mutex::new(wal),
+            wal_sync,
+            memtable: RwLock::new(memtable),
+            immutables,
+            flusher: Mutex::new(flusher),
+            lsm,
+            cache: Mutex::new(cache),
+            snapshots,
+            cursors: Mutex::new(BTreeMap::new()),
+            recovery,
+            config,
+        };
+
+        // if wal has records that were not flushed, rebuild memtable from them
+        // their segments are kept until the memtable is flushed
+        let mut previous = 0;
+        for records in wal_records {
+            // a batch has to be applied after everything written before it
+            for (sequence, record) in records {
+                if sequence <= previous {
+                    return Err(anyhow!("WAL record {sequence} comes after record {previous}"));
+                }
+                previous = sequence;
+                if sequence <= flushed {
+                    continue;
+                }
+
+                let mut memtable = db.memtable_write()?;
+                memtable.apply_batch(record.into_entries());
+                if memtable.is_full() {
+                    db.flush_now(&mut memtable, sequence)
+                        .context("flushing memtable rebuilt from the WAL")?;
+                }
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// hits and misses of the value cache
+    pub fn cache_stats(&self) -> Result<CacheStats> {
+        Ok(self.lock_cache()?.stats())
+    }
+
+    /// hits and misses of the sstable block cache, None if it is turned off
+    pub fn block_cache_stats(&self) -> Result<Option<CacheStats>> {
+        self.lsm_read()?.block_cache_stats()
+    }
+
+    /// what the WAL recovery read and dropped when the database was opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// config the database was opened with, paths are already resolved
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// waits for the background flushes, flushes the memtable and purges the WAL
+    pub fn close(self) -> Result<()> {
+        self.lock_flusher()?.stop()
+            .context("finishing background flushes")?;
+
+        let mut wal = self.lock_wal()?;
+        let mut memtable = self.memtable_write()?;
+        if memtable.len > 0 {
+            self.flush_now(&mut memtable, wal.last_sequence())
+                .context("flushing memtable")?;
+        }
+
+        wal.purge().context("purging wal")
+    }
+
+    /// returns the value stored under the key, None if the key does not exist or is deleted
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.get_entry(key)?.and_then(|entry| entry.value))
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(key, Some(value.to_vec()))
+    }
+
+    /// the key reads as deleted once the ttl has passed, compaction drops it after that
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.durable_write(|wal| {
+            let timestamp = get_timestamp()?;
+            let mementry = MemtableEntry::new(timestamp, key.to_vec(), Some(value.to_vec()))
+                .with_expiry(Some(timestamp.saturating_add(ttl.as_nanos())));
+            self.write_entry(wal, mementry)
+        })
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.durable_write(|wal| {
+            let entry = MemtableEntry::new(get_timestamp()?, key.to_vec(), None);
+            let walentry = Entry::from(&entry);
+            check_len(&walentry)?;
+            let position = wal.add(&walentry).context("adding to WAL")?;
+
+            let mut memtable = self.memtable_write()?;
+            self.lock_cache()?.remove(key);
+            memtable.delete(entry);
+            self.freeze_if_full(wal, &mut memtable)?;
+            Ok(position)
+        })
+    }
+
+    /// deletes all keys for which start <= key <= end with a single entry
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        if start > end {
+            return Err(anyhow!("start of the range is after its end"));
+        }
+
+        self.durable_write(|wal| {
+            let tombstone = Entry::range_delete(get_timestamp()?, start.to_vec(), range_end(end));
+            check_len(&tombstone)?;
+            let position = wal.add(&tombstone).context("adding to WAL")?;
+
+            let mut memtable = self.memtable_write()?;
+            self.lock_cache()?.remove_range(&tombstone.key, tombstone.range_end().unwrap_or_default());
+            memtable.delete_range(tombstone);
+            self.freeze_if_full(wal, &mut memtable)?;
+            Ok(position)
+        })
+    }
+
+    /// all operations in the batch become durable and visible together
+    pub fn write_batch(&self, mut batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        for entry in &batch.entries {
+            check_len(entry)?;
+        }
+
+        self.durable_write(|wal| {
+            batch.set_timestamp(get_timestamp()?);
+            let position = wal.add_batch(&batch).context("adding batch to WAL")?;
+
+            let mut memtable = self.memtable_write()?;
+            let mut cache = self.lock_cache()?;
+            for entry in &batch.entries {
+                match entry.range_end() {
+                    Some(end) => cache.remove_range(&entry.key, end),
+                    None => _ = cache.remove(&entry.key[..]),
+                }
+            }
+            drop(cache);
+            memtable.apply_batch(batch.entries);
+            self.freeze_if_full(wal, &mut memtable)?;
+            Ok(position)
+        })
+    }
+
+    /// lazy iterator over all live key-value pairs in key order, can also be consumed in reverse
+    /// later writes are not visible through it
+    pub fn iter(&self) -> Result<MergeIterator> {
+        // a flush can not move entries out of the memtable before the tables are opened
+        let memtable = self.memtable_read()?;
+        let view = memtable.view();
+        self.merge_iter(view.iter(), view.range_tombstones().to_vec(), get_timestamp()?)
+    }
+
+    /// lazy iterator over the keys that start with the prefix
+    pub fn prefix_iter(&self, prefix: &[u8]) -> Result<MergeIterator> {
+        self.iter()?.with_prefix(prefix)
+    }
+
+    /// lazy iterator over the keys for which start <= key <= end
+    pub fn range_iter(&self, start: &[u8], end: &[u8]) -> Result<MergeIterator> {
+        self.iter()?.with_range(start, end)
+    }
+
+    /// all key-value pairs whose key starts with the prefix
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.prefix_iter(prefix)?
+            .collect::<Result<Vec<_>>>()
+            .context("running prefix scan")
+    }
+
+    /// all key-value pairs for which start <= key <= end
+    pub fn range_scan(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.range_iter(start, end)?
+            .collect::<Result<Vec<_>>>()
+            .context("running range scan")
+    }
+
+    /// consistent view of the database at this moment, later writes are not visible through it
+    /// versions the snapshot needs are kept by compaction until it is dropped
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        // no write older than the snapshot can still be on its way to the memtable
+        let _wal = self.lock_wal()?;
+        let memtable = self.memtable_read()?.view();
+        Ok(Snapshot::new(get_timestamp()?, memtable, &self.snapshots))
+    }
+
+    /// value of the key as seen by the snapshot
+    pub fn get_at(&self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let timestamp = snapshot.timestamp();
+        let mut range_delete = newest_range_delete(snapshot.memtable.range_tombstones(), key, timestamp);
+        if let Some(entry) = snapshot.memtable.read(key) {
+            return Ok(visible(&entry, range_delete, timestamp));
+        }
+
+        // frozen memtables are never updated in place, newer ones can hold versions the snapshot can not see
+        let immutables = self.immutables_read()?;
+        for immutable in immutables.iter() {
+            range_delete = range_delete.max(newest_range_delete(immutable.range_tombstones(), key, timestamp));
+            if let Some(entry) = immutable.read(key).filter(|entry| entry.timestamp <= timestamp) {
+                return Ok(visible(entry, range_delete, timestamp));
+            }
+        }
+        if range_delete.is_some() {
+            return Ok(None);
+        }
+
+        let entry = self.lsm_read()?.get_at(key, timestamp)
+            .context("reading tables")?;
+        Ok(entry.and_then(|entry| visible(&entry, None, timestamp)))
+    }
+
+    /// lazy iterator over all key-value pairs as seen by the snapshot
+    pub fn iter_at(&self, snapshot: &Snapshot) -> Result<MergeIterator> {
+        let memtable = &snapshot.memtable;
+        self.merge_iter(memtable.iter(), memtable.range_tombstones().to_vec(), snapshot.timestamp())
+    }
+
+    /// prefix scan as seen by the snapshot
+    pub fn prefix_scan_at(&self, snapshot: &Snapshot, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.iter_at(snapshot)?
+            .with_prefix(prefix)?
+            .collect::<Result<Vec<_>>>()
+            .context("running prefix scan")
+    }
+
+    /// range scan as seen by the snapshot
+    pub fn range_scan_at(&self, snapshot: &Snapshot, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.iter_at(snapshot)?
+            .with_range(start, end)?
+            .collect::<Result<Vec<_>>>()
+            .context("running range scan")
+    }
+
+    /// at most page_size pairs whose key starts with the prefix
+    /// pass the returned token back to get the next page, all pages read the same snapshot
+    pub fn prefix_scan_page(&self, prefix: &[u8], page_size: usize, token: Option<PageToken>) -> Result<Page> {
+        self.scan_page(|iter| iter.with_prefix(prefix), page_size, token)
+            .context("running prefix scan")
+    }
+
+    /// at most page_size pairs for which start <= key <= end
+    /// pass the returned token back to get the next page, all pages read the same snapshot
+    pub fn range_scan_page(&self, start: &[u8], end: &[u8], page_size: usize, token: Option<PageToken>) -> Result<Page> {
+        self.scan_page(|iter| iter.with_range(start, end), page_size, token)
+            .context("running range scan")
+    }
+
+    /// creates a new BloomFilter under the key, parameters are taken from the config
+    pub fn bf_new(&self, key: &str) -> Result<()> {
+        let bf_vars = self.config.bf.get_values();
+        self.bf_reserve(key, bf_vars.0, bf_vars.1)
+    }
+
+    /// creates a new BloomFilter under the key sized for item_count items
+    pub fn bf_reserve(&self, key: &str, item_count: u64, fp_prob: f64) -> Result<()> {
+        check_reserved_prefix(key, "bf_")?;
+        let bf = BloomFilter::new(item_count, fp_prob);
+        let bf_ser = bf.serialize()?;
+        self.write(key.as_bytes(), Some(bf_ser))
+    }
+
+    pub fn bf_add(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_reserved_prefix(key, "bf_")?;
+        self.update_structure(key, |bf_ser| {
+            let mut bf = BloomFilter::deserialize(&bf_ser[..])?;
+            bf.add(value)
+                .context("adding value to the bloomfilter")?;
+            Ok((bf.serialize()?, ()))
+        })
+    }
+
+    /// true if the value might be present in the BloomFilter
+    pub fn bf_check(&self, key: &str, value: &[u8]) -> Result<bool> {
+        check_reserved_prefix(key, "bf_")?;
+        let bf_ser = self.get_structure(key)?;
+        let bf = BloomFilter::deserialize(&bf_ser[..])?;
+        bf.check(value)
+            .context("checking if the value is present in the bf")
+    }
+
+    /// creates a new HyperLogLog under the key, parameters are taken from the config
+    pub fn hll_new(&self, key: &str) -> Result<()> {
+        check_reserved_prefix(key, "hll_")?;
+        let hll_vars = self.config.hll.get_values();
+        let hll = HyperLogLog::new(hll_vars);
+        let hll_ser = hll.serialize()?;
+        self.write(key.as_bytes(), Some(hll_ser))
+    }
+
+    pub fn hll_add(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_reserved_prefix(key, "hll_")?;
+        self.update_structure(key, |hll_ser| {
+            let mut hll = HyperLogLog::deserialize(&hll_ser[..])?;
+            hll.add(value);
+            Ok((hll.serialize()?, ()))
+        })
+    }
+
+    pub fn hll_count(&self, key: &str) -> Result<f64> {
+        check_reserved_prefix(key, "hll_")?;
+        let hll_ser = self.get_structure(key)?;
+        let hll = HyperLogLog::deserialize(&hll_ser[..])?;
+        Ok(hll.count())
+    }
+
+    /// creates a new CountMinSketch under the key, parameters are taken from the config
+    pub fn cms_new(&self, key: &str) -> Result<()> {
+        let cms_vars = self.config.cms.get_values();
+        self.cms_reserve(key, cms_vars.0, cms_vars.1)
+    }
+
+    /// creates a new CountMinSketch under the key with the given accuracy and certainty
+    pub fn cms_reserve(&self, key: &str, desired_accuracy: f64, certainty: f64) -> Result<()> {
+        check_reserved_prefix(key, "cms_")?;
+        let cms = CountMinSketch::new(desired_accuracy, certainty);
+        let cms_ser = cms.serialize()?;
+        self.write(key.as_bytes(), Some(cms_ser))
+    }
+
+    /// returns the count of the value after adding it
+    pub fn cms_add(&self, key: &str, value: &str) -> Result<u64> {
+        self.cms_add_count(key, value, 1)
+    }
+
+    /// adds the value count times with a single write, returns its count after that
+    pub fn cms_add_count(&self, key: &str, value: &str, count: u64) -> Result<u64> {
+        check_reserved_prefix(key, "cms_")?;
+        self.update_structure(key, |cms_ser| {
+            let mut cms = CountMinSketch::deserialize(&cms_ser)?;
+            cms.add_count(value, count)?;
+            let count = cms.count(value).context("counting in cms")?;
+            Ok((cms.serialize()?, count))
+        })
+    }
+
+    pub fn cms_count(&self, key: &str, value: &str) -> Result<u64> {
+        check_reserved_prefix(key, "cms_")?;
+        let cms_ser = self.get_structure(key)?;
+        let cms = CountMinSketch::deserialize(&cms_ser)?;
+        cms.count(value).context("counting in cms")
+    }
+
+    /// hashes the text using SimHash and stores the fingerprint under the key
+    pub fn sh_hash(&self, key: &str, text: &str) -> Result<()> {
+        check_reserved_prefix(key, "sh_")?;
+        let sh_vars = self.config.simhash.get_values();
+        let mut sh = SimHash::new(sh_vars.0, sh_vars.1);
+        sh.calculate(text);
+
+        let fingerprint = sh.fingerprint();
+        let fingerprint_ser = BINCODE_OPTIONS.serialize(&fingerprint)?;
+        self.write(key.as_bytes(), Some(fingerprint_ser))
+    }
+
+    /// similarity of the fingerprints stored under the keys
+    pub fn sh_similarity(&self, left_key: &str, right_key: &str) -> Result<f64> {
+        check_reserved_prefix(left_key, "sh_")?;
+        check_reserved_prefix(right_key, "sh_")?;
+
+        let left_ser = self.get_structure(left_key)?;
+        let left_fingerprint = BINCODE_OPTIONS.deserialize(&left_ser[..])
+            .context("deserializing left fingerprint")?;
+
+        let right_ser = self.get_structure(right_key)?;
+        let right_fingerprint = BINCODE_OPTIONS.deserialize(&right_ser[..])
+            .context("deserializing right fingerprint")?;
+
+        Ok(similarity(left_fingerprint, right_fingerprint))
+    }
+
+    fn get_entry(&self, key: &[u8]) -> Result<Option<Entry>> {
+        let memtable = self.memtable_read()?;
+        let now = get_timestamp()?;
+        let mut range_delete = newest_range_delete(memtable.range_tombstones(), key, u128::MAX);
+        if let Some(mem_entry) = memtable.read(key) {
+            let entry = Entry::from(&mem_entry);
+            return Ok(visible(&entry, range_delete, now).is_some().then_some(entry));
+        }
+
+        // taken before the memtable is released so a memtable frozen in between is not missed,
+        // a frozen memtable leaves the list only after its table is in the lsm
+        let immutables = self.immutables_read()?;
+        // writes after the memtable was read remove the key from the cache
+        let removals = self.lock_cache()?.removals();
+        drop(memtable);
+        for immutable in immutables.iter() {
+            range_delete = range_delete.max(newest_range_delete(immutable.range_tombstones(), key, u128::MAX));
+            if let Some(entry) = immutable.read(key) {
+                return Ok(visible(entry, range_delete, now).is_some().then(|| entry.clone()));
+            }
+        }
+        drop(immutables);
+
+        // everything in the tables is older than the range tombstone
+        if range_delete.is_some() {
+            return Ok(None);
+        }
+
+        if let Some(value) = self.lock_cache()?.find(key).cloned() {
+            let entry = Entry {
+                timestamp: 0,
+                key: key.to_vec(),
+                value,
+                kind: EntryKind::Point,
+                expires_at: None,
+            };
+            return Ok(Some(entry));
+        }
+
+        // no memtable lock is held, writes go on while a compaction is swapped into the levels
+        let result: Option<Entry> = self.lsm_read()?.get(key.to_vec())
+            .context("reading tables")?;
+        // the cache does not know about expiry, entries that expire are not cached,
+        // neither are entries a write may have replaced while the tables were read
+        if let Some(entry) = result.as_ref().filter(|entry| entry.expires_at.is_none()) {
+            let size = entry.key.len() + entry.value.as_ref().map_or(0, Vec::len);
+            let mut cache = self.lock_cache()?;
+            if cache.removals() == removals {
+                cache.add(entry.key.clone(), entry.value.clone(), size);
+            }
+        }
+
+        Ok(result.filter(|entry| visible(entry, None, now).is_some()))
+    }
+
+    /// key of the structure with the prefix added, None if it does not exist
+    pub fn existing_structure(&self, prefix: &str, key: &[u8]) -> Result<Option<String>> {
+        let key = structure_key(prefix, key)?;
+        Ok(self.get(key.as_bytes())?.map(|_| key))
+    }
+
+    /// reads a serialized probabilistic structure, fails if it does not exist
+    fn get_structure(&self, key: &str) -> Result<Vec<u8>> {
+        self.get(key.as_bytes())?
+            .ok_or_else(|| anyhow!("entry '{}' not found", key))
+    }
+
+    /// read-modify-write of a serialized structure
+    /// the WAL lock is held throughout so concurrent updates of the same structure are not lost
+    fn update_structure<R>(&self, key: &str, update: impl FnOnce(Vec<u8>) -> Result<(Vec<u8>, R)>) -> Result<R> {
+        let (result, position) = {
+            let mut wal = self.lock_wal()?;
+            let (structure_ser, result) = update(self.get_structure(key)?)?;
+            (result, self.write_locked(&mut wal, key.as_bytes(), Some(structure_ser))?)
+        };
+        self.wal_sync.wait(position).context("syncing WAL")?;
+        Ok(result)
+    }
+
+    fn write(&self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        self.durable_write(|wal| self.write_locked(wal, key, value))
+    }
+
+    /// runs the write while the WAL is locked, then waits for its record to be synced without holding any lock
+    /// the write returns the position of its record
+    fn durable_write(&self, write: impl FnOnce(&mut WriteAheadLog) -> Result<u64>) -> Result<()> {
+        let position = write(&mut *self.lock_wal()?)?;
+        self.wal_sync.wait(position).context("syncing WAL")
+    }
+
+    /// the caller holds the WAL lock, returns the position of the record
+    fn write_locked(&self, wal: &mut WriteAheadLog, key: &[u8], value: Option<Vec<u8>>) -> Result<u64> {
+        let mementry = MemtableEntry::new(get_timestamp()?, key.to_vec(), value);
+        self.write_entry(wal, mementry)
+    }
+
+    /// the caller holds the WAL lock, returns the position of the record
+    fn write_entry(&self, wal: &mut WriteAheadLog, mementry: MemtableEntry) -> Result<u64> {
+        let walentry = Entry::from(&mementry);
+        check_len(&walentry)?;
+        let position = wal.add(&walentry).context("adding to WAL")?;
+
+        let mut memtable = self.memtable_write()?;
+        self.lock_cache()?.remove(&walentry.key[..]);
+        memtable.create(mementry);
+        self.freeze_if_full(wal, &mut memtable)?;
+        Ok(position)
+    }
+
+    /// hands a full memtable over to the flush thread and starts a new WAL segment for the next one
+    /// the caller holds the WAL lock and the memtable write lock
+    fn freeze_if_full(&self, wal: &mut WriteAheadLog, memtable: &mut Memtable) -> Result<()> {
+        if !memtable.is_full() {
+            return Ok(());
+        }
+
+        wal.rotate().context("rotating WAL")?;
+        let immutable = Arc::new(memtable.freeze());
+        self.immutables_write()?.push_front(Arc::clone(&immutable));
+        self.lock_flusher()?.schedule(immutable, wal.last_sequence())
+            .context("scheduling memtable flush")
+    }
+
+    /// flushes on the calling thread, used while the flush thread can not run
+    /// the memtable holds the WAL records up to the sequence, segments holding only those are removed
+    fn flush_now(&self, memtable: &mut Memtable, sequence: u64) -> Result<()> {
+        memtable.flush().context("writing memtable")?;
+        self.lsm_write()?
+            .insert_flushed("memtable", sequence)
+            .context("inserting memetable into lsm")?;
+        flusher::compact(&self.lsm).context("compacting tables")?;
+        WriteAheadLog::purge_through(&self.config.wal.get_values().0, sequence)
+            .context("purging flushed wal segments")
+    }
+
+    fn scan_page(
+        &self,
+        bounds: impl FnOnce(MergeIterator) -> Result<MergeIterator>,
+        page_size: usize,
+        token: Option<PageToken>,
+    ) -> Result<Page> {
+        if page_size == 0 {
+            return Err(anyhow!("page size has to be at least 1"));
+        }
+
+        let snapshot = match token.as_ref() {
+            Some(token) => self.lock_cursors()?
+                .get(&token.snapshot_id)
+                .cloned()
+                .ok_or_else(|| anyhow!("page token expired"))?,
+            None => {
+                let snapshot = Arc::new(self.snapshot()?);
+                let mut cursors = self.lock_cursors()?;
+                cursors.insert(snapshot.id(), Arc::clone(&snapshot));
+                while cursors.len() > MAX_OPEN_CURSORS || memtable_copies(&cursors) > MAX_CURSOR_MEMTABLES {
+                    cursors.pop_first();
+                }
+                snapshot
+            }
+        };
+
+        // the cursors are not locked while reading so other scans are not blocked
+        let page = self.read_page(&snapshot, bounds, page_size, token);
+
+        // the snapshot is kept while there are more pages, until then a token can be used again
+        if !matches!(page, Ok(Page { next: Some(_), .. })) {
+            self.lock_cursors()?.remove(&snapshot.id());
+        }
+        page
+    }
+
+    fn read_page(
+        &self,
+        snapshot: &Snapshot,
+        bounds: impl FnOnce(MergeIterator) -> Result<MergeIterator>,
+        page_size: usize,
+        token: Option<PageToken>,
+    ) -> Result<Page> {
+        let mut iter = bounds(self.iter_at(snapshot)?)?;
+        if let Some(token) = token {
+            // smallest key after the last returned one
+            let mut from = token.last_key;
+            from.push(0);
+            iter.seek(&from)?;
+        }
+
+        let entries = iter.by_ref()
+            .take(page_size)
+            .collect::<Result<Vec<_>>>()?;
+        if iter.next().transpose()?.is_none() {
+            return Ok(Page { entries, next: None });
+        }
+
+        let next = PageToken {
+            last_key: entries.last().unwrap().0.clone(),
+            snapshot_id: snapshot.id(),
+        };
+        Ok(Page { entries, next: Some(next) })
+    }
+
+    /// merges the memtable with the frozen memtables and all tables, versions newer than the timestamp are hidden
+    fn merge_iter(&self, memtable: MemtableIterator, mut range_tombstones: Vec<Entry>, timestamp: u128) -> Result<MergeIterator> {
+        let mut sources: Vec<Box<dyn EntryIterator>> = vec![Box::new(memtable)];
+        for immutable in self.immutables_read()?.iter() {
+            sources.push(Box::new(immutable.iter()));
+            range_tombstones.extend_from_slice(immutable.range_tombstones());
+        }
+
+        let lsm = self.lsm_read()?;
+        sources.extend(lsm.sources().context("opening table cursors")?);
+        range_tombstones.extend(lsm.range_tombstones().context("reading range tombstones")?);
+        Ok(MergeIterator::new(sources, timestamp).with_range_tombstones(range_tombstones))
+    }
+
+    fn lock_wal(&self) -> Result<MutexGuard<'_, WriteAheadLog>> {
+        self.wal.lock().map_err(|_| anyhow!("wal lock poisoned"))
+    }
+
+    fn memtable_read(&self) -> Result<RwLockReadGuard<'_, Memtable>> {
+        self.memtable.read().map_err(|_| anyhow!("memtable lock poisoned"))
+    }
+
+    fn memtable_write(&self) -> Result<RwLockWriteGuard<'_, Memtable>> {
+        self.memtable.write().map_err(|_| anyhow!("memtable lock poisoned"))
+    }
+
+    fn lock_flusher(&self) -> Result<MutexGuard<'_, Flusher>> {
+        self.flusher.lock().map_err(|_| anyhow!("flusher lock poisoned"))
+    }
+
+    fn immutables_read(&self) -> Result<RwLockReadGuard<'_, VecDeque<Arc<ImmutableMemtable>>>> {
+        self.immutables.read().map_err(|_| anyhow!("immutable memtables lock poisoned"))
+    }
+
+    fn immutables_write(&self) -> Result<RwLockWriteGuard<'_, VecDeque<Arc<ImmutableMemtable>>>> {
+        self.immutables.write().map_err(|_| anyhow!("immutable memtables lock poisoned"))
+    }
+
+    fn lsm_read(&self) -> Result<RwLockReadGuard<'_, Box<dyn LSMTreeInterface>>> {
+        self.lsm.read().map_err(|_| anyhow!("lsm lock poisoned"))
+    }
+
+    fn lsm_write(&self) -> Result<RwLockWriteGuard<'_, Box<dyn LSMTreeInterface>>> {
+        self.lsm.write().map_err(|_| anyhow!("lsm lock poisoned"))
+    }
+
+    fn lock_cache(&self) -> Result<MutexGuard<'_, ValueCache>> {
+        self.cache.lock().map_err(|_| anyhow!("cache lock poisoned"))
+    }
+
+    fn lock_cursors(&self) -> Result<MutexGuard<'_, BTreeMap<u64, Arc<Snapshot>>>> {
+        self.cursors.lock().map_err(|_| anyhow!("cursors lock poisoned"))
+    }
+}
+
+impl Drop for Db {
+    /// pending flushes are finished so the tables are complete when the database is opened again
+    fn drop(&mut self) {
+        if let Ok(flusher) = self.flusher.get_mut() {
+            // errors are reported by close, a dropped handle has nobody to report them to
+            let _ = flusher.stop();
+        }
+    }
+}
+
+/// value of the entry as read at `now`, None for a tombstone, an expired entry
+/// or if the range tombstone at `range_delete` is newer
+fn visible(entry: &Entry, range_delete: Option<u128>, now: u128) -> Option<Vec<u8>> {
+    if range_delete.is_some_and(|deleted| deleted > entry.timestamp) || entry.is_expired(now) {
+        return None;
+    }
+    entry.value.clone()
+}
+
+/// number of memtable copies the open scans keep, scans that opened between two writes share one
+/// cursors are ordered by snapshot id, so scans sharing a copy are next to each other
+fn memtable_copies(cursors: &BTreeMap<u64, Arc<Snapshot>>) -> usize {
+    let views = cursors.values().map(|snapshot| &snapshot.memtable).collect::<Vec<_>>();
+    let changes = views.windows(2).filter(|pair| !pair[0].shares_storage(pair[1])).count();
+    if views.is_empty() { 0 } else { changes + 1 }
+}
+
+/// tables can not hold longer keys and values, they are turned away before they reach the WAL
+fn check_len(entry: &Entry) -> Result<()> {
+    for key in [Some(&entry.key[..]), entry.range_end()].into_iter().flatten() {
+        if key.len() as u64 > MAX_KEY_LEN {
+            return Err(anyhow!("key is {} bytes long, the limit is {MAX_KEY_LEN}", key.len()));
+        }
+    }
+    if let Some(value) = entry.value.as_ref().filter(|value| value.len() as u64 > MAX_VAL_LEN) {
+        return Err(anyhow!("value is {} bytes long, the limit is {MAX_VAL_LEN}", value.len()));
+    }
+    Ok(())
+}
+
+/// true if the key belongs to a probabilistic structure
+pub fn is_reserved(key: &[u8]) -> bool {
+    RESERVED_PREFIXES.iter().any(|prefix| key.starts_with(prefix.as_bytes()))
+}
+
+/// fails if the key belongs to a probabilistic structure
+pub fn check_plain_key(key: &[u8]) -> Result<()> {
+    if is_reserved(key) {
+        return Err(anyhow!("keys starting with {} are reserved", RESERVED_PREFIXES.join(", ")));
+    }
+    Ok(())
+}
+
+/// key of a structure as the front ends name it, with the prefix added
+pub fn structure_key(prefix: &str, key: &[u8]) -> Result<String> {
+    Ok(format!("{prefix}{}", utf8(key)?))
+}
+
+pub fn utf8(arg: &[u8]) -> Result<&str> {
+    std::str::from_utf8(arg).context("argument is not valid utf8")
+}
+
+fn check_reserved_prefix(key: &str, prefix: &str) -> Result<()> {
+    if !key.starts_with(prefix) {
+        return Err(anyhow!("key {} doesnt start with the reserved prefix {}", key, prefix));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_dir_all, write},
+        io::{BufRead, BufReader},
+        path::Path,
+        process::{Command, Stdio},
+        sync::{mpsc::{sync_channel, Receiver, SyncSender}, Arc, Mutex},
+        thread::{self, sleep},
+        time::Duration
+    };
+    use anyhow::Result;
+    use bincode::Options;
+    use crc::{Crc, CRC_32_JAMCRC};
+    use rand::Rng;
+    use crate::building_blocks::{
+        CacheStats, CompactionOutput, Entry, EntryIterator, LSMTree, LSMTreeInterface, SnapshotList, BINCODE_OPTIONS, MAX_KEY_LEN, MF,
+    };
+    use crate::{Config, WriteBatch};
+    use super::Db;
+
+    fn open_clean(path: &str) -> Db {
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        Db::open(path, Config::default()).expect("opening db")
+    }
+
+    fn skiplist_config() -> Config {
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        config["memtable"]["storage"] = "SkipList".into();
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn every_write_config() -> Config {
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        config["wal"]["sync_mode"] = "EveryWrite".into();
+        serde_json::from_value(config).unwrap()
+    }
+
+    /// small memtable and lsm levels so flushes and merges happen often
+    fn small_config() -> Config {
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        config["memtable"]["capacity"] = 5.into();
+        config["lsm"]["size_threshold"] = 2.into();
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn opens_config_of_older_versions() {
+        let path = "test-data/db-older-config";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        // the shape of data/config.json before the cache was bounded by bytes
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "token_bucket": { "capacity": 5, "reset_interval": { "secs": 2, "nanos": 0 } },
+            "cms": { "desired_accuracy": 0.01, "certainty": 0.01 },
+            "bf": { "item_count": 10, "fp_prob": 0.01 },
+            "lsm": {
+                "file_organization": { "MultiFile": null },
+                "fp_prob": 0.01,
+                "summary_nth": 50,
+                "data_dir": format!("{path}/table_data"),
+                "size_threshold": 20,
+                "number_of_levels": 5
+            },
+            "hll": { "number_of_bits": 10 },
+            "ssconfig": { "file_organization": { "MultiFile": null }, "summary_nth": 50, "filter_fp_prob": 0.01 },
+            "skiplist": { "max_level": 10 },
+            "simhash": { "simhash": 0, "stopwords": ["the", "is", "this", "some", "a", "with", "to"] },
+            "memtable": {
+                "storage": "BTree",
+                "capacity": 50,
+                "sstable_type": { "MultiFile": null },
+                "fp_prob": 0.01,
+                "summary_nth": 50,
+                "data_folder": format!("{path}/table_data")
+            },
+            "wal": { "segment_size": 20000, "path": format!("{path}/WAL") },
+            "cache": { "capacity": 1000 }
+        })).unwrap();
+
+        let db = Db::open(path, config).unwrap();
+        db.put(b"key", b"value").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn replays_wal_segments_of_format_version_1() {
+        let path = "test-data/db-wal-version-1";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        // segments of format version 1 have no header, records of entries without an expiry are preallocated zeros apart
+        create_dir_all(format!("{path}/WAL")).unwrap();
+        let segments: [&[(&str, Option<&str>)]; 2] = [&[("a", Some("1")), ("b", Some("2")), ("a", None)], &[("c", Some("3"))]];
+        for (index, entries) in segments.iter().enumerate() {
+            let mut segment = Vec::new();
+            for (timestamp, (key, value)) in entries.iter().enumerate() {
+                let entry = (timestamp as u128 + 1, key.as_bytes().to_vec(), value.map(|value| value.as_bytes().to_vec()));
+                let entry_ser = BINCODE_OPTIONS.serialize(&entry).unwrap();
+                segment.extend(BINCODE_OPTIONS.serialize(&(entry_ser.len() as u64)).unwrap());
+                segment.extend(BINCODE_OPTIONS.serialize(&Crc::<u32>::new(&CRC_32_JAMCRC).checksum(&entry_ser)).unwrap());
+                segment.extend(entry_ser);
+            }
+            segment.resize(1000, 0);
+            write(format!("{path}/WAL/segment-{index}"), segment).unwrap();
+        }
+
+        let db = Db::open(path, Config::default()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), None);
+        assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(b"c").unwrap(), Some(b"3".to_vec()));
+        db.put(b"d", b"4").unwrap();
+        drop(db);
+
+        // the segments were rewritten once, their records are not replayed over the newer ones
+        let db = Db::open(path, Config::default()).unwrap();
+        assert_eq!(db.recovery_report().records, 5);
+        assert_eq!(db.get(b"a").unwrap(), None);
+        assert_eq!(db.get(b"c").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(db.get(b"d").unwrap(), Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn put_get_delete() {
+        let db = open_clean("test-data/db-put-get-delete");
+
+        assert_eq!(db.get(b"key").unwrap(), None);
+
+        db.put(b"key", b"value").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+        db.put(b"key", b"new value").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some(b"new value".to_vec()));
+
+        db.delete(b"key").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), None);
+    }
+
+    #[test]
+    fn cache_is_dropped_on_writes() {
+        let path = "test-data/db-cache-writes";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+        db.put(b"key", b"old").unwrap();
+        db.close().unwrap();
+
+        // read from the table and cached
+        let db = Db::open(path, small_config()).unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(db.get(b"key").unwrap(), Some(b"old".to_vec()));
+        let stats = db.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // the new value leaves the memtable for a table, the cached one must not come back
+        db.put(b"key", b"new").unwrap();
+        for i in 0..4 {
+            db.put(format!("filler-{i}").as_bytes(), b"value").unwrap();
+        }
+        while !db.immutables_read().unwrap().is_empty() {
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(db.get(b"key").unwrap(), Some(b"new".to_vec()));
+
+        db.delete(b"key").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), None);
+        assert!(db.block_cache_stats().unwrap().is_some());
+    }
+
+    #[test]
+    fn reopen() {
+        let path = "test-data/db-reopen";
+        let db = open_clean(path);
+        for i in 0..120 {
+            db.put(i.to_string().as_bytes(), i.to_string().as_bytes()).unwrap();
+        }
+        db.delete(b"7").unwrap();
+        db.close().unwrap();
+
+        let db = Db::open(path, Config::default()).unwrap();
+        assert_eq!(db.get(b"7").unwrap(), None);
+        assert_eq!(db.get(b"42").unwrap(), Some(b"42".to_vec()));
+        assert_eq!(db.get(b"119").unwrap(), Some(b"119".to_vec()));
+    }
+
+    #[test]
+    fn damaged_table_fails_the_read() {
+        let path = "test-data/db-damaged-table";
+        let db = open_clean(path);
+        db.put(b"key", b"value").unwrap();
+        db.close().unwrap();
+
+        // flips a byte of the only data block
+        let data = format!("{path}/table_data/sstable-0-1/data");
+        let mut bytes = std::fs::read(&data).expect("reading table");
+        let offset = bytes.len() / 2;
+        bytes[offset] ^= 0xff;
+        std::fs::write(&data, bytes).expect("damaging table");
+
+        let db = Db::open(path, Config::default()).unwrap();
+        assert!(db.get(b"key").is_err());
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        let path = "test-data/db-oversized";
+        let db = open_clean(path);
+        let long_key = vec![b'k'; MAX_KEY_LEN as usize + 1];
+
+        assert!(db.put(&long_key, b"value").is_err());
+        assert!(db.delete(&long_key).is_err());
+        assert!(db.delete_range(b"a", &long_key).is_err());
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1").put(&long_key, b"2");
+        assert!(db.write_batch(batch).is_err());
+
+        // nothing of the rejected batch is written
+        assert_eq!(db.get(b"a").unwrap(), None);
+
+        // the longest key is read back from the table
+        db.put(&long_key[1..], b"value").unwrap();
+        db.close().unwrap();
+        let db = Db::open(path, Config::default()).unwrap();
+        assert_eq!(db.get(&long_key[1..]).unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn crash_loses_only_the_torn_write() {
+        let path = "test-data/db-crash-torn-write";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, every_write_config()).unwrap();
+        for i in 0..10 {
+            db.put(format!("key-{i}").as_bytes(), b"value").unwrap();
+        }
+        let wal_path = db.config().wal.get_values().0;
+        drop(db);
+
+        // the machine went down in the middle of the last record, the segment ends inside it
+        let segment = std::fs::read_dir(&wal_path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .max_by_key(|path| path.file_name().unwrap().to_str().unwrap()
+                .rsplit('-').next().unwrap().parse::<usize>().unwrap())
+            .unwrap();
+        let written = std::fs::read(&segment).unwrap();
+        let last_byte = written.iter().rposition(|byte| *byte != 0).unwrap();
+        std::fs::OpenOptions::new().write(true).open(&segment).unwrap()
+            .set_len(last_byte as u64).unwrap();
+
+        let db = Db::open(path, every_write_config()).unwrap();
+        for i in 0..9 {
+            assert_eq!(db.get(format!("key-{i}").as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(db.get(b"key-9").unwrap(), None);
+        assert_eq!(db.recovery_report().records, 9);
+        assert_eq!(db.recovery_report().dropped.len(), 1);
+    }
+
+    #[test]
+    fn replayed_writes_survive_another_crash() {
+        let path = "test-data/db-crash-twice";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, every_write_config()).unwrap();
+        for i in 0..3 {
+            db.put(format!("key-{i}").as_bytes(), b"value").unwrap();
+        }
+        drop(db);
+
+        // the replayed records stay in the WAL until their memtable is flushed
+        drop(Db::open(path, every_write_config()).unwrap());
+        let db = Db::open(path, every_write_config()).unwrap();
+        for i in 0..3 {
+            assert_eq!(db.get(format!("key-{i}").as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(db.recovery_report().records, 3);
+    }
+
+    #[test]
+    fn flushed_segments_are_removed() {
+        let path = "test-data/db-wal-retention";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+        for i in 0..100 {
+            db.put(format!("key-{i}").as_bytes(), b"value").unwrap();
+        }
+        while !db.immutables_read().unwrap().is_empty() {
+            sleep(Duration::from_millis(10));
+        }
+        let wal_path = db.config().wal.get_values().0;
+        let segments = std::fs::read_dir(&wal_path).unwrap().count();
+        assert!(segments <= 2, "{segments} segments left in the WAL");
+        drop(db);
+
+        // only the records of the memtable that was never flushed are replayed
+        let db = Db::open(path, small_config()).unwrap();
+        assert!(db.recovery_report().records < 5);
+        for i in 0..100 {
+            assert_eq!(db.get(format!("key-{i}").as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    /// writes done by the child process of `killed_at_random_points` in every round
+    const CRASH_WRITES: usize = 300;
+
+    /// every write of a round puts the round as the value of its own key, every tenth one is a batch
+    /// of the two batch keys, a write is acknowledged on stdout once it returns
+    /// only runs as the child of `killed_at_random_points`
+    #[test]
+    #[ignore]
+    fn crash_writer() {
+        let (Ok(path), Ok(round)) = (std::env::var("NEBULA_CRASH_PATH"), std::env::var("NEBULA_CRASH_ROUND")) else {
+            return;
+        };
+        let db = Db::open(path, small_config()).unwrap();
+        for i in 0..CRASH_WRITES {
+            if i % 10 == 0 {
+                let mut batch = WriteBatch::new();
+                batch.put(b"batch-a", format!("{round}-{i}").as_bytes()).put(b"batch-b", format!("{round}-{i}").as_bytes());
+                db.write_batch(batch).unwrap();
+            }
+            db.put(format!("key-{i:03}").as_bytes(), round.as_bytes()).unwrap();
+            println!("ack {i}");
+        }
+    }
+
+    #[test]
+    fn killed_at_random_points() {
+        let path = "test-data/db-killed";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut values: Vec<Option<Vec<u8>>> = vec![None; CRASH_WRITES];
+        for round in 0..8 {
+            let mut child = Command::new(std::env::current_exe().unwrap())
+                .args(["db::tests::crash_writer", "--exact", "--ignored", "--nocapture", "--test-threads=1"])
+                .env("NEBULA_CRASH_PATH", path)
+                .env("NEBULA_CRASH_ROUND", round.to_string())
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("starting writer");
+
+            // killed in the middle of writes, flushes, merges or the replay of the previous round
+            let kill_after = rng.gen_range(0..CRASH_WRITES);
+            let mut acked = 0;
+            let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+            while acked < kill_after {
+                let Some(line) = lines.next() else {
+                    break;
+                };
+                if line.unwrap().starts_with("ack ") {
+                    acked += 1;
+                }
+            }
+            child.kill().unwrap();
+            child.wait().unwrap();
+
+            // the writes of the round are replayed in order, the ones that made it are a prefix of them
+            let db = Db::open(path, small_config()).unwrap();
+            let round_value = round.to_string().into_bytes();
+            let mut written = 0;
+            for (i, value) in values.iter_mut().enumerate() {
+                let found = db.get(format!("key-{i:03}").as_bytes()).unwrap();
+                if found.as_ref() == Some(&round_value) && written == i {
+                    written += 1;
+                } else {
+                    assert_eq!(&found, value, "round {round}: key {i} changed after key {written} was lost");
+                }
+                *value = found;
+            }
+            assert!(written >= acked, "round {round}: {acked} writes were acknowledged, {written} survived");
+            assert_eq!(db.get(b"batch-a").unwrap(), db.get(b"batch-b").unwrap(), "round {round}: batch was torn");
+        }
+    }
+
+    #[test]
+    fn binary_keys() {
+        let path = "test-data/db-binary-keys";
+        let keys: Vec<Vec<u8>> = vec![
+            vec![0xff, 0x00, 0xfe],
+            vec![0xc3, 0x28],
+            "ključ".as_bytes().to_vec(),
+            42u64.to_be_bytes().to_vec(),
+        ];
+
+        let db = open_clean(path);
+        for key in &keys {
+            db.put(key, key).unwrap();
+        }
+        // dropped without closing, memtable is rebuilt from the WAL
+        drop(db);
+
+        let db = Db::open(path, Config::default()).unwrap();
+        db.close().unwrap();
+
+        // entries are now read from the flushed sstable
+        let db = Db::open(path, Config::default()).unwrap();
+        for key in &keys {
+            assert_eq!(db.get(key).unwrap(), Some(key.clone()));
+        }
+
+        let res = db.prefix_scan(&[0xff]).unwrap();
+        assert_eq!(res, vec![(keys[0].clone(), keys[0].clone())]);
+    }
+
+    #[test]
+    fn binary_keys_skiplist() {
+        let path = "test-data/db-binary-keys-skiplist";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, skiplist_config()).unwrap();
+        db.put(&[0xc3, 0x28], b"value").unwrap();
+        drop(db);
+
+        let db = Db::open(path, skiplist_config()).unwrap();
+        assert_eq!(db.get(&[0xc3, 0x28]).unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn write_batch() {
+        let path = "test-data/db-write-batch";
+        let db = open_clean(path);
+        db.put(b"b", b"old").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"1").delete(b"b").put(b"c", b"3");
+        db.write_batch(batch).unwrap();
+        drop(db);
+
+        // replayed from the WAL as a whole
+        let db = Db::open(path, Config::default()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(b"b").unwrap(), None);
+        assert_eq!(db.get(b"c").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn write_batch_over_capacity() {
+        let db = open_clean("test-data/db-write-batch-capacity");
+
+        // memtable capacity is 50, the batch is flushed only after it is applied completely
+        let mut batch = WriteBatch::new();
+        for i in 0..120 {
+            batch.put(i.to_string().as_bytes(), b"value");
+        }
+        db.write_batch(batch).unwrap();
+
+        for i in 0..120 {
+            assert_eq!(db.get(i.to_string().as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn snapshot_memtable() {
+        let db = open_clean("test-data/db-snapshot-memtable");
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"1").unwrap();
+
+        let snapshot = db.snapshot().unwrap();
+        db.put(b"a", b"2").unwrap();
+        db.delete(b"b").unwrap();
+        db.put(b"c", b"2").unwrap();
+
+        assert_eq!(db.get_at(&snapshot, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get_at(&snapshot, b"b").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get_at(&snapshot, b"c").unwrap(), None);
+        assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_survives_compaction() {
+        let path = "test-data/db-snapshot-compaction";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+
+        let keys = (0..10).map(|i| format!("key-{i}")).collect::<Vec<_>>();
+        for key in &keys {
+            db.put(key.as_bytes(), b"old").unwrap();
+        }
+        let snapshot = db.snapshot().unwrap();
+
+        for key in &keys {
+            db.put(key.as_bytes(), b"new").unwrap();
+        }
+        db.delete(b"key-3").unwrap();
+
+        // enough flushes to merge the old versions into deeper levels
+        for i in 0..100 {
+            db.put(format!("other-{i}").as_bytes(), b"value").unwrap();
+        }
+
+        for key in &keys {
+            assert_eq!(db.get_at(&snapshot, key.as_bytes()).unwrap(), Some(b"old".to_vec()));
+        }
+        assert_eq!(db.get_at(&snapshot, b"other-0").unwrap(), None);
+        assert_eq!(db.get(b"key-3").unwrap(), None);
+        assert_eq!(db.get(b"key-4").unwrap(), Some(b"new".to_vec()));
+
+        let scan = db.prefix_scan_at(&snapshot, b"key-").unwrap();
+        assert_eq!(scan.len(), keys.len());
+        assert!(scan.iter().all(|(_, value)| value == b"old"));
+
+        let scan = db.range_scan_at(&snapshot, b"key-2", b"key-4").unwrap();
+        assert_eq!(scan.len(), 3);
+
+        let scan = db.prefix_scan(b"key-").unwrap();
+        assert_eq!(scan.len(), keys.len() - 1);
+        assert!(scan.iter().all(|(_, value)| value == b"new"));
+    }
+
+    /// small tables split into many summary ranges
+    fn iter_config(file_organization: &str) -> Config {
+        let mut config = serde_json::to_value(small_config()).unwrap();
+        config["memtable"]["summary_nth"] = 2.into();
+        config["lsm"]["summary_nth"] = 2.into();
+        config["lsm"]["file_organization"] = serde_json::json!({ file_organization: null });
+        config["memtable"]["sstable_type"] = serde_json::json!({ file_organization: null });
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn check_iterators(path: &str, config: Config) {
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, config).unwrap();
+
+        // versions spread across the memtable and tables on several levels
+        for i in (0..40).rev() {
+            db.put(format!("key-{i:02}").as_bytes(), b"old").unwrap();
+        }
+        for i in (0..40).step_by(3) {
+            db.put(format!("key-{i:02}").as_bytes(), b"new").unwrap();
+        }
+        for i in (0..40).step_by(5) {
+            db.delete(format!("key-{i:02}").as_bytes()).unwrap();
+        }
+
+        let expected = (0..40)
+            .filter(|i| i % 5 != 0)
+            .map(|i| {
+                let value = if i % 3 == 0 { b"new".to_vec() } else { b"old".to_vec() };
+                (format!("key-{i:02}").into_bytes(), value)
+            })
+            .collect::<Vec<_>>();
+
+        let pairs = db.iter().unwrap().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(pairs, expected);
+
+        let mut reversed = db.iter().unwrap().rev().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+
+        let mut iter = db.iter().unwrap();
+        iter.seek(b"key-20").unwrap();
+        let (key, _) = iter.next().unwrap().unwrap();
+        assert_eq!(key, b"key-21");
+        let (key, _) = iter.next_back().unwrap().unwrap();
+        assert_eq!(key, b"key-39");
+
+        let pairs = db.range_iter(b"key-10", b"key-14").unwrap()
+            .collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(pairs, expected[8..12]);
+
+        let pairs = db.prefix_iter(b"key-3").unwrap()
+            .rev()
+            .map(|pair| pair.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(pairs.first().unwrap(), b"key-39");
+        assert_eq!(pairs.len(), 8);
+    }
+
+    #[test]
+    fn iterators_multifile() {
+        check_iterators("test-data/db-iterators-multi", iter_config("MultiFile"));
+    }
+
+    #[test]
+    fn iterators_singlefile() {
+        check_iterators("test-data/db-iterators-single", iter_config("SingleFile"));
+    }
+
+    #[test]
+    fn paginated_scans() {
+        let path = "test-data/db-pagination";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+
+        for i in 0..23 {
+            db.put(format!("key-{i:02}").as_bytes(), b"old").unwrap();
+        }
+
+        let mut pages = vec![];
+        let mut token = None;
+        loop {
+            let page = db.prefix_scan_page(b"key-", 5, token).unwrap();
+            pages.push(page.entries);
+
+            // writes between pages are not visible to the scan
+            db.put(b"key-000", b"new").unwrap();
+            db.put(b"key-22", b"new").unwrap();
+            db.delete(b"key-21").unwrap();
+
+            match page.next {
+                Some(next) => token = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages.iter().map(|page| page.len()).collect::<Vec<_>>(), vec![5, 5, 5, 5, 3]);
+        let keys = pages.concat().into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+        let expected = (0..23).map(|i| format!("key-{i:02}").into_bytes()).collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+        assert!(pages.concat().iter().all(|(_, value)| value == b"old"));
+
+        // a token stays valid until the scan ends, the last page releases the snapshot
+        let first = db.range_scan_page(b"key-05", b"key-12", 3, None).unwrap();
+        let token = first.next.unwrap();
+        let second = db.range_scan_page(b"key-05", b"key-12", 3, Some(token.clone())).unwrap();
+        let again = db.range_scan_page(b"key-05", b"key-12", 3, Some(token.clone())).unwrap();
+        assert_eq!(second.entries, again.entries);
+        assert_eq!(second.entries.first().unwrap().0, b"key-08");
+
+        let last = db.range_scan_page(b"key-05", b"key-12", 3, second.next).unwrap();
+        assert_eq!(last.entries.len(), 2);
+        assert!(last.next.is_none());
+        assert!(db.range_scan_page(b"key-05", b"key-12", 3, Some(token)).is_err());
+        assert!(db.prefix_scan_page(b"key-", 0, None).is_err());
+    }
+
+    #[test]
+    fn open_scans_keep_a_bounded_number_of_memtable_copies() {
+        let db = open_clean("test-data/db-scan-copies");
+        for i in 0..10 {
+            db.put(format!("key-{i}").as_bytes(), b"v").unwrap();
+        }
+
+        // scans opened with no write between them share a single copy
+        let shared = (0..10)
+            .map(|_| db.prefix_scan_page(b"key-", 2, None).unwrap().next.unwrap())
+            .collect::<Vec<_>>();
+        // every write after a scan opened makes the next scan keep a copy of its own
+        let mut own = Vec::new();
+        for i in 0..super::MAX_CURSOR_MEMTABLES {
+            db.put(format!("key-new-{i}").as_bytes(), b"v").unwrap();
+            own.push(db.prefix_scan_page(b"key-", 2, None).unwrap().next.unwrap());
+        }
+
+        for token in shared {
+            assert!(db.prefix_scan_page(b"key-", 2, Some(token)).is_err());
+        }
+        for token in own {
+            assert!(db.prefix_scan_page(b"key-", 2, Some(token)).is_ok());
+        }
+    }
+
+    #[test]
+    fn background_flush() {
+        let path = "test-data/db-background-flush";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+        let snapshot = db.snapshot().unwrap();
+
+        // every fifth put freezes the memtable, reads are served while the flushes run
+        for i in 0..40 {
+            db.put(format!("key-{i:02}").as_bytes(), b"value").unwrap();
+            assert_eq!(db.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(b"value".to_vec()));
+            assert_eq!(db.prefix_scan(b"key-").unwrap().len(), i + 1);
+            assert_eq!(db.get_at(&snapshot, format!("key-{i:02}").as_bytes()).unwrap(), None);
+        }
+
+        // segments of flushed memtables are removed, the new memtable writes to a fresh one
+        db.lock_flusher().unwrap().stop().unwrap();
+        let segments = std::fs::read_dir(format!("{path}/WAL")).unwrap().count();
+        assert_eq!(segments, 1);
+        drop(snapshot);
+        db.close().unwrap();
+
+        let db = Db::open(path, small_config()).unwrap();
+        assert_eq!(db.prefix_scan(b"key-").unwrap().len(), 40);
+    }
+
+    /// lsm that holds the first compaction it builds until the test lets it go
+    struct HeldCompaction {
+        inner: Box<dyn LSMTreeInterface>,
+        /// tells the test the compaction is built and takes the signal to go on
+        gate: Mutex<Option<(SyncSender<()>, Receiver<()>)>>,
+    }
+
+    impl LSMTreeInterface for HeldCompaction {
+        fn get_at(&self, key: &[u8], timestamp: u128) -> Result<Option<Entry>> {
+            self.inner.get_at(key, timestamp)
+        }
+
+        fn insert(&mut self, table_name: &str) -> Result<()> {
+            self.inner.insert(table_name)
+        }
+
+        fn load(&mut self) -> Result<()> {
+            self.inner.load()
+        }
+
+        fn insert_flushed(&mut self, table_name: &str, sequence: u64) -> Result<()> {
+            self.inner.insert_flushed(table_name, sequence)
+        }
+
+        fn build_compaction(&self) -> Result<Option<CompactionOutput>> {
+            let output = self.inner.build_compaction()?;
+            if output.is_some() {
+                if let Some((started, release)) = self.gate.lock().unwrap().take() {
+                    started.send(()).unwrap();
+                    release.recv().unwrap();
+                }
+            }
+            Ok(output)
+        }
+
+        fn apply_compaction(&mut self, output: CompactionOutput) -> Result<()> {
+            self.inner.apply_compaction(output)
+        }
+
+        fn flushed_sequence(&self) -> u64 {
+            self.inner.flushed_sequence()
+        }
+
+        fn sources(&self) -> Result<Vec<Box<dyn EntryIterator>>> {
+            self.inner.sources()
+        }
+
+        fn range_tombstones(&self) -> Result<Vec<Entry>> {
+            self.inner.range_tombstones()
+        }
+
+        fn set_snapshots(&mut self, snapshots: SnapshotList) {
+            self.inner.set_snapshots(snapshots)
+        }
+
+        fn block_cache_stats(&self) -> Result<Option<CacheStats>> {
+            self.inner.block_cache_stats()
+        }
+    }
+
+    #[test]
+    fn writes_and_reads_go_on_during_compaction() {
+        let path = "test-data/db-compaction-concurrency";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Arc::new(Db::open(path, small_config()).unwrap());
+
+        let (started, started_rx) = sync_channel(1);
+        let (release_tx, release) = sync_channel(1);
+        {
+            let mut lsm = db.lsm.write().unwrap();
+            let placeholder = Box::new(LSMTree::<MF>::new(0.1, 10, String::from(path), 2, 1));
+            let inner = std::mem::replace(&mut *lsm, placeholder);
+            *lsm = Box::new(HeldCompaction { inner, gate: Mutex::new(Some((started, release))) });
+        }
+
+        // the second flush fills level 0 and its merge is held
+        for i in 0..10 {
+            db.put(format!("key-{i:02}").as_bytes(), b"value").unwrap();
+        }
+        started_rx.recv_timeout(Duration::from_secs(10)).expect("compaction did not start");
+
+        let (done_tx, done) = sync_channel(1);
+        let worker = Arc::clone(&db);
+        thread::spawn(move || {
+            for i in 10..13 {
+                worker.put(format!("key-{i:02}").as_bytes(), b"value").unwrap();
+            }
+            // only the tables hold these keys
+            let flushed = worker.get(b"key-00").unwrap();
+            let missing = worker.get(b"missing").unwrap();
+            done_tx.send((flushed, missing)).unwrap();
+        });
+        let (flushed, missing) = done.recv_timeout(Duration::from_secs(10))
+            .expect("writes and reads blocked by the compaction");
+        assert_eq!(flushed, Some(b"value".to_vec()));
+        assert_eq!(missing, None);
+
+        release_tx.send(()).unwrap();
+        db.lock_flusher().unwrap().stop().unwrap();
+        assert_eq!(db.prefix_scan(b"key-").unwrap().len(), 13);
+        assert_eq!(db.get(b"key-05").unwrap(), Some(b"value".to_vec()));
+    }
+
+    fn leveled_config() -> Config {
+        let mut config = serde_json::to_value(small_config()).unwrap();
+        config["lsm"]["compaction"] = serde_json::json!({
+            "Leveled": { "base_level_bytes": 1000, "multiplier": 2, "table_bytes": 300 }
+        });
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn leveled_compaction() {
+        let path = "test-data/db-leveled";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let db = Db::open(path, leveled_config()).unwrap();
+        for round in 0..3 {
+            for i in 0..40 {
+                db.put(format!("key-{i:02}").as_bytes(), format!("value-{round}").as_bytes()).unwrap();
+            }
+        }
+        db.close().unwrap();
+
+        let db = Db::open(path, leveled_config()).unwrap();
+        let scan = db.prefix_scan(b"key-").unwrap();
+        assert_eq!(scan.len(), 40);
+        assert!(scan.iter().all(|(_, value)| value == b"value-2"));
+    }
+
+    #[test]
+    fn delete_range() {
+        let path = "test-data/db-delete-range";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let key = |i: usize| format!("key-{i:02}").into_bytes();
+
+        let db = Db::open(path, small_config()).unwrap();
+        for i in 0..20 {
+            db.put(&key(i), b"old").unwrap();
+        }
+        assert_eq!(db.get(&key(6)).unwrap(), Some(b"old".to_vec()));
+        let snapshot = db.snapshot().unwrap();
+
+        db.delete_range(&key(5), &key(9)).unwrap();
+        db.put(&key(7), b"new").unwrap();
+        // a put of the same batch is not deleted
+        let mut batch = WriteBatch::new();
+        batch.delete_range(&key(12), &key(14)).put(&key(13), b"new");
+        db.write_batch(batch).unwrap();
+        assert!(db.delete_range(b"b", b"a").is_err());
+
+        let check = |db: &Db| {
+            let deleted = [5, 6, 8, 9, 12, 14];
+            let expected = (0..20)
+                .filter(|i| !deleted.contains(i))
+                .map(|i| (key(i), if i == 7 || i == 13 { b"new".to_vec() } else { b"old".to_vec() }))
+                .collect::<Vec<_>>();
+            for i in 0..20 {
+                let value = expected.iter().find(|(k, _)| *k == key(i)).map(|(_, v)| v.clone());
+                assert_eq!(db.get(&key(i)).unwrap(), value, "key {i}");
+            }
+            assert_eq!(db.range_scan(&key(0), &key(19)).unwrap(), expected);
+            assert_eq!(db.range_iter(&key(0), &key(19)).unwrap().rev().count(), expected.len());
+        };
+        check(&db);
+        assert_eq!(db.get_at(&snapshot, &key(6)).unwrap(), Some(b"old".to_vec()));
+        assert_eq!(db.range_scan_at(&snapshot, &key(0), &key(19)).unwrap().len(), 20);
+
+        // the range tombstone is flushed and merged with the tables it deletes from
+        drop(snapshot);
+        for i in 20..40 {
+            db.put(&key(i), b"old").unwrap();
+        }
+        db.delete_range(&key(30), &key(39)).unwrap();
+        check(&db);
+        drop(db);
+
+        // and replayed from the WAL
+        let db = Db::open(path, small_config()).unwrap();
+        check(&db);
+        assert_eq!(db.prefix_scan(b"key-3").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn ttl() {
+        let path = "test-data/db-ttl";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let db = Db::open(path, small_config()).unwrap();
+        db.put(b"key-0", b"old").unwrap();
+        db.put_with_ttl(b"key-0", b"short", Duration::from_secs(1)).unwrap();
+        db.put_with_ttl(b"key-1", b"long", Duration::from_secs(3600)).unwrap();
+        db.put(b"key-2", b"value").unwrap();
+        let snapshot = db.snapshot().unwrap();
+        assert_eq!(db.get(b"key-0").unwrap(), Some(b"short".to_vec()));
+        sleep(Duration::from_millis(1100));
+
+        // an expired entry hides the older versions like a tombstone
+        let check = |db: &Db| {
+            assert_eq!(db.get(b"key-0").unwrap(), None);
+            assert_eq!(db.get(b"key-1").unwrap(), Some(b"long".to_vec()));
+            assert_eq!(db.prefix_scan(b"key-0").unwrap(), vec![]);
+            let expected = vec![(b"key-1".to_vec(), b"long".to_vec()), (b"key-2".to_vec(), b"value".to_vec())];
+            assert_eq!(db.range_scan(b"key-0", b"key-9").unwrap(), expected);
+        };
+        check(&db);
+        assert_eq!(db.get_at(&snapshot, b"key-0").unwrap(), Some(b"short".to_vec()));
+        drop(snapshot);
+
+        // flushed and merged, the expiry is kept in the tables
+        for i in 3..30 {
+            db.put(format!("other-{i:02}").as_bytes(), b"value").unwrap();
+        }
+        assert_eq!(db.get(b"key-0").unwrap(), None);
+        assert_eq!(db.get(b"key-1").unwrap(), Some(b"long".to_vec()));
+        db.put_with_ttl(b"key-3", b"short", Duration::from_millis(1)).unwrap();
+        drop(db);
+
+        // and in the WAL
+        let db = Db::open(path, small_config()).unwrap();
+        check(&db);
+        assert_eq!(db.get(b"key-3").unwrap(), None);
+    }
+
+    #[test]
+    fn concurrent_readers_and_writer() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Db>();
+
+        let path = "test-data/db-concurrent";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+        let db = Db::open(path, small_config()).unwrap();
+        for i in 0..50 {
+            db.put(format!("key-{i:02}").as_bytes(), b"old").unwrap();
+        }
+
+        std::thread::scope(|s| {
+            // the writer flushes the memtable many times while the readers run
+            s.spawn(|| {
+                for i in 0..50 {
+                    db.put(format!("key-{i:02}").as_bytes(), b"new").unwrap();
+                    db.put(format!("other-{i:02}").as_bytes(), b"value").unwrap();
+                }
+            });
+
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..50 {
+                        let value = db.get(format!("key-{i:02}").as_bytes()).unwrap();
+                        assert!(value == Some(b"old".to_vec()) || value == Some(b"new".to_vec()));
+
+                        let scan = db.prefix_scan(b"key-").unwrap();
+                        assert_eq!(scan.len(), 50);
+                    }
+                });
+            }
+        });
+
+        let scan = db.prefix_scan(b"key-").unwrap();
+        assert!(scan.iter().all(|(_, value)| value == b"new"));
+        assert_eq!(db.prefix_scan(b"other-").unwrap().len(), 50);
+    }
+
+    #[test]
+    fn concurrent_structure_updates() {
+        let db = open_clean("test-data/db-concurrent-structures");
+        db.bf_new("bf_shared").unwrap();
+        db.cms_new("cms_shared").unwrap();
+
+        // read-modify-write of the same structure from several threads loses no update
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let db = &db;
+                s.spawn(move || {
+                    for i in 0..5 {
+                        db.bf_add("bf_shared", format!("{t}-{i}").as_bytes()).unwrap();
+                        db.cms_add("cms_shared", "item").unwrap();
+                    }
+                });
+            }
+        });
+
+        for t in 0..4 {
+            for i in 0..5 {
+                assert!(db.bf_check("bf_shared", format!("{t}-{i}").as_bytes()).unwrap());
+            }
+        }
+        assert_eq!(db.cms_count("cms_shared", "item").unwrap(), 20);
+    }
+
+    #[test]
+    fn probabilistic_structures() {
+        let db = open_clean("test-data/db-probabilistic");
+
+        assert!(db.bf_new("not_bf").is_err());
+        db.bf_new("bf_test").unwrap();
+        db.bf_add("bf_test", b"value").unwrap();
+        assert!(db.bf_check("bf_test", b"value").unwrap());
+        assert!(db.bf_check("bf_missing", b"value").is_err());
+
+        db.hll_new("hll_test").unwrap();
+        db.hll_add("hll_test", b"value").unwrap();
+        assert!(db.hll_count("hll_test").unwrap() > 0.0);
+
+        db.cms_new("cms_test").unwrap();
+        assert_eq!(db.cms_add("cms_test", "value").unwrap(), 1);
+        assert_eq!(db.cms_count("cms_test", "value").unwrap(), 1);
+        assert_eq!(db.cms_add_count("cms_test", "value", 1_000_000_000).unwrap(), 1_000_000_001);
+        assert_eq!(db.existing_structure("cms_", b"test").unwrap(), Some(String::from("cms_test")));
+        assert_eq!(db.existing_structure("cms_", b"missing").unwrap(), None);
+        assert!(db.existing_structure("cms_", b"\xff").is_err());
+        assert!(super::check_plain_key(b"cms_test").is_err());
+
+        db.sh_hash("sh_left", "some text to hash").unwrap();
+        db.sh_hash("sh_right", "some text to hash").unwrap();
+        assert_eq!(db.sh_similarity("sh_left", "sh_right").unwrap(), 1.0);
+    }
+}
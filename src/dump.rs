@@ -0,0 +1,525 @@
+use std::{io::{ErrorKind, Write}, path::Path};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use nebula::building_blocks::{
+    Entry, EntryKind, MerkleRoot, RecoveryMode, SSTableReaderMultiFile, SSTableReaderSingleFile, TableReader,
+    WalRecord, WriteAheadLogReader, FORMAT_VERSION,
+};
+use crate::cli::{DumpArgs, DumpFormat, Encoding};
+
+#[derive(Serialize)]
+struct WalDump {
+    segments: Vec<SegmentDump>,
+    dropped: Vec<DroppedDump>,
+}
+
+#[derive(Serialize)]
+struct SegmentDump {
+    segment: usize,
+    /// offset after the last good record
+    end: u64,
+    records: Vec<RecordDump>,
+}
+
+#[derive(Serialize)]
+struct RecordDump {
+    sequence: u64,
+    batch: bool,
+    entries: Vec<EntryDump>,
+}
+
+#[derive(Serialize)]
+struct DroppedDump {
+    segment: usize,
+    offset: u64,
+    len: u64,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct EntryDump {
+    timestamp: u128,
+    /// put, delete or range_delete
+    kind: &'static str,
+    key: String,
+    value: Option<String>,
+    /// exclusive end of a range delete
+    end: Option<String>,
+    expires_at: Option<u128>,
+}
+
+#[derive(Serialize)]
+struct TableDump {
+    path: String,
+    multi_file: bool,
+    version: u32,
+    compression: String,
+    /// offsets of the sections of a single file table
+    header: Option<HeaderDump>,
+    filter: FilterDump,
+    first_key: String,
+    last_key: String,
+    summary: Vec<SummaryDump>,
+    index: Vec<IndexDump>,
+    data: Vec<EntryDump>,
+    range_tombstones: Vec<EntryDump>,
+    merkle_root: Option<String>,
+    /// damage found while reading, without verification the dump goes on after it
+    errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct HeaderDump {
+    data: u64,
+    range_tombstones: u64,
+    filter: u64,
+    meta: u64,
+    index: u64,
+    summary: u64,
+}
+
+#[derive(Serialize)]
+struct FilterDump {
+    item_count: u64,
+    fp_prob: f64,
+    hash_functions: u64,
+    bits: u64,
+}
+
+#[derive(Serialize)]
+struct SummaryDump {
+    first_key: String,
+    last_key: String,
+    /// position of the first index entry of the range
+    index_position: u64,
+}
+
+#[derive(Serialize)]
+struct IndexDump {
+    key: String,
+    offset: u64,
+}
+
+/// prints the records of a WAL folder, or of a single segment when the path is a segment file
+/// with verification the first damaged record fails the dump, otherwise it is reported and skipped
+pub fn dump_wal(args: &DumpArgs) -> Result<()> {
+    let dump = wal_dump(args)?;
+    match args.format {
+        DumpFormat::Json => print_json(&dump),
+        DumpFormat::Text => write_out(&wal_text(&dump)),
+    }
+}
+
+fn wal_dump(args: &DumpArgs) -> Result<WalDump> {
+    let (folder, segment) = wal_location(&args.path)?;
+    let mode = if args.verify { RecoveryMode::Strict } else { RecoveryMode::SkipCorrupt };
+    let mut reader = WriteAheadLogReader::iter(&folder)
+        .context("getting wal reader")?
+        .with_recovery_mode(mode);
+    if let Some(segment) = segment {
+        reader = reader.with_segment(segment);
+    }
+
+    let mut segments = Vec::new();
+    while let Some(records) = reader.next() {
+        let records = records.context("reading wal")?;
+        // every segment that yields records is in the report by now
+        let (segment, end) = *reader.report().segment_ends.last()
+            .ok_or(anyhow!("segment missing from the recovery report"))?;
+        let records = records.into_iter()
+            .map(|(sequence, record)| {
+                let batch = matches!(record, WalRecord::Batch(_));
+                let entries = record.into_entries().iter().map(|entry| entry_dump(entry, args.encoding)).collect();
+                RecordDump { sequence, batch, entries }
+            })
+            .collect();
+        segments.push(SegmentDump { segment, end, records });
+    }
+
+    let dropped = reader.report().dropped.iter()
+        .map(|dropped| DroppedDump {
+            segment: dropped.segment,
+            offset: dropped.offset,
+            len: dropped.len,
+            reason: dropped.reason.clone(),
+        })
+        .collect();
+    Ok(WalDump { segments, dropped })
+}
+
+/// prints every section of a table directory, single and multi file tables are told apart by their files
+/// with verification the first damaged section fails the dump and the merkle root is checked against the data
+pub fn dump_sstable(args: &DumpArgs) -> Result<()> {
+    let dump = sstable_dump(args)?;
+    match args.format {
+        DumpFormat::Json => print_json(&dump),
+        DumpFormat::Text => write_out(&table_text(&dump)),
+    }
+}
+
+fn sstable_dump(args: &DumpArgs) -> Result<TableDump> {
+    let dir = args.path.to_str()
+        .ok_or(anyhow!("table path is not valid UTF-8"))?;
+    if args.path.join("index").is_file() {
+        let reader = SSTableReaderMultiFile::load(dir)
+            .context("loading multi file table")?;
+        table_dump(dir, &reader, None, args)
+    } else {
+        let reader = SSTableReaderSingleFile::load(dir)
+            .context("loading single file table")?;
+        let header = HeaderDump {
+            data: reader.header.data_offset,
+            range_tombstones: reader.header.range_tombstones_offset,
+            filter: reader.header.filter_offset,
+            meta: reader.header.meta_offset,
+            index: reader.header.index_offset,
+            summary: reader.header.summary_offset,
+        };
+        table_dump(dir, &reader, Some(header), args)
+    }
+}
+
+fn table_dump<R: TableReader>(dir: &str, reader: &R, header: Option<HeaderDump>, args: &DumpArgs) -> Result<TableDump> {
+    let encoding = args.encoding;
+    let mut errors = Vec::new();
+    // a damaged section ends the dump only with verification
+    let mut damaged = |e: anyhow::Error| -> Result<()> {
+        if args.verify {
+            return Err(e);
+        }
+        errors.push(format!("{e:#}"));
+        Ok(())
+    };
+
+    let filter = reader.filter();
+    let filter = FilterDump {
+        item_count: filter.item_count,
+        fp_prob: filter.fp_prob(),
+        hash_functions: filter.hash_functions(),
+        bits: filter.bit_len(),
+    };
+    let (first_key, last_key) = reader.range();
+
+    let summary = reader.ranges()
+        .context("reading summary")?
+        .iter()
+        .map(|range| SummaryDump {
+            first_key: render(&range.first_key, encoding),
+            last_key: render(&range.last_key, encoding),
+            index_position: range.offset,
+        })
+        .collect();
+
+    let mut index = Vec::new();
+    for entry in reader.index().context("reading index")? {
+        match entry.context("reading index entry") {
+            Ok(entry) => index.push(IndexDump { key: render(&entry.key, encoding), offset: entry.offset }),
+            Err(e) => {
+                damaged(e)?;
+                break;
+            }
+        }
+    }
+
+    let mut data = Vec::new();
+    let mut values = Vec::new();
+    for entry in reader.entries().context("reading data")? {
+        match entry.context("reading data block") {
+            Ok(entry) => {
+                data.push(entry_dump(&entry, encoding));
+                values.push(entry.value.unwrap_or_default());
+            }
+            Err(e) => {
+                damaged(e)?;
+                break;
+            }
+        }
+    }
+
+    let merkle_root = match reader.merkle_root().context("reading merkle root") {
+        Ok(root) => root.get_root_hash().map(hex::encode),
+        Err(e) => {
+            damaged(e)?;
+            None
+        }
+    };
+    if args.verify && MerkleRoot::new(values).get_root_hash().map(hex::encode) != merkle_root {
+        return Err(anyhow!("merkle root does not match the data"));
+    }
+
+    Ok(TableDump {
+        path: dir.to_string(),
+        multi_file: header.is_none(),
+        // tables of other versions can not be loaded
+        version: FORMAT_VERSION,
+        compression: format!("{:?}", reader.compression()),
+        header,
+        filter,
+        first_key: render(first_key, encoding),
+        last_key: render(last_key, encoding),
+        summary,
+        index,
+        data,
+        range_tombstones: reader.range_tombstones().iter().map(|entry| entry_dump(entry, encoding)).collect(),
+        merkle_root,
+        errors,
+    })
+}
+
+/// the folder and the only segment to read, None reads all of them
+fn wal_location(path: &Path) -> Result<(String, Option<usize>)> {
+    let to_string = |path: &Path| path.to_str()
+        .map(str::to_string)
+        .ok_or(anyhow!("wal path is not valid UTF-8"));
+    if path.is_dir() {
+        return Ok((to_string(path)?, None));
+    }
+
+    let segment = path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("segment-"))
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or(anyhow!("'{}' is neither a wal folder nor a segment", path.display()))?;
+    let folder = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => to_string(parent)?,
+        _ => String::from("."),
+    };
+    Ok((folder, Some(segment)))
+}
+
+fn entry_dump(entry: &Entry, encoding: Encoding) -> EntryDump {
+    let (kind, end) = match &entry.kind {
+        EntryKind::Point if entry.value.is_some() => ("put", None),
+        EntryKind::Point => ("delete", None),
+        EntryKind::RangeDelete { end } => ("range_delete", Some(render(end, encoding))),
+    };
+    EntryDump {
+        timestamp: entry.timestamp,
+        kind,
+        key: render(&entry.key, encoding),
+        value: entry.value.as_ref().map(|value| render(value, encoding)),
+        end,
+        expires_at: entry.expires_at,
+    }
+}
+
+fn render(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Hex => hex::encode(bytes),
+    }
+}
+
+fn print_json<T: Serialize>(dump: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(dump)
+        .context("serializing dump")?;
+    write_out(&format!("{json}\n"))
+}
+
+/// dumps are often piped into other tools, one that stops reading early is not a panic
+fn write_out(text: &str) -> Result<()> {
+    match std::io::stdout().lock().write_all(text.as_bytes()) {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        res => res.context("writing dump"),
+    }
+}
+
+fn entry_text(entry: &EntryDump) -> String {
+    let mut text = match (entry.kind, entry.value.as_ref(), entry.end.as_ref()) {
+        ("range_delete", _, Some(end)) => format!("{} range delete [{}, {end})", entry.timestamp, entry.key),
+        (_, Some(value), _) => format!("{} put {} = {value}", entry.timestamp, entry.key),
+        _ => format!("{} delete {}", entry.timestamp, entry.key),
+    };
+    if let Some(expires_at) = entry.expires_at {
+        text.push_str(&format!(" (expires at {expires_at})"));
+    }
+    text
+}
+
+fn wal_text(dump: &WalDump) -> String {
+    let mut text = String::new();
+    for segment in &dump.segments {
+        text.push_str(&format!("segment {}, {} records ending at offset {}\n", segment.segment, segment.records.len(), segment.end));
+        for record in &segment.records {
+            if record.batch {
+                text.push_str(&format!("  #{} batch of {}\n", record.sequence, record.entries.len()));
+                for entry in &record.entries {
+                    text.push_str(&format!("    {}\n", entry_text(entry)));
+                }
+            } else if let Some(entry) = record.entries.first() {
+                text.push_str(&format!("  #{} {}\n", record.sequence, entry_text(entry)));
+            }
+        }
+    }
+    for dropped in &dump.dropped {
+        text.push_str(&format!(
+            "dropped {} bytes of segment {} at offset {}: {}\n",
+            dropped.len, dropped.segment, dropped.offset, dropped.reason
+        ));
+    }
+    text
+}
+
+fn table_text(dump: &TableDump) -> String {
+    let organization = if dump.multi_file { "multi file" } else { "single file" };
+    let mut text = format!("sstable {} ({organization}), version {}, compression {}\n", dump.path, dump.version, dump.compression);
+    if let Some(header) = &dump.header {
+        text.push_str(&format!(
+            "header: data at {}, range tombstones at {}, filter at {}, meta at {}, index at {}, summary at {}\n",
+            header.data, header.range_tombstones, header.filter, header.meta, header.index, header.summary
+        ));
+    }
+    text.push_str(&format!(
+        "filter: {} items, false positive probability {}, {} hash functions, {} bits\n",
+        dump.filter.item_count, dump.filter.fp_prob, dump.filter.hash_functions, dump.filter.bits
+    ));
+    text.push_str(&format!("keys: {} .. {}\n", dump.first_key, dump.last_key));
+
+    text.push_str(&format!("summary: {} ranges\n", dump.summary.len()));
+    for range in &dump.summary {
+        text.push_str(&format!("  {} .. {} from index entry {}\n", range.first_key, range.last_key, range.index_position));
+    }
+    text.push_str(&format!("index: {} blocks\n", dump.index.len()));
+    for entry in &dump.index {
+        text.push_str(&format!("  {} at {}\n", entry.key, entry.offset));
+    }
+    text.push_str(&format!("data: {} entries\n", dump.data.len()));
+    for entry in &dump.data {
+        text.push_str(&format!("  {}\n", entry_text(entry)));
+    }
+    text.push_str(&format!("range tombstones: {}\n", dump.range_tombstones.len()));
+    for entry in &dump.range_tombstones {
+        text.push_str(&format!("  {}\n", entry_text(entry)));
+    }
+    text.push_str(&format!("merkle root: {}\n", dump.merkle_root.as_deref().unwrap_or("none")));
+    for error in &dump.errors {
+        text.push_str(&format!("damaged: {error}\n"));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_dir_all, OpenOptions},
+        io::{Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    };
+    use nebula::building_blocks::{Compression, Entry, EntryKind, LSMTreeUnderlying, WriteAheadLog, WriteBatch, MF, SF};
+    use crate::cli::{DumpArgs, DumpFormat, Encoding};
+    use super::{sstable_dump, wal_dump};
+
+    fn clean_dir(path: &str) {
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old test data");
+        }
+        create_dir_all(path).expect("creating test dir");
+    }
+
+    fn args(path: &str, encoding: Encoding, verify: bool) -> DumpArgs {
+        DumpArgs { path: PathBuf::from(path), format: DumpFormat::Json, encoding, verify }
+    }
+
+    /// overwrites the byte at the offset of the file
+    fn corrupt(file_name: &str, offset: u64) {
+        let mut file = OpenOptions::new().write(true).open(file_name).expect("opening file");
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+    }
+
+    fn entries() -> Vec<Entry> {
+        let mut entries = (0..100u128)
+            .map(|i| Entry {
+                timestamp: i,
+                key: format!("key-{i:03}").into_bytes(),
+                value: (i % 10 != 0).then(|| format!("value-{i}").into_bytes()),
+                kind: EntryKind::Point,
+                expires_at: None,
+            })
+            .collect::<Vec<_>>();
+        entries.push(Entry::range_delete(100, b"key-010".to_vec(), b"key-020".to_vec()));
+        entries
+    }
+
+    #[test]
+    fn dumping_wal() {
+        let path = "test-data/dump-wal";
+        clean_dir(path);
+        let mut wal = WriteAheadLog::new(path, 1000).expect("creating wal");
+        wal.add(&Entry { timestamp: 1, key: b"a".to_vec(), value: Some(b"1".to_vec()), kind: EntryKind::Point, expires_at: None })
+            .expect("adding entry");
+        wal.rotate().expect("rotating wal");
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2").delete(b"c").delete_range(b"d", b"f");
+        wal.add_batch(&batch).expect("adding batch");
+        drop(wal);
+
+        let dump = wal_dump(&args(path, Encoding::Utf8, true)).expect("dumping wal");
+        assert_eq!(dump.segments.len(), 2);
+        assert!(dump.dropped.is_empty());
+        let first = &dump.segments[0].records[0];
+        assert_eq!((first.sequence, first.batch), (1, false));
+        assert_eq!((first.entries[0].kind, first.entries[0].value.as_deref()), ("put", Some("1")));
+        let second = &dump.segments[1].records[0];
+        assert_eq!((second.sequence, second.batch), (2, true));
+        let kinds = second.entries.iter().map(|entry| entry.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, ["put", "delete", "range_delete"]);
+        // the batch keeps the exclusive end of the inclusive range
+        assert_eq!(second.entries[2].end.as_deref(), Some("f\0"));
+
+        // a single segment, keys in hex
+        let dump = wal_dump(&args(&format!("{path}/segment-1"), Encoding::Hex, true)).expect("dumping segment");
+        assert_eq!(dump.segments.len(), 1);
+        assert_eq!(dump.segments[0].segment, 1);
+        assert_eq!(dump.segments[0].records[0].entries[0].key, "62");
+
+        // the damaged record is reported, or fails the dump with verification
+        corrupt(&format!("{path}/segment-1"), dump.segments[0].end - 1);
+        let dump = wal_dump(&args(path, Encoding::Utf8, false)).expect("dumping damaged wal");
+        assert_eq!(dump.dropped.len(), 1);
+        assert_eq!(dump.dropped[0].segment, 1);
+        assert_eq!(dump.segments[0].records.len(), 1);
+        assert!(dump.segments[1].records.is_empty());
+        assert!(wal_dump(&args(path, Encoding::Utf8, true)).is_err());
+    }
+
+    fn dumping_sstable<T: LSMTreeUnderlying>(path: &str, multi_file: bool) {
+        clean_dir(path);
+        T::write(path, "table", entries(), 0.01, 3, Compression::None).expect("writing table");
+        let table = format!("{path}/table");
+
+        let dump = sstable_dump(&args(&table, Encoding::Utf8, true)).expect("dumping table");
+        assert_eq!(dump.multi_file, multi_file);
+        assert_eq!(dump.header.is_none(), multi_file);
+        assert_eq!(dump.filter.item_count, 101);
+        assert_eq!((dump.first_key.as_str(), dump.last_key.as_str()), ("key-000", "key-099"));
+        assert!(!dump.summary.is_empty());
+        assert!(dump.index.len() >= dump.summary.len());
+        assert!(dump.index.windows(2).all(|blocks| blocks[0].offset < blocks[1].offset));
+        assert_eq!(dump.data.len(), 100);
+        assert_eq!(dump.data[0].kind, "delete");
+        assert_eq!(dump.data[1].value.as_deref(), Some("value-1"));
+        assert_eq!(dump.range_tombstones.len(), 1);
+        assert_eq!(dump.range_tombstones[0].end.as_deref(), Some("key-020"));
+        assert!(dump.merkle_root.is_some());
+        assert!(dump.errors.is_empty());
+
+        // the damaged second block is reported and the rest of the table is still dumped
+        corrupt(&format!("{table}/data"), dump.index[1].offset + 10);
+        let dump = sstable_dump(&args(&table, Encoding::Utf8, false)).expect("dumping damaged table");
+        assert_eq!(dump.errors.len(), 1);
+        assert!(dump.data.len() < 100);
+        assert_eq!(dump.range_tombstones.len(), 1);
+        assert!(sstable_dump(&args(&table, Encoding::Utf8, true)).is_err());
+    }
+
+    #[test]
+    fn dumping_sstable_singlefile() {
+        dumping_sstable::<SF>("test-data/dump-sstable-singlefile", false);
+    }
+
+    #[test]
+    fn dumping_sstable_multifile() {
+        dumping_sstable::<MF>("test-data/dump-sstable-multifile", true);
+    }
+}
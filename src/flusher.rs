@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use anyhow::{Context, Result, anyhow};
+use crate::building_blocks::{ImmutableMemtable, LSMTreeInterface, WriteAheadLog};
+
+/// frozen memtables waiting for the flusher, newest first
+pub(crate) type Immutables = Arc<RwLock<VecDeque<Arc<ImmutableMemtable>>>>;
+
+pub(crate) type SharedLsm = Arc<RwLock<Box<dyn LSMTreeInterface>>>;
+
+/// writers block once this many frozen memtables wait to be flushed
+const MAX_PENDING_FLUSHES: usize = 4;
+
+/// frozen memtable together with the sequence of the last WAL record it holds
+struct FlushTask {
+    memtable: Arc<ImmutableMemtable>,
+    sequence: u64,
+}
+
+/// background thread that writes frozen memtables to sstables, oldest first
+/// the table is inserted into the lsm before the memtable stops serving reads,
+/// after that the WAL segments holding only records up to its sequence are removed and the levels are compacted
+pub(crate) struct Flusher {
+    sender: Option<SyncSender<FlushTask>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Flusher {
+    pub fn start(lsm: SharedLsm, immutables: Immutables, wal_folder: String) -> Result<Self> {
+        let (sender, tasks) = sync_channel::<FlushTask>(MAX_PENDING_FLUSHES);
+        let handle = thread::Builder::new()
+            .name(String::from("nebula-flush"))
+            .spawn(move || {
+                // a failed flush stops the thread, the memtable and its WAL segments are kept
+                for task in tasks {
+                    task.memtable.flush()
+                        .context("writing memtable")?;
+                    lsm.write()
+                        .map_err(|_| anyhow!("lsm lock poisoned"))?
+                        .insert_flushed("memtable", task.sequence)
+                        .context("inserting memetable into lsm")?;
+
+                    immutables.write()
+                        .map_err(|_| anyhow!("immutable memtables lock poisoned"))?
+                        .pop_back();
+                    WriteAheadLog::purge_through(&wal_folder, task.sequence)
+                        .context("purging flushed wal segments")?;
+                    compact(&lsm).context("compacting tables")?;
+                }
+                Ok(())
+            })
+            .context("spawning flush thread")?;
+
+        Ok(Flusher { sender: Some(sender), handle: Some(handle) })
+    }
+
+    /// blocks while too many memtables wait to be flushed
+    /// the memtable has to be at the front of the immutables already
+    pub fn schedule(&mut self, memtable: Arc<ImmutableMemtable>, sequence: u64) -> Result<()> {
+        let sent = match self.sender.as_ref() {
+            Some(sender) => sender.send(FlushTask { memtable, sequence }).is_ok(),
+            None => false,
+        };
+        if sent {
+            return Ok(());
+        }
+
+        // the thread is gone only if a flush failed, join it to get the error
+        self.stop()?;
+        Err(anyhow!("flush thread stopped"))
+    }
+
+    /// waits until every scheduled memtable is flushed and stops the thread
+    pub fn stop(&mut self) -> Result<()> {
+        self.sender = None;
+        match self.handle.take() {
+            Some(handle) => handle.join()
+                .map_err(|_| anyhow!("flush thread panicked"))?
+                .context("flushing memtable in the background"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// compacts until the levels need no more compaction, the new tables are written under the read lock
+/// so lookups and scans go on, the write lock is held only while they are swapped into the levels
+/// only one thread compacts at a time, the flush thread or the one flushing while it does not run
+pub(crate) fn compact(lsm: &SharedLsm) -> Result<()> {
+    loop {
+        let output = lsm.read()
+            .map_err(|_| anyhow!("lsm lock poisoned"))?
+            .build_compaction()
+            .context("building compaction")?;
+        let Some(output) = output else {
+            return Ok(());
+        };
+        lsm.write()
+            .map_err(|_| anyhow!("lsm lock poisoned"))?
+            .apply_compaction(output)
+            .context("applying compaction")?;
+    }
+}
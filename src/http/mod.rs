@@ -0,0 +1,242 @@
+mod routes;
+
+use std::{io::Read, str::Utf8Error};
+use anyhow::{Result, Context, anyhow};
+use nebula::Db;
+use serde_json::{json, Value};
+use tiny_http::{Header, Request, Server};
+
+/// max size of a request body, same as the max size of a value
+const MAX_BODY_LEN: u64 = nebula::building_blocks::MAX_VAL_LEN;
+
+/// serves HTTP requests until POST /shutdown, requests are handled one at a time
+pub fn serve(db: &Db, server: &Server) -> Result<()> {
+    loop {
+        let mut request = server.recv().context("receiving request")?;
+        let response = handle(db, &mut request);
+        let shutdown = matches!(response, Ok(Response { shutdown: true, .. }));
+
+        let response = response.unwrap_or_else(|e| e.into_response());
+        let header = Header::from_bytes(&b"Content-Type"[..], response.content_type.as_bytes())
+            .map_err(|_| anyhow!("invalid content type"))?;
+        let http_response = tiny_http::Response::from_data(response.body)
+            .with_status_code(response.status)
+            .with_header(header);
+
+        // a client that hung up only loses its own response
+        let _ = request.respond(http_response);
+        if shutdown {
+            return Ok(());
+        }
+    }
+}
+
+fn handle(db: &Db, request: &mut Request) -> Result<Response, HttpError> {
+    let too_large = || HttpError::too_large(anyhow!("request body is bigger than {MAX_BODY_LEN} bytes"));
+    if request.body_length().is_some_and(|len| len as u64 > MAX_BODY_LEN) {
+        return Err(too_large());
+    }
+
+    // chunked bodies have no length up front, one byte more than allowed is enough to tell
+    let mut body = Vec::new();
+    request.as_reader()
+        .take(MAX_BODY_LEN + 1)
+        .read_to_end(&mut body)
+        .context("reading request body")
+        .map_err(HttpError::bad_request)?;
+    if body.len() as u64 > MAX_BODY_LEN {
+        return Err(too_large());
+    }
+
+    let (path, query) = request.url()
+        .split_once('?')
+        .unwrap_or((request.url(), ""));
+    let method = request.method().as_str().to_uppercase();
+    routes::route(db, &method, path, query, body)
+}
+
+pub struct Response {
+    status: u16,
+    body: Vec<u8>,
+    content_type: &'static str,
+    shutdown: bool,
+}
+
+impl Response {
+    fn json(status: u16, value: Value) -> Self {
+        Response {
+            status,
+            body: value.to_string().into_bytes(),
+            content_type: "application/json",
+            shutdown: false,
+        }
+    }
+
+    fn raw(body: Vec<u8>) -> Self {
+        Response { status: 200, body, content_type: "application/octet-stream", shutdown: false }
+    }
+
+    fn no_content() -> Self {
+        Response { status: 204, body: vec![], content_type: "application/json", shutdown: false }
+    }
+}
+
+/// error with the http status it maps to, the body carries the whole context chain
+pub struct HttpError {
+    status: u16,
+    error: anyhow::Error,
+}
+
+impl HttpError {
+    fn bad_request(error: anyhow::Error) -> Self {
+        HttpError { status: 400, error }
+    }
+
+    fn not_found(error: anyhow::Error) -> Self {
+        HttpError { status: 404, error }
+    }
+
+    fn too_large(error: anyhow::Error) -> Self {
+        HttpError { status: 413, error }
+    }
+
+    fn into_response(self) -> Response {
+        let chain = self.error
+            .chain()
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>();
+        Response::json(self.status, json!({ "error": chain[0], "chain": chain }))
+    }
+}
+
+impl From<anyhow::Error> for HttpError {
+    /// url segments and bodies that are not utf8 are the client's fault, anything else is on the server
+    fn from(error: anyhow::Error) -> Self {
+        let status = if error.is::<Utf8Error>() { 400 } else { 500 };
+        HttpError { status, error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::remove_dir_all,
+        io::{Read, Write},
+        net::TcpStream,
+        path::Path,
+        thread,
+    };
+    use nebula::{Config, Db};
+    use serde_json::Value;
+    use tiny_http::Server;
+    use super::serve;
+
+    /// returns the status code and the body
+    fn send(addr: &str, method: &str, url: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let head = format!("{method} {url} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", body.len());
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+
+    fn json(response: (u16, Vec<u8>)) -> (u16, Value) {
+        (response.0, serde_json::from_slice(&response.1).unwrap())
+    }
+
+    #[test]
+    fn http_session() {
+        let path = "test-data/http";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let db = Db::open(path, Config::default()).unwrap();
+            serve(&db, &server).unwrap();
+            db.close().unwrap();
+        });
+        let addr = &addr[..];
+
+        // "alice" and "bob" in base64
+        assert_eq!(send(addr, "PUT", "/kv/user:1", b"YWxpY2U=").0, 204);
+        assert_eq!(send(addr, "PUT", "/kv/user:2?encoding=raw", b"bob").0, 204);
+        assert_eq!(send(addr, "PUT", "/kv/user%3A3?encoding=raw", b"carol").0, 204);
+        assert_eq!(send(addr, "GET", "/kv/user:2?encoding=raw", b""), (200, b"bob".to_vec()));
+
+        let (status, value) = json(send(addr, "GET", "/kv/user:1", b""));
+        assert_eq!(status, 200);
+        assert_eq!(value["value"], "YWxpY2U=");
+
+        let (status, value) = json(send(addr, "GET", "/kv/missing", b""));
+        assert_eq!(status, 404);
+        assert!(value["error"].is_string());
+
+        // the whole context chain is returned
+        let (status, value) = json(send(addr, "PUT", "/kv/bad", b"not base64!"));
+        assert_eq!(status, 400);
+        assert!(value["chain"].as_array().unwrap().len() > 1);
+
+        let (status, value) = json(send(addr, "GET", "/kv?prefix=user:&limit=2&encoding=raw", b""));
+        assert_eq!(status, 200);
+        assert_eq!(value["items"][0]["key"], "user:1");
+        assert_eq!(value["items"][1]["value"], "bob");
+        let cursor = value["cursor"].as_str().unwrap().to_string();
+
+        let (_, value) = json(send(addr, "GET", &format!("/kv?prefix=user:&limit=2&cursor={cursor}&encoding=raw"), b""));
+        assert_eq!(value["items"][0]["key"], "user:3");
+        assert!(value["cursor"].is_null());
+
+        let (_, value) = json(send(addr, "GET", "/kv?start=user:2&end=user:3", b""));
+        assert_eq!(value["items"].as_array().unwrap().len(), 2);
+        assert_eq!(send(addr, "GET", "/kv?prefix=user:&limit=0", b"").0, 400);
+        assert_eq!(send(addr, "GET", "/kv?prefix=user:&cursor=nope", b"").0, 400);
+        assert_eq!(send(addr, "GET", "/kv?prefix=user:&start=user:2", b"").0, 400);
+
+        assert_eq!(send(addr, "DELETE", "/kv/user:2", b"").0, 204);
+        assert_eq!(send(addr, "GET", "/kv/user:2", b"").0, 404);
+        assert_eq!(send(addr, "GET", "/kv/bf_x", b"").0, 400);
+
+        assert_eq!(send(addr, "PUT", "/bf/seen", b"").0, 204);
+        assert_eq!(send(addr, "POST", "/bf/seen/items", b"a").0, 204);
+        let (_, value) = json(send(addr, "GET", "/bf/seen/items/a", b""));
+        assert_eq!(value["present"], true);
+        assert_eq!(send(addr, "GET", "/bf/missing/items/a", b"").0, 404);
+        assert_eq!(send(addr, "PUT", "/bf/%FF", b"").0, 400);
+
+        assert_eq!(send(addr, "PUT", "/hll/visitors", b"").0, 204);
+        assert_eq!(send(addr, "POST", "/hll/visitors/items", b"a").0, 204);
+        let (_, value) = json(send(addr, "GET", "/hll/visitors/count", b""));
+        assert!(value["count"].as_f64().unwrap() > 0.0);
+
+        assert_eq!(send(addr, "PUT", "/cms/words", b"").0, 204);
+        let (_, value) = json(send(addr, "POST", "/cms/words/items", b"a"));
+        assert_eq!(value["count"], 1);
+        let (_, value) = json(send(addr, "GET", "/cms/words/items/a", b""));
+        assert_eq!(value["count"], 1);
+
+        assert_eq!(send(addr, "PUT", "/sh/left", b"some text to hash").0, 204);
+        assert_eq!(send(addr, "PUT", "/sh/right", b"some text to hash").0, 204);
+        let (_, value) = json(send(addr, "GET", "/sh/left/similarity/right", b""));
+        assert_eq!(value["similarity"], 1.0);
+
+        // keys are encoded like the values, raw mode has no way to show keys that are not utf8
+        assert_eq!(send(addr, "PUT", "/kv/%FF?encoding=raw", b"v").0, 204);
+        let (_, value) = json(send(addr, "GET", "/kv/%FF", b""));
+        assert_eq!(value["key"], "/w==");
+        let (_, value) = json(send(addr, "GET", "/kv?prefix=%FF", b""));
+        assert_eq!(value["items"][0]["key"], "/w==");
+        assert_eq!(send(addr, "GET", "/kv?prefix=%FF&encoding=raw", b"").0, 400);
+
+        assert_eq!(send(addr, "GET", "/nope", b"").0, 404);
+        assert_eq!(send(addr, "POST", "/shutdown", b"").0, 204);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use std::num::NonZeroUsize;
+use anyhow::{Context, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use nebula::{Db, Page, PageToken, check_plain_key, is_reserved, structure_key, utf8};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use super::{HttpError, Response};
+
+/// number of items in a page of a scan when the limit is not set
+const DEFAULT_LIMIT: usize = 100;
+
+/// how values are passed in request bodies and returned in responses
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    /// values are base64 strings in json
+    Base64,
+    /// values are the body itself, in json they are utf8 strings
+    Raw,
+}
+
+pub fn route(db: &Db, method: &str, path: &str, query: &str, body: Vec<u8>) -> Result<Response, HttpError> {
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| percent_decode_str(segment).collect::<Vec<u8>>())
+        .collect::<Vec<_>>();
+    let segments = segments.iter().map(|segment| &segment[..]).collect::<Vec<_>>();
+    let query = Query::parse(query);
+
+    match (method, &segments[..]) {
+        ("GET", [b"kv"]) => scan(db, &query),
+        ("GET", [b"kv", key]) => {
+            check_plain_key(key).map_err(HttpError::bad_request)?;
+            let value = db.get(key)?
+                .ok_or_else(|| HttpError::not_found(anyhow!("key not found")))?;
+            match query.encoding()? {
+                Encoding::Raw => Ok(Response::raw(value)),
+                Encoding::Base64 => Ok(Response::json(200, pair(key, &value, Encoding::Base64)?)),
+            }
+        }
+        ("PUT", [b"kv", key]) => {
+            check_plain_key(key).map_err(HttpError::bad_request)?;
+            let value = match query.encoding()? {
+                Encoding::Raw => body,
+                Encoding::Base64 => BASE64.decode(&body)
+                    .context("decoding base64 value")
+                    .context("value has to be base64, use encoding=raw for raw bytes")
+                    .map_err(HttpError::bad_request)?,
+            };
+            db.put(key, &value)?;
+            Ok(Response::no_content())
+        }
+        ("DELETE", [b"kv", key]) => {
+            check_plain_key(key).map_err(HttpError::bad_request)?;
+            db.delete(key)?;
+            Ok(Response::no_content())
+        }
+
+        ("PUT", [b"bf", key]) => {
+            db.bf_new(&structure_key("bf_", key)?)?;
+            Ok(Response::no_content())
+        }
+        ("POST", [b"bf", key, b"items"]) => {
+            let key = existing_structure(db, "bf_", key)?;
+            db.bf_add(&key, &body)?;
+            Ok(Response::no_content())
+        }
+        ("GET", [b"bf", key, b"items", item]) => {
+            let key = existing_structure(db, "bf_", key)?;
+            let present = db.bf_check(&key, item)?;
+            Ok(Response::json(200, json!({ "present": present })))
+        }
+
+        ("PUT", [b"hll", key]) => {
+            db.hll_new(&structure_key("hll_", key)?)?;
+            Ok(Response::no_content())
+        }
+        ("POST", [b"hll", key, b"items"]) => {
+            let key = existing_structure(db, "hll_", key)?;
+            db.hll_add(&key, &body)?;
+            Ok(Response::no_content())
+        }
+        ("GET", [b"hll", key, b"count"]) => {
+            let key = existing_structure(db, "hll_", key)?;
+            let count = db.hll_count(&key)?;
+            Ok(Response::json(200, json!({ "count": count })))
+        }
+
+        ("PUT", [b"cms", key]) => {
+            db.cms_new(&structure_key("cms_", key)?)?;
+            Ok(Response::no_content())
+        }
+        ("POST", [b"cms", key, b"items"]) => {
+            let key = existing_structure(db, "cms_", key)?;
+            let count = db.cms_add(&key, utf8(&body)?)?;
+            Ok(Response::json(200, json!({ "count": count })))
+        }
+        ("GET", [b"cms", key, b"items", item]) => {
+            let key = existing_structure(db, "cms_", key)?;
+            let count = db.cms_count(&key, utf8(item)?)?;
+            Ok(Response::json(200, json!({ "count": count })))
+        }
+
+        ("PUT", [b"sh", key]) => {
+            db.sh_hash(&structure_key("sh_", key)?, utf8(&body)?)?;
+            Ok(Response::no_content())
+        }
+        ("GET", [b"sh", left, b"similarity", right]) => {
+            let left = existing_structure(db, "sh_", left)?;
+            let right = existing_structure(db, "sh_", right)?;
+            let similarity = db.sh_similarity(&left, &right)?;
+            Ok(Response::json(200, json!({ "similarity": similarity })))
+        }
+
+        ("POST", [b"shutdown"]) => Ok(Response { shutdown: true, ..Response::no_content() }),
+        _ => Err(HttpError::not_found(anyhow!("no route for {method} {path}"))),
+    }
+}
+
+/// GET /kv?prefix=&cursor=&limit= or GET /kv?start=&end=&cursor=&limit=
+/// the cursor of the last page is null
+fn scan(db: &Db, query: &Query) -> Result<Response, HttpError> {
+    // only the arguments are the client's fault, errors of the scan itself are on the server
+    let limit = match query.get("limit") {
+        Some(limit) => utf8(limit)?
+            .parse::<NonZeroUsize>()
+            .context("parsing limit, it has to be at least 1")
+            .map_err(HttpError::bad_request)?
+            .get(),
+        None => DEFAULT_LIMIT,
+    };
+    let token = match query.get("cursor") {
+        Some(cursor) => Some(PageToken::decode(utf8(cursor)?)
+            .context("invalid cursor")
+            .map_err(HttpError::bad_request)?),
+        None => None,
+    };
+
+    let page = match (query.get("prefix"), query.get("start"), query.get("end")) {
+        (Some(prefix), None, None) => db.prefix_scan_page(prefix, limit, token),
+        (None, Some(start), Some(end)) => db.range_scan_page(start, end, limit, token),
+        (None, None, None) => db.prefix_scan_page(b"", limit, token),
+        _ => return Err(HttpError::bad_request(anyhow!("use either prefix or both start and end"))),
+    }?;
+
+    page_json(page, query.encoding()?)
+}
+
+fn page_json(page: Page, encoding: Encoding) -> Result<Response, HttpError> {
+    let items = page.entries
+        .iter()
+        .filter(|(key, _)| !is_reserved(key))
+        .map(|(key, value)| pair(key, value, encoding))
+        .collect::<Result<Vec<_>, _>>()?;
+    let cursor = page.next
+        .map(|next| next.encode())
+        .transpose()?;
+
+    Ok(Response::json(200, json!({ "items": items, "cursor": cursor })))
+}
+
+/// keys are encoded the same way as values
+fn pair(key: &[u8], value: &[u8], encoding: Encoding) -> Result<Value, HttpError> {
+    let encode = |bytes: &[u8]| match encoding {
+        Encoding::Base64 => Ok(BASE64.encode(bytes)),
+        Encoding::Raw => std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .context("not valid utf8, use encoding=base64")
+            .map_err(HttpError::bad_request),
+    };
+    Ok(json!({ "key": encode(key)?, "value": encode(value)? }))
+}
+
+struct Query {
+    params: Vec<(String, Vec<u8>)>,
+}
+
+impl Query {
+    fn parse(query: &str) -> Self {
+        let params = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                (name.to_string(), percent_decode_str(value).collect())
+            })
+            .collect();
+        Query { params }
+    }
+
+    fn get(&self, name: &str) -> Option<&[u8]> {
+        self.params.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    fn encoding(&self) -> Result<Encoding, HttpError> {
+        match self.get("encoding") {
+            None | Some(b"base64") => Ok(Encoding::Base64),
+            Some(b"raw") => Ok(Encoding::Raw),
+            Some(_) => Err(HttpError::bad_request(anyhow!("encoding has to be base64 or raw"))),
+        }
+    }
+}
+
+/// the structure named in the url, 404 if it does not exist
+fn existing_structure(db: &Db, prefix: &str, key: &[u8]) -> Result<String, HttpError> {
+    db.existing_structure(prefix, key)?
+        .ok_or_else(|| HttpError::not_found(anyhow!("structure '{}' not found", String::from_utf8_lossy(key))))
+}
@@ -0,0 +1,20 @@
+//! nebula - write-heavy key-value store built on an LSM tree
+//!
+//! the database is used through the [`Db`] handle:
+//! ```no_run
+//! use nebula::{Config, Db};
+//!
+//! let db = Db::open("data", Config::default()).unwrap();
+//! db.put(b"key", b"value").unwrap();
+//! assert_eq!(db.get(b"key").unwrap(), Some(b"value".to_vec()));
+//! db.close().unwrap();
+//! ```
+
+pub mod building_blocks;
+pub mod utils;
+mod db;
+mod flusher;
+
+pub use db::{Db, RESERVED_PREFIXES, is_reserved, check_plain_key, structure_key, utf8};
+pub use building_blocks::{MergeIterator, Page, PageToken, Snapshot, WriteBatch};
+pub use utils::config::Config;
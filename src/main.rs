@@ -1,15 +1,16 @@
-mod building_blocks;
 mod cli;
-mod engine;
+mod dump;
+mod http;
 mod repl;
+mod server;
 mod test_data_gen;
-mod utils;
 
-use std::{path::Path, fs::{create_dir, remove_file, remove_dir_all}};
-use crate::engine::Engine;
-use anyhow::{Context, Error, Result, anyhow};
+use std::{path::Path, net::TcpListener, fs::{create_dir, remove_file, remove_dir_all}, sync::Arc};
+use nebula::{Config, Db};
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
-use cli::CliCommands;
+use cli::{CliCommands, SSTableCommands, WalCommands};
+use repl::print_err;
 use test_data_gen::generate_test_data;
 
 fn main() {
@@ -30,17 +31,29 @@ fn main() {
             }
         }
         CliCommands::Start => {
-            let engine = Engine::new().context("instantiating engine");
-            if let Err(e) = engine {
+            if let Err(e) = start() {
+                print_err(e);
+            }
+        }
+        CliCommands::Serve { port, host } => {
+            if let Err(e) = serve(&host, port) {
+                print_err(e);
+            }
+        }
+        CliCommands::Http { port, host } => {
+            if let Err(e) = serve_http(&host, port) {
+                print_err(e);
+            }
+        }
+        CliCommands::Wal { command: WalCommands::Dump(args) } => {
+            if let Err(e) = dump::dump_wal(&args) {
+                print_err(e);
+            }
+        }
+        CliCommands::Sstable { command: SSTableCommands::Dump(args) } => {
+            if let Err(e) = dump::dump_sstable(&args) {
                 print_err(e);
-            } else {
-                let mut engine = engine.unwrap();
-
-                if let Err(e) =  engine.start().context("starting engine") {
-                    print_err(e);
-                }
             }
-
         }
         CliCommands::GenerateTestData => {
             if let Err(e) = generate_test_data() {
@@ -49,15 +62,56 @@ fn main() {
                 println!("successfully generated test data");
             }
         },
-        CliCommands::DummyData { file_name } => todo!(),
+        CliCommands::DummyData { file_name: _ } => todo!(),
     }
 }
 
-fn print_err(e: Error) {
-    println!("error: {}", e);
-    for (i, small_e) in e.chain().enumerate().skip(1) {
-        println!("{}{small_e}", "\t".repeat(i));
+/// opens the database and tells what the WAL recovery had to drop
+fn open_db(config: Config) -> Result<Db> {
+    let db = Db::open("./data", config).context("opening database")?;
+    for dropped in &db.recovery_report().dropped {
+        println!(
+            "WAL recovery dropped {} bytes of segment {} at offset {}: {}",
+            dropped.len, dropped.segment, dropped.offset, dropped.reason
+        );
     }
+    Ok(db)
+}
+
+fn start() -> Result<()> {
+    let config = Config::load_from_file()
+        .unwrap_or_else(|_| Config::default());
+
+    let db = open_db(config)?;
+    repl::run(&db).context("running repl")?;
+    db.close().context("closing database")
+}
+
+fn serve(host: &str, port: u16) -> Result<()> {
+    let config = Config::load_from_file()
+        .unwrap_or_else(|_| Config::default());
+
+    let listener = TcpListener::bind((host, port))
+        .with_context(|| format!("listening on {host}:{port}"))?;
+    let db = Arc::new(open_db(config)?);
+    println!("listening on {host}:{port}");
+    server::serve(Arc::clone(&db), listener).context("serving clients")?;
+    Arc::into_inner(db)
+        .ok_or_else(|| anyhow!("database is still used by a connection"))?
+        .close()
+        .context("closing database")
+}
+
+fn serve_http(host: &str, port: u16) -> Result<()> {
+    let config = Config::load_from_file()
+        .unwrap_or_else(|_| Config::default());
+
+    let server = tiny_http::Server::http((host, port))
+        .map_err(|e| anyhow!("listening on {host}:{port}: {e}"))?;
+    let db = open_db(config)?;
+    println!("listening on http://{host}:{port}");
+    http::serve(&db, &server).context("serving requests")?;
+    db.close().context("closing database")
 }
 
 // creates all the directories and generates a default config
@@ -93,7 +147,12 @@ fn clear_fs() -> Result<()> {
             let entries = path.read_dir().context("reading 'table_data'")?;
             for entry in entries {
                 let entry = entry.context("reading dir in 'table_data'")?;
-                remove_dir_all(entry.path()).context("removing file in 'table_data'")?;
+                // tables are dirs, the manifest is a file
+                if entry.path().is_dir() {
+                    remove_dir_all(entry.path()).context("removing dir in 'table_data'")?;
+                } else {
+                    remove_file(entry.path()).context("removing file in 'table_data'")?;
+                }
             }
         } else {
             return Err(anyhow!("missing dir './data/table_data'"));
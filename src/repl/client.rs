@@ -0,0 +1,163 @@
+use std::time::Duration;
+use anyhow::{Context, Error, Result};
+use nebula::{Db, Page, PageToken, is_reserved};
+use nebula::building_blocks::TokenBucket;
+use super::{REPL, BloomFilterCommands, CMSCommands, Commands, HLLCommands, SimHashCommands};
+
+/// drives the database from stdin until the user quits
+pub fn run(db: &Db) -> Result<()> {
+    let token_bucket_vars = db.config().token_bucket.get_values();
+    let mut token_bucket = TokenBucket::new(token_bucket_vars.0, token_bucket_vars.1);
+    let mut repl = REPL::new();
+    loop {
+        let query = repl.get_query().context("getting query")?;
+        if let Commands::Quit = query.commands {
+            break;
+        }
+
+        if !token_bucket.take(1) {
+            let timestamp = chrono::Local::now();
+            println!("{} => FAIL...[TOKEN BUCKET CONSTRAINT]", timestamp.format("%H:%M:%S"));
+            continue;
+        }
+
+        if let Err(e) = execute(db, query.commands) {
+            print_err(e);
+        }
+    }
+    Ok(())
+}
+
+fn execute(db: &Db, command: Commands) -> Result<()> {
+    match command {
+        Commands::Get { key } => {
+            let value = db.get(key.as_bytes()).context("getting entry")?;
+            if !is_reserved(key.as_bytes()) {
+                match value {
+                    Some(value) => print_pair(key.as_bytes(), &value)?,
+                    None => println!("Key not found"),
+                }
+            }
+        }
+        Commands::Put { key, value, ttl: None } => {
+            db.put(key.as_bytes(), value.as_bytes())
+                .with_context(|| format!("putting {key} {value}"))?;
+        }
+        Commands::Put { key, value, ttl: Some(ttl) } => {
+            db.put_with_ttl(key.as_bytes(), value.as_bytes(), Duration::from_secs(ttl))
+                .with_context(|| format!("putting {key} {value} {ttl}"))?;
+        }
+        Commands::Delete { key } => {
+            db.delete(key.as_bytes())
+                .with_context(|| format!("deleting {key}"))?;
+        }
+        Commands::DeleteRange { start_key, end_key } => {
+            db.delete_range(start_key.as_bytes(), end_key.as_bytes())
+                .with_context(|| format!("deleting range {start_key} {end_key}"))?;
+        }
+        Commands::Bf(cmd) => bloomfilter(db, cmd)?,
+        Commands::Hll(cmd) => hll(db, cmd)?,
+        Commands::Cms(cmd) => cms(db, cmd)?,
+        Commands::Sh(cmd) => simhash(db, cmd)?,
+        Commands::List { key_prefix, page_size: Some(page_size), token } => {
+            let token = token.map(|token| PageToken::decode(&token)).transpose()?;
+            let page = db.prefix_scan_page(key_prefix.as_bytes(), page_size, token)?;
+            print_page(page)?;
+        }
+        Commands::List { key_prefix, page_size: None, .. } => {
+            let res = db.prefix_scan(key_prefix.as_bytes())
+                .context("running prefix scan")?;
+            print_pairs(&res)?;
+        }
+        Commands::RangeScan { start_key, end_key, page_size: Some(page_size), token } => {
+            let token = token.map(|token| PageToken::decode(&token)).transpose()?;
+            let page = db.range_scan_page(start_key.as_bytes(), end_key.as_bytes(), page_size, token)?;
+            print_page(page)?;
+        }
+        Commands::RangeScan { start_key, end_key, page_size: None, .. } => {
+            let res = db.range_scan(start_key.as_bytes(), end_key.as_bytes())
+                .context("running range scan")?;
+            print_pairs(&res)?;
+        }
+        Commands::Quit => unreachable!(),
+    }
+    Ok(())
+}
+
+fn bloomfilter(db: &Db, cmd: BloomFilterCommands) -> Result<()> {
+    match cmd {
+        BloomFilterCommands::New { bloom_filter_key } => db.bf_new(&bloom_filter_key)?,
+        BloomFilterCommands::Add { bloom_filter_key, value } => {
+            db.bf_add(&bloom_filter_key, value.as_bytes())?
+        }
+        BloomFilterCommands::Check { bloom_filter_key, value } => {
+            if db.bf_check(&bloom_filter_key, value.as_bytes())? {
+                println!("Value is present in the bloomfilter");
+            } else {
+                println!("Value is not present in the bloomfilter");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn hll(db: &Db, cmd: HLLCommands) -> Result<()> {
+    match cmd {
+        HLLCommands::New { hll_key } => db.hll_new(&hll_key)?,
+        HLLCommands::Add { hll_key, value } => db.hll_add(&hll_key, value.as_bytes())?,
+        HLLCommands::Count { hll_key } => println!("Count: {}", db.hll_count(&hll_key)?),
+    }
+    Ok(())
+}
+
+fn cms(db: &Db, cmd: CMSCommands) -> Result<()> {
+    match cmd {
+        CMSCommands::New { cms_key } => db.cms_new(&cms_key)?,
+        CMSCommands::Add { cms_key, value } => println!("Count: {}", db.cms_add(&cms_key, &value)?),
+        CMSCommands::Count { cms_key } => println!("Count: {}", db.cms_count(&cms_key, "")?),
+    }
+    Ok(())
+}
+
+fn simhash(db: &Db, cmd: SimHashCommands) -> Result<()> {
+    match cmd {
+        SimHashCommands::Hash { key, value } => db.sh_hash(&key, &value)?,
+        SimHashCommands::Similarity { left_key, right_key } => {
+            println!("Similarity: {}", db.sh_similarity(&left_key, &right_key)?);
+        }
+    }
+    Ok(())
+}
+
+fn print_pairs(entries: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+    for (key, value) in entries {
+        print_pair(key, value)?;
+        println!();
+    }
+    Ok(())
+}
+
+/// the token is printed only if there are more pages
+fn print_page(page: Page) -> Result<()> {
+    print_pairs(&page.entries)?;
+    if let Some(next) = page.next {
+        println!("Next page token: {}", next.encode()?);
+    }
+    Ok(())
+}
+
+fn print_pair(key: &[u8], value: &[u8]) -> Result<()> {
+    // keys written through the library api can be arbitrary bytes
+    let key = String::from_utf8_lossy(key);
+    let value = String::from_utf8(value.to_vec()).context("converting value to string")?;
+    println!("Key: {key}");
+    println!("Value: {value}");
+    Ok(())
+}
+
+pub fn print_err(e: Error) {
+    println!("error: {}", e);
+    for (i, small_e) in e.chain().enumerate().skip(1) {
+        println!("{}{small_e}", "\t".repeat(i));
+    }
+}
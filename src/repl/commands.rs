@@ -10,24 +10,37 @@ pub struct Repl {
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     Get { key: String },
-    Put { key: String, value: String },
+    Put {
+        key: String,
+        value: String,
+
+        /// seconds until the entry expires, it never does if missing
+        ttl: Option<u64>,
+    },
     Delete { key: String },
 
+    /// deletes every key for which start_key <= key <= end_key
+    DeleteRange { start_key: String, end_key: String },
+
     List {
         key_prefix: String,
 
-        #[arg(num_args=2, value_names = ["PAGE NUMBER", "PAGE SIZE"])]
-        /// page number and page size
-        pagination: Option<Vec<u64>>,
+        /// number of entries per page, all entries are printed if missing
+        page_size: Option<usize>,
+
+        /// token printed after the previous page
+        token: Option<String>,
     },
 
     RangeScan {
         start_key: String,
         end_key: String,
 
-        #[arg(num_args=2, value_names = ["PAGE NUMBER", "PAGE SIZE"])]
-        /// page number and page size
-        pagination: Option<Vec<u64>>,
+        /// number of entries per page, all entries are printed if missing
+        page_size: Option<usize>,
+
+        /// token printed after the previous page
+        token: Option<String>,
     },
 
     #[command(subcommand)]
@@ -1,5 +1,6 @@
 mod parser;
 mod commands;
+mod client;
 
 pub use commands::Repl;
 pub use commands::Commands;
@@ -8,4 +9,4 @@ pub use commands::SimHashCommands;
 pub use commands::HLLCommands;
 pub use commands::CMSCommands;
 pub use parser::REPL;
-pub use parser::parse_from_str;
+pub use client::{run, print_err};
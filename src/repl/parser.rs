@@ -3,6 +3,7 @@ use anyhow::{Result, Context};
 use clap::Parser;
 use super::Repl;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct REPL {
     stdin: Stdin,
     stdout: Stdout,
@@ -24,9 +25,14 @@ impl REPL {
                 .context("flushing prompt to stdout")?;
 
             buff.clear();
-            self.stdin.read_line(&mut buff)
+            let read = self.stdin.read_line(&mut buff)
                 .context("reading user input")?;
 
+            // stdin was closed
+            if read == 0 {
+                return parse_from_str("quit");
+            }
+
             let query = parse_from_str(&buff);
             if let Err(e) = query {
                 println!("{}", e);
@@ -0,0 +1,282 @@
+use std::{collections::BTreeMap, sync::Mutex};
+use anyhow::{Result, Context, anyhow};
+use nebula::{Db, PageToken, check_plain_key, is_reserved, structure_key, utf8};
+use super::Reply;
+
+/// scan cursors kept for clients, the database keeps as many snapshots for unfinished scans
+const MAX_CURSORS: usize = 64;
+
+/// page tokens of unfinished scans by the numeric cursors handed out for them,
+/// clients parse the cursor as an integer so the token itself stays on the server
+#[derive(Default)]
+pub struct Cursors {
+    state: Mutex<CursorState>,
+}
+
+#[derive(Default)]
+struct CursorState {
+    last: u64,
+    tokens: BTreeMap<u64, PageToken>,
+}
+
+impl Cursors {
+    /// token the cursor stands for, None for 0 which starts a new scan
+    /// a cursor can be sent again until newer cursors push it out
+    fn token(&self, cursor: u64) -> Result<Option<PageToken>> {
+        if cursor == 0 {
+            return Ok(None);
+        }
+        let state = self.state.lock().map_err(|_| anyhow!("cursors lock poisoned"))?;
+        let token = state.tokens.get(&cursor).ok_or_else(|| anyhow!("invalid cursor"))?;
+        Ok(Some(token.clone()))
+    }
+
+    /// new cursor for the token, 0 once the scan is over
+    fn add(&self, token: Option<PageToken>) -> Result<u64> {
+        let Some(token) = token else {
+            return Ok(0);
+        };
+        let mut state = self.state.lock().map_err(|_| anyhow!("cursors lock poisoned"))?;
+        state.last += 1;
+        let cursor = state.last;
+        state.tokens.insert(cursor, token);
+        if state.tokens.len() > MAX_CURSORS {
+            state.tokens.pop_first();
+        }
+        Ok(cursor)
+    }
+}
+
+/// result of a single command, the server stops after replying to a shutdown
+pub enum Outcome {
+    Reply(Reply),
+    Shutdown,
+}
+
+/// runs a single RESP command against the database
+pub fn execute(db: &Db, cursors: &Cursors, args: Vec<Vec<u8>>) -> Outcome {
+    let Some(name) = args.first() else {
+        return Outcome::Reply(Reply::error("empty command"));
+    };
+    let name = String::from_utf8_lossy(name).to_uppercase();
+    if name == "SHUTDOWN" {
+        return Outcome::Shutdown;
+    }
+
+    let reply = dispatch(db, cursors, &name, &args[1..]).unwrap_or_else(|e| {
+        let mut msg = e.to_string();
+        for cause in e.chain().skip(1) {
+            msg.push_str(&format!(": {cause}"));
+        }
+        Reply::error(msg)
+    });
+    Outcome::Reply(reply)
+}
+
+fn dispatch(db: &Db, cursors: &Cursors, name: &str, args: &[Vec<u8>]) -> Result<Reply> {
+    match name {
+        "PING" => match args {
+            [] => Ok(Reply::Simple(String::from("PONG"))),
+            [msg] => Ok(Reply::Bulk(Some(msg.clone()))),
+            _ => Err(arity(name)),
+        },
+        "ECHO" => {
+            let [msg] = args else { return Err(arity(name)) };
+            Ok(Reply::Bulk(Some(msg.clone())))
+        }
+        // clients ask for these on connect, there is nothing to report
+        "COMMAND" | "CONFIG" | "CLIENT" => Ok(Reply::Array(vec![])),
+        "GET" => {
+            let [key] = args else { return Err(arity(name)) };
+            check_plain_key(key)?;
+            Ok(Reply::Bulk(db.get(key).context("getting entry")?))
+        }
+        "SET" => {
+            let [key, value] = args else { return Err(arity(name)) };
+            check_plain_key(key)?;
+            db.put(key, value).context("putting entry")?;
+            Ok(Reply::ok())
+        }
+        "DEL" => {
+            if args.is_empty() {
+                return Err(arity(name));
+            }
+
+            let mut deleted = 0;
+            for key in args {
+                check_plain_key(key)?;
+                if db.get(key)?.is_some() {
+                    db.delete(key).context("deleting entry")?;
+                    deleted += 1;
+                }
+            }
+            Ok(Reply::Integer(deleted))
+        }
+        "SCAN" => scan(db, cursors, args),
+        "BF.RESERVE" => {
+            let [key, fp_prob, item_count] = args else { return Err(arity(name)) };
+            db.bf_reserve(&structure_key("bf_", key)?, parse(item_count)?, parse(fp_prob)?)?;
+            Ok(Reply::ok())
+        }
+        "BF.ADD" => {
+            let [key, item] = args else { return Err(arity(name)) };
+            Ok(Reply::Integer(bf_add(db, key, item)?))
+        }
+        "BF.MADD" => {
+            let [key, items @ ..] = args else { return Err(arity(name)) };
+            if items.is_empty() {
+                return Err(arity(name));
+            }
+
+            let added = items.iter()
+                .map(|item| bf_add(db, key, item).map(Reply::Integer))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Reply::Array(added))
+        }
+        "BF.EXISTS" => {
+            let [key, item] = args else { return Err(arity(name)) };
+            Ok(Reply::Integer(bf_exists(db, key, item)?))
+        }
+        "BF.MEXISTS" => {
+            let [key, items @ ..] = args else { return Err(arity(name)) };
+            if items.is_empty() {
+                return Err(arity(name));
+            }
+
+            let exists = items.iter()
+                .map(|item| bf_exists(db, key, item).map(Reply::Integer))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Reply::Array(exists))
+        }
+        "PFADD" => {
+            let [key, items @ ..] = args else { return Err(arity(name)) };
+            let key = structure_key("hll_", key)?;
+
+            let mut before = None;
+            if db.get(key.as_bytes())?.is_some() {
+                before = Some(db.hll_count(&key)?);
+            } else {
+                db.hll_new(&key)?;
+            }
+            for item in items {
+                db.hll_add(&key, item)?;
+            }
+
+            // 1 if the key was created or the estimate changed
+            let changed = match before {
+                Some(before) => db.hll_count(&key)? != before,
+                None => true,
+            };
+            Ok(Reply::Integer(changed as i64))
+        }
+        "PFCOUNT" => {
+            let [key] = args else { return Err(arity(name)) };
+            let key = structure_key("hll_", key)?;
+            if db.get(key.as_bytes())?.is_none() {
+                return Ok(Reply::Integer(0));
+            }
+            Ok(Reply::Integer(db.hll_count(&key)?.round() as i64))
+        }
+        "CMS.INITBYPROB" => {
+            let [key, error, probability] = args else { return Err(arity(name)) };
+            db.cms_reserve(&structure_key("cms_", key)?, parse(error)?, parse(probability)?)?;
+            Ok(Reply::ok())
+        }
+        "CMS.INCRBY" => {
+            let [key, pairs @ ..] = args else { return Err(arity(name)) };
+            if pairs.is_empty() || pairs.len() % 2 != 0 {
+                return Err(arity(name));
+            }
+
+            let key = db.existing_structure("cms_", key)?
+                .ok_or_else(|| anyhow!("key does not exist"))?;
+            let mut counts = Vec::new();
+            for pair in pairs.chunks(2) {
+                let item = utf8(&pair[0])?;
+                let increment: u64 = parse(&pair[1])?;
+                let count = db.cms_add_count(&key, item, increment)?;
+                counts.push(Reply::Integer(count as i64));
+            }
+            Ok(Reply::Array(counts))
+        }
+        "CMS.QUERY" => {
+            let [key, items @ ..] = args else { return Err(arity(name)) };
+            if items.is_empty() {
+                return Err(arity(name));
+            }
+
+            let key = db.existing_structure("cms_", key)?
+                .ok_or_else(|| anyhow!("key does not exist"))?;
+            let counts = items.iter()
+                .map(|item| Ok(Reply::Integer(db.cms_count(&key, utf8(item)?)? as i64)))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Reply::Array(counts))
+        }
+        _ => Err(anyhow!("unknown command '{name}'")),
+    }
+}
+
+/// SCAN cursor [MATCH prefix*] [COUNT count]
+/// the cursor is a number standing for a page token, 0 starts a new scan and is returned once it ends
+fn scan(db: &Db, cursors: &Cursors, args: &[Vec<u8>]) -> Result<Reply> {
+    let [cursor, options @ ..] = args else { return Err(arity("SCAN")) };
+
+    let mut prefix = Vec::new();
+    let mut count = 10;
+    for option in options.chunks(2) {
+        let [name, value] = option else { return Err(anyhow!("syntax error")) };
+        match String::from_utf8_lossy(name).to_uppercase().as_str() {
+            "MATCH" => {
+                let pattern = value.strip_suffix(b"*").unwrap_or(value);
+                if pattern.iter().any(|byte| b"*?[]\\".contains(byte)) {
+                    return Err(anyhow!("only prefix patterns like 'user:*' are supported"));
+                }
+                prefix = pattern.to_vec();
+            }
+            "COUNT" => count = parse(value)?,
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+
+    let token = cursors.token(parse(cursor).context("invalid cursor")?)?;
+    let page = db.prefix_scan_page(&prefix, count, token)?;
+
+    let cursor = cursors.add(page.next)?.to_string();
+    let keys = page.entries
+        .into_iter()
+        .filter(|(key, _)| !is_reserved(key))
+        .map(|(key, _)| Reply::Bulk(Some(key)))
+        .collect();
+
+    Ok(Reply::Array(vec![Reply::Bulk(Some(cursor.into_bytes())), Reply::Array(keys)]))
+}
+
+fn bf_add(db: &Db, key: &[u8], item: &[u8]) -> Result<i64> {
+    let key = structure_key("bf_", key)?;
+    if db.get(key.as_bytes())?.is_none() {
+        db.bf_new(&key)?;
+    } else if db.bf_check(&key, item)? {
+        return Ok(0);
+    }
+
+    db.bf_add(&key, item)?;
+    Ok(1)
+}
+
+fn bf_exists(db: &Db, key: &[u8], item: &[u8]) -> Result<i64> {
+    let key = structure_key("bf_", key)?;
+    if db.get(key.as_bytes())?.is_none() {
+        return Ok(0);
+    }
+    Ok(db.bf_check(&key, item)? as i64)
+}
+
+fn parse<T: std::str::FromStr>(arg: &[u8]) -> Result<T> {
+    utf8(arg)?
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a valid number", String::from_utf8_lossy(arg)))
+}
+
+fn arity(name: &str) -> anyhow::Error {
+    anyhow!("wrong number of arguments for '{}' command", name.to_lowercase())
+}
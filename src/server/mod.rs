@@ -0,0 +1,297 @@
+mod resp;
+mod handler;
+
+use std::{
+    io::{BufReader, BufWriter, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+};
+use anyhow::{Result, Context, anyhow};
+use nebula::Db;
+use handler::{Cursors, Outcome};
+pub use resp::Reply;
+
+/// what the connections share, commands run while `running` is read locked
+/// a shutdown takes the write lock, so it waits for the commands that already started
+struct Shared {
+    db: Arc<Db>,
+    cursors: Cursors,
+    running: RwLock<bool>,
+    /// where the server listens, a shutdown connects to it to wake up the accept
+    addr: SocketAddr,
+}
+
+/// serves RESP2 clients until one of them sends SHUTDOWN
+/// every connection gets its own thread and runs its commands on it, the database is shared between them
+/// once it returns the connections are closed and the database is no longer shared
+pub fn serve(db: Arc<Db>, listener: TcpListener) -> Result<()> {
+    let mut addr = listener.local_addr().context("getting listener address")?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(Ipv4Addr::LOCALHOST.into());
+    }
+    let shared = Arc::new(Shared { db, cursors: Cursors::default(), running: RwLock::new(true), addr });
+
+    let mut connections: Vec<(TcpStream, JoinHandle<()>)> = Vec::new();
+    for stream in listener.incoming() {
+        if !*shared.running.read().map_err(|_| anyhow!("server state poisoned"))? {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let Ok(handle) = stream.try_clone() else {
+            continue;
+        };
+
+        let shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || {
+            // the client is gone, there is nobody to report the error to
+            let _ = handle_connection(stream, &shared);
+        });
+        connections.retain(|(_, thread)| !thread.is_finished());
+        connections.push((handle, thread));
+    }
+
+    // idle clients are disconnected, the others after the command they are running
+    for (stream, thread) in connections {
+        let _ = stream.shutdown(Shutdown::Both);
+        let _ = thread.join();
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone().context("cloning stream")?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let args = match resp::read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                // the stream can not be resynchronized after a protocol error
+                Reply::Error(format!("ERR Protocol error: {e}")).write_to(&mut writer)?;
+                writer.flush()?;
+                return Ok(());
+            }
+        };
+
+        let quit = args.first().is_some_and(|name| name.eq_ignore_ascii_case(b"QUIT"));
+        let outcome = if quit {
+            Outcome::Reply(Reply::ok())
+        } else {
+            let running = shared.running.read().map_err(|_| anyhow!("server state poisoned"))?;
+            if !*running {
+                Reply::error("server is shutting down").write_to(&mut writer)?;
+                writer.flush()?;
+                return Ok(());
+            }
+            handler::execute(&shared.db, &shared.cursors, args)
+        };
+
+        let reply = match outcome {
+            Outcome::Reply(reply) => reply,
+            Outcome::Shutdown => {
+                *shared.running.write().map_err(|_| anyhow!("server state poisoned"))? = false;
+                Reply::ok().write_to(&mut writer)?;
+                writer.flush().context("writing reply")?;
+                // the accept loop only sees the shutdown once it accepts a connection
+                TcpStream::connect(shared.addr).context("waking up the server")?;
+                return Ok(());
+            }
+        };
+        reply.write_to(&mut writer)?;
+
+        // pipelined commands are answered together
+        if quit || reader.buffer().is_empty() {
+            writer.flush().context("writing replies")?;
+        }
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::remove_dir_all,
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        path::Path,
+        sync::Arc,
+        thread,
+    };
+    use nebula::{Config, Db};
+    use super::serve;
+
+    /// sends an inline command and reads the raw reply up to the given number of lines
+    fn send(stream: &mut BufReader<TcpStream>, command: &str, lines: usize) -> String {
+        stream.get_mut().write_all(format!("{command}\r\n").as_bytes()).unwrap();
+        let mut reply = String::new();
+        for _ in 0..lines {
+            stream.read_line(&mut reply).unwrap();
+        }
+        reply
+    }
+
+    #[test]
+    fn resp_session() {
+        let path = "test-data/server";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let db = Arc::new(Db::open(path, Config::default()).unwrap());
+            serve(Arc::clone(&db), listener).unwrap();
+            Arc::into_inner(db).expect("connections are closed").close().unwrap();
+        });
+
+        let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+        assert_eq!(send(&mut client, "PING", 1), "+PONG\r\n");
+        assert_eq!(send(&mut client, "SET user:1 alice", 1), "+OK\r\n");
+        assert_eq!(send(&mut client, "SET user:2 bob", 1), "+OK\r\n");
+        assert_eq!(send(&mut client, "GET user:1", 2), "$5\r\nalice\r\n");
+        assert_eq!(send(&mut client, "GET missing", 1), "$-1\r\n");
+        assert_eq!(send(&mut client, "DEL user:2 missing", 1), ":1\r\n");
+        assert!(send(&mut client, "GET bf_x", 1).starts_with("-ERR"));
+        assert!(send(&mut client, "NOPE", 1).starts_with("-ERR"));
+
+        assert_eq!(send(&mut client, "BF.ADD seen a", 1), ":1\r\n");
+        assert_eq!(send(&mut client, "BF.ADD seen a", 1), ":0\r\n");
+        assert_eq!(send(&mut client, "BF.EXISTS seen a", 1), ":1\r\n");
+        assert_eq!(send(&mut client, "BF.EXISTS other a", 1), ":0\r\n");
+
+        assert_eq!(send(&mut client, "PFADD visitors a b c", 1), ":1\r\n");
+        // the count is only an estimate
+        let count = send(&mut client, "PFCOUNT visitors", 1);
+        assert!(count.starts_with(':') && count != ":0\r\n");
+        assert_eq!(send(&mut client, "PFCOUNT nobody", 1), ":0\r\n");
+
+        assert_eq!(send(&mut client, "CMS.INITBYPROB words 0.01 0.01", 1), "+OK\r\n");
+        assert_eq!(send(&mut client, "CMS.INCRBY words a 2 b 1", 3), "*2\r\n:2\r\n:1\r\n");
+        assert_eq!(send(&mut client, "CMS.QUERY words a", 2), "*1\r\n:2\r\n");
+
+        // structures are hidden from scans
+        assert_eq!(send(&mut client, "SCAN 0", 4), "*2\r\n$1\r\n0\r\n*1\r\n");
+        let mut key = String::new();
+        client.read_line(&mut key).unwrap();
+        client.read_line(&mut key).unwrap();
+        assert_eq!(key, "$6\r\nuser:1\r\n");
+
+        // pipelined commands in a single write
+        client.get_mut().write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").unwrap();
+        let mut replies = vec![0; b"+OK\r\n$1\r\nv\r\n".len()];
+        client.read_exact(&mut replies).unwrap();
+        assert_eq!(replies, b"+OK\r\n$1\r\nv\r\n");
+
+        assert_eq!(send(&mut client, "SHUTDOWN", 1), "+OK\r\n");
+        server.join().unwrap();
+    }
+
+    /// cursor and keys of a SCAN reply
+    fn scan(client: &mut BufReader<TcpStream>, cursor: u64) -> (u64, Vec<String>) {
+        let header = send(client, &format!("SCAN {cursor} MATCH user:* COUNT 2"), 3);
+        let lines = header.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "*2");
+        let cursor = lines[2].parse().expect("integer cursor");
+
+        let mut count = String::new();
+        client.read_line(&mut count).unwrap();
+        let count = count.trim_end()[1..].parse::<usize>().unwrap();
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            let mut key = String::new();
+            client.read_line(&mut key).unwrap();
+            key.clear();
+            client.read_line(&mut key).unwrap();
+            keys.push(key.trim_end().to_string());
+        }
+        (cursor, keys)
+    }
+
+    #[test]
+    fn scan_pages_with_integer_cursor() {
+        let path = "test-data/server-scan";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let db = Arc::new(Db::open(path, Config::default()).unwrap());
+            serve(Arc::clone(&db), listener).unwrap();
+            Arc::into_inner(db).expect("connections are closed").close().unwrap();
+        });
+
+        let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+        for i in 0..5 {
+            assert_eq!(send(&mut client, &format!("SET user:{i} name"), 1), "+OK\r\n");
+        }
+
+        let mut keys = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = scan(&mut client, cursor);
+            keys.extend(page);
+            if next == 0 {
+                break;
+            }
+            assert!(keys.len() < 5);
+            cursor = next;
+        }
+        assert_eq!(keys, (0..5).map(|i| format!("user:{i}")).collect::<Vec<_>>());
+
+        assert!(send(&mut client, "SCAN 12345", 1).starts_with("-ERR"));
+        assert!(send(&mut client, "SCAN abc", 1).starts_with("-ERR"));
+
+        assert_eq!(send(&mut client, "SHUTDOWN", 1), "+OK\r\n");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connections_run_commands_side_by_side() {
+        let path = "test-data/server-connections";
+        if Path::new(path).is_dir() {
+            remove_dir_all(path).expect("removing old db");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let db = Arc::new(Db::open(path, Config::default()).unwrap());
+            serve(Arc::clone(&db), listener).unwrap();
+            Arc::into_inner(db).expect("connections are closed").close().unwrap();
+        });
+
+        let mut idle = BufReader::new(TcpStream::connect(addr).unwrap());
+        assert_eq!(send(&mut idle, "PING", 1), "+PONG\r\n");
+
+        let clients = (0..4)
+            .map(|client| thread::spawn(move || {
+                let mut stream = BufReader::new(TcpStream::connect(addr).unwrap());
+                for i in 0..50 {
+                    assert_eq!(send(&mut stream, &format!("SET {client}:{i} v{i}"), 1), "+OK\r\n");
+                }
+            }))
+            .collect::<Vec<_>>();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+        assert_eq!(send(&mut client, "GET 3:49", 2), "$3\r\nv49\r\n");
+
+        // the idle connection is closed by the shutdown and does not keep the server up
+        assert_eq!(send(&mut client, "SHUTDOWN", 1), "+OK\r\n");
+        server.join().unwrap();
+        let mut rest = Vec::new();
+        idle.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{BufRead, Read, Write};
+use anyhow::{Result, Context, anyhow};
+use nebula::building_blocks::MAX_VAL_LEN;
+
+/// max number of arguments in a single command
+const MAX_ARGS: usize = 1024 * 1024;
+/// max number of bytes in all bulk strings of a single command
+const MAX_COMMAND_LEN: u64 = 1024 * 1024 * 1024;
+/// max length of a single line, inline commands included
+const MAX_LINE_LEN: u64 = 1024 * 1024;
+
+/// RESP2 reply sent back to the client
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    /// None is the null bulk string
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Simple(String::from("OK"))
+    }
+
+    pub fn error(msg: impl std::fmt::Display) -> Self {
+        Reply::Error(format!("ERR {msg}"))
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        match self {
+            Reply::Simple(s) => write!(out, "+{s}\r\n")?,
+            // new lines would end the error early
+            Reply::Error(e) => write!(out, "-{}\r\n", e.replace(['\r', '\n'], " "))?,
+            Reply::Integer(i) => write!(out, ":{i}\r\n")?,
+            Reply::Bulk(None) => write!(out, "$-1\r\n")?,
+            Reply::Bulk(Some(bytes)) => {
+                write!(out, "${}\r\n", bytes.len())?;
+                out.write_all(bytes)?;
+                out.write_all(b"\r\n")?;
+            }
+            Reply::Array(replies) => {
+                write!(out, "*{}\r\n", replies.len())?;
+                for reply in replies {
+                    reply.write_to(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// reads one command, either an array of bulk strings or an inline command
+/// returns None when the client closed the connection
+pub fn read_command<R: BufRead>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let Some(line) = read_line(reader)? else {
+            return Ok(None);
+        };
+
+        if let Some(count) = line.strip_prefix(b"*") {
+            let count = parse_len(count, MAX_ARGS as u64).context("parsing array length")?;
+            // memory is only taken for the bytes that actually arrive
+            let mut args = Vec::with_capacity((count as usize).min(1024));
+            let mut budget = MAX_COMMAND_LEN;
+            for _ in 0..count {
+                let bulk = read_bulk(reader, budget)?;
+                budget -= bulk.len() as u64;
+                args.push(bulk);
+            }
+            return Ok(Some(args));
+        }
+
+        // inline commands are space separated, empty lines are ignored
+        let args = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_vec())
+            .collect::<Vec<_>>();
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// budget is the number of bytes the command may still take
+fn read_bulk<R: BufRead>(reader: &mut R, budget: u64) -> Result<Vec<u8>> {
+    let line = read_line(reader)?
+        .ok_or_else(|| anyhow!("connection closed inside a command"))?;
+    let len = line.strip_prefix(b"$")
+        .ok_or_else(|| anyhow!("expected bulk string"))?;
+    let len = parse_len(len, MAX_VAL_LEN.min(budget)).context("parsing bulk string length")?;
+
+    let mut bulk = Vec::new();
+    reader.by_ref().take(len + 2).read_to_end(&mut bulk)
+        .context("reading bulk string")?;
+    if bulk.len() as u64 != len + 2 {
+        return Err(anyhow!("connection closed inside a bulk string"));
+    }
+    if !bulk.ends_with(b"\r\n") {
+        return Err(anyhow!("bulk string is not terminated"));
+    }
+    bulk.truncate(len as usize);
+    Ok(bulk)
+}
+
+/// line without the trailing \r\n, None on eof
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(MAX_LINE_LEN).read_until(b'\n', &mut line)
+        .context("reading line")?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 == MAX_LINE_LEN && !line.ends_with(b"\n") {
+        return Err(anyhow!("line is longer than {MAX_LINE_LEN}"));
+    }
+
+    if line.ends_with(b"\n") {
+        line.pop();
+    }
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(len: &[u8], max: u64) -> Result<u64> {
+    let len: u64 = std::str::from_utf8(len)
+        .context("length is not utf8")?
+        .parse()
+        .context("length is not a number")?;
+    if len > max {
+        return Err(anyhow!("length {len} is bigger than {max}"));
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::{read_command, Reply, MAX_LINE_LEN};
+
+    #[test]
+    fn read_array_and_inline() {
+        let mut input = Cursor::new(b"*2\r\n$3\r\nGET\r\n$4\r\nk\r\ny\r\n\r\nPING  hi\r\n".to_vec());
+        assert_eq!(read_command(&mut input).unwrap(), Some(vec![b"GET".to_vec(), b"k\r\ny".to_vec()]));
+        assert_eq!(read_command(&mut input).unwrap(), Some(vec![b"PING".to_vec(), b"hi".to_vec()]));
+        assert_eq!(read_command(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn read_malformed() {
+        assert!(read_command(&mut Cursor::new(b"*1\r\n$5\r\nab\r\n".to_vec())).is_err());
+        assert!(read_command(&mut Cursor::new(b"*1\r\n:5\r\n".to_vec())).is_err());
+        assert!(read_command(&mut Cursor::new(b"*x\r\n".to_vec())).is_err());
+        assert!(read_command(&mut Cursor::new(b"*1\r\n$99999999999\r\n".to_vec())).is_err());
+    }
+
+    #[test]
+    fn read_bounded() {
+        // a declared length bigger than what arrives fails without taking the memory up front
+        assert!(read_command(&mut Cursor::new(b"*1\r\n$500000000\r\nab\r\n".to_vec())).is_err());
+        assert!(read_command(&mut Cursor::new(b"*1048576\r\n$1\r\na\r\n".to_vec())).is_err());
+
+        let mut long_line = vec![b'a'; MAX_LINE_LEN as usize + 1];
+        long_line.extend_from_slice(b"\r\n");
+        assert!(read_command(&mut Cursor::new(long_line)).is_err());
+    }
+
+    #[test]
+    fn write_replies() {
+        let reply = Reply::Array(vec![
+            Reply::ok(),
+            Reply::Integer(-3),
+            Reply::Bulk(Some(b"v".to_vec())),
+            Reply::Bulk(None),
+            Reply::error("bad\r\nthing"),
+        ]);
+        let mut out = Vec::new();
+        reply.write_to(&mut out).unwrap();
+        assert_eq!(out, b"*5\r\n+OK\r\n:-3\r\n$1\r\nv\r\n$-1\r\n-ERR bad  thing\r\n".to_vec());
+    }
+}
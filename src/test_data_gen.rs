@@ -3,7 +3,7 @@ use std::{
     io::{Seek, Write, Read}, path::Path
 };
 use anyhow::{Result, Context};
-use crate::building_blocks::{BloomFilter, IndexBuilder, SummaryBuilder, SSTableBuilderMultiFile, SSTableBuilderSingleFile, Entry};
+use nebula::building_blocks::{BloomFilter, IndexBuilder, SummaryBuilder, SSTableBuilderMultiFile, SSTableBuilderSingleFile, SSTableReaderSingleFile, Entry, EntryKind};
 
 pub fn generate_test_data() -> Result<()> {
     generate_dir()?;
@@ -134,16 +134,19 @@ fn generate_sstable_multifile() -> Result<()> {
     remove_dir("./test-data/read-invalid-sstable-multifile")
         .context("removing the old invalid sstable")?;
 
-    let mut sstable_valid = SSTableBuilderMultiFile::new("test-data", "read-valid-sstable-multifile", 100, 0.1, 10)
+    let mut sstable_valid = SSTableBuilderMultiFile::new("test-data", "read-valid-sstable-multifile", 1000, 0.1, 10)
         .context("creating the valid sstable")?;
-    let mut sstable_invalid = SSTableBuilderMultiFile::new("test-data", "read-invalid-sstable-multifile", 100, 0.1, 10)
+    let mut sstable_invalid = SSTableBuilderMultiFile::new("test-data", "read-invalid-sstable-multifile", 1000, 0.1, 10)
         .context("creating the invalid sstable")?;
 
-    for i in 0..100 {
+    // enough entries for a few blocks
+    for i in 0..1000 {
         let entry = Entry {
             key: i.to_string().into_bytes(),
             value: Some(i.to_string().into_bytes()),
             timestamp: i,
+            kind: EntryKind::Point,
+            expires_at: None,
         };
         sstable_valid.insert(entry.clone())
             .context("inserting entry into the valid sstable")?;
@@ -165,7 +168,8 @@ fn generate_sstable_multifile() -> Result<()> {
     data_file.rewind()
         .context("rewinding the data file of the invalid sstable")?;
 
-    corrupt(&mut data_ser);
+    // the header is left intact so only reading the entries fails
+    corrupt(&mut data_ser[64..]);
     data_file.write_all(&data_ser)
         .context("writing the data file of the invalid sstable")?;
 
@@ -178,16 +182,19 @@ fn generate_sstable_singlefile() -> Result<()> {
     remove_dir("./test-data/read-invalid-sstable-singlefile")
         .context("removing the old invalid sstable")?;
 
-    let mut sstable_valid = SSTableBuilderSingleFile::new("test-data", "read-valid-sstable-singlefile", 100, 0.1, 10)
+    let mut sstable_valid = SSTableBuilderSingleFile::new("test-data", "read-valid-sstable-singlefile", 1000, 0.1, 10)
         .context("creating the valid sstable")?;
-    let mut sstable_invalid = SSTableBuilderSingleFile::new("test-data", "read-invalid-sstable-singlefile", 100, 0.1, 10)
+    let mut sstable_invalid = SSTableBuilderSingleFile::new("test-data", "read-invalid-sstable-singlefile", 1000, 0.1, 10)
         .context("creating the invalid sstable")?;
 
-    for i in 0..100 {
+    // enough entries for a few blocks
+    for i in 0..1000 {
         let entry = Entry {
             key: i.to_string().into_bytes(),
             value: Some(i.to_string().into_bytes()),
             timestamp: i,
+            kind: EntryKind::Point,
+            expires_at: None,
         };
         sstable_valid.insert(entry.clone())
             .context("inserting entry into the valid sstable")?;
@@ -209,7 +216,12 @@ fn generate_sstable_singlefile() -> Result<()> {
     data_file.rewind()
         .context("rewinding the data file of the invalid sstable")?;
 
-    corrupt(&mut data_ser);
+    // the header and everything after the data are left intact so only reading the entries fails
+    let data_end = SSTableReaderSingleFile::load("test-data/read-valid-sstable-singlefile")
+        .context("loading the valid sstable")?
+        .header
+        .range_tombstones_offset as usize;
+    corrupt(&mut data_ser[64..data_end]);
     data_file.write_all(&data_ser)
         .context("writing the data file of the invalid sstable")?;
 
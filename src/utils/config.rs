@@ -1,19 +1,16 @@
 use crate::building_blocks::{
-    BloomFilter, CountMinSketch, Entry, FileOrganization, LSMTreeUnderlying, SSTableConfig,
-    SkipListNode, TokenBucket, MF, SF,
+    Compaction, Compression, FileOrganization, SSTableConfig, SyncMode, RecoveryMode, TABLE_CACHE_CAPACITY,
 };
-use core::cell::RefCell;
-use crate::building_blocks::StorageCRUD;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
-use std::rc::Rc;
-use std::time::{Duration, Instant};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 use anyhow::{Result, Context};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
     pub token_bucket: TokenBucketConfig,
     pub cms: CountMinSketchConfig,
@@ -29,22 +26,6 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn default() -> Self {
-        Config {
-            token_bucket: TokenBucketConfig::default(),
-            cms: CountMinSketchConfig::default(),
-            bf: BloomFilterConfig::default(),
-            lsm: LSMTreeConfig::default(),
-            hll: HLLConfig::default(),
-            ssconfig: SSTableConfig::default(),
-            skiplist: SkipListConfig::default(),
-            simhash: SimHashConfig::default(),
-            memtable: MemtableConfig::default(),
-            wal: WALConfig::default(),
-            cache: CacheConfig::default(),
-        }
-    }
-
     // Method to load the JSON configuration from a file into a Config struct
     pub fn load_from_file() -> Result<Self> {
         // Open the file
@@ -63,6 +44,38 @@ impl Config {
         Ok(config)
     }
 
+    /// settings that can not be used as they are, outdated ones that are ignored only get a warning
+    pub fn validate(&self) -> Result<()> {
+        if let Some(entries) = self.cache.capacity_entries {
+            eprintln!(
+                "warning: cache.capacity ({entries} entries) is ignored, the cache is bounded by cache.capacity_bytes ({} bytes)",
+                self.cache.capacity_bytes,
+            );
+        }
+        self.wal.sync_mode.validate()
+            .context("checking wal.sync_mode")
+    }
+
+    /// relative data directories are joined onto the database root
+    /// older configs named them relative to the working directory with the root in front, like "data/table_data",
+    /// those still resolve to the same directories
+    pub fn resolve_paths(&mut self, root: &Path) -> Result<()> {
+        let root_prefix = root.components()
+            .filter(|component| *component != Component::CurDir)
+            .collect::<PathBuf>();
+        for dir in [&mut self.lsm.data_dir, &mut self.memtable.data_folder, &mut self.wal.path] {
+            let relative = Path::new(&*dir);
+            let resolved = match relative.strip_prefix(&root_prefix) {
+                Ok(inside) if relative.is_relative() && !inside.as_os_str().is_empty() => root.join(inside),
+                _ => root.join(relative),
+            };
+            *dir = resolved.to_str()
+                .context("converting data dir path to string")?
+                .to_owned();
+        }
+        Ok(())
+    }
+
     pub fn write_defaults_to_file() -> Result<()> {
         // Create a default Config
         let config = Config::default();
@@ -88,13 +101,16 @@ pub struct TokenBucketConfig {
     reset_interval: Duration, // not directly serializable :D
 }
 
-impl TokenBucketConfig {
-    pub fn default() -> Self {
+impl Default for TokenBucketConfig {
+    fn default() -> Self {
         TokenBucketConfig {
             capacity: 5,
             reset_interval: Duration::from_secs(10),
         }
     }
+}
+
+impl TokenBucketConfig {
     pub fn get_values(&self) -> (usize, Duration) {
         (self.capacity, self.reset_interval)
     }
@@ -106,13 +122,16 @@ pub struct CountMinSketchConfig {
     certainty: f64,
 }
 
-impl CountMinSketchConfig {
-    pub fn default() -> Self {
+impl Default for CountMinSketchConfig {
+    fn default() -> Self {
         CountMinSketchConfig {
             desired_accuracy: 0.01,
             certainty: 0.01,
         }
     }
+}
+
+impl CountMinSketchConfig {
     pub fn get_values(&self) -> (f64, f64) {
         (self.desired_accuracy, self.certainty)
     }
@@ -124,14 +143,16 @@ pub struct BloomFilterConfig {
     fp_prob: f64,
 }
 
-impl BloomFilterConfig {
-    pub fn default() -> Self {
+impl Default for BloomFilterConfig {
+    fn default() -> Self {
         BloomFilterConfig {
             item_count: 10,
             fp_prob: 0.01,
         }
     }
+}
 
+impl BloomFilterConfig {
     pub fn get_values(&self) -> (u64, f64) {
         (self.item_count, self.fp_prob)
     }
@@ -145,21 +166,36 @@ pub struct LSMTreeConfig {
     data_dir: String,
     size_threshold: usize,
     number_of_levels: usize,
+    #[serde(default)]
+    compaction: Compaction,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default = "default_table_cache_capacity")]
+    table_cache_capacity: usize,
 }
 
-impl LSMTreeConfig {
-    pub fn default() -> Self {
+fn default_table_cache_capacity() -> usize {
+    TABLE_CACHE_CAPACITY
+}
+
+impl Default for LSMTreeConfig {
+    fn default() -> Self {
         LSMTreeConfig {
             file_organization: FileOrganization::MultiFile(()),
             fp_prob: 0.01,
             summary_nth: 50,
-            data_dir: String::from("data/table_data"),
+            data_dir: String::from("table_data"),
             size_threshold: 20,
             number_of_levels: 5,
+            compaction: Compaction::Tiered,
+            compression: Compression::None,
+            table_cache_capacity: TABLE_CACHE_CAPACITY,
         }
     }
+}
 
-    pub fn get_values(&self) -> (FileOrganization, f64, u64, String, usize, usize) {
+impl LSMTreeConfig {
+    pub fn get_values(&self) -> (FileOrganization, f64, u64, String, usize, usize, Compaction, Compression, usize) {
         (
             self.file_organization.clone(),
             self.fp_prob,
@@ -167,23 +203,31 @@ impl LSMTreeConfig {
             self.data_dir.clone(),
             self.size_threshold,
             self.number_of_levels,
+            self.compaction.clone(),
+            self.compression,
+            self.table_cache_capacity,
         )
     }
 }
 
-impl SSTableConfig {
-    pub fn default() -> Self {
+impl Default for SSTableConfig {
+    fn default() -> Self {
         SSTableConfig {
             file_organization: FileOrganization::MultiFile(()),
             filter_fp_prob: 0.01,
             summary_nth: 50,
+            compression: Compression::None,
         }
     }
-    pub fn get_values(&self) -> (FileOrganization, f64, u64) {
+}
+
+impl SSTableConfig {
+    pub fn get_values(&self) -> (FileOrganization, f64, u64, Compression) {
         (
             self.file_organization.clone(),
             self.filter_fp_prob,
             self.summary_nth,
+            self.compression,
         )
     }
 }
@@ -193,11 +237,13 @@ pub struct HLLConfig {
     number_of_bits: u8,
 }
 
-impl HLLConfig {
-    pub fn default() -> Self {
+impl Default for HLLConfig {
+    fn default() -> Self {
         HLLConfig { number_of_bits: 10 }
     }
+}
 
+impl HLLConfig {
     pub fn get_values(&self) -> u8 {
         self.number_of_bits
     }
@@ -217,20 +263,26 @@ pub struct MemtableConfig {
     fp_prob: f64,
     summary_nth: u64,
     data_folder: String,
+    #[serde(default)]
+    compression: Compression,
 }
 
-impl MemtableConfig {
-    pub fn default() -> Self {
+impl Default for MemtableConfig {
+    fn default() -> Self {
         MemtableConfig {
             storage: MemtableStorage::BTree,
             capacity: 50,
             sstable_type: FileOrganization::MultiFile(()),
             fp_prob: 0.01,
             summary_nth: 50,
-            data_folder: String::from("data/table_data"),
+            data_folder: String::from("table_data"),
+            compression: Compression::None,
         }
     }
-    pub fn get_values(&self) -> (MemtableStorage, u64, FileOrganization, f64, u64, String) {
+}
+
+impl MemtableConfig {
+    pub fn get_values(&self) -> (MemtableStorage, u64, FileOrganization, f64, u64, String, Compression) {
         (
             self.storage.clone(),
             self.capacity,
@@ -238,6 +290,7 @@ impl MemtableConfig {
             self.fp_prob,
             self.summary_nth,
             self.data_folder.clone(),
+            self.compression,
         )
     }
 }
@@ -248,8 +301,8 @@ pub struct SimHashConfig {
     stopwords: HashSet<String>,
 }
 
-impl SimHashConfig {
-    pub fn default() -> Self {
+impl Default for SimHashConfig {
+    fn default() -> Self {
         let stopwords: HashSet<String> = ["this", "is", "a", "with", "to", "the", "some"]
             .iter()
             .map(|&word| word.to_string())
@@ -260,7 +313,9 @@ impl SimHashConfig {
             stopwords,
         }
     }
+}
 
+impl SimHashConfig {
     pub fn get_values(&self) -> (u64, HashSet<String>) {
         (self.simhash, self.stopwords.clone())
     }
@@ -271,11 +326,13 @@ pub struct SkipListConfig {
     max_level: usize,
 }
 
-impl SkipListConfig {
-    pub fn default() -> Self {
+impl Default for SkipListConfig {
+    fn default() -> Self {
         SkipListConfig { max_level: 10 }
     }
+}
 
+impl SkipListConfig {
     pub fn get_values(&self) -> usize {
         self.max_level
     }
@@ -284,29 +341,59 @@ impl SkipListConfig {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WALConfig {
     segment_size: u64,
-    path: String
+    path: String,
+    #[serde(default)]
+    sync_mode: SyncMode,
+    #[serde(default)]
+    recovery_mode: RecoveryMode,
 }
 
-impl WALConfig {
-    pub fn default() -> Self {
-        WALConfig { segment_size: 20000, path: String::from("data/WAL") }
+impl Default for WALConfig {
+    fn default() -> Self {
+        WALConfig { segment_size: 20000, path: String::from("WAL"), sync_mode: SyncMode::Group, recovery_mode: RecoveryMode::TruncateAtCorruption }
     }
-    pub fn get_values(&self) -> (String, u64) {
-        (self.path.clone(), self.segment_size)
+}
+
+impl WALConfig {
+    pub fn get_values(&self) -> (String, u64, SyncMode, RecoveryMode) {
+        (self.path.clone(), self.segment_size, self.sync_mode, self.recovery_mode)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CacheConfig {
-    capacity: u64,
+    /// bytes of keys and values
+    #[serde(default = "default_capacity_bytes")]
+    capacity_bytes: usize,
+    /// bytes of sstable blocks, 0 turns the block cache off
+    #[serde(default = "default_block_capacity_bytes")]
+    block_capacity_bytes: usize,
+    /// number of entries, only older configs set it and it is ignored
+    #[serde(default, rename = "capacity", skip_serializing)]
+    capacity_entries: Option<usize>,
 }
 
-impl CacheConfig {
-    pub fn default() -> Self {
-        CacheConfig { capacity: 1000 }
+fn default_capacity_bytes() -> usize {
+    1 << 20
+}
+
+fn default_block_capacity_bytes() -> usize {
+    8 << 20
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity_bytes: default_capacity_bytes(),
+            block_capacity_bytes: default_block_capacity_bytes(),
+            capacity_entries: None,
+        }
     }
-    pub fn get_values(&self) -> u64 {
-        self.capacity
+}
+
+impl CacheConfig {
+    pub fn get_values(&self) -> (usize, usize) {
+        (self.capacity_bytes, self.block_capacity_bytes)
     }
 }
 
@@ -411,3 +498,53 @@ impl CacheConfig {
 //     // FILENAME
 //     pub(super) path: String,
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::building_blocks::SyncMode;
+    use super::{CacheConfig, Config};
+
+    #[test]
+    fn resolving_paths() {
+        let mut config = Config::default();
+        config.resolve_paths(Path::new("./data")).unwrap();
+        assert_eq!(config.lsm.data_dir, "./data/table_data");
+        assert_eq!(config.wal.path, "./data/WAL");
+
+        // directories of older configs already start with the root
+        let mut config = Config::default();
+        config.lsm.data_dir = String::from("data/table_data");
+        config.wal.path = String::from("data/WAL");
+        config.resolve_paths(Path::new("./data")).unwrap();
+        assert_eq!(config.lsm.data_dir, "./data/table_data");
+        assert_eq!(config.wal.path, "./data/WAL");
+
+        let mut config = Config::default();
+        config.lsm.data_dir = String::from("/var/lib/nebula");
+        config.resolve_paths(Path::new("db")).unwrap();
+        assert_eq!(config.lsm.data_dir, "/var/lib/nebula");
+        assert_eq!(config.memtable.data_folder, "db/table_data");
+    }
+
+    #[test]
+    fn cache_capacity_in_entries_is_ignored() {
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        assert!(config["cache"].get("capacity").is_none());
+
+        config["cache"] = serde_json::json!({ "capacity": 1000 });
+        let config: Config = serde_json::from_value(config).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.cache.get_values(), CacheConfig::default().get_values());
+    }
+
+    #[test]
+    fn zero_sync_interval_is_rejected() {
+        let mut config = Config::default();
+        config.wal.sync_mode = SyncMode::Interval(0);
+        assert!(config.validate().is_err());
+
+        config.wal.sync_mode = SyncMode::Interval(1);
+        assert!(config.validate().is_ok());
+    }
+}
@@ -1,5 +1,8 @@
-use std::io::prelude::*;
 
+use std::{fs::File, time::{SystemTime, UNIX_EPOCH}};
+use anyhow::{Context, Result};
+
+#[allow(clippy::approx_constant)]
 pub const EULER_NUMBER: f64 = 2.71828;
 
 /// finding modulo using a mask only works if the divisor is a power of 2
@@ -14,6 +17,21 @@ pub fn closest_pow(n: u64) -> u64{
     1 << (64 - n.leading_zeros() - n.is_power_of_two() as u32)
 }
 
+/// nanos since the epoch, entry timestamps and expiry times use this clock
+pub fn get_timestamp() -> Result<u128> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("getting epoch time")?
+        .as_nanos())
+}
+
+/// files created, renamed or removed in the directory stay that way after a crash once this returns
+pub fn sync_dir(path: &str) -> Result<()> {
+    File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("syncing directory '{path}'"))
+}
+
 #[cfg(test)]
 mod tests{
     use super::*;